@@ -0,0 +1,384 @@
+//! Procedural macros for `serde_json_matcher`, re-exported by it behind the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote, quote_spanned};
+use syn::ext::IdentExt;
+use syn::meta::ParseNestedMeta;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident, LitStr, Visibility};
+
+/// Generates a `<Name>Filter` type with one function per field of the struct, returning the
+/// `serde_json_matcher::Field` under which serde (de)serializes that field, and implements
+/// `serde_json_matcher::Matchable` so that the struct can be named in `path!`.
+///
+/// `#[serde(rename = "...")]`, `#[serde(rename_all = "...")]` and `#[serde(skip)]` are honored,
+/// so renaming a field renames its filter function rather than silently changing the query.
+/// Fields are named as they are deserialized, so a rename for serialization only is ignored.
+/// `#[serde(alias = "...")]` and `#[serde(flatten)]` fail to compile, as a field read under
+/// several names, or with no name of its own, cannot be matched through a single path.
+#[proc_macro_derive(Matchable, attributes(serde))]
+pub fn derive_matchable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    Span::call_site(),
+                    "Matchable can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "Matchable can only be derived for structs",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "Matchable cannot be derived for generic structs",
+        ));
+    }
+    let container = SerdeAttrs::parse(&input.attrs)?;
+    let vis = &input.vis;
+    let ident = &input.ident;
+    let filter = format_ident!("{}Filter", ident);
+    let mut functions = Vec::new();
+    for field in fields {
+        let attrs = SerdeAttrs::parse(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+        let ident = field.ident.as_ref().expect("named field");
+        let name = match attrs.rename {
+            Some(name) => name,
+            None => {
+                let name = unraw(ident);
+                match &container.rename_all {
+                    Some(rule) => rule.apply(&name),
+                    None => name,
+                }
+            }
+        };
+        let path = escape_field(&name);
+        let doc = format!("The `{}` field.", name);
+        let step = format_ident!("__path_{}", unraw(ident));
+        let ty = &field.ty;
+        functions.push(quote! {
+            #[doc = #doc]
+            #[must_use]
+            #vis fn #ident() -> ::serde_json_matcher::Field {
+                ::serde_json_matcher::Field::new(#path)
+            }
+
+            #[doc(hidden)]
+            #vis fn #step<P>(
+                self,
+                parent: ::serde_json_matcher::FieldPath<P>,
+            ) -> ::serde_json_matcher::FieldPath<#ty> {
+                parent.join(#path)
+            }
+        });
+    }
+    let doc = format!("Typed fields of [`{}`] for building matchers.", ident);
+    Ok(quote! {
+        #[doc = #doc]
+        #vis struct #filter;
+
+        impl #filter {
+            #(#functions)*
+        }
+
+        impl ::serde_json_matcher::Matchable for #ident {
+            type Fields = #filter;
+
+            fn fields() -> #filter {
+                #filter
+            }
+        }
+    })
+}
+
+/// Expands `path!(User.address.city)` to the `serde_json_matcher::Field` at that path, failing to
+/// compile unless each field exists on the type before it, all of which derive `Matchable`.
+#[proc_macro]
+pub fn path(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as FieldPathInput);
+    let root = &input.root;
+    let steps = input.fields.iter().map(|field| {
+        let step = format_ident!("__path_{}", unraw(field), span = field.span());
+        quote_spanned! {field.span()=>
+            let path = path.fields().#step(path);
+        }
+    });
+    quote! {{
+        let path = ::serde_json_matcher::FieldPath::<#root>::root();
+        #(#steps)*
+        ::serde_json_matcher::Field::from(path)
+    }}
+    .into()
+}
+
+/// A type followed by one or more dot-separated field names.
+struct FieldPathInput {
+    root: syn::Path,
+    fields: Vec<Ident>,
+}
+
+impl Parse for FieldPathInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let root = input.call(syn::Path::parse_mod_style)?;
+        let mut fields = Vec::new();
+        while !input.is_empty() {
+            input.parse::<syn::Token![.]>()?;
+            fields.push(input.call(Ident::parse_any)?);
+        }
+        if fields.is_empty() {
+            return Err(input.error("expected `.` followed by a field name"));
+        }
+        Ok(FieldPathInput { root, fields })
+    }
+}
+
+/// Declares statics holding matchers parsed from string literals on first use:
+///
+/// ```ignore
+/// lazy_matcher! {
+///     /// Adult users.
+///     pub static ADULTS = r#"{"age": {"$gte": 18}}"#;
+/// }
+/// ```
+///
+/// Each static is a `std::sync::LazyLock<serde_json_matcher::ObjMatcher>`. Literals that are not
+/// valid JSON fail to compile; an operator with an invalid operand still panics on first use.
+#[proc_macro]
+pub fn lazy_matcher(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as LazyMatchers);
+    let statics = input.statics.iter().map(|item| {
+        let LazyMatcher {
+            attrs,
+            vis,
+            ident,
+            query,
+        } = item;
+        let message = format!("invalid matcher `{}`", ident);
+        quote! {
+            #(#attrs)*
+            #vis static #ident: ::std::sync::LazyLock<::serde_json_matcher::ObjMatcher> =
+                ::std::sync::LazyLock::new(|| ::serde_json_matcher::from_str(#query).expect(#message));
+        }
+    });
+    quote!(#(#statics)*).into()
+}
+
+struct LazyMatchers {
+    statics: Vec<LazyMatcher>,
+}
+
+/// `static NAME = "query";`, with attributes and visibility.
+struct LazyMatcher {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    query: LitStr,
+}
+
+impl Parse for LazyMatchers {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut statics = Vec::new();
+        while !input.is_empty() {
+            let attrs = input.call(Attribute::parse_outer)?;
+            let vis = input.parse()?;
+            input.parse::<syn::Token![static]>()?;
+            let ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            let query: LitStr = input.parse()?;
+            input.parse::<syn::Token![;]>()?;
+            check_query(&query)?;
+            statics.push(LazyMatcher {
+                attrs,
+                vis,
+                ident,
+                query,
+            });
+        }
+        Ok(LazyMatchers { statics })
+    }
+}
+
+/// Rejects queries that are not valid JSON, which `serde_json_matcher::from_str` would reject.
+fn check_query(query: &LitStr) -> syn::Result<()> {
+    match serde_json::from_str::<serde_json::Value>(&query.value()) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(syn::Error::new(
+            query.span(),
+            format!("invalid matcher: {}", err),
+        )),
+    }
+}
+
+/// The `#[serde(...)]` attributes affecting the name a field is (de)serialized under.
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<RenameRule>,
+    skip: bool,
+}
+
+impl SerdeAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = SerdeAttrs::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    if let Some(name) = deserialize_name(&meta)? {
+                        parsed.rename = Some(name.value());
+                    }
+                } else if meta.path.is_ident("rename_all") {
+                    if let Some(rule) = deserialize_name(&meta)? {
+                        parsed.rename_all = Some(RenameRule::parse(&rule)?);
+                    }
+                } else if meta.path.is_ident("alias") || meta.path.is_ident("flatten") {
+                    let name = if meta.path.is_ident("alias") {
+                        "alias"
+                    } else {
+                        "flatten"
+                    };
+                    return Err(
+                        meta.error(format!("Matchable does not support `#[serde({})]`", name))
+                    );
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                    parsed.skip = true;
+                } else {
+                    skip_meta(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Reads `name = "..."` or `name(deserialize = "...")`, the name a document is read under, or
+/// `None` for `name(serialize = "...")`, which leaves it unchanged.
+fn deserialize_name(meta: &ParseNestedMeta) -> syn::Result<Option<LitStr>> {
+    if meta.input.peek(syn::Token![=]) {
+        return meta.value()?.parse().map(Some);
+    }
+    let mut name = None;
+    meta.parse_nested_meta(|nested| {
+        if nested.path.is_ident("deserialize") {
+            name = Some(nested.value()?.parse()?);
+        } else {
+            skip_meta(&nested)?;
+        }
+        Ok(())
+    })?;
+    Ok(name)
+}
+
+/// Consumes an attribute the derive does not care about, such as `default = "..."`.
+fn skip_meta(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Lit>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|nested| skip_meta(&nested))?;
+    }
+    Ok(())
+}
+
+enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    fn parse(rule: &LitStr) -> syn::Result<Self> {
+        Ok(match rule.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebab,
+            other => {
+                return Err(syn::Error::new(
+                    rule.span(),
+                    format!("unknown rename rule `{}`", other),
+                ))
+            }
+        })
+    }
+
+    /// Renames a snake_case field name, as serde does.
+    fn apply(&self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Pascal => {
+                let mut pascal = String::with_capacity(field.len());
+                let mut capitalize = true;
+                for c in field.chars() {
+                    if c == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(c.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(c);
+                    }
+                }
+                pascal
+            }
+            RenameRule::Camel => {
+                let pascal = RenameRule::Pascal.apply(field);
+                let mut chars = pascal.chars();
+                match chars.next() {
+                    Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+                    None => pascal,
+                }
+            }
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::ScreamingKebab => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+fn unraw(ident: &Ident) -> String {
+    let name = ident.to_string();
+    match name.strip_prefix("r#") {
+        Some(name) => name.to_string(),
+        None => name,
+    }
+}
+
+/// Mirrors `serde_json_matcher::escape_field`, which this crate cannot depend on.
+fn escape_field(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        if c == '.' || c == '\\' || (i == 0 && c == '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,64 @@
+//! Node.js bindings: the matchers of `serde_json_matcher`, applied to JavaScript values.
+//!
+//! Values cross into Rust as JSON would, so `undefined` fields are missing and `Date`s and other
+//! objects with a `toJSON` are not converted; pass them as strings or numbers.
+
+use napi::{Error, Result, Status};
+use napi_derive::napi;
+use serde_json::Value;
+
+use serde_json_matcher::{CompiledMatcher, ObjMatcher};
+
+/// A parsed query, compiled once and matched against many documents.
+#[napi]
+pub struct Matcher {
+    matcher: ObjMatcher,
+    compiled: CompiledMatcher,
+}
+
+#[napi]
+impl Matcher {
+    /// Parses a query given as an object, or as JSON text.
+    #[napi(constructor)]
+    pub fn new(query: Value) -> Result<Self> {
+        let query = match query {
+            Value::String(text) => serde_json::from_str(&text).map_err(invalid_query)?,
+            query => query,
+        };
+        let matcher = serde_json_matcher::from_json(query).map_err(invalid_query)?;
+        let compiled = matcher.compile().map_err(invalid_query)?;
+        Ok(Matcher { matcher, compiled })
+    }
+
+    #[napi]
+    pub fn matches(&self, document: Value) -> bool {
+        self.compiled.matches(&document)
+    }
+
+    /// The documents that match, in order.
+    #[napi]
+    pub fn filter(&self, documents: Vec<Value>) -> Vec<Value> {
+        documents
+            .into_iter()
+            .filter(|document| self.compiled.matches(document))
+            .collect()
+    }
+
+    /// The outcome of every clause of the query against `document`.
+    #[napi]
+    pub fn explain(&self, document: Value) -> Result<Value> {
+        serde_json::to_value(self.matcher.explain(&document))
+            .map_err(|err| Error::new(Status::GenericFailure, err.to_string()))
+    }
+
+    /// The query, as accepted by the constructor.
+    #[napi(js_name = "toJSON")]
+    pub fn to_json(&self) -> Result<Value> {
+        serde_json::to_value(&self.matcher)
+            .map_err(|err| Error::new(Status::GenericFailure, err.to_string()))
+    }
+}
+
+fn invalid_query(err: serde_json::Error) -> Error {
+    Error::new(Status::InvalidArg, format!("invalid query: {}", err))
+}
@@ -0,0 +1,25 @@
+const assert = require('node:assert');
+const test = require('node:test');
+
+const { Matcher } = require('..');
+
+test('matches and filters', () => {
+  const adults = new Matcher({ age: { $gte: 18 }, status: { $in: ['active', 'trial'] } });
+  assert.strictEqual(adults.matches({ age: 30, status: 'active' }), true);
+  assert.strictEqual(adults.matches({ age: 12, status: 'active' }), false);
+  assert.deepStrictEqual(
+    adults.filter([{ age: 30, status: 'trial' }, { age: 30, status: 'banned' }]),
+    [{ age: 30, status: 'trial' }],
+  );
+});
+
+test('parses JSON text and explains', () => {
+  const matcher = new Matcher('{"tags": {"$len": 2}}');
+  assert.strictEqual(matcher.matches({ tags: ['a', 'b'] }), true);
+  assert.strictEqual(matcher.explain({ tags: [] }).outcome, false);
+  assert.deepStrictEqual(matcher.toJSON(), { tags: { $len: 2 } });
+});
+
+test('rejects invalid queries', () => {
+  assert.throws(() => new Matcher({ a: { $type: 'nope' } }), /invalid query/);
+});
@@ -0,0 +1,10 @@
+from typing import Any, Iterable, List
+
+class Matcher:
+    def __init__(self, query: Any) -> None: ...
+    def matches(self, record: Any) -> bool: ...
+    def filter(self, records: Iterable[Any]) -> List[Any]: ...
+    def explain(self, record: Any) -> dict: ...
+
+def parse(query: Any) -> Matcher: ...
+def matches(query: Any, record: Any) -> bool: ...
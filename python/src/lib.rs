@@ -0,0 +1,165 @@
+//! Python bindings: the matchers of `serde_json_matcher`, applied to Python records.
+//!
+//! Records are converted to JSON values the way `json.dumps` would, except that NumPy scalars and
+//! arrays are converted through their `tolist()` and that `NaN`, which pandas uses for missing
+//! values, becomes `None`.
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use serde_json::{Map, Number, Value};
+
+use serde_json_matcher::{CompiledMatcher, ObjMatcher};
+
+/// A parsed query, compiled once and matched against many records.
+#[pyclass(module = "serde_json_matcher", frozen)]
+struct Matcher {
+    matcher: ObjMatcher,
+    compiled: CompiledMatcher,
+}
+
+#[pymethods]
+impl Matcher {
+    /// Parses a query given as JSON text or as the equivalent Python object.
+    #[new]
+    fn new(query: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let query = match query.downcast::<PyString>() {
+            Ok(text) => serde_json::from_str(text.to_str()?).map_err(invalid_query)?,
+            Err(_) => to_value(query)?,
+        };
+        let matcher = serde_json_matcher::from_json(query).map_err(invalid_query)?;
+        let compiled = matcher.compile().map_err(invalid_query)?;
+        Ok(Matcher { matcher, compiled })
+    }
+
+    fn matches(&self, record: &Bound<'_, PyAny>) -> PyResult<bool> {
+        Ok(self.compiled.matches(&to_value(record)?))
+    }
+
+    /// The records of an iterable, such as `DataFrame.to_dict("records")`, that match.
+    fn filter<'py>(&self, records: &Bound<'py, PyAny>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let mut matching = Vec::new();
+        for record in records.iter()? {
+            let record = record?;
+            if self.compiled.matches(&to_value(&record)?) {
+                matching.push(record);
+            }
+        }
+        Ok(matching)
+    }
+
+    /// The outcome of every clause of the query against `record`, as nested dictionaries.
+    fn explain(&self, py: Python<'_>, record: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        let report = self.matcher.explain(&to_value(record)?);
+        let report =
+            serde_json::to_value(report).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        to_python(py, &report)
+    }
+
+    fn __repr__(&self) -> String {
+        let query = serde_json::to_string(&self.matcher).unwrap_or_default();
+        format!("Matcher({})", query)
+    }
+}
+
+/// Parses a query, as `Matcher(query)` does.
+#[pyfunction]
+fn parse(query: &Bound<'_, PyAny>) -> PyResult<Matcher> {
+    Matcher::new(query)
+}
+
+/// Whether `record` matches `query`, parsing the query on every call.
+#[pyfunction]
+fn matches(query: &Bound<'_, PyAny>, record: &Bound<'_, PyAny>) -> PyResult<bool> {
+    Matcher::new(query)?.matches(record)
+}
+
+fn invalid_query(err: serde_json::Error) -> PyErr {
+    PyValueError::new_err(format!("invalid query: {}", err))
+}
+
+fn to_value(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if obj.is_none() {
+        return Ok(Value::Null);
+    }
+    // Before integers, as `bool` is a subclass of `int`.
+    if let Ok(b) = obj.downcast::<PyBool>() {
+        return Ok(Value::Bool(b.is_true()));
+    }
+    if obj.is_instance_of::<PyInt>() {
+        if let Ok(n) = obj.extract::<i64>() {
+            return Ok(Value::from(n));
+        }
+        return match obj.extract::<u64>() {
+            Ok(n) => Ok(Value::from(n)),
+            Err(_) => Err(PyValueError::new_err("integer does not fit in 64 bits")),
+        };
+    }
+    if let Ok(f) = obj.downcast::<PyFloat>() {
+        return Ok(Number::from_f64(f.value()).map_or(Value::Null, Value::Number));
+    }
+    if let Ok(s) = obj.downcast::<PyString>() {
+        return Ok(Value::String(s.to_str()?.to_owned()));
+    }
+    if let Ok(dict) = obj.downcast::<PyDict>() {
+        let mut map = Map::new();
+        for (key, value) in dict.iter() {
+            let key = match key.downcast::<PyString>() {
+                Ok(key) => key.to_str()?.to_owned(),
+                Err(_) => return Err(PyTypeError::new_err("dictionary keys must be strings")),
+            };
+            map.insert(key, to_value(&value)?);
+        }
+        return Ok(Value::Object(map));
+    }
+    if let Ok(list) = obj.downcast::<PyList>() {
+        return list.iter().map(|item| to_value(&item)).collect();
+    }
+    if let Ok(tuple) = obj.downcast::<PyTuple>() {
+        return tuple.iter().map(|item| to_value(&item)).collect();
+    }
+    // NumPy scalars and arrays.
+    if obj.hasattr("tolist")? {
+        return to_value(&obj.call_method0("tolist")?);
+    }
+    Err(PyTypeError::new_err(format!(
+        "cannot convert {} to JSON",
+        obj.get_type().name()?
+    )))
+}
+
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => n.into_py(py),
+            (None, Some(n)) => n.into_py(py),
+            (None, None) => n.as_f64().unwrap_or(0.0).into_py(py),
+        },
+        Value::String(s) => s.into_py(py),
+        Value::Array(a) => {
+            let items = a
+                .iter()
+                .map(|item| to_python(py, item))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new_bound(py, items).into_py(py)
+        }
+        Value::Object(o) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in o {
+                dict.set_item(key, to_python(py, value)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+#[pymodule]
+#[pyo3(name = "serde_json_matcher")]
+fn module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Matcher>()?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(matches, m)?)?;
+    Ok(())
+}
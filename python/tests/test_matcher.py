@@ -0,0 +1,25 @@
+import pytest
+
+import serde_json_matcher as sjm
+
+
+def test_matches():
+    adults = sjm.Matcher({"age": {"$gte": 18}, "status": {"$in": ["active", "trial"]}})
+    assert adults.matches({"age": 30, "status": "active"})
+    assert not adults.matches({"age": 12, "status": "active"})
+    assert sjm.matches('{"tags": {"$len": 2}}', {"tags": ["a", "b"]})
+
+
+def test_filter_and_explain():
+    matcher = sjm.parse('{"score": {"$gt": 0.5}}')
+    records = [{"score": 0.9}, {"score": 0.1}, {"score": float("nan")}]
+    assert matcher.filter(records) == [{"score": 0.9}]
+    report = matcher.explain({"score": 0.1})
+    assert report["outcome"] is False
+
+
+def test_invalid_query():
+    with pytest.raises(ValueError):
+        sjm.Matcher({"a": {"$type": "nope"}})
+    with pytest.raises(TypeError):
+        sjm.Matcher({"a": 1}).matches({1: "a"})
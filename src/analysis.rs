@@ -0,0 +1,258 @@
+//! Static reasoning about the sets of documents matchers accept.
+
+use serde_json::Value;
+
+use crate::constraints::{self, Constraints};
+use crate::walk::{self, Condition};
+use crate::ObjMatcher;
+
+fn constraints(matcher: &ObjMatcher) -> Constraints {
+    matcher
+        .constraints()
+        .into_iter()
+        .map(|c| (c.path, c.values))
+        .collect()
+}
+
+/// Whether the values one matcher requires at a path are all excluded by the other.
+fn excluded(required: &Constraints, excluded: &Constraints) -> bool {
+    required
+        .iter()
+        .any(|(path, values)| match excluded.get(path) {
+            Some(excluded) => values.iter().all(|v| excluded.contains(v)),
+            None => false,
+        })
+}
+
+/// Whether no document can match both `a` and `b`.
+///
+/// The check is conservative: `true` is a proof, derived from the equality constraints and the
+/// `$ne` and `$nin` exclusions of the matchers and distributed over top-level `$or`s, while
+/// `false` only means that disjointness could not be shown.
+#[must_use]
+pub fn are_disjoint(a: &ObjMatcher, b: &ObjMatcher) -> bool {
+    if let ObjMatcher::Or(op) = a {
+        return op.val.iter().all(|a| are_disjoint(a, b));
+    }
+    if let ObjMatcher::Or(op) = b {
+        return op.val.iter().all(|b| are_disjoint(a, b));
+    }
+    let (required_a, required_b) = (constraints(a), constraints(b));
+    let contradictory = required_a
+        .iter()
+        .chain(required_b.iter())
+        .any(|(_, values)| values.is_empty());
+    let conflicting = required_a
+        .iter()
+        .any(|(path, values)| match required_b.get(path) {
+            Some(other) => !values.iter().any(|v| other.contains(v)),
+            None => false,
+        });
+    contradictory
+        || conflicting
+        || excluded(&required_a, &constraints::exclusions(b))
+        || excluded(&required_b, &constraints::exclusions(a))
+}
+
+/// Canonical form of a matcher: conditions on the same field are grouped, conjunctions and
+/// disjunctions are flattened, deduplicated and sorted, and `$eq`, `$in`, `$ne` and `$nin` are
+/// rewritten in terms of equality, `$or` and `$not`.
+#[derive(Debug, Clone, PartialEq)]
+enum Norm {
+    And(Vec<Norm>),
+    Or(Vec<Norm>),
+    Not(Box<Norm>),
+    Field(String, Box<Norm>),
+    Equal(Value),
+    /// Any other operator, in its serialized form.
+    Other(Value),
+}
+
+impl Norm {
+    /// Matches every document, like `{}`.
+    fn always() -> Norm {
+        Norm::And(Vec::new())
+    }
+
+    /// Matches no document, like `{"$or": []}`.
+    fn never() -> Norm {
+        Norm::Or(Vec::new())
+    }
+
+    fn sort_key(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+fn normalize(matcher: &ObjMatcher) -> Norm {
+    match matcher {
+        ObjMatcher::Eq(op) => normalize(&op.val),
+        ObjMatcher::In(op) => or(op.val.iter().map(normalize).collect()),
+        ObjMatcher::Ne(op) => not(normalize(&op.val)),
+        ObjMatcher::Nin(op) => not(or(op.val.iter().map(normalize).collect())),
+        ObjMatcher::And(op) => and(op.val.iter().map(normalize).collect()),
+        ObjMatcher::Or(op) => or(op.val.iter().map(normalize).collect()),
+        ObjMatcher::Not(op) => not(normalize(&op.val)),
+        ObjMatcher::Value(value) => match walk::value(value, None) {
+            walk::Clause::Operator(matcher) => normalize(&matcher),
+            walk::Clause::Fields(fields) => and(walk::conditions(fields, None)
+                .map(|(key, condition)| {
+                    let condition = match condition {
+                        Condition::Operator(matcher) => normalize(&matcher),
+                        Condition::Literal(value) => Norm::Equal(value.clone()),
+                    };
+                    field(key, condition)
+                })
+                .collect()),
+            walk::Clause::Literal(value) => Norm::Equal(value.clone()),
+        },
+        _ => Norm::Other(serde_json::to_value(matcher).unwrap_or(Value::Null)),
+    }
+}
+
+fn field(path: &str, condition: Norm) -> Norm {
+    if condition == Norm::always() || condition == Norm::never() {
+        return condition;
+    }
+    let segments: Vec<_> = crate::path::segments(path).collect();
+    segments
+        .into_iter()
+        .rev()
+        .fold(condition, |condition, segment| {
+            Norm::Field(segment.into_owned(), Box::new(condition))
+        })
+}
+
+/// Negation pushed down to the leaves, so that negated conjunctions and disjunctions have a
+/// single form.
+fn not(norm: Norm) -> Norm {
+    match norm {
+        Norm::Not(norm) => *norm,
+        Norm::And(children) => or(children.into_iter().map(not).collect()),
+        Norm::Or(children) => and(children.into_iter().map(not).collect()),
+        Norm::Field(key, condition) => Norm::Field(key, Box::new(not(*condition))),
+        norm => Norm::Not(Box::new(norm)),
+    }
+}
+
+fn and(children: Vec<Norm>) -> Norm {
+    combine(children, true)
+}
+
+fn or(children: Vec<Norm>) -> Norm {
+    combine(children, false)
+}
+
+/// Builds a flattened conjunction or disjunction, merging the conditions on each field into one
+/// since they all test the same value.
+fn combine(children: Vec<Norm>, conjunction: bool) -> Norm {
+    let (identity, absorbing) = if conjunction {
+        (Norm::always(), Norm::never())
+    } else {
+        (Norm::never(), Norm::always())
+    };
+    let mut flat = Vec::new();
+    let mut fields: Vec<(String, Vec<Norm>)> = Vec::new();
+    for child in children {
+        match child {
+            Norm::And(children) if conjunction => flat.extend(children),
+            Norm::Or(children) if !conjunction => flat.extend(children),
+            child => flat.push(child),
+        }
+    }
+    let mut result = Vec::new();
+    for child in flat {
+        match child {
+            Norm::Field(key, condition) => match fields.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, conditions)) => conditions.push(*condition),
+                None => fields.push((key, vec![*condition])),
+            },
+            child if child == absorbing => return absorbing,
+            child if child == identity => {}
+            child => result.push(child),
+        }
+    }
+    for (key, conditions) in fields {
+        let condition = combine(conditions, conjunction);
+        if condition == absorbing {
+            return absorbing;
+        }
+        if condition != identity {
+            result.push(Norm::Field(key, Box::new(condition)));
+        }
+    }
+    result.sort_by_key(Norm::sort_key);
+    result.dedup();
+    match result.len() {
+        1 => result.pop().unwrap(),
+        _ if conjunction => Norm::And(result),
+        _ => Norm::Or(result),
+    }
+}
+
+/// Whether `a` and `b` match exactly the same documents.
+///
+/// The matchers are compared after rewriting them into a canonical form, so equivalent matchers
+/// written with `$eq` or literals, `$in` or `$or`, dotted or nested `$eq` paths, or with their
+/// clauses in a different order compare equal. Like [`are_disjoint`], `false` only means that
+/// equivalence could not be shown.
+#[must_use]
+pub fn equivalent(a: &ObjMatcher, b: &ObjMatcher) -> bool {
+    normalize(a) == normalize(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    fn disjoint(a: &str, b: &str) -> bool {
+        are_disjoint(&from_str(a).unwrap(), &from_str(b).unwrap())
+    }
+
+    #[test]
+    pub fn test_are_disjoint() {
+        assert!(disjoint(r#"{"region": "eu"}"#, r#"{"region": "us"}"#));
+        assert!(disjoint(
+            r#"{"region": {"$in": ["eu", "uk"]}}"#,
+            r#"{"region": {"$nin": ["eu", "uk"]}, "tier": 1}"#
+        ));
+        assert!(disjoint(
+            r#"{"$or": [{"region": "eu"}, {"region": "uk"}]}"#,
+            r#"{"$and": [{"region": {"$ne": "eu"}}, {"region": {"$ne": "uk"}}]}"#
+        ));
+        assert!(disjoint(r#"{"$and": [{"a": 1}, {"a": 2}]}"#, r"{}"));
+        assert!(!disjoint(r#"{"region": "eu"}"#, r#"{"tier": 1}"#));
+        assert!(!disjoint(
+            r#"{"region": {"$in": ["eu", "uk"]}}"#,
+            r#"{"region": {"$ne": "eu"}}"#
+        ));
+        assert!(!disjoint(
+            r#"{"n": {"$type": ["number"]}}"#,
+            r#"{"n": {"$type": ["string"]}}"#
+        ));
+    }
+
+    fn equal(a: &str, b: &str) -> bool {
+        equivalent(&from_str(a).unwrap(), &from_str(b).unwrap())
+    }
+
+    #[test]
+    pub fn test_equivalent() {
+        assert!(equal(
+            r#"{"a": {"$in": [2, 1]}, "b": {"$eq": 3}, "$comment": "x"}"#,
+            r#"{"$and": [{"b": 3}, {"$or": [{"a": 1}, {"a": 2}, {"a": 1}]}]}"#
+        ));
+        assert!(equal(
+            r#"{"a": {"$nin": [1, 2]}}"#,
+            r#"{"$and": [{"a": {"$ne": 2}}, {"a": {"$not": {"$not": {"$ne": 1}}}}]}"#
+        ));
+        assert!(equal(
+            r#"{"a.b": 1, "a.c": {"$ne": 2}}"#,
+            r#"{"a": {"$eq": {"c": {"$ne": 2}, "b": 1}}}"#
+        ));
+        assert!(equal(r#"{"a": {"$in": []}, "b": 1}"#, r#"{"$or": []}"#));
+        assert!(!equal(r#"{"a.b": 1}"#, r#"{"a": {"b": 1}}"#));
+        assert!(!equal(r#"{"a": 1}"#, r#"{"a": 1, "b": 2}"#));
+    }
+}
@@ -0,0 +1,141 @@
+//! Arena-backed matchers for workloads that build and drop many matchers at a time.
+//!
+//! A [`MatcherArena`] stores the nodes of every matcher compiled into it in a single buffer, so
+//! compiling a matcher costs no per-node allocations once the buffer has grown, and
+//! [`MatcherArena::clear`] releases all of them at once while keeping the capacity for reuse.
+
+use serde_json::Value;
+
+use crate::compiled::{compile_into, Interner, NodeId, Nodes};
+use crate::{MatchOptions, ObjMatcher, ParseOptions};
+
+/// Handle to a matcher compiled into a [`MatcherArena`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaMatcher {
+    root: NodeId,
+    generation: u64,
+}
+
+/// Storage for many compiled matchers, freed together.
+#[derive(Debug, Default)]
+pub struct MatcherArena {
+    nodes: Nodes,
+    interner: Interner,
+    generation: u64,
+}
+
+impl MatcherArena {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an arena with room for `capacity` nodes before it needs to grow.
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        MatcherArena {
+            nodes: Nodes::with_capacity(capacity),
+            ..Self::default()
+        }
+    }
+
+    /// Compiles a matcher into the arena, failing if one of its operators has an invalid operand.
+    pub fn compile(&mut self, matcher: &ObjMatcher) -> Result<ArenaMatcher, serde_json::Error> {
+        self.compile_with_options(matcher, &ParseOptions::default())
+    }
+
+    /// Compiles a matcher into the arena, recognizing user-defined operators from the options'
+    /// registry.
+    pub fn compile_with_options(
+        &mut self,
+        matcher: &ObjMatcher,
+        options: &ParseOptions,
+    ) -> Result<ArenaMatcher, serde_json::Error> {
+        let len = self.nodes.len();
+        match compile_into(&mut self.nodes, &mut self.interner, options, matcher) {
+            Ok(root) => Ok(ArenaMatcher {
+                root,
+                generation: self.generation,
+            }),
+            Err(err) => {
+                self.nodes.truncate(len);
+                Err(err)
+            }
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the arena was cleared after `matcher` was compiled into it.
+    #[must_use]
+    pub fn matches(&self, matcher: ArenaMatcher, other: &Value) -> bool {
+        self.matches_with_options(matcher, other, &MatchOptions::default())
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the arena was cleared after `matcher` was compiled into it.
+    #[must_use]
+    pub fn matches_with_options(
+        &self,
+        matcher: ArenaMatcher,
+        other: &Value,
+        options: &MatchOptions,
+    ) -> bool {
+        assert_eq!(
+            matcher.generation, self.generation,
+            "matcher belongs to a cleared arena"
+        );
+        self.nodes.eval(matcher.root, other, options)
+    }
+
+    /// Number of nodes currently stored.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 0
+    }
+
+    /// Drops every matcher in the arena, invalidating their handles.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.interner.clear();
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_arena() {
+        let mut arena = MatcherArena::new();
+        let a = arena
+            .compile(&from_str(r#"{"a": {"$in": [1, 2]}}"#).unwrap())
+            .unwrap();
+        let b = arena
+            .compile(&from_str(r#"{"b": {"$ne": "x"}}"#).unwrap())
+            .unwrap();
+        assert!(arena.matches(a, &json!({"a": 2})));
+        assert!(!arena.matches(a, &json!({"a": 3})));
+        assert!(arena.matches(b, &json!({"b": "y"})));
+        assert!(!arena.matches(b, &json!({"b": "x"})));
+
+        let len = arena.len();
+        let invalid = ObjMatcher::Value(json!({"c": {"$type": "nope"}}));
+        assert!(arena.compile(&invalid).is_err());
+        assert_eq!(arena.len(), len);
+
+        arena.clear();
+        assert!(arena.is_empty());
+        let stale = std::panic::catch_unwind(|| arena.matches(a, &json!({"a": 2})));
+        assert!(stale.is_err());
+    }
+}
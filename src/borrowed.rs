@@ -0,0 +1,503 @@
+//! Matchers borrowing their field names and string literals from the query text, for loading
+//! large rule sets without allocating a copy of every string.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+
+use crate::compat::{self, Candidate};
+use crate::{
+    coercion, ejson, from_json_with_options, is_operator, order, path, values_equal, Compatibility,
+    MatchOptions, ObjMatcher, ParseOptions,
+};
+
+/// A matcher parsed from a `&'a str` that refers to its field names and string literals in place
+/// rather than copying them, unless they contain escape sequences.
+///
+/// Matches exactly like the [`ObjMatcher`] parsed from the same query. Field conditions,
+/// `$eq`, `$ne`, `$in`, `$nin`, `$and`, `$or`, `$not` and comparisons with scalars borrow from
+/// the query; other operators, object and array literals and context references are parsed into
+/// owned [`ObjMatcher`]s.
+#[derive(Debug, Clone)]
+pub struct BorrowedMatcher<'a> {
+    root: Node<'a>,
+}
+
+#[derive(Debug, Clone)]
+enum Node<'a> {
+    Fields(Vec<(Cow<'a, str>, Node<'a>)>),
+    All(Vec<Node<'a>>),
+    Any(Vec<Node<'a>>),
+    Not(Box<Node<'a>>),
+    Equal(Scalar<'a>),
+    /// The orderings of the candidate relative to the operand that satisfy the comparison.
+    Compare(Scalar<'a>, &'static [Ordering]),
+    Literal(Value),
+    Owned(ObjMatcher),
+}
+
+#[derive(Debug, Clone)]
+enum Scalar<'a> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Cow<'a, str>),
+}
+
+impl<'a> BorrowedMatcher<'a> {
+    pub fn parse(s: &'a str) -> Result<Self, serde_json::Error> {
+        Self::parse_with_options(s, &ParseOptions::default())
+    }
+
+    pub fn parse_with_options(
+        s: &'a str,
+        options: &ParseOptions,
+    ) -> Result<Self, serde_json::Error> {
+        let raw: Raw<'a> = serde_json::from_str(s)?;
+        let valid = options.matcher_registry().is_none()
+            && options.limits().size.map_or(true, |max| s.len() <= max)
+            && Builder { options }.check(&raw, &mut 0);
+        if valid {
+            if let Some(root) = (Builder { options }).matcher(&raw) {
+                return Ok(BorrowedMatcher { root });
+            }
+        }
+        // Queries that are invalid, or whose `$ref`s must be resolved, go through the owned
+        // parser, which also produces the same errors as `from_str`.
+        let matcher = from_json_with_options(raw.to_value(), options)?;
+        Ok(BorrowedMatcher {
+            root: Node::Owned(matcher),
+        })
+    }
+
+    #[must_use]
+    pub fn matches(&self, other: &Value) -> bool {
+        self.matches_with_options(other, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_with_options(&self, other: &Value, options: &MatchOptions) -> bool {
+        self.root.evaluate(other, options)
+    }
+}
+
+impl Node<'_> {
+    fn evaluate(&self, other: &Value, options: &MatchOptions) -> bool {
+        match self {
+            Node::Fields(fields) if options.profile() == Compatibility::Native => fields
+                .iter()
+                .all(|(key, node)| node.evaluate(path::lookup(other, key), options)),
+            Node::Fields(fields) => fields.iter().all(|(key, node)| {
+                node.evaluate_candidates(&compat::candidates(other, key, options), options)
+            }),
+            Node::All(nodes) => nodes.iter().all(|node| node.evaluate(other, options)),
+            Node::Any(nodes) => nodes.iter().any(|node| node.evaluate(other, options)),
+            Node::Not(node) => !node.evaluate(other, options),
+            Node::Equal(expected) => match (expected, &*ejson::decoded(other)) {
+                (expected, other) if ejson::is_literal(other) => {
+                    values_equal(&expected.to_value(), other, options)
+                }
+                (Scalar::Null, Value::Null) => true,
+                (Scalar::Bool(a), Value::Bool(b)) => a == b,
+                (Scalar::Number(a), Value::Number(b)) => a == b,
+                (Scalar::String(a), Value::String(b)) => options.str_eq(a, b),
+                _ if options.coercion_rules().is_none() => false,
+                (expected, other) => {
+                    coercion::compare(other, &expected.to_value(), options) == Some(Ordering::Equal)
+                }
+            },
+            Node::Compare(expected, orderings) if options.profile() == Compatibility::SiftJs => {
+                compat::compare(&expected.to_value(), &ejson::decoded(other), options)
+                    .map_or(false, |ordering| orderings.contains(&ordering))
+            }
+            Node::Compare(expected, orderings) => {
+                let ordering = match (expected, &*ejson::decoded(other)) {
+                    (expected, other) if ejson::is_literal(other) => {
+                        match ejson::compare(other, &expected.to_value()) {
+                            Some(ordering) => ordering,
+                            None => return false,
+                        }
+                    }
+                    (Scalar::Null, Value::Null) => Ordering::Equal,
+                    (Scalar::Bool(a), Value::Bool(b)) => b.cmp(a),
+                    (Scalar::Number(a), Value::Number(b)) => order::compare_numbers(b, a),
+                    (Scalar::String(a), Value::String(b)) => options.str_cmp(b, a),
+                    _ if options.coercion_rules().is_none() => return false,
+                    (expected, other) => {
+                        match coercion::compare(other, &expected.to_value(), options) {
+                            Some(ordering) => ordering,
+                            None => return false,
+                        }
+                    }
+                };
+                orderings.contains(&ordering)
+            }
+            Node::Literal(expected) => values_equal(expected, other, options),
+            Node::Owned(matcher) => matcher.evaluate(other, options),
+        }
+    }
+
+    /// Evaluates a field condition on the candidates found by [`compat::candidates`].
+    fn evaluate_candidates(&self, candidates: &[Candidate<'_>], options: &MatchOptions) -> bool {
+        match self {
+            Node::All(nodes) => nodes
+                .iter()
+                .all(|node| node.evaluate_candidates(candidates, options)),
+            Node::Any(nodes) => nodes
+                .iter()
+                .any(|node| node.evaluate_candidates(candidates, options)),
+            Node::Not(node) => !node.evaluate_candidates(candidates, options),
+            Node::Owned(matcher) => compat::matches_candidates(matcher, candidates, options),
+            _ => candidates.iter().any(|candidate| match candidate {
+                Some(value) => self.evaluate(value, options),
+                None => match self {
+                    Node::Compare(..) if options.profile() == Compatibility::SiftJs => false,
+                    _ => self.evaluate(&Value::Null, options),
+                },
+            }),
+        }
+    }
+}
+
+impl Scalar<'_> {
+    fn to_value(&self) -> Value {
+        match self {
+            Scalar::Null => Value::Null,
+            Scalar::Bool(b) => Value::Bool(*b),
+            Scalar::Number(n) => Value::Number(n.clone()),
+            Scalar::String(s) => Value::String(s.to_string()),
+        }
+    }
+}
+
+struct Builder<'o> {
+    options: &'o ParseOptions,
+}
+
+impl Builder<'_> {
+    /// Applies the limits and denied operators of the options, as the owned parser does.
+    fn check(&self, raw: &Raw, operators: &mut usize) -> bool {
+        let limits = self.options.limits();
+        let fits = |s: &str| limits.string_len.map_or(true, |max| s.len() <= max);
+        match raw {
+            Raw::String(s) => fits(s),
+            Raw::Array(a) => a.iter().all(|raw| self.check(raw, operators)),
+            Raw::Object(o) => o.iter().all(|(key, raw)| {
+                if is_operator(key, self.options.operator_registry()) {
+                    *operators += 1;
+                }
+                let arity = match (key.as_ref(), raw) {
+                    ("$in" | "$nin" | "$and" | "$or", Raw::Array(a)) => a.len(),
+                    _ => 0,
+                };
+                fits(key)
+                    && !self.options.is_denied(key)
+                    && limits.operators.map_or(true, |max| *operators <= max)
+                    && limits.arity.map_or(true, |max| arity <= max)
+                    && self.check(raw, operators)
+            }),
+            _ => true,
+        }
+    }
+
+    fn is_operator(&self, key: &str) -> bool {
+        is_operator(key, self.options.operator_registry())
+    }
+
+    /// A whole query, or the operand of `$eq`, `$in` and the like. Returns `None` if it is
+    /// invalid.
+    fn matcher<'a>(&self, raw: &Raw<'a>) -> Option<Node<'a>> {
+        let o = match raw {
+            Raw::Object(o) => o,
+            _ => return Some(Self::literal(raw)),
+        };
+        if o.iter().any(|(key, _)| self.is_operator(key)) {
+            return self.operators(o);
+        }
+        if o.iter()
+            .any(|(key, _)| key.starts_with('$') && key != "$comment")
+        {
+            // Extended JSON literals, context references, placeholders and fields starting
+            // with `$`.
+            let value = raw.to_value();
+            if let Some(decoded) = ejson::decode(&value) {
+                return Some(Node::Literal(decoded));
+            }
+            return Some(Node::Owned(ObjMatcher::Value(value)));
+        }
+        self.fields(o)
+    }
+
+    fn fields<'a>(&self, o: &[(Cow<'a, str>, Raw<'a>)]) -> Option<Node<'a>> {
+        let mut fields = Vec::with_capacity(o.len());
+        for (key, raw) in o.iter().filter(|(key, _)| key != "$comment") {
+            let node = match raw {
+                Raw::Object(o) if o.iter().any(|(key, _)| self.is_operator(key)) => {
+                    self.operators(o)?
+                }
+                _ => Self::literal(raw),
+            };
+            fields.push((key.clone(), node));
+        }
+        Some(Node::Fields(fields))
+    }
+
+    fn operators<'a>(&self, o: &[(Cow<'a, str>, Raw<'a>)]) -> Option<Node<'a>> {
+        if o.iter().any(|(key, _)| key == "$default") {
+            return from_json_with_options(Raw::Object(o.to_vec()).to_value(), self.options)
+                .ok()
+                .map(Node::Owned);
+        }
+        let logical = o
+            .iter()
+            .filter(|(key, _)| self.is_operator(key))
+            .all(|(key, _)| key == "$and" || key == "$or");
+        let mut clauses = Vec::new();
+        let mut fields = Vec::new();
+        for (key, raw) in o {
+            if key == "$comment" {
+                continue;
+            }
+            if self.is_operator(key) {
+                clauses.push(self.operator(key, raw)?);
+            } else if logical && !key.starts_with('$') {
+                fields.push((key.clone(), raw.clone()));
+            } else {
+                return None;
+            }
+        }
+        if !fields.is_empty() {
+            clauses.push(self.fields(&fields)?);
+        }
+        Some(if clauses.len() == 1 {
+            clauses.remove(0)
+        } else {
+            Node::All(clauses)
+        })
+    }
+
+    fn operator<'a>(&self, key: &str, operand: &Raw<'a>) -> Option<Node<'a>> {
+        let list = |raw: &Raw<'a>| match raw {
+            Raw::Array(a) => a.iter().map(|raw| self.matcher(raw)).collect(),
+            _ => None,
+        };
+        let compare = |orderings: &'static [Ordering]| match operand.scalar() {
+            Some(scalar) => Some(Node::Compare(scalar, orderings)),
+            None => self.owned(key, operand),
+        };
+        match key {
+            "$eq" => self.matcher(operand),
+            "$ne" | "$not" => Some(Node::Not(Box::new(self.matcher(operand)?))),
+            "$in" | "$or" => list(operand).map(Node::Any),
+            "$nin" => list(operand).map(|nodes| Node::Not(Box::new(Node::Any(nodes)))),
+            "$and" => list(operand).map(Node::All),
+            "$gt" => compare(&[Ordering::Greater]),
+            "$gte" => compare(&[Ordering::Greater, Ordering::Equal]),
+            "$lt" => compare(&[Ordering::Less]),
+            "$lte" => compare(&[Ordering::Less, Ordering::Equal]),
+            _ => self.owned(key, operand),
+        }
+    }
+
+    fn owned<'a>(&self, key: &str, operand: &Raw<'a>) -> Option<Node<'a>> {
+        let mut single = Map::new();
+        single.insert(key.to_string(), operand.to_value());
+        from_json_with_options(Value::Object(single), self.options)
+            .ok()
+            .map(Node::Owned)
+    }
+
+    /// A value compared for equality.
+    fn literal<'a>(raw: &Raw<'a>) -> Node<'a> {
+        match raw.scalar() {
+            Some(scalar) => Node::Equal(scalar),
+            None => Node::Literal(raw.to_value()),
+        }
+    }
+}
+
+/// A JSON value whose strings borrow from the source when they contain no escape sequences.
+#[derive(Debug, Clone)]
+enum Raw<'a> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Cow<'a, str>),
+    Array(Vec<Raw<'a>>),
+    Object(Vec<(Cow<'a, str>, Raw<'a>)>),
+}
+
+impl<'a> Raw<'a> {
+    fn scalar(&self) -> Option<Scalar<'a>> {
+        match self {
+            Raw::Null => Some(Scalar::Null),
+            Raw::Bool(b) => Some(Scalar::Bool(*b)),
+            Raw::Number(n) => Some(Scalar::Number(n.clone())),
+            Raw::String(s) => Some(Scalar::String(s.clone())),
+            Raw::Array(_) | Raw::Object(_) => None,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Raw::Null => Value::Null,
+            Raw::Bool(b) => Value::Bool(*b),
+            Raw::Number(n) => Value::Number(n.clone()),
+            Raw::String(s) => Value::String(s.to_string()),
+            Raw::Array(a) => Value::Array(a.iter().map(Raw::to_value).collect()),
+            Raw::Object(o) => Value::Object(
+                o.iter()
+                    .map(|(key, raw)| (key.to_string(), raw.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Raw<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RawVisitor)
+    }
+}
+
+struct RawVisitor;
+
+impl<'de> Visitor<'de> for RawVisitor {
+    type Value = Raw<'de>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Raw<'de>, E> {
+        Ok(Raw::Null)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Raw<'de>, E> {
+        Ok(Raw::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Raw<'de>, E> {
+        Ok(Raw::Number(n.into()))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Raw<'de>, E> {
+        Ok(Raw::Number(n.into()))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Raw<'de>, E> {
+        Ok(Number::from_f64(n).map_or(Raw::Null, Raw::Number))
+    }
+
+    fn visit_borrowed_str<E>(self, s: &'de str) -> Result<Raw<'de>, E> {
+        Ok(Raw::String(Cow::Borrowed(s)))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Raw<'de>, E> {
+        Ok(Raw::String(Cow::Owned(s.to_string())))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Raw<'de>, E> {
+        Ok(Raw::String(Cow::Owned(s)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Raw<'de>, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Raw::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Raw<'de>, A::Error> {
+        let mut entries: Vec<(Cow<'de, str>, Raw<'de>)> = Vec::new();
+        while let Some(key) = map.next_key::<Raw<'de>>()? {
+            let key = match key {
+                Raw::String(key) => key,
+                _ => return Err(de::Error::custom("expected a string key")),
+            };
+            let value = map.next_value()?;
+            // A repeated key replaces the earlier value, as it does in `serde_json::Value`.
+            match entries.iter_mut().find(|(existing, _)| *existing == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+        }
+        // With `arbitrary_precision`, serde_json hands numbers over as their text, in a map with
+        // a single private key.
+        #[cfg(feature = "arbitrary-precision")]
+        {
+            if let [(key, Raw::String(text))] = entries.as_slice() {
+                if key == "$serde_json::private::Number" {
+                    return serde_json::from_str(text)
+                        .map(Raw::Number)
+                        .map_err(de::Error::custom);
+                }
+            }
+        }
+        Ok(Raw::Object(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_borrowed_matcher() {
+        let queries = [
+            r#"{"name": "ann", "age": {"$gte": 18, "$lt": 65}}"#,
+            r#"{"$or": [{"tags": {"$in": ["a", "b"]}}, {"address.city": {"$ne": "Paris"}}]}"#,
+            r#"{"role": {"$nin": ["admin", null]}, "$and": [{"age": {"$not": {"$gt": 30}}}]}"#,
+            r#"{"tags": ["a", "b"], "age": {"$type": ["number"]}, "name": {"$len": 3}}"#,
+            r#"{"name": {"$eq": "ann"}, "n": {"$gt": [1]}, "$comment": "mixed"}"#,
+            r#"{"when": {"$now": {}}, "esc\\.aped": "a\"b"}"#,
+            r"5",
+        ];
+        let docs = [
+            json!({"name": "ann", "age": 30, "tags": ["a", "b"], "role": "user"}),
+            json!({"name": "bob", "age": 70, "address": {"city": "Paris"}, "n": [2]}),
+            json!({"esc.aped": "a\"b", "tags": "a", "role": "admin"}),
+            json!(5),
+            json!(null),
+        ];
+        for query in &queries {
+            let borrowed = BorrowedMatcher::parse(query).unwrap();
+            let owned = from_str(query).unwrap();
+            for doc in &docs {
+                assert_eq!(borrowed.matches(doc), owned.matches(doc), "{query} {doc}");
+            }
+        }
+
+        let query = r#"{"name": "ann", "escaped\\.key": "x"}"#.to_string();
+        let matcher = BorrowedMatcher::parse(&query).unwrap();
+        let fields = match &matcher.root {
+            Node::Fields(fields) => fields,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(matches!(fields[0].0, Cow::Borrowed("name")));
+        assert!(matches!(
+            &fields[0].1,
+            Node::Equal(Scalar::String(Cow::Borrowed("ann")))
+        ));
+        assert!(matches!(fields[1].0, Cow::Owned(_)));
+
+        for query in &[
+            r#"{"a": {"$in": 1}}"#,
+            r#"{"a": {"$gt": 1, "b": 2}}"#,
+            r#"{"a": {"$type": "nope"}}"#,
+        ] {
+            assert_eq!(
+                BorrowedMatcher::parse(query).unwrap_err().to_string(),
+                from_str(query).unwrap_err().to_string()
+            );
+        }
+        let options = ParseOptions::new().deny_operator("$in");
+        assert!(BorrowedMatcher::parse_with_options(r#"{"a": {"$in": [1]}}"#, &options).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Building matchers in code rather than parsing them, e.g. `Field::new("age").gt(21)`.
+
+use std::marker::PhantomData;
+
+use serde_json::{Map, Value};
+
+use crate::{AndOperator, ObjMatcher, OrOperator};
+
+/// A field of the matched document, from which conditions on that field are built.
+///
+/// The path is dotted and escaped as in queries; see [`escape_field`](crate::escape_field).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Field {
+    path: String,
+}
+
+impl Field {
+    #[must_use]
+    pub fn new(path: impl Into<String>) -> Self {
+        Field { path: path.into() }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Matches if the field equals `value`.
+    #[must_use]
+    pub fn eq(self, value: impl Into<Value>) -> ObjMatcher {
+        self.condition("$eq", value.into())
+    }
+
+    /// Matches if the field does not equal `value`, or is missing.
+    #[must_use]
+    pub fn ne(self, value: impl Into<Value>) -> ObjMatcher {
+        self.condition("$ne", value.into())
+    }
+
+    /// Matches if the field is greater than `value`.
+    #[must_use]
+    pub fn gt(self, value: impl Into<Value>) -> ObjMatcher {
+        self.condition("$gt", value.into())
+    }
+
+    /// Matches if the field is greater than or equal to `value`.
+    #[must_use]
+    pub fn gte(self, value: impl Into<Value>) -> ObjMatcher {
+        self.condition("$gte", value.into())
+    }
+
+    /// Matches if the field is less than `value`.
+    #[must_use]
+    pub fn lt(self, value: impl Into<Value>) -> ObjMatcher {
+        self.condition("$lt", value.into())
+    }
+
+    /// Matches if the field is less than or equal to `value`.
+    #[must_use]
+    pub fn lte(self, value: impl Into<Value>) -> ObjMatcher {
+        self.condition("$lte", value.into())
+    }
+
+    /// Matches if the field equals one of `values`.
+    #[must_use]
+    pub fn is_in<I: IntoIterator>(self, values: I) -> ObjMatcher
+    where
+        I::Item: Into<Value>,
+    {
+        self.condition("$in", values.into_iter().map(Into::into).collect())
+    }
+
+    /// Matches if the field equals none of `values`, or is missing.
+    #[must_use]
+    pub fn not_in<I: IntoIterator>(self, values: I) -> ObjMatcher
+    where
+        I::Item: Into<Value>,
+    {
+        self.condition("$nin", values.into_iter().map(Into::into).collect())
+    }
+
+    /// Matches if the field is a string starting with `prefix`.
+    ///
+    /// Built as the range of strings from `prefix` up to the next string without it, e.g.
+    /// `{"$gte": "ab", "$lt": "ac"}`, as with `$anyKey`.
+    #[must_use]
+    pub fn starts_with(self, prefix: &str) -> ObjMatcher {
+        let mut condition = Map::new();
+        if prefix.is_empty() {
+            condition.insert("$type".to_string(), Value::from(vec!["string"]));
+        } else {
+            condition.insert("$gte".to_string(), Value::from(prefix));
+            if let Some(end) = prefix_end(prefix) {
+                condition.insert("$lt".to_string(), Value::String(end));
+            }
+        }
+        self.conditions(condition)
+    }
+
+    fn condition(self, operator: &str, operand: Value) -> ObjMatcher {
+        let mut condition = Map::new();
+        condition.insert(operator.to_string(), operand);
+        self.conditions(condition)
+    }
+
+    fn conditions(self, condition: Map<String, Value>) -> ObjMatcher {
+        let mut query = Map::new();
+        query.insert(self.path, Value::Object(condition));
+        ObjMatcher::Value(Value::Object(query))
+    }
+}
+
+/// The least string greater than every string starting with `prefix`, or `None` if there is
+/// none, as when `prefix` only holds `char::MAX`.
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars = prefix.chars().collect::<Vec<_>>();
+    while let Some(last) = chars.pop() {
+        let next = (last as u32 + 1..=0x10_FFFF).find_map(std::char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// A type whose fields can be named in `path!`, implemented by `#[derive(Matchable)]`.
+pub trait Matchable {
+    /// The generated type with one function per field, e.g. `UserFilter` for `User`.
+    type Fields;
+
+    #[doc(hidden)]
+    fn fields() -> Self::Fields;
+}
+
+impl<T: Matchable> Matchable for Option<T> {
+    type Fields = T::Fields;
+
+    fn fields() -> T::Fields {
+        T::fields()
+    }
+}
+
+impl<T: Matchable> Matchable for Box<T> {
+    type Fields = T::Fields;
+
+    fn fields() -> T::Fields {
+        T::fields()
+    }
+}
+
+/// A path into documents of type `T`, built one field at a time by `path!`.
+#[doc(hidden)]
+pub struct FieldPath<T> {
+    path: String,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> FieldPath<T> {
+    #[must_use]
+    pub fn root() -> Self {
+        FieldPath {
+            path: String::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Appends an escaped field name.
+    #[must_use]
+    pub fn join<U>(self, field: &str) -> FieldPath<U> {
+        let path = if self.path.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", self.path, field)
+        };
+        FieldPath {
+            path,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Matchable> FieldPath<T> {
+    #[must_use]
+    pub fn fields(&self) -> T::Fields {
+        T::fields()
+    }
+}
+
+impl<T> From<FieldPath<T>> for Field {
+    fn from(path: FieldPath<T>) -> Field {
+        Field::new(path.path)
+    }
+}
+
+impl ObjMatcher {
+    /// Combines two matchers into one matching only if both do.
+    #[must_use]
+    pub fn and(self, other: ObjMatcher) -> ObjMatcher {
+        match self {
+            ObjMatcher::And(mut op) if op.comment.is_none() => {
+                op.val.push(other);
+                ObjMatcher::And(op)
+            }
+            matcher => ObjMatcher::And(AndOperator {
+                val: vec![matcher, other],
+                comment: None,
+            }),
+        }
+    }
+
+    /// Combines two matchers into one matching if either does.
+    #[must_use]
+    pub fn or(self, other: ObjMatcher) -> ObjMatcher {
+        match self {
+            ObjMatcher::Or(mut op) if op.comment.is_none() => {
+                op.val.push(other);
+                ObjMatcher::Or(op)
+            }
+            matcher => ObjMatcher::Or(OrOperator {
+                val: vec![matcher, other],
+                comment: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_field() {
+        let matcher = Field::new("age")
+            .gte(21)
+            .and(Field::new("address.city").is_in(vec!["London", "Paris"]))
+            .and(Field::new("role").ne("guest"));
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"$and": [
+                {"age": {"$gte": 21}},
+                {"address.city": {"$in": ["London", "Paris"]}},
+                {"role": {"$ne": "guest"}},
+            ]})
+        );
+        assert!(matcher.matches(&json!({"age": 30, "address": {"city": "Paris"}})));
+        assert!(!matcher.matches(&json!({"age": 30, "address": {"city": "Rome"}})));
+        assert!(!matcher.matches(&json!({"age": 18, "address": {"city": "Paris"}})));
+
+        let matcher = Field::new("name").starts_with("an");
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"name": {"$gte": "an", "$lt": "ao"}})
+        );
+        for name in &["an", "ann", "an\u{10ffff}"] {
+            assert!(matcher.matches(&json!({ "name": name })), "{}", name);
+        }
+        for name in &["a", "ao", "bob", "An"] {
+            assert!(!matcher.matches(&json!({ "name": name })), "{}", name);
+        }
+        assert!(!matcher.matches(&json!({"name": 1})));
+        assert!(!matcher.matches(&json!({})));
+        let matcher = Field::new("name").starts_with("a\u{10ffff}");
+        assert!(matcher.matches(&json!({"name": "a\u{10ffff}z"})));
+        assert!(!matcher.matches(&json!({"name": "b"})));
+        assert!(Field::new("name")
+            .starts_with("")
+            .matches(&json!({"name": ""})));
+        assert!(!Field::new("name")
+            .starts_with("")
+            .matches(&json!({"name": 1})));
+
+        let matcher = Field::new("a").eq(1).or(Field::new("b").lt(0));
+        assert!(matcher.matches(&json!({"a": 1})));
+        assert!(!matcher.matches(&json!({"a": 2})));
+        assert!(matcher.matches(&json!({"b": -1})));
+        assert!(!matcher.matches(&json!({"a": {"b": -1}})));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    pub fn test_derive_matchable() {
+        use crate::Matchable;
+        use serde::Deserialize;
+
+        #[allow(dead_code)]
+        #[derive(Deserialize, Matchable)]
+        #[serde(rename_all = "camelCase")]
+        struct User {
+            user_name: String,
+            age: u32,
+            #[serde(rename = "e.mail")]
+            email: String,
+            #[serde(skip)]
+            cache: (),
+            #[serde(rename(serialize = "nick"))]
+            nickname: String,
+        }
+
+        let matcher = UserFilter::age()
+            .gt(21)
+            .and(UserFilter::user_name().eq("ann"))
+            .and(UserFilter::email().ne("x"));
+        let prefixed = UserFilter::user_name()
+            .starts_with("a")
+            .and(UserFilter::nickname().eq("x"));
+        assert!(prefixed.matches(&json!({"userName": "ann", "nickname": "x"})));
+        assert!(!prefixed.matches(&json!({"userName": "bob", "nickname": "x"})));
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"$and": [
+                {"age": {"$gt": 21}},
+                {"userName": {"$eq": "ann"}},
+                {"e\\.mail": {"$ne": "x"}},
+            ]})
+        );
+        assert!(matcher.matches(&json!({"userName": "ann", "age": 22, "e.mail": "y"})));
+        assert!(!matcher.matches(&json!({"user_name": "ann", "age": 22})));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    pub fn test_path_macro() {
+        use crate::{path, Matchable};
+        use serde::Deserialize;
+
+        #[allow(dead_code)]
+        #[derive(Deserialize, Matchable)]
+        struct Address {
+            city: String,
+            #[serde(rename = "zip.code")]
+            zip: String,
+        }
+
+        #[allow(dead_code)]
+        #[derive(Deserialize, Matchable)]
+        struct User {
+            address: Address,
+            previous: Option<Box<Address>>,
+            r#type: String,
+        }
+
+        assert_eq!(path!(User.address.city).path(), "address.city");
+        assert_eq!(path!(User.previous.zip).path(), "previous.zip\\.code");
+        assert_eq!(path!(User.r#type).path(), "type");
+        let matcher = path!(User.address.city).eq("London");
+        assert!(matcher.matches(&json!({"address": {"city": "London"}})));
+    }
+}
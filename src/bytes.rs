@@ -0,0 +1,199 @@
+//! `$bytes`, matching strings that encode binary data by the bytes they decode to, so that a
+//! payload written in hex by one producer and in base64 by another is matched alike.
+//!
+//! `{"payload": {"$bytes": {"prefix": "89504e47", "length": {"$gte": 8}}}}` matches a `payload`
+//! holding at least 8 bytes that start with the PNG signature, whether it is written as
+//! `"89504E470D0A1A0A"`, `"0x89504e470d0a1a0a"` or `"iVBORw0KGgo="`. The operand may hold:
+//!
+//! - `eq`: the exact bytes.
+//! - `prefix`: bytes the value starts with.
+//! - `length`: a matcher applied to the number of bytes, as with `$len`.
+//! - `encoding`: `"hex"`, `"base64"` or, by default, `"any"`, which reads strings of an even
+//!   number of hexadecimal digits as hex and others as base64. Base64 is read in the standard or
+//!   URL-safe alphabet, with or without padding.
+//!
+//! Bytes in the operand are written in hex, or as Extended JSON binaries, which also match
+//! whatever the encoding. Values that do not decode never match.
+
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{ejson, BytesOperator, MatchOptions, MatchesValue, ObjMatcher};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Hex,
+    Base64,
+    Any,
+}
+
+/// Operand of `$bytes`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Map<String, Value>", into = "Map<String, Value>")]
+pub struct Bytes {
+    source: Map<String, Value>,
+    encoding: Encoding,
+    eq: Option<Vec<u8>>,
+    prefix: Option<Vec<u8>>,
+    length: Option<Box<ObjMatcher>>,
+}
+
+impl TryFrom<Map<String, Value>> for Bytes {
+    type Error = String;
+
+    fn try_from(source: Map<String, Value>) -> Result<Self, String> {
+        let mut bytes = Bytes {
+            source: Map::new(),
+            encoding: Encoding::Any,
+            eq: None,
+            prefix: None,
+            length: None,
+        };
+        let operand = |key: &str, value: &Value| {
+            let decoded = match value {
+                Value::String(s) => decode(s, Encoding::Hex),
+                _ => ejson::binary_bytes(&ejson::decoded(value)),
+            };
+            decoded.ok_or_else(|| format!("`{key}` of `$bytes` expects hex or a binary"))
+        };
+        for (key, value) in &source {
+            match key.as_str() {
+                "encoding" => {
+                    bytes.encoding = match value.as_str() {
+                        Some("hex") => Encoding::Hex,
+                        Some("base64") => Encoding::Base64,
+                        Some("any") => Encoding::Any,
+                        _ => {
+                            return Err(
+                                "`encoding` of `$bytes` is one of `hex`, `base64` and `any`"
+                                    .to_string(),
+                            )
+                        }
+                    }
+                }
+                "eq" => bytes.eq = Some(operand(key, value)?),
+                "prefix" => bytes.prefix = Some(operand(key, value)?),
+                "length" => {
+                    let matcher =
+                        serde_json::from_value(value.clone()).map_err(|err| err.to_string())?;
+                    bytes.length = Some(Box::new(matcher));
+                }
+                _ => return Err(format!("unknown field `{key}` in `$bytes`")),
+            }
+        }
+        if bytes.eq.is_none() && bytes.prefix.is_none() && bytes.length.is_none() {
+            return Err("`$bytes` needs `eq`, `prefix` or `length`".to_string());
+        }
+        bytes.source = source;
+        Ok(bytes)
+    }
+}
+
+impl From<Bytes> for Map<String, Value> {
+    fn from(bytes: Bytes) -> Map<String, Value> {
+        bytes.source
+    }
+}
+
+impl Bytes {
+    pub(crate) fn length(&self) -> Option<&ObjMatcher> {
+        self.length.as_deref()
+    }
+}
+
+/// The bytes `text` encodes, if it is valid in `encoding`.
+fn decode(text: &str, encoding: Encoding) -> Option<Vec<u8>> {
+    let hex = |text: &str| {
+        let digits = if text.starts_with("0x") || text.starts_with("0X") {
+            &text[2..]
+        } else {
+            text
+        };
+        if digits.len() % 2 != 0 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        (0..digits.len() / 2)
+            .map(|i| u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).ok())
+            .collect()
+    };
+    match encoding {
+        Encoding::Hex => hex(text),
+        Encoding::Base64 => ejson::decode_base64(text),
+        Encoding::Any => hex(text).or_else(|| ejson::decode_base64(text)),
+    }
+}
+
+impl MatchesValue for BytesOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        let bytes = match other {
+            Value::String(s) => decode(s, self.val.encoding),
+            _ => ejson::binary_bytes(&ejson::decoded(other)),
+        };
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        self.val.eq.as_ref().map_or(true, |eq| *eq == bytes)
+            && self
+                .val
+                .prefix
+                .as_ref()
+                .map_or(true, |prefix| bytes.starts_with(prefix))
+            && self.val.length.as_ref().map_or(true, |length| {
+                length.evaluate(&Value::from(bytes.len()), options)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_bytes() {
+        let png =
+            from_str(r#"{"payload": {"$bytes": {"prefix": "89504e47", "length": {"$gte": 8}}}}"#)
+                .unwrap();
+        for payload in &[
+            json!("89504E470D0A1A0A"),
+            json!("0x89504e470d0a1a0a"),
+            json!("iVBORw0KGgo="),
+            json!("iVBORw0KGgo"),
+            json!({"$binary": {"base64": "iVBORw0KGgo=", "subType": "00"}}),
+        ] {
+            assert!(png.matches(&json!({ "payload": payload })), "{}", payload);
+        }
+        for payload in &[
+            json!("89504e47"),
+            json!("ffd8ffe000104a46"),
+            json!("not bytes!"),
+            json!(7),
+        ] {
+            assert!(!png.matches(&json!({ "payload": payload })), "{}", payload);
+        }
+        assert!(png
+            .compile()
+            .unwrap()
+            .matches(&json!({"payload": "iVBORw0KGgo="})));
+
+        // `cafe` is also valid base64, read as hex unless told otherwise.
+        let cafe = from_str(r#"{"$bytes": {"eq": "cafe"}}"#).unwrap();
+        assert!(cafe.matches(&json!("yv4=")));
+        assert!(cafe.matches(&json!("CAFE")));
+        let base64 = from_str(r#"{"$bytes": {"eq": "71a7de", "encoding": "base64"}}"#).unwrap();
+        assert!(base64.matches(&json!("cafe")));
+        assert!(!base64.matches(&json!("71a7de")));
+        assert_eq!(
+            serde_json::to_value(&base64).unwrap(),
+            json!({"$bytes": {"eq": "71a7de", "encoding": "base64"}})
+        );
+
+        assert!(from_str(r#"{"$bytes": {}}"#).is_err());
+        assert!(from_str(r#"{"$bytes": {"eq": "xyz"}}"#).is_err());
+        assert!(from_str(r#"{"$bytes": {"suffix": "00"}}"#).is_err());
+        assert!(from_str(r#"{"$bytes": {"length": 1, "encoding": "utf8"}}"#).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+//! Caching match results for documents that are evaluated repeatedly, such as redelivered
+//! messages, and compiled matchers for queries that are received repeatedly.
+//!
+//! Every method locking a cache panics if another thread panicked while holding its lock.
+#![allow(clippy::missing_panics_doc)]
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use crate::{from_json_with_options, CompiledMatcher, MatchOptions, ParseOptions};
+
+/// A [`CompiledMatcher`] that remembers its results for the most recently used documents.
+///
+/// Documents are identified by a key chosen by the caller, such as a message id, or by a
+/// structural hash of the document with [`CachedMatcher::matches`]. The cache can be shared
+/// between threads.
+pub struct CachedMatcher<K = u64> {
+    matcher: CompiledMatcher,
+    options: MatchOptions,
+    capacity: usize,
+    cache: Mutex<Lru<K, bool>>,
+    /// Results of [`CachedMatcher::matches`] by document hash, with the document they are for.
+    documents: Mutex<Lru<u64, (Value, bool)>>,
+    hasher: RandomState,
+}
+
+struct Lru<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick at which they were last used, oldest first.
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    fn new() -> Self {
+        Lru {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.1);
+        self.order.insert(tick, key.clone());
+        entry.1 = tick;
+        self.tick += 1;
+        Some(&entry.0)
+    }
+
+    fn insert(&mut self, key: K, value: V, capacity: usize) {
+        if let Some((_, tick)) = self.entries.remove(&key) {
+            self.order.remove(&tick);
+        }
+        while self.entries.len() >= capacity {
+            let oldest = match self.order.keys().next() {
+                Some(&oldest) => oldest,
+                None => return,
+            };
+            if let Some(key) = self.order.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        self.tick += 1;
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+impl<K: Hash + Eq + Clone> CachedMatcher<K> {
+    /// Wraps `matcher`, remembering the results for up to `capacity` documents.
+    #[must_use]
+    pub fn new(matcher: CompiledMatcher, capacity: usize) -> Self {
+        CachedMatcher {
+            matcher,
+            options: MatchOptions::default(),
+            capacity,
+            cache: Mutex::new(Lru::new()),
+            documents: Mutex::new(Lru::new()),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Evaluates the matcher with the given options instead of the defaults.
+    #[must_use]
+    pub fn options(mut self, options: MatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns whether `value` matches, reusing the result last computed for `key`.
+    ///
+    /// The caller is responsible for giving different documents different keys.
+    pub fn matches_keyed(&self, key: K, value: &Value) -> bool {
+        if let Some(&result) = self.cache.lock().unwrap().get(&key) {
+            return result;
+        }
+        let result = self.matcher.matches_with_options(value, &self.options);
+        if self.capacity > 0 {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key, result, self.capacity);
+        }
+        result
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len() + self.documents.lock().unwrap().entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every cached result.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+        self.documents.lock().unwrap().clear();
+    }
+}
+
+impl CachedMatcher<u64> {
+    /// Returns whether `value` matches, keyed by a structural hash of the document.
+    ///
+    /// The hash is seeded randomly for each cache, and a copy of every cached document is kept
+    /// so that a document whose hash collides with another's is evaluated rather than given the
+    /// other's result.
+    pub fn matches(&self, value: &Value) -> bool {
+        let mut hasher = self.hasher.build_hasher();
+        hash_value(value, &mut hasher);
+        let key = hasher.finish();
+        if let Some((document, result)) = self.documents.lock().unwrap().get(&key) {
+            if document == value {
+                return *result;
+            }
+        }
+        let result = self.matcher.matches_with_options(value, &self.options);
+        if self.capacity > 0 {
+            self.documents
+                .lock()
+                .unwrap()
+                .insert(key, (value.clone(), result), self.capacity);
+        }
+        result
+    }
+}
+
+impl<K> fmt::Debug for CachedMatcher<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedMatcher")
+            .field("matcher", &self.matcher)
+            .field("options", &self.options)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+static GLOBAL: Mutex<Option<Arc<CompileCache>>> = Mutex::new(None);
+
+/// Compiled matchers for the most recently used queries, so that queries received over and over
+/// are parsed and compiled once.
+///
+/// Queries are identified by their text, or for [`CompileCache::get_json`] by their serialized
+/// form. Queries that fail to parse are not cached. The cache can be shared between threads,
+/// either as its own value or through [`CompileCache::global`].
+pub struct CompileCache {
+    options: ParseOptions,
+    capacity: usize,
+    cache: Mutex<Lru<String, Arc<CompiledMatcher>>>,
+}
+
+impl CompileCache {
+    /// Capacity of the global cache unless replaced with [`CompileCache::set_global`].
+    pub const DEFAULT_CAPACITY: usize = 256;
+
+    /// Creates a cache holding up to `capacity` compiled matchers.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        CompileCache {
+            options: ParseOptions::default(),
+            capacity,
+            cache: Mutex::new(Lru::new()),
+        }
+    }
+
+    /// Parses and compiles queries with the given options instead of the defaults.
+    #[must_use]
+    pub fn options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The process-wide cache, created with [`DEFAULT_CAPACITY`](Self::DEFAULT_CAPACITY) and
+    /// default options on first use.
+    #[must_use]
+    pub fn global() -> Arc<CompileCache> {
+        GLOBAL
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| Arc::new(CompileCache::new(Self::DEFAULT_CAPACITY)))
+            .clone()
+    }
+
+    /// Replaces the process-wide cache, for instance to change its capacity or options.
+    pub fn set_global(cache: CompileCache) {
+        *GLOBAL.lock().unwrap() = Some(Arc::new(cache));
+    }
+
+    /// Returns the compiled matcher for the query `s`, parsing and compiling it unless it is
+    /// cached.
+    pub fn get(&self, s: &str) -> Result<Arc<CompiledMatcher>, serde_json::Error> {
+        if let Some(matcher) = self.cache.lock().unwrap().get(&s.to_string()) {
+            return Ok(matcher.clone());
+        }
+        let v = serde_json::from_str(s)?;
+        self.insert(s.to_string(), v)
+    }
+
+    /// Returns the compiled matcher for the query `v`, keyed by its serialization.
+    pub fn get_json(&self, v: &Value) -> Result<Arc<CompiledMatcher>, serde_json::Error> {
+        let key = serde_json::to_string(v)?;
+        if let Some(matcher) = self.cache.lock().unwrap().get(&key) {
+            return Ok(matcher.clone());
+        }
+        self.insert(key, v.clone())
+    }
+
+    fn insert(&self, key: String, v: Value) -> Result<Arc<CompiledMatcher>, serde_json::Error> {
+        let matcher = from_json_with_options(v, &self.options)?;
+        let matcher = Arc::new(matcher.compile_with_options(&self.options)?);
+        if self.capacity > 0 {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key, matcher.clone(), self.capacity);
+        }
+        Ok(matcher)
+    }
+
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every cached matcher.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+impl fmt::Debug for CompileCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompileCache")
+            .field("options", &self.options)
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+    match value {
+        Value::Null => state.write_u8(0),
+        Value::Bool(b) => {
+            state.write_u8(1);
+            b.hash(state);
+        }
+        Value::Number(n) => {
+            state.write_u8(2);
+            if let Some(n) = n.as_u64() {
+                state.write_u8(0);
+                n.hash(state);
+            } else if let Some(n) = n.as_i64() {
+                state.write_u8(1);
+                n.hash(state);
+            } else {
+                state.write_u8(2);
+                n.as_f64().unwrap_or(0.0).to_bits().hash(state);
+            }
+        }
+        Value::String(s) => {
+            state.write_u8(3);
+            s.hash(state);
+        }
+        Value::Array(a) => {
+            state.write_u8(4);
+            a.len().hash(state);
+            for v in a {
+                hash_value(v, state);
+            }
+        }
+        Value::Object(o) => {
+            state.write_u8(5);
+            o.len().hash(state);
+            for (k, v) in o {
+                k.hash(state);
+                hash_value(v, state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_cached_matcher() {
+        let compiled = from_str(r#"{"a": {"$in": [1, 2]}}"#)
+            .unwrap()
+            .compile()
+            .unwrap();
+        let cached = CachedMatcher::new(compiled, 2);
+        assert!(cached.matches(&json!({"a": 1})));
+        assert!(!cached.matches(&json!({"a": 3})));
+        assert!(cached.matches(&json!({"a": 1})));
+        assert_eq!(cached.len(), 2);
+        assert!(cached.matches(&json!({"a": 2})));
+        assert_eq!(cached.len(), 2);
+
+        // Keyed results are trusted, even for a different document.
+        let compiled = from_str(r#"{"a": 1}"#).unwrap().compile().unwrap();
+        let cached = CachedMatcher::new(compiled, 10);
+        assert!(cached.matches_keyed("msg-1", &json!({"a": 1})));
+        assert!(cached.matches_keyed("msg-1", &json!({"a": 2})));
+        assert!(!cached.matches_keyed("msg-2", &json!({"a": 2})));
+        cached.clear();
+        assert!(cached.is_empty());
+
+        // A document whose hash collides with a cached one's is evaluated on its own.
+        let compiled = from_str(r#"{"a": 1}"#).unwrap().compile().unwrap();
+        let cached = CachedMatcher::new(compiled, 10);
+        let (benign, forged) = (json!({"a": 1}), json!({"a": 2}));
+        let mut hasher = cached.hasher.build_hasher();
+        hash_value(&forged, &mut hasher);
+        cached
+            .documents
+            .lock()
+            .unwrap()
+            .insert(hasher.finish(), (benign, true), 10);
+        assert!(!cached.matches(&forged));
+        assert!(!cached.matches(&forged));
+    }
+
+    #[test]
+    pub fn test_evicts_least_recently_used() {
+        let compiled = from_str(r#"{"a": 1}"#).unwrap().compile().unwrap();
+        let cached = CachedMatcher::new(compiled, 2);
+        assert!(cached.matches_keyed(1, &json!({"a": 1})));
+        assert!(!cached.matches_keyed(2, &json!({"a": 2})));
+        assert!(cached.matches_keyed(1, &json!(null)));
+        assert!(!cached.matches_keyed(3, &json!({"a": 3})));
+        // Key 2 was least recently used and has been evicted; key 1 is still cached.
+        assert!(cached.matches_keyed(1, &json!(null)));
+        assert!(cached.matches_keyed(2, &json!({"a": 1})));
+    }
+
+    #[test]
+    pub fn test_compile_cache() {
+        let cache = CompileCache::new(2);
+        let query = r#"{"a": {"$gt": 1}}"#;
+        let first = cache.get(query).unwrap();
+        assert!(first.matches(&json!({"a": 2})));
+        assert!(Arc::ptr_eq(&first, &cache.get(query).unwrap()));
+        assert!(cache.get(r#"{"a": {"$gt": "x"}"#).is_err());
+        assert!(cache.get(r#"{"a": {"$type": "nope"}}"#).is_err());
+        assert_eq!(cache.len(), 1);
+
+        let json = cache.get_json(&json!({"b": 1})).unwrap();
+        assert!(Arc::ptr_eq(
+            &json,
+            &cache.get_json(&json!({"b": 1})).unwrap()
+        ));
+        cache.get(r#"{"c": 1}"#).unwrap();
+        // The first query was least recently used and has been evicted.
+        assert_eq!(cache.len(), 2);
+        assert!(!Arc::ptr_eq(&first, &cache.get(query).unwrap()));
+
+        let global = CompileCache::global();
+        assert!(Arc::ptr_eq(&global, &CompileCache::global()));
+        assert_eq!(global.capacity(), CompileCache::DEFAULT_CAPACITY);
+    }
+}
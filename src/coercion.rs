@@ -0,0 +1,110 @@
+//! Comparing strings with values of other types under [`Coercion`] rules.
+
+use std::cmp::Ordering;
+
+use serde_json::{Number, Value};
+
+use crate::{order, Coercion, MatchOptions};
+
+/// The number `s` holds, if it is written as a JSON number.
+fn parse_number(s: &str) -> Option<Number> {
+    if s.trim() != s {
+        return None;
+    }
+    serde_json::from_str(s).ok()
+}
+
+/// `value` as a value of the type of `like`, if it is a string the rules let stand for one.
+fn coerce(value: &Value, like: &Value, rules: Coercion) -> Option<Value> {
+    let s = value.as_str()?;
+    match like {
+        Value::Number(_) if rules.coerces_numbers() => parse_number(s).map(Value::Number),
+        Value::Bool(_) if rules.coerces_booleans() => match s {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        Value::Null if rules.coerces_nulls() && (s.is_empty() || s == "null") => Some(Value::Null),
+        _ => None,
+    }
+}
+
+/// Orders `actual` relative to `expected` if one is a string standing for a value of the
+/// other's type. Coerced numbers compare by their values, so `"5.0"` equals `5`.
+pub(crate) fn compare(
+    actual: &Value,
+    expected: &Value,
+    options: &MatchOptions,
+) -> Option<Ordering> {
+    let rules = options.coercion_rules();
+    if rules.is_none() {
+        return None;
+    }
+    let (actual, expected) = match (
+        coerce(actual, expected, rules),
+        coerce(expected, actual, rules),
+    ) {
+        (Some(actual), _) => (actual, expected.clone()),
+        (None, Some(expected)) => (actual.clone(), expected),
+        (None, None) => return None,
+    };
+    match (&actual, &expected) {
+        (Value::Number(a), Value::Number(b)) => Some(order::compare_numbers(a, b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// The number `value` stands for, if it is a string and numbers are coerced.
+pub(crate) fn number(value: &Value, options: &MatchOptions) -> Option<Number> {
+    match value {
+        Value::String(s) if options.coercion_rules().coerces_numbers() => parse_number(s),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_json, BorrowedMatcher, Coercion, MatchOptions};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_coercion() {
+        let form = json!({"age": "42", "price": "9.50", "subscribed": "true", "referrer": ""});
+        let query = json!({
+            "age": {"$gte": 18, "$lt": 65, "$ne": 40},
+            "price": 9.5,
+            "subscribed": true,
+            "referrer": null,
+        });
+        let matcher = from_json(query.clone()).unwrap();
+        let compiled = matcher.compile().unwrap();
+        let text = query.to_string();
+        let borrowed = BorrowedMatcher::parse(&text).unwrap();
+        assert!(!matcher.matches(&form));
+
+        let options = MatchOptions::new().coercion(Coercion::all());
+        assert!(matcher.matches_with_options(&form, &options));
+        assert!(compiled.matches_with_options(&form, &options));
+        assert!(borrowed.matches_with_options(&form, &options));
+        for doc in &[
+            json!({"age": "17", "price": "9.50", "subscribed": "true", "referrer": ""}),
+            json!({"age": "40.0", "price": "9.50", "subscribed": "true", "referrer": ""}),
+            json!({"age": " 42", "price": "9.50", "subscribed": "true", "referrer": ""}),
+            json!({"age": "42", "price": "9.50", "subscribed": "True", "referrer": ""}),
+            json!({"age": "42", "price": "9.50", "subscribed": "true", "referrer": "ads"}),
+        ] {
+            assert!(!matcher.matches_with_options(doc, &options), "{}", doc);
+            assert!(!compiled.matches_with_options(doc, &options), "{}", doc);
+            assert!(!borrowed.matches_with_options(doc, &options), "{}", doc);
+        }
+
+        // Each rule is opt-in, and strings in the query are coerced as well.
+        let numbers = MatchOptions::new().coercion(Coercion::new().numbers(true));
+        assert!(!matcher.matches_with_options(&form, &numbers));
+        let ids = from_json(json!({"id": {"$in": ["7", "8"]}})).unwrap();
+        assert!(ids.matches_with_options(&json!({"id": 7}), &numbers));
+        assert!(!ids.matches(&json!({"id": 7})));
+    }
+}
@@ -0,0 +1,106 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use icu_collator::options::{CollatorOptions, Strength};
+use icu_collator::{CollatorBorrowed, CollatorPreferences};
+use icu_locale_core::Locale;
+
+/// Comparison level of a [`Collation`], following MongoDB's `strength` collation option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollationStrength {
+    /// Compares base letters only, so `"a"`, `"á"` and `"A"` are equal.
+    Primary,
+    /// Also distinguishes diacritics, so `"a"` and `"A"` are equal but `"á"` is not.
+    Secondary,
+    /// Also distinguishes case. This is the default strength.
+    #[default]
+    Tertiary,
+    /// Also distinguishes punctuation when it is otherwise ignored.
+    Quaternary,
+    /// Breaks any remaining ties by comparing code points.
+    Identical,
+}
+
+impl From<CollationStrength> for Strength {
+    fn from(strength: CollationStrength) -> Strength {
+        match strength {
+            CollationStrength::Primary => Strength::Primary,
+            CollationStrength::Secondary => Strength::Secondary,
+            CollationStrength::Tertiary => Strength::Tertiary,
+            CollationStrength::Quaternary => Strength::Quaternary,
+            CollationStrength::Identical => Strength::Identical,
+        }
+    }
+}
+
+/// Error returned when a [`Collation`] cannot be created.
+#[derive(Debug)]
+pub enum CollationError {
+    /// The locale identifier could not be parsed.
+    InvalidLocale(String),
+    /// No collation data is available for the locale.
+    Data(String),
+}
+
+impl fmt::Display for CollationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollationError::InvalidLocale(locale) => write!(f, "invalid locale `{locale}`"),
+            CollationError::Data(err) => write!(f, "collation data unavailable: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CollationError {}
+
+/// Locale-aware string comparison, used in place of code point comparison when set on
+/// [`MatchOptions`](crate::MatchOptions).
+#[derive(Clone)]
+pub struct Collation {
+    locale: String,
+    strength: CollationStrength,
+    collator: Arc<CollatorBorrowed<'static>>,
+}
+
+impl Collation {
+    /// Creates a collation for a BCP 47 locale identifier such as `"de"` or `"tr-TR"`.
+    pub fn new(locale: &str, strength: CollationStrength) -> Result<Self, CollationError> {
+        let parsed = Locale::try_from_str(locale)
+            .map_err(|_| CollationError::InvalidLocale(locale.to_string()))?;
+        let mut options = CollatorOptions::default();
+        options.strength = Some(strength.into());
+        let collator = CollatorBorrowed::try_new(CollatorPreferences::from(&parsed), options)
+            .map_err(|err| CollationError::Data(err.to_string()))?;
+        Ok(Collation {
+            locale: locale.to_string(),
+            strength,
+            collator: Arc::new(collator),
+        })
+    }
+
+    #[must_use]
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    #[must_use]
+    pub fn strength(&self) -> CollationStrength {
+        self.strength
+    }
+
+    /// Orders two strings according to the locale's collation rules.
+    #[must_use]
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.collator.compare(a, b)
+    }
+}
+
+impl fmt::Debug for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Collation")
+            .field("locale", &self.locale)
+            .field("strength", &self.strength)
+            .finish()
+    }
+}
@@ -0,0 +1,254 @@
+//! Collecting, while documents are matched, how often each clause holds and how long it takes,
+//! to feed back into the planner.
+//!
+//! Reading or resetting a collector panics if a thread panicked while recording into it. Rates
+//! are computed from counts in `f64`, exactly up to 2^53 evaluations.
+#![allow(clippy::cast_precision_loss, clippy::missing_panics_doc)]
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{escape_field, Clause, Observer, PathStatistics, Statistics};
+
+/// Number of distinct values remembered per path for estimating its cardinality.
+const DISTINCT_VALUES: usize = 1024;
+
+/// How often one clause was evaluated and held, as returned by
+/// [`StatisticsCollector::clauses`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClauseStatistics {
+    /// Dotted path of the value the clause was applied to, empty for the document itself.
+    pub path: String,
+    /// Operator of the clause, or `None` for a literal comparison or a group of field clauses.
+    pub operator: Option<String>,
+    pub evaluations: u64,
+    pub hits: u64,
+    /// Time spent evaluating the clause, its sub-clauses included.
+    pub time: Duration,
+}
+
+impl ClauseStatistics {
+    /// Fraction of the evaluations in which the clause held.
+    #[must_use]
+    pub fn hit_rate(&self) -> f64 {
+        if self.evaluations == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.evaluations as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    evaluations: u64,
+    hits: u64,
+    time: Duration,
+}
+
+#[derive(Default)]
+struct PathCounts {
+    evaluations: u64,
+    hits: u64,
+    values: HashSet<String>,
+}
+
+/// A clause being evaluated.
+struct Frame {
+    depth: usize,
+    start: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    clauses: BTreeMap<(String, Option<String>), Counts>,
+    /// Outcomes of the outermost clause on each path, which decides whether the path matches.
+    paths: BTreeMap<String, PathCounts>,
+    frames: HashMap<ThreadId, Vec<Frame>>,
+}
+
+/// An [`Observer`] recording the hit rate and evaluation time of every clause of the matchers it
+/// is set on with [`MatchOptions::observer`](crate::MatchOptions::observer), and exporting them
+/// as [`Statistics`] for [`ParseOptions::statistics`](crate::ParseOptions::statistics), so that
+/// long-running filters can recompile their matchers with the clauses ordered for the documents
+/// they actually see.
+///
+/// Clauses skipped because the outcome was already decided are not recorded, so the hit rate of
+/// a clause is measured on the documents that reached it. One collector can observe many
+/// matchers, from many threads.
+#[derive(Default)]
+pub struct StatisticsCollector {
+    state: Mutex<State>,
+}
+
+impl StatisticsCollector {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Statistics of every clause evaluated so far, by path and then operator.
+    #[must_use]
+    pub fn clauses(&self) -> Vec<ClauseStatistics> {
+        let state = self.state.lock().unwrap();
+        state
+            .clauses
+            .iter()
+            .map(|((path, operator), counts)| ClauseStatistics {
+                path: path.clone(),
+                operator: operator.clone(),
+                evaluations: counts.evaluations,
+                hits: counts.hits,
+                time: counts.time,
+            })
+            .collect()
+    }
+
+    /// The match rate of every path evaluated so far and the number of distinct values seen
+    /// there, counting at most 1024.
+    #[must_use]
+    pub fn snapshot(&self) -> Statistics {
+        let state = self.state.lock().unwrap();
+        state
+            .paths
+            .iter()
+            .filter(|(_, counts)| counts.evaluations > 0)
+            .fold(Statistics::new(), |statistics, (path, counts)| {
+                statistics.path(
+                    path.clone(),
+                    PathStatistics {
+                        match_rate: Some(counts.hits as f64 / counts.evaluations as f64),
+                        cardinality: Some(counts.values.len() as u64),
+                    },
+                )
+            })
+    }
+
+    /// Forgets everything recorded so far.
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.clauses.clear();
+        state.paths.clear();
+    }
+}
+
+impl Observer for StatisticsCollector {
+    fn enter(&self, clause: &Clause<'_>) {
+        let mut state = self.state.lock().unwrap();
+        let frame = Frame {
+            depth: clause.path.len(),
+            start: Instant::now(),
+        };
+        state
+            .frames
+            .entry(thread::current().id())
+            .or_default()
+            .push(frame);
+    }
+
+    fn exit(&self, clause: &Clause<'_>, outcome: bool) {
+        let mut state = self.state.lock().unwrap();
+        let thread = thread::current().id();
+        let (frame, outermost) = match state.frames.get_mut(&thread) {
+            Some(frames) => match frames.pop() {
+                Some(frame) => {
+                    // Sub-clauses on a field are deeper than the clauses containing them, while
+                    // the operators combined on one field are at the same depth.
+                    let outermost = frames
+                        .last()
+                        .map_or(true, |parent| parent.depth < frame.depth);
+                    if frames.is_empty() {
+                        state.frames.remove(&thread);
+                    }
+                    (frame, outermost)
+                }
+                None => return,
+            },
+            None => return,
+        };
+        let path = clause
+            .path
+            .iter()
+            .map(|field| escape_field(field))
+            .collect::<Vec<_>>()
+            .join(".");
+        let operator = clause.operator.map(str::to_string);
+        let counts = state.clauses.entry((path.clone(), operator)).or_default();
+        counts.evaluations += 1;
+        counts.hits += u64::from(outcome);
+        counts.time += frame.start.elapsed();
+        if outermost && !path.is_empty() {
+            let counts = state.paths.entry(path).or_default();
+            counts.evaluations += 1;
+            counts.hits += u64::from(outcome);
+            if let Some(value) = clause.value {
+                if counts.values.len() < DISTINCT_VALUES {
+                    counts.values.insert(value.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, MatchOptions, ParseOptions};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn test_statistics_collector() {
+        let matcher = from_str(r#"{"status": "active", "n": {"$gte": 5, "$lt": 100}}"#).unwrap();
+        let collector = Arc::new(StatisticsCollector::new());
+        let options = MatchOptions::new().observer(collector.clone());
+        let compiled = matcher.compile().unwrap();
+        for i in 0..10 {
+            let status = if i < 9 { "active" } else { "inactive" };
+            let doc = json!({"status": status, "n": i * 20});
+            assert_eq!(
+                compiled.matches_with_options(&doc, &options),
+                matcher.matches(&doc)
+            );
+        }
+
+        let clauses = collector.clauses();
+        let range = clauses
+            .iter()
+            .find(|c| c.operator.as_deref() == Some("$range"))
+            .unwrap();
+        assert_eq!(range.path, "n");
+        // `n` is only reached when `status` holds, the cheaper clause running first.
+        assert_eq!((range.evaluations, range.hits), (9, 4));
+        let status = clauses.iter().find(|c| c.path == "status").unwrap();
+        assert_eq!((status.evaluations, status.hits), (10, 9));
+        assert!((status.hit_rate() - 0.9).abs() < 1e-9);
+
+        let statistics = collector.snapshot();
+        assert_eq!(
+            statistics.get("n"),
+            Some(&PathStatistics {
+                match_rate: Some(4.0 / 9.0),
+                cardinality: Some(9),
+            })
+        );
+        assert_eq!(statistics.get("status").unwrap().cardinality, Some(2));
+
+        // The interpreter reports the operators on `n` separately, but only the outermost
+        // clause on a path decides its match rate.
+        collector.reset();
+        assert!(!matcher.matches_with_options(&json!({"status": "active", "n": 100}), &options));
+        assert_eq!(collector.snapshot().get("n").unwrap().match_rate, Some(0.0));
+
+        let options = ParseOptions::new().statistics(Arc::new(statistics));
+        let plan = matcher
+            .compile_with_options(&options)
+            .unwrap()
+            .explain_plan_with_options(&options);
+        assert_eq!(plan.children[0].path, "n");
+    }
+}
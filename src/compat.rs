@@ -0,0 +1,417 @@
+//! Evaluating field conditions with the corner-case semantics of another engine, as selected by
+//! [`MatchOptions::compatibility`].
+//!
+//! Under the profiles that traverse arrays, the path of a field condition leads to several
+//! candidates: the values found through arrays of objects, plus the elements of an array found at
+//! the end. Each condition on them holds if it holds for any candidate, while a negation holds
+//! if what it negates holds for none.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+use crate::walk::{self, Condition};
+use crate::{
+    order, path, try_into_operator, values_equal, Compatibility, MatchOptions, ObjMatcher,
+};
+
+static NULL: Value = Value::Null;
+
+/// A value a field condition is tested against, or `None` for a missing field.
+pub(crate) type Candidate<'a> = Option<&'a Value>;
+
+/// The candidates found at `path` in `value`.
+pub(crate) fn candidates<'a>(
+    value: &'a Value,
+    path: &str,
+    options: &MatchOptions,
+) -> Vec<Candidate<'a>> {
+    let segments = path::segments(path).collect::<Vec<_>>();
+    let mut found = Vec::new();
+    collect(Some(value), &segments, options.profile(), &mut found);
+    found
+}
+
+/// The candidates found at `path` in `value`, each with the fields and element indexes leading
+/// to it. Missing fields are located where they would be.
+pub(crate) fn located_candidates<'a>(
+    value: &'a Value,
+    path: &str,
+    options: &MatchOptions,
+) -> Vec<(Vec<String>, Candidate<'a>)> {
+    let segments = path::segments(path).collect::<Vec<_>>();
+    let mut located = Located {
+        trail: Vec::new(),
+        found: Vec::new(),
+    };
+    collect(Some(value), &segments, options.profile(), &mut located);
+    located.found
+}
+
+/// A step of [`collect`] into a field or an array element.
+enum Step<'s> {
+    Field(&'s str),
+    Element(usize),
+}
+
+/// Receives the candidates of [`collect`], and the steps leading to them if it needs them.
+trait Found<'a> {
+    fn enter(&mut self, _step: Step<'_>) {}
+
+    fn leave(&mut self) {}
+
+    /// Receives a candidate, with the segments of the path left to follow if it is missing.
+    fn found(&mut self, candidate: Candidate<'a>, rest: &[Cow<'_, str>]);
+}
+
+impl<'a> Found<'a> for Vec<Candidate<'a>> {
+    fn found(&mut self, candidate: Candidate<'a>, _rest: &[Cow<'_, str>]) {
+        self.push(candidate);
+    }
+}
+
+struct Located<'a> {
+    trail: Vec<String>,
+    found: Vec<(Vec<String>, Candidate<'a>)>,
+}
+
+impl<'a> Found<'a> for Located<'a> {
+    fn enter(&mut self, step: Step<'_>) {
+        self.trail.push(match step {
+            Step::Field(field) => field.to_string(),
+            Step::Element(index) => index.to_string(),
+        });
+    }
+
+    fn leave(&mut self) {
+        self.trail.pop();
+    }
+
+    fn found(&mut self, candidate: Candidate<'a>, rest: &[Cow<'_, str>]) {
+        let mut location = self.trail.clone();
+        location.extend(rest.iter().map(std::string::ToString::to_string));
+        self.found.push((location, candidate));
+    }
+}
+
+fn collect<'a, F: Found<'a>>(
+    value: Candidate<'a>,
+    segments: &[Cow<'_, str>],
+    profile: Compatibility,
+    found: &mut F,
+) {
+    let (segment, rest) = if let Some(split) = segments.split_first() {
+        split
+    } else {
+        found.found(value, segments);
+        if let Some(Value::Array(elements)) = value {
+            for (index, element) in elements.iter().enumerate() {
+                found.enter(Step::Element(index));
+                found.found(Some(element), segments);
+                found.leave();
+            }
+        }
+        return;
+    };
+    match value {
+        Some(Value::Object(o)) => {
+            found.enter(Step::Field(segment));
+            collect(o.get(segment.as_ref()), rest, profile, found);
+            found.leave();
+        }
+        Some(Value::Array(elements)) => {
+            if let Ok(index) = segment.parse::<usize>() {
+                found.enter(Step::Element(index));
+                collect(elements.get(index), rest, profile, found);
+                found.leave();
+                return;
+            }
+            for (index, element) in elements.iter().enumerate() {
+                found.enter(Step::Element(index));
+                match element {
+                    Value::Object(o) => {
+                        found.enter(Step::Field(segment));
+                        collect(o.get(segment.as_ref()), rest, profile, found);
+                        found.leave();
+                    }
+                    Value::Array(_) if profile == Compatibility::SiftJs => {
+                        collect(Some(element), segments, profile, found);
+                    }
+                    _ if profile == Compatibility::SiftJs => found.found(None, segments),
+                    _ => {}
+                }
+                found.leave();
+            }
+            if profile == Compatibility::SiftJs {
+                found.found(None, segments);
+            }
+        }
+        _ => found.found(None, segments),
+    }
+}
+
+/// Whether the field condition `condition`, taken from a query, holds for `candidates`. A value
+/// that is not an operator is a literal the field must equal.
+pub(crate) fn field_matches(
+    condition: &Value,
+    candidates: &[Candidate<'_>],
+    options: &MatchOptions,
+) -> bool {
+    match walk::condition(condition, options.operator_registry()) {
+        Condition::Operator(matcher) => matches_candidates(&matcher, candidates, options),
+        Condition::Literal(condition) => candidates
+            .iter()
+            .any(|candidate| values_equal(condition, candidate.unwrap_or(&NULL), options)),
+    }
+}
+
+pub(crate) fn matches_candidates(
+    matcher: &ObjMatcher,
+    candidates: &[Candidate<'_>],
+    options: &MatchOptions,
+) -> bool {
+    match matcher {
+        ObjMatcher::Eq(op) => matches_candidates(&op.val, candidates, options),
+        ObjMatcher::Ne(op) => !matches_candidates(&op.val, candidates, options),
+        ObjMatcher::Not(op) => !matches_candidates(&op.val, candidates, options),
+        ObjMatcher::And(op) => op
+            .val
+            .iter()
+            .all(|m| matches_candidates(m, candidates, options)),
+        ObjMatcher::Or(op) => op
+            .val
+            .iter()
+            .any(|m| matches_candidates(m, candidates, options)),
+        ObjMatcher::Nin(op) => !candidates
+            .iter()
+            .any(|candidate| op.val.any(candidate.unwrap_or(&NULL), options)),
+        ObjMatcher::Default(op) => {
+            let candidates = candidates
+                .iter()
+                .map(|candidate| Some(op.apply(candidate.unwrap_or(&NULL))))
+                .collect::<Vec<_>>();
+            matches_candidates(op.matcher(), &candidates, options)
+        }
+        ObjMatcher::Value(value) => match try_into_operator(value, options.operator_registry()) {
+            Some(matcher) => matches_candidates(&matcher, candidates, options),
+            None => any(matcher, candidates, options),
+        },
+        _ => any(matcher, candidates, options),
+    }
+}
+
+fn any(matcher: &ObjMatcher, candidates: &[Candidate<'_>], options: &MatchOptions) -> bool {
+    candidates.iter().any(|candidate| match candidate {
+        Some(value) => matcher.evaluate(value, options),
+        None => matches_missing(matcher, options),
+    })
+}
+
+/// Whether `matcher`, which is not a logical operator, holds for a missing field.
+pub(crate) fn matches_missing(matcher: &ObjMatcher, options: &MatchOptions) -> bool {
+    match matcher {
+        ObjMatcher::Type(_) => false,
+        ObjMatcher::Gt(_) | ObjMatcher::Gte(_) | ObjMatcher::Lt(_) | ObjMatcher::Lte(_)
+            if options.profile() == Compatibility::SiftJs =>
+        {
+            false
+        }
+        _ => matcher.evaluate(&NULL, options),
+    }
+}
+
+/// Orders `actual` relative to `expected` for `$gt`, `$gte`, `$lt` and `$lte`, or `None` if
+/// they are not comparable.
+pub(crate) fn compare(
+    expected: &Value,
+    actual: &Value,
+    options: &MatchOptions,
+) -> Option<Ordering> {
+    match options.profile() {
+        Compatibility::SiftJs => js_compare(actual, expected, options),
+        Compatibility::Native | Compatibility::StrictMongo => {
+            order::compare_same_type(actual, expected, options)
+        }
+    }
+}
+
+/// Orders `a` relative to `b` as JavaScript's relational operators do, if they have the same
+/// `typeof`; `None` stands for comparisons involving `NaN`, which are all false.
+fn js_compare(a: &Value, b: &Value, options: &MatchOptions) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => Some(order::compare_numbers(a, b)),
+        (Value::String(a), Value::String(b)) => {
+            Some(utf16_cmp(&options.prepare_str(a), &options.prepare_str(b)))
+        }
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Number(_) | Value::String(_) | Value::Bool(_), _)
+        | (_, Value::Number(_) | Value::String(_) | Value::Bool(_)) => None,
+        // `typeof` is "object" for `null`, arrays and objects alike, which are compared as
+        // strings if both convert to one, and as numbers otherwise.
+        (Value::Null, _) | (_, Value::Null) => js_number(a).partial_cmp(&js_number(b)),
+        _ => Some(utf16_cmp(&js_string(a), &js_string(b))),
+    }
+}
+
+fn utf16_cmp(a: &str, b: &str) -> Ordering {
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+/// `String(value)` in JavaScript, for values converted to strings when compared.
+fn js_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < 1e21 => format!("{f:.0}"),
+            _ => n.to_string(),
+        },
+        Value::String(s) => s.clone(),
+        Value::Array(elements) => elements.iter().map(js_string).collect::<Vec<_>>().join(","),
+        Value::Object(_) => "[object Object]".to_string(),
+    }
+}
+
+/// `Number(value)` in JavaScript.
+#[allow(clippy::cast_precision_loss)]
+fn js_number(value: &Value) -> f64 {
+    let s = match value {
+        Value::Null => return 0.0,
+        Value::Bool(b) => return if *b { 1.0 } else { 0.0 },
+        Value::Number(n) => return n.as_f64().unwrap_or(f64::NAN),
+        _ => js_string(value),
+    };
+    let s = s.trim();
+    let radix = |prefix: &str, radix: u32| {
+        if s.len() > prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix) {
+            Some(u64::from_str_radix(&s[prefix.len()..], radix).map(|n| n as f64))
+        } else {
+            None
+        }
+    };
+    match s {
+        "" => 0.0,
+        "Infinity" | "+Infinity" => f64::INFINITY,
+        "-Infinity" => f64::NEG_INFINITY,
+        _ => match radix("0x", 16)
+            .or_else(|| radix("0o", 8))
+            .or_else(|| radix("0b", 2))
+        {
+            Some(n) => n.unwrap_or(f64::NAN),
+            None if s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) => {
+                s.parse().unwrap_or(f64::NAN)
+            }
+            None => f64::NAN,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_json, BorrowedMatcher, ParseOptions, SpecSuite};
+    use serde_json::json;
+    use std::path::Path;
+
+    #[test]
+    pub fn test_compatibility() {
+        let mongo = MatchOptions::new().compatibility(Compatibility::StrictMongo);
+        let sift = MatchOptions::new().compatibility(Compatibility::SiftJs);
+        let matches = |query: Value, doc: Value, options: &MatchOptions| {
+            from_json(query)
+                .unwrap()
+                .matches_with_options(&doc, options)
+        };
+
+        let doc = json!({"tags": ["a", "b"], "items": [{"sku": "A1", "qty": 2}, {"sku": "B2"}]});
+        for query in &[
+            json!({"tags": "a"}),
+            json!({"tags": ["a", "b"]}),
+            json!({"items.sku": "B2", "items.qty": {"$gt": 1}}),
+            json!({"items.qty": null}),
+            json!({"tags": {"$nin": ["c"]}, "items.sku": {"$ne": "C3"}}),
+        ] {
+            assert!(matches(query.clone(), doc.clone(), &mongo), "{}", query);
+            assert!(matches(query.clone(), doc.clone(), &sift), "{}", query);
+        }
+        assert!(!matches(
+            json!({"tags": "a"}),
+            doc.clone(),
+            &MatchOptions::new()
+        ));
+        assert!(!matches(json!({"tags": {"$ne": "a"}}), doc.clone(), &mongo));
+        assert!(!matches(
+            json!({"tags": {"$not": {"$in": ["b"]}}}),
+            doc.clone(),
+            &mongo
+        ));
+
+        // Missing fields.
+        let query = json!({"deleted": {"$type": ["null"]}});
+        assert!(matches(query.clone(), json!({}), &MatchOptions::new()));
+        assert!(!matches(query.clone(), json!({}), &mongo));
+        assert!(matches(query, json!({"deleted": null}), &mongo));
+        let query = json!({"deleted": {"$lte": null}});
+        assert!(matches(query.clone(), json!({}), &mongo));
+        assert!(!matches(query.clone(), json!({}), &sift));
+        assert!(matches(query, json!({"deleted": null}), &sift));
+        // sift.js also looks fields up on the array itself.
+        assert!(!matches(json!({"items.sku": null}), doc.clone(), &mongo));
+        assert!(matches(json!({"items.sku": null}), doc.clone(), &sift));
+
+        // Type ordering.
+        assert!(!matches(
+            json!({"n": {"$gte": null}}),
+            json!({"n": []}),
+            &mongo
+        ));
+        assert!(matches(
+            json!({"n": {"$gte": null}}),
+            json!({"n": [[]]}),
+            &sift
+        ));
+        assert!(matches(
+            json!({"n": {"$gt": [9]}}),
+            json!({"n": [[10]]}),
+            &mongo
+        ));
+        assert!(!matches(
+            json!({"n": {"$gt": [9]}}),
+            json!({"n": [[10]]}),
+            &sift
+        ));
+        assert!(matches(
+            json!({"s": {"$gt": "\u{ffff}"}}),
+            json!({"s": "\u{10000}"}),
+            &mongo
+        ));
+        assert!(!matches(
+            json!({"s": {"$gt": "\u{ffff}"}}),
+            json!({"s": "\u{10000}"}),
+            &sift
+        ));
+
+        let conformance = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        for (profile, file) in &[
+            (Compatibility::Native, "native.json"),
+            (Compatibility::StrictMongo, "strict-mongo.json"),
+            (Compatibility::SiftJs, "sift-js.json"),
+        ] {
+            let suite = SpecSuite::load(conformance.join(file)).unwrap();
+            let options = MatchOptions::new().compatibility(*profile);
+            let report = suite.run_with_options(&ParseOptions::default(), &options);
+            assert!(report.is_success(), "{}: {}", file, report);
+            // Compiled and borrowed matchers follow the same profile.
+            let report = suite.run_with(|query, document| {
+                let compiled = from_json(query.clone()).unwrap().compile().unwrap();
+                let text = query.to_string();
+                let borrowed = BorrowedMatcher::parse(&text).unwrap();
+                let outcome = compiled.matches_with_options(document, &options);
+                assert_eq!(borrowed.matches_with_options(document, &options), outcome);
+                Ok(outcome)
+            });
+            assert!(report.is_success(), "{}: {}", file, report);
+        }
+    }
+}
@@ -0,0 +1,1257 @@
+//! Compiled matchers: a flattened, pre-validated form of an [`ObjMatcher`] that can be shipped
+//! between processes as a compact binary blob.
+//!
+//! Compiling resolves every operator once, so evaluating a [`CompiledMatcher`] never re-parses the
+//! query. Operators without a dedicated node are kept as [`ObjMatcher`]s and evaluated by the
+//! interpreter.
+//!
+//! Identical subtrees within a matcher are stored once, and a subtree reached from several
+//! branches is evaluated at most once per value it is applied to. Field names and literals are
+//! shared through an [`Interner`], which can be reused across many matchers so that large rule
+//! sets keep a single copy of each.
+//!
+//! Numeric `$gt`, `$gte`, `$lt`, `$lte` and `$ne` clauses on the same field are merged into a
+//! single interval checked in one step, and a field that no number can satisfy, such as
+//! `{"$gt": 5, "$lt": 3}`, compiles to a clause that never holds.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+
+use serde_json::{Map, Number, Value};
+
+use crate::compat::{self, Candidate};
+use crate::ejson;
+use crate::interval::{Bound, Interval};
+use crate::planner::{Plan, Planner};
+use crate::{
+    context, from_json, observe, parse_operator, path, values_equal, Compatibility, MatchOptions,
+    ObjMatcher, ParseOptions,
+};
+
+static NULL: Value = Value::Null;
+
+const MAGIC: &[u8; 4] = b"SJMC";
+const VERSION: u8 = 1;
+/// Deepest literal accepted when decoding, matching `serde_json`'s own recursion limit.
+const MAX_DEPTH: usize = 128;
+/// Number of literals in `$in` or `$nin` above which they are hashed rather than compiled into
+/// one node each.
+const HASHED_MEMBERS: usize = 16;
+
+pub(crate) type NodeId = u32;
+
+#[derive(Debug, Clone)]
+pub(crate) enum Node {
+    And(Vec<NodeId>),
+    Or(Vec<NodeId>),
+    Not(NodeId),
+    Field(Arc<str>, NodeId),
+    Literal(Arc<Value>),
+    Interpreted(ObjMatcher),
+    Range(Interval),
+}
+
+/// Identity of a node for sharing: interned strings and literals compare by address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum NodeKey {
+    And(Vec<NodeId>),
+    Or(Vec<NodeId>),
+    Not(NodeId),
+    Field(usize, NodeId),
+    Literal(usize),
+    Interpreted(String),
+    Range(String),
+}
+
+impl Node {
+    pub(crate) fn children(&self) -> &[NodeId] {
+        match self {
+            Node::And(children) | Node::Or(children) => children,
+            Node::Not(child) | Node::Field(_, child) => std::slice::from_ref(child),
+            Node::Literal(_) | Node::Interpreted(_) | Node::Range(_) => &[],
+        }
+    }
+
+    /// This node with its children replaced by their new ids.
+    fn renumbered(self, ids: &[NodeId]) -> Node {
+        let id = |child: NodeId| ids[child as usize];
+        match self {
+            Node::And(children) => Node::And(children.into_iter().map(id).collect()),
+            Node::Or(children) => Node::Or(children.into_iter().map(id).collect()),
+            Node::Not(child) => Node::Not(id(child)),
+            Node::Field(key, child) => Node::Field(key, id(child)),
+            node => node,
+        }
+    }
+
+    /// Identity used to share this node, if it can be shared.
+    fn key(&self) -> Option<NodeKey> {
+        Some(match self {
+            Node::And(children) => NodeKey::And(children.clone()),
+            Node::Or(children) => NodeKey::Or(children.clone()),
+            Node::Not(child) => NodeKey::Not(*child),
+            Node::Field(key, child) => NodeKey::Field(key.as_ptr() as usize, *child),
+            Node::Literal(value) => NodeKey::Literal(std::ptr::addr_of!(**value) as usize),
+            Node::Interpreted(matcher) => {
+                NodeKey::Interpreted(serde_json::to_string(matcher).ok()?)
+            }
+            Node::Range(interval) => NodeKey::Range(format!("{interval:?}")),
+        })
+    }
+}
+
+/// Flat node storage in which every node's children precede it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Nodes {
+    list: Vec<Node>,
+    /// Number of references to each node from its parents.
+    parents: Vec<u32>,
+    /// Nodes that can be shared, by identity.
+    shared: HashMap<NodeKey, NodeId>,
+}
+
+/// Results of shared subtrees, keyed by node and by the address of the value they were applied to.
+type Memo = HashMap<(NodeId, usize), bool>;
+
+impl Nodes {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Nodes {
+            list: Vec::with_capacity(capacity),
+            parents: Vec::with_capacity(capacity),
+            shared: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Id of the next node pushed. Ids are `u32`s to keep nodes small, which no matcher comes
+    /// near to exhausting.
+    #[allow(clippy::cast_possible_truncation)]
+    fn next_id(&self) -> NodeId {
+        self.list.len() as NodeId
+    }
+
+    /// Id of the last node, the root of a compiled matcher.
+    pub(crate) fn root(&self) -> NodeId {
+        self.next_id() - 1
+    }
+
+    pub(crate) fn get(&self, id: NodeId) -> &Node {
+        &self.list[id as usize]
+    }
+
+    pub(crate) fn push(&mut self, node: Node) -> NodeId {
+        for &child in node.children() {
+            self.parents[child as usize] += 1;
+        }
+        let id = self.next_id();
+        if let Some(key) = node.key() {
+            self.shared.entry(key).or_insert(id);
+        }
+        self.list.push(node);
+        self.parents.push(0);
+        id
+    }
+
+    /// Appends a node, or returns the identical node already stored.
+    fn share(&mut self, node: Node) -> NodeId {
+        if let Some(id) = node.key().and_then(|key| self.shared.get(&key)) {
+            return *id;
+        }
+        self.push(node)
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        while self.list.len() > len {
+            let node = self.list.pop().unwrap();
+            self.parents.pop();
+            if let Some(key) = node.key() {
+                if self.shared.get(&key) == Some(&self.next_id()) {
+                    self.shared.remove(&key);
+                }
+            }
+            for &child in node.children() {
+                self.parents[child as usize] -= 1;
+            }
+        }
+    }
+
+    /// Keeps only the nodes reachable from `root`, which becomes the last one. Merging clauses
+    /// leaves behind the nodes compiled for them separately.
+    fn prune(&mut self, root: NodeId) {
+        let mut reachable = vec![false; self.list.len()];
+        reachable[root as usize] = true;
+        for id in (0..=root as usize).rev() {
+            if reachable[id] {
+                for &child in self.list[id].children() {
+                    reachable[child as usize] = true;
+                }
+            }
+        }
+        if root as usize + 1 == self.list.len() && reachable.iter().all(|&r| r) {
+            return;
+        }
+        let mut pruned = Nodes::with_capacity(root as usize + 1);
+        let mut ids = vec![0; root as usize + 1];
+        for (id, node) in self.list.drain(..=root as usize).enumerate() {
+            if reachable[id] {
+                ids[id] = pruned.push(node.renumbered(&ids));
+            }
+        }
+        *self = pruned;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.list.clear();
+        self.parents.clear();
+        self.shared.clear();
+    }
+
+    pub(crate) fn eval(&self, id: NodeId, other: &Value, options: &MatchOptions) -> bool {
+        self.eval_memo(id, other, options, &mut Memo::new())
+    }
+
+    /// Evaluates several roots against the same value, sharing the results of common subtrees.
+    pub(crate) fn eval_all<'a, I: IntoIterator<Item = &'a NodeId>>(
+        &self,
+        roots: I,
+        other: &Value,
+        options: &MatchOptions,
+    ) -> Vec<bool> {
+        let mut memo = Memo::new();
+        roots
+            .into_iter()
+            .map(|&id| self.eval_memo(id, other, options, &mut memo))
+            .collect()
+    }
+
+    fn eval_memo(
+        &self,
+        id: NodeId,
+        other: &Value,
+        options: &MatchOptions,
+        memo: &mut Memo,
+    ) -> bool {
+        let node = &self.list[id as usize];
+        let shared = self.parents[id as usize] > 1 && !is_literal(node);
+        let key = (id, other as *const Value as usize);
+        if shared {
+            if let Some(&result) = memo.get(&key) {
+                return result;
+            }
+        }
+        let result = match node {
+            Node::And(children) => children
+                .iter()
+                .all(|&c| self.eval_memo(c, other, options, memo)),
+            Node::Or(children) => children
+                .iter()
+                .any(|&c| self.eval_memo(c, other, options, memo)),
+            Node::Not(child) => !self.eval_memo(*child, other, options, memo),
+            Node::Field(key, child) if options.profile() == Compatibility::Native => {
+                self.eval_memo(*child, path::lookup(other, key), options, memo)
+            }
+            Node::Field(key, child) => {
+                let candidates = compat::candidates(other, key, options);
+                self.eval_candidates(*child, &candidates, options, memo)
+            }
+            Node::Literal(value) => values_equal(value, other, options),
+            Node::Interpreted(matcher) => matcher.evaluate(other, options),
+            Node::Range(interval) => interval.contains(other, options),
+        };
+        if shared {
+            memo.insert(key, result);
+        }
+        result
+    }
+
+    /// Evaluates a field condition on the candidates found by [`compat::candidates`].
+    fn eval_candidates(
+        &self,
+        id: NodeId,
+        candidates: &[Candidate<'_>],
+        options: &MatchOptions,
+        memo: &mut Memo,
+    ) -> bool {
+        match &self.list[id as usize] {
+            Node::And(children) => children
+                .iter()
+                .all(|&c| self.eval_candidates(c, candidates, options, memo)),
+            Node::Or(children) => children
+                .iter()
+                .any(|&c| self.eval_candidates(c, candidates, options, memo)),
+            Node::Not(child) => !self.eval_candidates(*child, candidates, options, memo),
+            Node::Interpreted(matcher) => compat::matches_candidates(matcher, candidates, options),
+            Node::Range(interval) => interval.contains_any(candidates, options),
+            Node::Field(..) | Node::Literal(_) => candidates
+                .iter()
+                .any(|candidate| self.eval_memo(id, candidate.unwrap_or(&NULL), options, memo)),
+        }
+    }
+}
+
+fn is_literal(node: &Node) -> bool {
+    matches!(node, Node::Literal(_))
+}
+
+/// Shared storage for the field names and literals of compiled matchers.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+    values: HashMap<String, Arc<Value>>,
+}
+
+impl Interner {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.strings.clear();
+        self.values.clear();
+    }
+
+    fn string(&mut self, s: &str) -> Arc<str> {
+        if let Some(s) = self.strings.get(s) {
+            return s.clone();
+        }
+        let s: Arc<str> = Arc::from(s);
+        self.strings.insert(s.clone());
+        s
+    }
+
+    fn value(&mut self, value: Value) -> Arc<Value> {
+        let key = value.to_string();
+        self.values
+            .entry(key)
+            .or_insert_with(|| Arc::new(value))
+            .clone()
+    }
+}
+
+/// A matcher flattened into a list of nodes, children before their parents; the last node is the
+/// root.
+#[derive(Debug, Clone)]
+pub struct CompiledMatcher {
+    nodes: Nodes,
+}
+
+impl ObjMatcher {
+    /// Compiles this matcher, failing if one of its operators has an invalid operand.
+    pub fn compile(&self) -> Result<CompiledMatcher, serde_json::Error> {
+        self.compile_with_options(&ParseOptions::default())
+    }
+
+    /// Compiles this matcher, recognizing user-defined operators from the options' registry.
+    pub fn compile_with_options(
+        &self,
+        options: &ParseOptions,
+    ) -> Result<CompiledMatcher, serde_json::Error> {
+        self.compile_with_interner(options, &mut Interner::new())
+    }
+
+    /// Compiles this matcher, sharing field names and literals with other matchers compiled with
+    /// the same interner.
+    pub fn compile_with_interner(
+        &self,
+        options: &ParseOptions,
+        interner: &mut Interner,
+    ) -> Result<CompiledMatcher, serde_json::Error> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "compile",
+            nodes = tracing::field::Empty,
+            ok = tracing::field::Empty
+        )
+        .entered();
+        let mut nodes = Nodes::default();
+        let result = compile_into(&mut nodes, interner, options, self);
+        #[cfg(feature = "tracing")]
+        {
+            span.record("nodes", nodes.len());
+            span.record("ok", result.is_ok());
+        }
+        nodes.prune(result?);
+        Ok(CompiledMatcher { nodes })
+    }
+}
+
+/// Appends the nodes of `matcher` to `nodes`, returning the id of its root.
+pub(crate) fn compile_into(
+    nodes: &mut Nodes,
+    interner: &mut Interner,
+    options: &ParseOptions,
+    matcher: &ObjMatcher,
+) -> Result<NodeId, serde_json::Error> {
+    Compiler {
+        nodes,
+        interner,
+        options,
+        path: String::new(),
+    }
+    .matcher(matcher)
+}
+
+/// Like [`compile_into`], but keeps a matcher that fails to compile whole, to be evaluated by the
+/// interpreter exactly as [`ObjMatcher::matches`] would.
+pub(crate) fn compile_or_interpret(
+    nodes: &mut Nodes,
+    interner: &mut Interner,
+    options: &ParseOptions,
+    matcher: &ObjMatcher,
+) -> NodeId {
+    let len = nodes.len();
+    if let Ok(root) = compile_into(nodes, interner, options, matcher) {
+        root
+    } else {
+        nodes.truncate(len);
+        nodes.push(Node::Interpreted(matcher.clone()))
+    }
+}
+
+/// Evaluates every matcher against `value`, walking shared paths and comparing shared literals
+/// once for all of them rather than once per matcher.
+#[must_use]
+pub fn evaluate_all(matchers: &[ObjMatcher], value: &Value) -> Vec<bool> {
+    evaluate_all_with_options(matchers, value, &MatchOptions::default())
+}
+
+#[must_use]
+pub fn evaluate_all_with_options(
+    matchers: &[ObjMatcher],
+    value: &Value,
+    options: &MatchOptions,
+) -> Vec<bool> {
+    let mut nodes = Nodes::default();
+    let mut interner = Interner::new();
+    let parse_options = options.parse_options();
+    let roots: Vec<NodeId> = matchers
+        .iter()
+        .map(|matcher| compile_or_interpret(&mut nodes, &mut interner, &parse_options, matcher))
+        .collect();
+    nodes.eval_all(&roots, value, options)
+}
+
+struct Compiler<'a> {
+    nodes: &'a mut Nodes,
+    interner: &'a mut Interner,
+    options: &'a ParseOptions,
+    /// Dotted path of the values the node being compiled is applied to.
+    path: String,
+}
+
+impl Compiler<'_> {
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.share(node)
+    }
+
+    fn planner(&self) -> Planner<'_> {
+        Planner {
+            nodes: self.nodes,
+            options: self.options,
+        }
+    }
+
+    /// Orders the clauses of a conjunction or disjunction of the values at the current path.
+    fn ordered(&self, mut children: Vec<NodeId>, conjunction: bool) -> Vec<NodeId> {
+        self.planner().order(&mut children, &self.path, conjunction);
+        children
+    }
+
+    /// Runs `compile` with `key` appended to the current path.
+    fn at<T>(&mut self, key: &str, compile: impl FnOnce(&mut Self) -> T) -> T {
+        let len = self.path.len();
+        if len > 0 {
+            self.path.push('.');
+        }
+        self.path.push_str(key);
+        let result = compile(self);
+        self.path.truncate(len);
+        result
+    }
+
+    fn literal(&mut self, value: &Value) -> NodeId {
+        let value = self.interner.value(value.clone());
+        self.push(Node::Literal(value))
+    }
+
+    /// Compiles the clauses of `$and`, `$or`, `$in` or `$nin`, cheapest first.
+    fn all(
+        &mut self,
+        matchers: &[ObjMatcher],
+        conjunction: bool,
+    ) -> Result<Vec<NodeId>, serde_json::Error> {
+        let children = matchers
+            .iter()
+            .map(|m| self.matcher(m))
+            .collect::<Result<_, _>>()?;
+        Ok(self.ordered(children, conjunction))
+    }
+
+    fn matcher(&mut self, matcher: &ObjMatcher) -> Result<NodeId, serde_json::Error> {
+        let node = match matcher {
+            ObjMatcher::Eq(op) => return self.matcher(&op.val),
+            ObjMatcher::Ne(op) => Node::Not(self.matcher(&op.val)?),
+            ObjMatcher::Not(op) => Node::Not(self.matcher(&op.val)?),
+            // Long lists of literals are looked up in the operand's hash set instead.
+            ObjMatcher::In(op) if op.val.hashed() > HASHED_MEMBERS => {
+                Node::Interpreted(matcher.clone())
+            }
+            ObjMatcher::Nin(op) if op.val.hashed() > HASHED_MEMBERS => {
+                Node::Interpreted(matcher.clone())
+            }
+            ObjMatcher::In(op) => Node::Or(self.all(&op.val, false)?),
+            ObjMatcher::Nin(op) => {
+                let any = Node::Or(self.all(&op.val, false)?);
+                Node::Not(self.push(any))
+            }
+            ObjMatcher::And(op) => {
+                let children = self.all(&op.val, true)?;
+                return Ok(self.and(children));
+            }
+            ObjMatcher::Or(op) => Node::Or(self.all(&op.val, false)?),
+            ObjMatcher::Value(value) => return self.value(value),
+            other => Node::Interpreted(other.clone()),
+        };
+        Ok(self.push(node))
+    }
+
+    fn value(&mut self, value: &Value) -> Result<NodeId, serde_json::Error> {
+        if let Some(matcher) = parse_operator(value.clone(), self.options.operator_registry())? {
+            return self.matcher(&matcher);
+        }
+        let fields = match value {
+            Value::Object(o) if !context::is_reference(value) && !ejson::is_literal(value) => o,
+            _ => return Ok(self.literal(value)),
+        };
+        let mut children = Vec::with_capacity(fields.len());
+        for (key, value) in fields {
+            if key == "$comment" {
+                continue;
+            }
+            let child = match parse_operator(value.clone(), self.options.operator_registry())? {
+                Some(matcher) => self.at(key, |compiler| compiler.matcher(&matcher))?,
+                None => self.literal(value),
+            };
+            let key = self.interner.string(key);
+            children.push(self.push(Node::Field(key, child)));
+        }
+        Ok(self.and(children))
+    }
+
+    /// Conjunction of `children`, with the clauses on the same field grouped and the numeric
+    /// ranges among them merged into one interval.
+    fn and(&mut self, children: Vec<NodeId>) -> NodeId {
+        let mut clauses = Vec::with_capacity(children.len());
+        for &child in &children {
+            match &self.nodes.list[child as usize] {
+                Node::And(nested) => clauses.extend_from_slice(nested),
+                _ => clauses.push(child),
+            }
+        }
+        let mut fields: Vec<(Arc<str>, Vec<NodeId>)> = Vec::new();
+        let mut ranges = Vec::new();
+        let mut rest = Vec::new();
+        for &clause in &clauses {
+            match &self.nodes.list[clause as usize] {
+                Node::Field(key, child) => match fields.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, group)) => group.push(*child),
+                    None => fields.push((key.clone(), vec![*child])),
+                },
+                _ => match self.interval(clause) {
+                    Some(interval) => ranges.push((clause, interval)),
+                    None => rest.push(clause),
+                },
+            }
+        }
+        let coalesce = ranges.len() > 1 && ranges.iter().any(|(_, i)| i.is_bounded());
+        if !coalesce && fields.iter().all(|(_, group)| group.len() == 1) {
+            let children = self.ordered(children, true);
+            return self.push(Node::And(children));
+        }
+
+        if coalesce {
+            let mut merged = Interval::default();
+            for (_, interval) in &ranges {
+                merged.intersect(interval);
+            }
+            rest.push(self.push(Node::Range(merged)));
+        } else {
+            rest.extend(ranges.into_iter().map(|(clause, _)| clause));
+        }
+        for (key, group) in fields {
+            let child = if group.len() == 1 {
+                group[0]
+            } else {
+                self.at(&key, |compiler| compiler.and(group))
+            };
+            rest.push(self.push(Node::Field(key, child)));
+        }
+        if rest.len() == 1 {
+            return rest[0];
+        }
+        let rest = self.ordered(rest, true);
+        self.push(Node::And(rest))
+    }
+
+    /// The numbers a node accepts, if it is a numeric range or the exclusion of a number.
+    fn interval(&self, id: NodeId) -> Option<Interval> {
+        match &self.nodes.list[id as usize] {
+            Node::Interpreted(matcher) => Interval::of(matcher),
+            Node::Range(interval) => Some(interval.clone()),
+            Node::Not(child) => match &self.nodes.list[*child as usize] {
+                Node::Literal(value) => match &**value {
+                    Value::Number(n) => Some(Interval::excluding(n.clone())),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn operator_name(matcher: &ObjMatcher) -> Option<&str> {
+    Some(match matcher {
+        ObjMatcher::Gt(_) => "$gt",
+        ObjMatcher::Gte(_) => "$gte",
+        ObjMatcher::Lt(_) => "$lt",
+        ObjMatcher::Lte(_) => "$lte",
+        ObjMatcher::Len(_) => "$len",
+        ObjMatcher::Type(_) => "$type",
+        ObjMatcher::BitsAllSet(_) => "$bitsAllSet",
+        ObjMatcher::BitsAnySet(_) => "$bitsAnySet",
+        ObjMatcher::BitsAllClear(_) => "$bitsAllClear",
+        ObjMatcher::BitsAnyClear(_) => "$bitsAnyClear",
+        ObjMatcher::SampleRate(_) => "$sampleRate",
+        ObjMatcher::Bucket(_) => "$bucket",
+        ObjMatcher::Lookup(_) => "$lookup",
+        ObjMatcher::Expr(_) => "$expr",
+        ObjMatcher::CountMatching(_) => "$countMatching",
+        ObjMatcher::AnyKey(_) => "$anyKey",
+        ObjMatcher::AllKeys(_) => "$allKeys",
+        ObjMatcher::HasKeys(_) => "$hasKeys",
+        ObjMatcher::Exact(_) => "$exact",
+        ObjMatcher::Descend(_) => "$descend",
+        ObjMatcher::SetEquals(_) => "$setEquals",
+        ObjMatcher::SetIsSubset(_) => "$setIsSubset",
+        ObjMatcher::SetIntersects(_) => "$setIntersects",
+        ObjMatcher::Bytes(_) => "$bytes",
+        ObjMatcher::Format(_) => "$format",
+        ObjMatcher::Truthy(_) => "$truthy",
+        ObjMatcher::Falsy(_) => "$falsy",
+        ObjMatcher::Default(_) => "$default",
+        #[cfg(feature = "rhai")]
+        ObjMatcher::Where(_) => "$where",
+        #[cfg(feature = "geo")]
+        ObjMatcher::GeoWithin(_) => "$geoWithin",
+        #[cfg(feature = "geo")]
+        ObjMatcher::Near(_) => "$near",
+        ObjMatcher::Custom(call) => call.name(),
+        _ => return None,
+    })
+}
+
+impl CompiledMatcher {
+    #[must_use]
+    pub fn matches(&self, other: &Value) -> bool {
+        self.matches_with_options(other, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_with_options(&self, other: &Value, options: &MatchOptions) -> bool {
+        #[cfg(feature = "tracing")]
+        let span = tracing::trace_span!(
+            "evaluate",
+            nodes = self.nodes.len(),
+            outcome = tracing::field::Empty
+        )
+        .entered();
+        let root = self.nodes.root();
+        let outcome = match options.evaluation_observer() {
+            Some(observer) => {
+                observe::evaluate_compiled(&self.nodes, root, other, options, observer)
+            }
+            None => self.nodes.eval(root, other, options),
+        };
+        #[cfg(feature = "tracing")]
+        span.record("outcome", outcome);
+        outcome
+    }
+
+    /// Describes how this matcher is evaluated, without evaluating it: the order of its clauses,
+    /// their estimated costs, and which were hashed or coalesced when compiling.
+    #[must_use]
+    pub fn explain_plan(&self) -> Plan {
+        self.explain_plan_with_options(&ParseOptions::default())
+    }
+
+    /// Describes how this matcher is evaluated, estimating costs with the hints of `options` and,
+    /// if it has [`Statistics`](crate::Statistics), how often each clause holds.
+    #[must_use]
+    pub fn explain_plan_with_options(&self, options: &ParseOptions) -> Plan {
+        let planner = Planner {
+            nodes: &self.nodes,
+            options,
+        };
+        planner.explain(self.nodes.root(), "")
+    }
+
+    /// Encodes this matcher in a compact binary form readable by [`CompiledMatcher::from_bytes`].
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        write_len(&mut out, self.nodes.len());
+        for node in &self.nodes.list {
+            match node {
+                Node::And(children) => {
+                    out.push(0);
+                    write_ids(&mut out, children);
+                }
+                Node::Or(children) => {
+                    out.push(1);
+                    write_ids(&mut out, children);
+                }
+                Node::Not(child) => {
+                    out.push(2);
+                    write_varint(&mut out, u64::from(*child));
+                }
+                Node::Field(key, child) => {
+                    out.push(3);
+                    write_str(&mut out, key);
+                    write_varint(&mut out, u64::from(*child));
+                }
+                Node::Literal(value) => {
+                    out.push(4);
+                    write_value(&mut out, value);
+                }
+                Node::Interpreted(matcher) => {
+                    out.push(5);
+                    let value = serde_json::to_value(matcher).unwrap_or(Value::Null);
+                    write_value(&mut out, &value);
+                }
+                Node::Range(interval) => {
+                    out.push(6);
+                    write_bound(&mut out, interval.lower.as_ref());
+                    write_bound(&mut out, interval.upper.as_ref());
+                    write_len(&mut out, interval.excluded.len());
+                    for n in &interval.excluded {
+                        write_value(&mut out, &Value::Number(n.clone()));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Decodes a matcher produced by [`CompiledMatcher::to_bytes`].
+    ///
+    /// The encoding is checked structurally, but operands are trusted to have been validated when
+    /// the matcher was compiled.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CompiledMatcher, serde_json::Error> {
+        Self::from_bytes_with_interner(bytes, &mut Interner::new())
+    }
+
+    /// Decodes a matcher, sharing field names and literals through the given interner.
+    pub fn from_bytes_with_interner(
+        bytes: &[u8],
+        interner: &mut Interner,
+    ) -> Result<CompiledMatcher, serde_json::Error> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a compiled matcher"));
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+        let count = reader.len()?;
+        if count == 0 {
+            return Err(invalid("no nodes"));
+        }
+        let mut nodes = Nodes::default();
+        for index in 0..count {
+            let node = match reader.byte()? {
+                0 => Node::And(reader.ids(index)?),
+                1 => Node::Or(reader.ids(index)?),
+                2 => Node::Not(reader.id(index)?),
+                3 => {
+                    let key = interner.string(&reader.string()?);
+                    Node::Field(key, reader.id(index)?)
+                }
+                4 => Node::Literal(interner.value(reader.value(0)?)),
+                5 => Node::Interpreted(from_json(reader.value(0)?)?),
+                6 => {
+                    let lower = reader.bound()?;
+                    let upper = reader.bound()?;
+                    let excluded = (0..reader.len()?)
+                        .map(|_| reader.number())
+                        .collect::<Result<_, _>>()?;
+                    Node::Range(Interval {
+                        lower,
+                        upper,
+                        excluded,
+                    })
+                }
+                tag => return Err(invalid(&format!("unknown node tag {tag}"))),
+            };
+            nodes.push(node);
+        }
+        if reader.pos != bytes.len() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(CompiledMatcher { nodes })
+    }
+}
+
+fn invalid(message: &str) -> serde_json::Error {
+    serde::de::Error::custom(format!("invalid compiled matcher: {message}"))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    write_varint(out, len as u64);
+}
+
+fn write_ids(out: &mut Vec<u8>, ids: &[NodeId]) {
+    write_len(out, ids.len());
+    for id in ids {
+        write_varint(out, u64::from(*id));
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => out.push(0),
+        Value::Bool(false) => out.push(1),
+        Value::Bool(true) => out.push(2),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                out.push(3);
+                write_varint(out, n);
+            } else if let Some(n) = n.as_i64() {
+                out.push(4);
+                write_varint(out, zigzag(n));
+            } else if cfg!(feature = "arbitrary-precision") {
+                // The text, which holds integers beyond 64 bits and tells `1.0` from `1.00`.
+                out.push(9);
+                write_str(out, &n.to_string());
+            } else {
+                out.push(5);
+                out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_bits().to_le_bytes());
+            }
+        }
+        Value::String(s) => {
+            out.push(6);
+            write_str(out, s);
+        }
+        Value::Array(a) => {
+            out.push(7);
+            write_len(out, a.len());
+            for v in a {
+                write_value(out, v);
+            }
+        }
+        Value::Object(o) => {
+            out.push(8);
+            write_len(out, o.len());
+            for (k, v) in o {
+                write_str(out, k);
+                write_value(out, v);
+            }
+        }
+    }
+}
+
+/// Maps integers of small magnitude to small unsigned ones: `0, -1, 1, -2` to `0, 1, 2, 3`.
+#[allow(clippy::cast_sign_loss)]
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+/// Writes `0` for no bound, else `1` for an exclusive and `2` for an inclusive one, then its value.
+fn write_bound(out: &mut Vec<u8>, bound: Option<&Bound>) {
+    match bound {
+        Some(bound) => {
+            out.push(if bound.inclusive { 2 } else { 1 });
+            write_value(out, &Value::Number(bound.value.clone()));
+        }
+        None => out.push(0),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], serde_json::Error> {
+        if self.bytes.len() - self.pos < n {
+            return Err(invalid("unexpected end of input"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, serde_json::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, serde_json::Error> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(invalid("varint too long"))
+    }
+
+    /// Reads a length, rejecting any that could not possibly fit in the remaining input.
+    fn len(&mut self) -> Result<usize, serde_json::Error> {
+        match usize::try_from(self.varint()?) {
+            Ok(len) if len <= self.bytes.len() - self.pos => Ok(len),
+            _ => Err(invalid("length out of bounds")),
+        }
+    }
+
+    /// Reads a reference to a node, which must precede the node at `index`.
+    fn id(&mut self, index: usize) -> Result<NodeId, serde_json::Error> {
+        let id = self.varint()?;
+        match NodeId::try_from(id) {
+            Ok(valid) if (valid as usize) < index => Ok(valid),
+            _ => Err(invalid(&format!("node {index} refers to node {id}"))),
+        }
+    }
+
+    fn ids(&mut self, index: usize) -> Result<Vec<NodeId>, serde_json::Error> {
+        let len = self.len()?;
+        (0..len).map(|_| self.id(index)).collect()
+    }
+
+    fn string(&mut self) -> Result<String, serde_json::Error> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string is not valid UTF-8"))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, serde_json::Error> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("literal nested too deeply"));
+        }
+        Ok(match self.byte()? {
+            0 => Value::Null,
+            1 => Value::Bool(false),
+            2 => Value::Bool(true),
+            3 => Value::from(self.varint()?),
+            4 => Value::from(unzigzag(self.varint()?)),
+            5 => {
+                let bits = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                match Number::from_f64(f64::from_bits(bits)) {
+                    Some(n) => Value::Number(n),
+                    None => return Err(invalid("non-finite number")),
+                }
+            }
+            6 => Value::String(self.string()?),
+            7 => {
+                let len = self.len()?;
+                let mut array = Vec::new();
+                for _ in 0..len {
+                    array.push(self.value(depth + 1)?);
+                }
+                Value::Array(array)
+            }
+            8 => {
+                let len = self.len()?;
+                let mut object = Map::new();
+                for _ in 0..len {
+                    let key = self.string()?;
+                    object.insert(key, self.value(depth + 1)?);
+                }
+                Value::Object(object)
+            }
+            9 => match serde_json::from_str(&self.string()?) {
+                Ok(n) => Value::Number(n),
+                Err(_) => return Err(invalid("malformed number")),
+            },
+            tag => return Err(invalid(&format!("unknown value tag {tag}"))),
+        })
+    }
+
+    fn number(&mut self) -> Result<Number, serde_json::Error> {
+        match self.value(0)? {
+            Value::Number(n) => Ok(n),
+            _ => Err(invalid("expected a number")),
+        }
+    }
+
+    fn bound(&mut self) -> Result<Option<Bound>, serde_json::Error> {
+        let inclusive = match self.byte()? {
+            0 => return Ok(None),
+            1 => false,
+            2 => true,
+            tag => return Err(invalid(&format!("unknown bound tag {tag}"))),
+        };
+        let value = self.number()?;
+        Ok(Some(Bound { value, inclusive }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, OperatorRegistry};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    pub fn test_compiled() {
+        let query = r#"{
+            "a": {"$in": [1, "two", {"x": [1.5, -3]}]},
+            "b": {"$nin": [null, false]},
+            "c": {"$not": {"$eq": "c"}},
+            "d": {"$type": ["number"]},
+            "$comment": "ignored"
+        }"#;
+        let matcher = from_str(query).unwrap();
+        let compiled = matcher.compile().unwrap();
+        let decoded = CompiledMatcher::from_bytes(&compiled.to_bytes()).unwrap();
+
+        let docs = [
+            json!({"a": 1, "b": true, "c": "d", "d": 4}),
+            json!({"a": {"x": [1.5, -3]}, "b": 0, "c": "x", "d": 4.5}),
+            json!({"a": {"x": [1.5, -3], "y": 1}, "b": 0, "c": "x", "d": 4}),
+            json!({"a": "two", "b": false, "c": "x", "d": 4}),
+            json!({"a": "two", "b": 1, "c": "c", "d": 4}),
+            json!({"a": "two", "b": 1, "c": "x", "d": "4"}),
+            json!({"b": 1, "c": "x", "d": 4}),
+        ];
+        for doc in &docs {
+            assert_eq!(compiled.matches(doc), matcher.matches(doc), "{doc}");
+            assert_eq!(decoded.matches(doc), matcher.matches(doc), "{doc}");
+        }
+        assert!(decoded.matches(&docs[0]));
+        assert!(!decoded.matches(&docs[4]));
+    }
+
+    #[test]
+    pub fn test_interning() {
+        let mut interner = Interner::new();
+        let options = ParseOptions::default();
+        let a = from_str(r#"{"$or": [{"tenant": "acme"}, {"tenant": "acme"}]}"#)
+            .unwrap()
+            .compile_with_interner(&options, &mut interner)
+            .unwrap();
+        let b = from_str(r#"{"tenant": "acme", "region": "eu"}"#)
+            .unwrap()
+            .compile_with_interner(&options, &mut interner)
+            .unwrap();
+        // The repeated `{"tenant": "acme"}` subtree is stored once.
+        assert_eq!(a.nodes.len(), 4);
+        let fields = |m: &CompiledMatcher| -> Vec<(Arc<str>, Arc<Value>)> {
+            m.nodes
+                .list
+                .iter()
+                .filter_map(|node| match node {
+                    Node::Field(key, child) => match &m.nodes.list[*child as usize] {
+                        Node::Literal(value) => Some((key.clone(), value.clone())),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect()
+        };
+        let (a, b) = (fields(&a), fields(&b));
+        let tenant = b.iter().find(|(key, _)| &**key == "tenant").unwrap();
+        assert!(Arc::ptr_eq(&a[0].0, &tenant.0));
+        assert!(Arc::ptr_eq(&a[0].1, &tenant.1));
+    }
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counted;
+
+    impl crate::CustomOperator for Counted {
+        fn name(&self) -> &'static str {
+            "$counted"
+        }
+
+        fn parse(&self, _operand: &Value) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn evaluate(&self, operand: &Value, value: &Value) -> bool {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            operand == value
+        }
+    }
+
+    #[test]
+    pub fn test_shared_subtree_evaluated_once() {
+        let mut registry = OperatorRegistry::new();
+        registry.register(Counted);
+        let registry = Arc::new(registry);
+        let query = r#"{"$or": [
+            {"$and": [{"a": {"$counted": 1}}, {"b": 1}]},
+            {"$and": [{"a": {"$counted": 1}}, {"c": 1}]}
+        ]}"#;
+        let parse_options = ParseOptions::new().registry(registry.clone());
+        let compiled = crate::from_str_with_options(query, &parse_options)
+            .unwrap()
+            .compile_with_options(&parse_options)
+            .unwrap();
+        let options = MatchOptions::new().registry(registry);
+
+        assert!(compiled.matches_with_options(&json!({"a": 1, "c": 1}), &options));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    pub fn test_evaluate_all() {
+        let matchers: Vec<ObjMatcher> = [
+            r#"{"type": "order", "region": {"$in": ["eu", "us"]}}"#,
+            r#"{"type": "order", "total": {"$ne": 0}}"#,
+            r#"{"type": {"$nin": ["order"]}}"#,
+            r#"{"region": "eu"}"#,
+        ]
+        .iter()
+        .map(|query| from_str(query).unwrap())
+        .collect();
+        let doc = json!({"type": "order", "region": "us", "total": 0});
+        assert_eq!(
+            evaluate_all(&matchers, &doc),
+            vec![true, false, false, false]
+        );
+        for doc in &[doc, json!({"type": "refund", "region": "eu"})] {
+            let expected: Vec<bool> = matchers.iter().map(|m| m.matches(doc)).collect();
+            assert_eq!(evaluate_all(&matchers, doc), expected);
+        }
+    }
+
+    #[test]
+    pub fn test_clause_order() {
+        let matcher = from_str(r#"{"$or": [{"$type": ["number"]}, 1]}"#).unwrap();
+        let first = |compiled: &CompiledMatcher| match compiled.nodes.list.last() {
+            Some(Node::Or(children)) => compiled.nodes.list[children[0] as usize].clone(),
+            _ => unreachable!(),
+        };
+        assert!(is_literal(&first(&matcher.compile().unwrap())));
+
+        let options = ParseOptions::new().operator_cost("$type", 0);
+        let compiled = matcher.compile_with_options(&options).unwrap();
+        assert!(!is_literal(&first(&compiled)));
+        assert!(compiled.matches(&json!(1)));
+        assert!(compiled.matches(&json!(2)));
+        assert!(!compiled.matches(&json!("1")));
+    }
+
+    #[test]
+    pub fn test_intervals() {
+        let ranges = |compiled: &CompiledMatcher| {
+            compiled
+                .nodes
+                .list
+                .iter()
+                .filter(|node| matches!(node, Node::Range(_)))
+                .count()
+        };
+        let queries = [
+            r#"{"n": {"$gte": 5, "$lt": 100, "$ne": 7}}"#,
+            r#"{"$and": [{"n": {"$gt": 5}}, {"n": {"$lte": 10, "$ne": 10}}, {"n": {"$gt": 2}}]}"#,
+            r#"{"n": {"$gt": 1.5, "$lt": 3}, "m": {"$ne": 1}}"#,
+        ];
+        let docs = [
+            json!({"n": 5, "m": 2}),
+            json!({"n": 7}),
+            json!({"n": 7.0}),
+            json!({"n": 10}),
+            json!({"n": 2, "m": 1}),
+            json!({"n": 2.5, "m": 1}),
+            json!({"n": 99.5}),
+            json!({"n": 100}),
+            json!({"n": "8"}),
+            json!({"m": 2}),
+        ];
+        for query in &queries {
+            let matcher = from_str(query).unwrap();
+            let compiled = matcher.compile().unwrap();
+            assert_eq!(ranges(&compiled), 1, "{query}");
+            let decoded = CompiledMatcher::from_bytes(&compiled.to_bytes()).unwrap();
+            for doc in &docs {
+                assert_eq!(compiled.matches(doc), matcher.matches(doc), "{query} {doc}");
+                assert_eq!(decoded.matches(doc), matcher.matches(doc), "{query} {doc}");
+            }
+        }
+
+        for query in &[
+            r#"{"n": {"$gt": 5, "$lt": 3}}"#,
+            r#"{"n": {"$gte": 5, "$lt": 5}}"#,
+            r#"{"$and": [{"n": {"$gt": 5}}, {"n": {"$lte": 5}}]}"#,
+        ] {
+            let compiled = from_str(query).unwrap().compile().unwrap();
+            assert_eq!(ranges(&compiled), 1, "{query}");
+            assert!(!compiled.matches(&json!({"n": 5})), "{}", query);
+            assert!(!compiled.matches(&json!({"n": 5.5})), "{}", query);
+        }
+        let point = from_str(r#"{"n": {"$gte": 5, "$lte": 5}}"#)
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert!(point.matches(&json!({"n": 5.0})));
+        // A lone exclusion still matches values other than numbers.
+        let ne = from_str(r#"{"n": {"$ne": 7}}"#).unwrap().compile().unwrap();
+        assert_eq!(ranges(&ne), 0);
+        assert!(ne.matches(&json!({"n": "7"})));
+    }
+
+    #[test]
+    pub fn test_compile_invalid_operand() {
+        let matcher = ObjMatcher::Value(json!({"a": {"$type": "nope"}}));
+        assert!(matcher.compile().is_err());
+    }
+
+    #[test]
+    pub fn test_from_bytes_rejects_malformed() {
+        let bytes = from_str(r#"{"a": {"$ne": [1, 2]}}"#)
+            .unwrap()
+            .compile()
+            .unwrap()
+            .to_bytes();
+        for len in 0..bytes.len() {
+            assert!(CompiledMatcher::from_bytes(&bytes[..len]).is_err());
+        }
+        assert!(CompiledMatcher::from_bytes(b"SJMC\x02\x01\x04\x00").is_err());
+        // A `$not` node referring to itself.
+        assert!(CompiledMatcher::from_bytes(b"SJMC\x01\x01\x02\x00").is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(CompiledMatcher::from_bytes(&trailing).is_err());
+    }
+}
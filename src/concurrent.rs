@@ -0,0 +1,243 @@
+//! Evaluating a shared [`CompiledMatcher`] over asynchronous sources with bounded concurrency.
+//!
+//! Needs Rust 1.64 for `std::future::poll_fn`, above the crate's `rust-version`.
+
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::task::{JoinError, JoinSet};
+
+use crate::CompiledMatcher;
+
+/// Filters values received from a channel or stream with a shared [`CompiledMatcher`], e.g.
+/// `ConcurrentMatcher::new(matcher).concurrency(8).filter_channel(rx)`.
+///
+/// Values are evaluated on tokio's blocking thread pool, at most `concurrency` at a time, and the
+/// matching ones are sent to the returned channel as soon as they are evaluated: in the order
+/// they were received, or, when [`ordered`](ConcurrentMatcher::ordered) is disabled, in any
+/// order. Reading from the source stops once the returned receiver is dropped.
+#[derive(Debug, Clone)]
+pub struct ConcurrentMatcher {
+    matcher: Arc<CompiledMatcher>,
+    concurrency: usize,
+    ordered: bool,
+}
+
+impl ConcurrentMatcher {
+    /// Creates a filter evaluating one value at a time, in order.
+    #[must_use]
+    pub fn new(matcher: Arc<CompiledMatcher>) -> Self {
+        ConcurrentMatcher {
+            matcher,
+            concurrency: 1,
+            ordered: true,
+        }
+    }
+
+    /// Sets how many values are evaluated at the same time, at least one.
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets whether matches are yielded in the order the values were received.
+    #[must_use]
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Spawns a task filtering the values received on `input`. Must be called from within a tokio
+    /// runtime.
+    pub fn filter_channel(&self, input: mpsc::Receiver<Value>) -> mpsc::Receiver<Value> {
+        self.filter_stream(Channel(input))
+    }
+
+    /// Spawns a task filtering the values of `input`. Must be called from within a tokio runtime.
+    pub fn filter_stream<S>(&self, input: S) -> mpsc::Receiver<Value>
+    where
+        S: Stream<Item = Value> + Send + Unpin + 'static,
+    {
+        let (output, receiver) = mpsc::channel(self.concurrency);
+        let filter = self.clone();
+        tokio::spawn(async move {
+            if filter.ordered {
+                filter.run_ordered(input, output).await;
+            } else {
+                filter.run_unordered(input, output).await;
+            }
+        });
+        receiver
+    }
+
+    fn evaluate(&self, value: Value) -> impl FnOnce() -> Option<Value> + Send + 'static {
+        let matcher = self.matcher.clone();
+        move || {
+            if matcher.matches(&value) {
+                Some(value)
+            } else {
+                None
+            }
+        }
+    }
+
+    async fn run_ordered<S>(self, mut input: S, output: mpsc::Sender<Value>)
+    where
+        S: Stream<Item = Value> + Unpin,
+    {
+        let mut pending = VecDeque::with_capacity(self.concurrency);
+        let mut done = false;
+        loop {
+            let room = !done && pending.len() < self.concurrency;
+            let result = tokio::select! {
+                value = next(&mut input), if room => {
+                    match value {
+                        Some(value) => {
+                            pending.push_back(tokio::task::spawn_blocking(self.evaluate(value)));
+                        }
+                        None => done = true,
+                    }
+                    continue;
+                }
+                result = async { pending.front_mut().unwrap().await }, if !pending.is_empty() => {
+                    result
+                }
+                else => return,
+            };
+            pending.pop_front();
+            if let Some(value) = joined(result) {
+                if output.send(value).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn run_unordered<S>(self, mut input: S, output: mpsc::Sender<Value>)
+    where
+        S: Stream<Item = Value> + Unpin,
+    {
+        let mut pending = JoinSet::new();
+        let mut done = false;
+        loop {
+            let room = !done && pending.len() < self.concurrency;
+            let result = tokio::select! {
+                value = next(&mut input), if room => {
+                    match value {
+                        Some(value) => {
+                            pending.spawn_blocking(self.evaluate(value));
+                        }
+                        None => done = true,
+                    }
+                    continue;
+                }
+                Some(result) = pending.join_next(), if !pending.is_empty() => result,
+                else => return,
+            };
+            if let Some(value) = joined(result) {
+                if output.send(value).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+struct Channel(mpsc::Receiver<Value>);
+
+impl Stream for Channel {
+    type Item = Value;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Value>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+async fn next<S: Stream<Item = Value> + Unpin>(input: &mut S) -> Option<Value> {
+    poll_fn(|cx| Pin::new(&mut *input).poll_next(cx)).await
+}
+
+/// Re-raises a panic of the matcher evaluation in the filtering task.
+fn joined(result: Result<Option<Value>, JoinError>) -> Option<Value> {
+    match result {
+        Ok(value) => value,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    struct Values(std::vec::IntoIter<Value>);
+
+    impl Stream for Values {
+        type Item = Value;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Value>> {
+            Poll::Ready(self.0.next())
+        }
+    }
+
+    async fn collect(mut receiver: mpsc::Receiver<Value>) -> Vec<Value> {
+        let mut values = Vec::new();
+        while let Some(value) = receiver.recv().await {
+            values.push(value);
+        }
+        values
+    }
+
+    #[test]
+    pub fn test_concurrent_matcher() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let matcher = crate::from_str(r#"{"n": {"$type": ["number"]}}"#)
+            .unwrap()
+            .compile()
+            .unwrap();
+        let filter = ConcurrentMatcher::new(Arc::new(matcher)).concurrency(4);
+        let values: Vec<Value> = (0..20)
+            .map(|n| {
+                if n % 3 == 0 {
+                    json!({"n": "x"})
+                } else {
+                    json!({"n": n})
+                }
+            })
+            .collect();
+        let expected: Vec<Value> = values
+            .iter()
+            .filter(|v| v["n"].is_number())
+            .cloned()
+            .collect();
+
+        runtime.block_on(async {
+            let (sender, receiver) = mpsc::channel(2);
+            let matches = filter.filter_channel(receiver);
+            let inputs = values.clone();
+            tokio::spawn(async move {
+                for value in inputs {
+                    sender.send(value).await.unwrap();
+                }
+            });
+            assert_eq!(collect(matches).await, expected);
+
+            let matches = filter
+                .clone()
+                .ordered(false)
+                .filter_stream(Values(values.clone().into_iter()));
+            let mut matches = collect(matches).await;
+            matches.sort_by_key(|v| v["n"].as_i64());
+            assert_eq!(matches, expected);
+        });
+    }
+}
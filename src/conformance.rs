@@ -0,0 +1,302 @@
+//! Running behavioral examples from spec files, against this crate or against another engine
+//! implementing the same query language.
+//!
+//! A spec file holds either a single spec or an array of them, each a query with documents and
+//! whether the query is expected to match each of them:
+//!
+//! ```json
+//! [
+//!     {
+//!         "name": "in matches any element",
+//!         "query": {"n": {"$in": [1, 2]}},
+//!         "documents": [{"n": 1}, {"n": 3}],
+//!         "expected": [true, false]
+//!     }
+//! ]
+//! ```
+//!
+//! JSON files are always supported; YAML files (`.yaml`, `.yml`) require the `yaml` feature.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::rules::{collect_files, parse_definitions};
+use crate::{
+    from_json_with_options, ExplainReport, LoadError, MatchOptions, ParseOptions, RuleError,
+};
+
+/// A query with the documents it is expected to match or not.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Spec {
+    pub name: String,
+    pub query: Value,
+    pub documents: Vec<Value>,
+    /// Whether the query is expected to match each of `documents`, in order.
+    pub expected: Vec<bool>,
+    /// File and line the spec was loaded from, when it was.
+    #[serde(skip)]
+    pub source: Option<(PathBuf, Option<usize>)>,
+}
+
+/// A collection of [`Spec`]s, usually loaded from spec files.
+#[derive(Debug, Clone, Default)]
+pub struct SpecSuite {
+    specs: Vec<Spec>,
+}
+
+impl SpecSuite {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every spec file under `path`, which may be a single file or a directory that is
+    /// searched recursively. All failures are reported together.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SpecSuite, LoadError> {
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        collect_files(path.as_ref(), &mut files, &mut errors);
+        files.sort();
+
+        let mut suite = SpecSuite::new();
+        for file in files {
+            match fs::read_to_string(&file) {
+                Ok(source) => suite.load_source(&file, &source, &mut errors),
+                Err(err) => errors.push(RuleError::new(&file, None, None, err.to_string())),
+            }
+        }
+        if errors.is_empty() {
+            Ok(suite)
+        } else {
+            Err(LoadError { errors })
+        }
+    }
+
+    fn load_source(&mut self, file: &Path, source: &str, errors: &mut Vec<RuleError>) {
+        let definitions = match parse_definitions(file, source) {
+            Ok(definitions) => definitions,
+            Err(err) => return errors.push(err),
+        };
+        for (line, definition) in definitions {
+            let mut spec: Spec = match serde_json::from_value(definition) {
+                Ok(spec) => spec,
+                Err(err) => {
+                    errors.push(RuleError::new(file, line, None, err.to_string()));
+                    continue;
+                }
+            };
+            if spec.documents.len() != spec.expected.len() {
+                let message = format!(
+                    "spec `{}` has {} documents but {} expected outcomes",
+                    spec.name,
+                    spec.documents.len(),
+                    spec.expected.len()
+                );
+                errors.push(RuleError::new(file, line, None, message));
+                continue;
+            }
+            spec.source = Some((file.to_path_buf(), line));
+            self.specs.push(spec);
+        }
+    }
+
+    pub fn push(&mut self, spec: Spec) {
+        self.specs.push(spec);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Spec> {
+        self.specs.iter()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.specs.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.specs.is_empty()
+    }
+
+    /// Runs every spec against this crate.
+    #[must_use]
+    pub fn run(&self) -> SpecReport {
+        self.run_with_options(&ParseOptions::default(), &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn run_with_options(
+        &self,
+        parse_options: &ParseOptions,
+        options: &MatchOptions,
+    ) -> SpecReport {
+        self.run_engine(parse_options, options, |query, document| {
+            from_json_with_options(query.clone(), parse_options)
+                .map(|matcher| matcher.matches_with_options(document, options))
+                .map_err(|err| err.to_string())
+        })
+    }
+
+    /// Runs every spec against another engine, called with each query and document and returning
+    /// whether they match, or why the query was rejected.
+    ///
+    /// Failures carry this crate's explanation of the outcome, to tell apart a difference between
+    /// the engines from a wrong expectation.
+    pub fn run_with<F>(&self, engine: F) -> SpecReport
+    where
+        F: FnMut(&Value, &Value) -> Result<bool, String>,
+    {
+        self.run_engine(&ParseOptions::default(), &MatchOptions::default(), engine)
+    }
+
+    fn run_engine<F>(
+        &self,
+        parse_options: &ParseOptions,
+        options: &MatchOptions,
+        mut engine: F,
+    ) -> SpecReport
+    where
+        F: FnMut(&Value, &Value) -> Result<bool, String>,
+    {
+        let mut report = SpecReport::default();
+        for spec in &self.specs {
+            let matcher = from_json_with_options(spec.query.clone(), parse_options).ok();
+            for (index, (document, expected)) in
+                spec.documents.iter().zip(&spec.expected).enumerate()
+            {
+                let actual = engine(&spec.query, document);
+                if actual.as_ref() == Ok(expected) {
+                    report.passed += 1;
+                    continue;
+                }
+                report.failures.push(SpecFailure {
+                    name: spec.name.clone(),
+                    source: spec.source.clone(),
+                    document: index,
+                    expected: *expected,
+                    actual,
+                    explain: matcher
+                        .as_ref()
+                        .map(|matcher| matcher.explain_with_options(document, options)),
+                });
+            }
+        }
+        report
+    }
+}
+
+/// Outcome of running a [`SpecSuite`], counting each document of each spec as one check.
+#[derive(Debug, Clone, Default)]
+pub struct SpecReport {
+    pub passed: usize,
+    pub failures: Vec<SpecFailure>,
+}
+
+impl SpecReport {
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for SpecReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for failure in &self.failures {
+            writeln!(f, "{failure}")?;
+        }
+        write!(f, "{} passed, {} failed", self.passed, self.failures.len())
+    }
+}
+
+/// A document for which the engine did not return the expected outcome.
+#[derive(Debug, Clone)]
+pub struct SpecFailure {
+    pub name: String,
+    pub source: Option<(PathBuf, Option<usize>)>,
+    /// Index of the document in the spec.
+    pub document: usize,
+    pub expected: bool,
+    /// Outcome returned by the engine, or why it rejected the query.
+    pub actual: Result<bool, String>,
+    /// This crate's evaluation of the query, or `None` if it does not parse.
+    pub explain: Option<ExplainReport>,
+}
+
+impl fmt::Display for SpecFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((path, line)) = &self.source {
+            write!(f, "{}", path.display())?;
+            if let Some(line) = line {
+                write!(f, ":{line}")?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "spec `{}`, document {}: ", self.name, self.document)?;
+        match &self.actual {
+            Ok(actual) => write!(f, "expected {}, got {}", self.expected, actual)?,
+            Err(err) => write!(f, "expected {}, got error: {}", self.expected, err)?,
+        }
+        if let Some(explain) = &self.explain {
+            let explain = serde_json::to_string_pretty(explain).map_err(|_| fmt::Error)?;
+            write!(f, "\n{explain}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_spec_suite() {
+        let dir =
+            std::env::temp_dir().join(format!("serde_json_matcher_specs_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("in.json"),
+            r#"[
+                {"name": "in", "query": {"n": {"$in": [1, 2]}}, "documents": [{"n": 1}, {"n": 3}], "expected": [true, false]},
+                {"name": "wrong", "query": {"n": {"$gt": 1}}, "documents": [{"n": 1}], "expected": [true]}
+            ]"#,
+        )
+        .unwrap();
+
+        let suite = SpecSuite::load(&dir).unwrap();
+        assert_eq!(suite.len(), 2);
+        let report = suite.run();
+        assert_eq!(report.passed, 2);
+        assert_eq!(report.failures.len(), 1);
+        let failure = &report.failures[0];
+        assert_eq!((failure.name.as_str(), failure.document), ("wrong", 0));
+        assert_eq!(failure.actual, Ok(false));
+        let explain = failure.explain.as_ref().unwrap();
+        assert_eq!(explain.children[0].path, vec!["n".to_string()]);
+        assert_eq!(explain.children[0].actual, Some(json!(1)));
+        assert!(report
+            .to_string()
+            .contains("in.json:3: spec `wrong`, document 0: expected true, got false"));
+
+        let report = suite.run_with(|_, document| Ok(document == &json!({"n": 1})));
+        assert_eq!(report.passed, 3);
+        let report = suite.run_with(|_, _| Err("unsupported".to_string()));
+        assert_eq!(report.failures.len(), 3);
+
+        fs::write(
+            dir.join("bad.json"),
+            r#"{"name": "short", "query": {}, "documents": [{}], "expected": []}"#,
+        )
+        .unwrap();
+        let err = SpecSuite::load(&dir).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("has 1 documents but 0 expected outcomes"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
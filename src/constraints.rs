@@ -0,0 +1,233 @@
+//! Equality constraints implied by a matcher, for building prefilters and routing documents to
+//! the rules that could match them.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{context, parse_operator, ObjMatcher};
+
+/// A condition satisfied by every document a matcher matches: the value at `path` is equal to one
+/// of `values`.
+///
+/// Values are compared as with the default [`MatchOptions`](crate::MatchOptions); constraints on
+/// strings do not hold when matching with normalization, lowercasing or collation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub path: Vec<String>,
+    pub values: Vec<Value>,
+}
+
+pub(crate) type Constraints = BTreeMap<Vec<String>, Vec<Value>>;
+
+impl ObjMatcher {
+    /// The conjunction of equality constraints implied by this matcher, ordered by path.
+    ///
+    /// The result is conservative: a document satisfying every constraint may still not match,
+    /// but a document violating one never does. A constraint with no values means nothing can
+    /// match.
+    #[must_use]
+    pub fn constraints(&self) -> Vec<Constraint> {
+        extract(self, &[])
+            .into_iter()
+            .map(|(path, values)| Constraint { path, values })
+            .collect()
+    }
+}
+
+fn extract(matcher: &ObjMatcher, path: &[String]) -> Constraints {
+    match matcher {
+        ObjMatcher::Eq(op) => extract(&op.val, path),
+        ObjMatcher::In(op) => any(op.val.iter().map(|m| extract(m, path))),
+        ObjMatcher::And(op) => all(op.val.iter().map(|m| extract(m, path))),
+        ObjMatcher::Or(op) => any(op.val.iter().map(|m| extract(m, path))),
+        ObjMatcher::Value(value) => extract_value(value, path),
+        _ => Constraints::new(),
+    }
+}
+
+fn extract_value(value: &Value, path: &[String]) -> Constraints {
+    if let Ok(Some(matcher)) = parse_operator(value.clone(), None) {
+        return extract(&matcher, path);
+    }
+    match value {
+        Value::Object(o) if !context::is_reference(value) => all(o
+            .iter()
+            .filter(|(key, _)| *key != "$comment")
+            .map(|(key, value)| {
+                let mut path = path.to_vec();
+                path.extend(crate::path::segments(key).map(Cow::into_owned));
+                match parse_operator(value.clone(), None) {
+                    Ok(Some(matcher)) => extract(&matcher, &path),
+                    _ => literal(value, path),
+                }
+            })),
+        _ => literal(value, path.to_vec()),
+    }
+}
+
+/// Constraint that the value at `path` equals `value`, unless its meaning depends on operators
+/// or context that are only known when matching.
+fn literal(value: &Value, path: Vec<String>) -> Constraints {
+    let mut constraints = Constraints::new();
+    if !has_operators(value) {
+        constraints.insert(path, vec![value.clone()]);
+    }
+    constraints
+}
+
+/// Values the value at each path never equals in a matching document, implied by `$ne` and
+/// `$nin`. Unlike [`ObjMatcher::constraints`], every exclusion of every clause is kept.
+pub(crate) fn exclusions(matcher: &ObjMatcher) -> Constraints {
+    exclude(matcher, &[])
+}
+
+fn exclude(matcher: &ObjMatcher, path: &[String]) -> Constraints {
+    match matcher {
+        ObjMatcher::Ne(op) => literals(std::slice::from_ref(&*op.val), path),
+        ObjMatcher::Nin(op) => literals(&op.val, path),
+        ObjMatcher::And(op) => union(op.val.iter().map(|m| exclude(m, path))),
+        ObjMatcher::Value(value) => match parse_operator(value.clone(), None) {
+            Ok(Some(matcher)) => exclude(&matcher, path),
+            Ok(None) => match value {
+                Value::Object(o) if !context::is_reference(value) => union(
+                    o.iter()
+                        .filter(|(key, _)| *key != "$comment")
+                        .map(|(key, value)| {
+                            let mut path = path.to_vec();
+                            path.extend(crate::path::segments(key).map(Cow::into_owned));
+                            match parse_operator(value.clone(), None) {
+                                Ok(Some(matcher)) => exclude(&matcher, &path),
+                                _ => Constraints::new(),
+                            }
+                        }),
+                ),
+                _ => Constraints::new(),
+            },
+            Err(_) => Constraints::new(),
+        },
+        _ => Constraints::new(),
+    }
+}
+
+/// The literal values among `matchers`, all at `path`.
+fn literals(matchers: &[ObjMatcher], path: &[String]) -> Constraints {
+    let values: Vec<Value> = matchers
+        .iter()
+        .filter_map(|matcher| match matcher {
+            ObjMatcher::Value(value) if !has_operators(value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    let mut constraints = Constraints::new();
+    if !values.is_empty() {
+        constraints.insert(path.to_vec(), values);
+    }
+    constraints
+}
+
+fn has_operators(value: &Value) -> bool {
+    match value {
+        Value::Object(o) => o
+            .iter()
+            .any(|(k, v)| k.starts_with('$') || has_operators(v)),
+        Value::Array(a) => a.iter().any(has_operators),
+        _ => false,
+    }
+}
+
+/// Constraints of a conjunction: every constraint of every clause, intersected per path.
+fn all<I: Iterator<Item = Constraints>>(clauses: I) -> Constraints {
+    let mut result = Constraints::new();
+    for clause in clauses {
+        for (path, values) in clause {
+            match result.get_mut(&path) {
+                Some(existing) => existing.retain(|v| values.contains(v)),
+                None => {
+                    result.insert(path, values);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Exclusions of a conjunction: every exclusion of every clause, merged per path.
+fn union<I: Iterator<Item = Constraints>>(clauses: I) -> Constraints {
+    let mut result = Constraints::new();
+    for clause in clauses {
+        for (path, values) in clause {
+            let existing = result.entry(path).or_default();
+            for value in values {
+                if !existing.contains(&value) {
+                    existing.push(value);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Constraints of a disjunction: the paths constrained by every branch, with the union of their
+/// values.
+fn any<I: Iterator<Item = Constraints>>(mut branches: I) -> Constraints {
+    let mut result = match branches.next() {
+        Some(first) => first,
+        None => return Constraints::new(),
+    };
+    for branch in branches {
+        result = result
+            .into_iter()
+            .filter_map(|(path, mut values)| {
+                let other = branch.get(&path)?;
+                for value in other {
+                    if !values.contains(value) {
+                        values.push(value.clone());
+                    }
+                }
+                Some((path, values))
+            })
+            .collect();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    fn constraints(query: &str) -> Value {
+        let constraints = from_str(query).unwrap().constraints();
+        constraints
+            .into_iter()
+            .map(|c| json!([c.path, c.values]))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_constraints() {
+        assert_eq!(
+            constraints(r#"{"type": "order", "region": {"$in": ["eu", "us"]}, "n": {"$ne": 1}}"#),
+            json!([[["region"], ["eu", "us"]], [["type"], ["order"]]])
+        );
+        assert_eq!(
+            constraints(r#"{"$or": [{"type": "a", "x": 1}, {"type": {"$eq": "b"}}]}"#),
+            json!([[["type"], ["a", "b"]]])
+        );
+        assert_eq!(
+            constraints(r#"{"$and": [{"a": {"$in": [1, 2]}}, {"a": {"$in": [2, 3]}}]}"#),
+            json!([[["a"], [2]]])
+        );
+        assert_eq!(
+            constraints(r#"{"a": {"$eq": {"b": 1}}, "c": {"d": 1}}"#),
+            json!([[["a", "b"], [1]], [["c"], [{"d": 1}]]])
+        );
+        assert_eq!(
+            constraints(r#"{"a": {"$divisibleBy": 3}, "t": {"$env": "T"}}"#),
+            json!([])
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Values resolved when a matcher is evaluated rather than when it is written.
+//!
+//! A literal of the form `{"$now": {}}`, `{"$env": "NAME"}` or `{"$context": "key"}` is replaced
+//! by the value supplied by the [`ContextProvider`] set with
+//! [`MatchOptions::context`](crate::MatchOptions::context). Without a provider these literals are
+//! compared as written.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// Source of the values referenced by `$now`, `$env` and `$context` literals.
+pub trait ContextProvider: Send + Sync {
+    /// Value of `{"$now": {}}`. Defaults to the current time in milliseconds since the Unix
+    /// epoch.
+    fn now(&self) -> Value {
+        let millis = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+            Err(_) => 0,
+        };
+        Value::from(millis)
+    }
+
+    /// Value of `{"$env": name}`. Defaults to no value, so that query authors only see the
+    /// variables a provider chooses to expose; [`SystemContext`] reads the process environment.
+    fn env(&self, _name: &str) -> Option<Value> {
+        None
+    }
+
+    /// Value of `{"$context": key}`. Defaults to no value.
+    fn get(&self, _key: &str) -> Option<Value> {
+        None
+    }
+}
+
+/// [`ContextProvider`] backed by the system clock and the process environment.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemContext;
+
+impl ContextProvider for SystemContext {
+    fn env(&self, name: &str) -> Option<Value> {
+        std::env::var(name).ok().map(Value::String)
+    }
+}
+
+fn reference(value: &Value) -> Option<(&str, &Value)> {
+    let o = value.as_object()?;
+    if o.len() != 1 {
+        return None;
+    }
+    let (key, operand) = o.iter().next()?;
+    match key.as_str() {
+        "$now" | "$env" | "$context" => Some((key, operand)),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_reference(value: &Value) -> bool {
+    reference(value).is_some()
+}
+
+/// Resolves `value` if it is a context reference; references to missing values resolve to null.
+pub(crate) fn resolve(value: &Value, provider: &dyn ContextProvider) -> Option<Value> {
+    let (key, operand) = reference(value)?;
+    match key {
+        "$now" => Some(provider.now()),
+        "$env" => Some(
+            operand
+                .as_str()
+                .and_then(|name| provider.env(name))
+                .unwrap_or(Value::Null),
+        ),
+        "$context" => Some(
+            operand
+                .as_str()
+                .and_then(|key| provider.get(key))
+                .unwrap_or(Value::Null),
+        ),
+        _ => None,
+    }
+}
@@ -0,0 +1,130 @@
+//! `$countMatching`, bounding how many elements of an array satisfy a matcher.
+//!
+//! `{"reviews": {"$countMatching": {"matcher": {"stars": 5}, "gte": 3}}}` matches arrays with at
+//! least three five-star reviews. The bounds `eq`, `gt`, `gte`, `lt` and `lte` can be combined,
+//! and at least one is required. Values other than arrays never match.
+
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{CountMatchingOperator, MatchOptions, MatchesValue, ObjMatcher};
+
+/// Operand of `$countMatching`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "CountDefinition", into = "CountDefinition")]
+pub struct CountMatching(CountDefinition);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CountDefinition {
+    matcher: Box<ObjMatcher>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    eq: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gt: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gte: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lt: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lte: Option<usize>,
+}
+
+impl TryFrom<CountDefinition> for CountMatching {
+    type Error = String;
+
+    fn try_from(definition: CountDefinition) -> Result<Self, String> {
+        let bounds = [
+            definition.eq,
+            definition.gt,
+            definition.gte,
+            definition.lt,
+            definition.lte,
+        ];
+        if bounds.iter().all(Option::is_none) {
+            return Err("one of `eq`, `gt`, `gte`, `lt` or `lte` is required".to_string());
+        }
+        Ok(CountMatching(definition))
+    }
+}
+
+impl From<CountMatching> for CountDefinition {
+    fn from(count: CountMatching) -> CountDefinition {
+        count.0
+    }
+}
+
+impl CountMatching {
+    pub(crate) fn matcher(&self) -> &ObjMatcher {
+        &self.0.matcher
+    }
+
+    fn accepts(&self, count: usize) -> bool {
+        let d = &self.0;
+        d.eq.map_or(true, |n| count == n)
+            && d.gt.map_or(true, |n| count > n)
+            && d.gte.map_or(true, |n| count >= n)
+            && d.lt.map_or(true, |n| count < n)
+            && d.lte.map_or(true, |n| count <= n)
+    }
+}
+
+impl MatchesValue for CountMatchingOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Array(a) => {
+                let count = a
+                    .iter()
+                    .filter(|element| self.val.matcher().evaluate(element, options))
+                    .count();
+                self.val.accepts(count)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_count_matching() {
+        let reviews = |stars: &[u64]| {
+            let reviews: Vec<_> = stars.iter().map(|s| json!({ "stars": s })).collect();
+            json!({ "reviews": reviews })
+        };
+        let popular =
+            from_str(r#"{"reviews": {"$countMatching": {"matcher": {"stars": 5}, "gte": 3}}}"#)
+                .unwrap();
+        assert!(popular.matches(&reviews(&[5, 5, 4, 5])));
+        assert!(!popular.matches(&reviews(&[5, 5, 4])));
+        assert!(!popular.matches(&json!({"reviews": {"stars": 5}})));
+        assert!(!popular.matches(&json!({})));
+
+        let few_bad = from_str(
+            r#"{"reviews": {"$countMatching": {"matcher": {"stars": {"$lte": 2}}, "gt": 0, "lt": 3}}}"#,
+        )
+        .unwrap();
+        assert!(few_bad.matches(&reviews(&[1, 5, 2])));
+        assert!(!few_bad.matches(&reviews(&[5, 4])));
+        assert!(!few_bad.matches(&reviews(&[1, 1, 2])));
+        assert!(few_bad.compile().unwrap().matches(&reviews(&[1])));
+
+        let none =
+            from_str(r#"{"tags": {"$countMatching": {"matcher": "spam", "eq": 0}}}"#).unwrap();
+        assert!(none.matches(&json!({"tags": []})));
+        assert!(!none.matches(&json!({"tags": ["ok", "spam"]})));
+
+        assert!(from_str(r#"{"a": {"$countMatching": {"matcher": 1}}}"#).is_err());
+        assert!(from_str(r#"{"a": {"$countMatching": {"matcher": 1, "gte": -1}}}"#).is_err());
+        assert!(from_str(r#"{"a": {"$countMatching": {"matcher": 1, "min": 1}}}"#).is_err());
+        assert!(from_str(
+            r#"{"a": {"$countMatching": {"matcher": {"$gt": "x", "$nope": 1}, "eq": 1}}}"#
+        )
+        .is_err());
+    }
+}
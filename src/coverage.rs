@@ -0,0 +1,139 @@
+//! Clause coverage of a matcher over a corpus of sample documents, for finding dead clauses in
+//! aging rule sets.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{ExplainReport, MatchOptions, ObjMatcher};
+
+/// How often each clause of a matcher held over a set of documents, returned by
+/// [`ObjMatcher::coverage`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Coverage {
+    pub documents: usize,
+    /// Every clause, in the pre-order of [`ObjMatcher::explain`] reports.
+    pub clauses: Vec<ClauseCoverage>,
+}
+
+/// How often one clause held, identified as in [`ExplainReport`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClauseCoverage {
+    pub path: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<Value>,
+    /// Nesting depth of the clause, the whole matcher being at depth 0.
+    pub depth: usize,
+    pub matched: usize,
+    pub unmatched: usize,
+}
+
+impl ClauseCoverage {
+    /// Whether the clause was both true and false for some documents, so that it can influence
+    /// the outcome.
+    #[must_use]
+    pub fn is_covered(&self) -> bool {
+        self.matched > 0 && self.unmatched > 0
+    }
+}
+
+impl Coverage {
+    /// The clauses that were never true or never false.
+    pub fn uncovered(&self) -> impl Iterator<Item = &ClauseCoverage> {
+        self.clauses.iter().filter(|clause| !clause.is_covered())
+    }
+}
+
+impl ObjMatcher {
+    /// Evaluates every clause of the matcher against each of `values`, counting how often each
+    /// one held.
+    #[must_use]
+    pub fn coverage<'a, I>(&self, values: I) -> Coverage
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        self.coverage_with_options(values, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn coverage_with_options<'a, I>(&self, values: I, options: &MatchOptions) -> Coverage
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        let mut coverage = Coverage::default();
+        for value in values {
+            let report = self.explain_with_options(value, options);
+            if coverage.documents == 0 {
+                collect(&report, 0, &mut coverage.clauses);
+            }
+            let mut clauses = coverage.clauses.iter_mut();
+            count(&report, &mut clauses);
+            coverage.documents += 1;
+        }
+        coverage
+    }
+}
+
+fn collect(report: &ExplainReport, depth: usize, clauses: &mut Vec<ClauseCoverage>) {
+    clauses.push(ClauseCoverage {
+        path: report.path.clone(),
+        operator: report.operator.clone(),
+        expected: report.expected.clone(),
+        depth,
+        matched: 0,
+        unmatched: 0,
+    });
+    for child in &report.children {
+        collect(child, depth + 1, clauses);
+    }
+}
+
+/// Counts the outcomes of a report, whose clauses are the same for every document.
+fn count<'a, I>(report: &ExplainReport, clauses: &mut I)
+where
+    I: Iterator<Item = &'a mut ClauseCoverage>,
+{
+    if let Some(clause) = clauses.next() {
+        if report.outcome {
+            clause.matched += 1;
+        } else {
+            clause.unmatched += 1;
+        }
+    }
+    for child in &report.children {
+        count(child, clauses);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_coverage() {
+        let matcher =
+            from_str(r#"{"$or": [{"type": "order", "total": {"$gt": 0}}, {"legacy": true}]}"#)
+                .unwrap();
+        let docs = vec![
+            json!({"type": "order", "total": 10}),
+            json!({"type": "order", "total": 0}),
+            json!({"type": "refund", "total": 5}),
+        ];
+        let coverage = matcher.coverage(&docs);
+        assert_eq!(coverage.documents, 3);
+        assert_eq!(coverage.clauses.len(), 6);
+        let uncovered: Vec<_> = coverage
+            .uncovered()
+            .map(|clause| (clause.path.join("."), clause.matched, clause.unmatched))
+            .collect();
+        assert_eq!(
+            uncovered,
+            vec![(String::new(), 0, 3), ("legacy".to_string(), 0, 3)]
+        );
+        assert_eq!(coverage.clauses[0].operator.as_deref(), Some("$or"));
+        assert_eq!(coverage.clauses[3].depth, 2);
+        assert!(matcher.coverage(&[]).clauses.is_empty());
+    }
+}
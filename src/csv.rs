@@ -0,0 +1,341 @@
+//! Matching the rows of CSV files with headers.
+//!
+//! Each row becomes an object mapping the header names to the row's fields, taken literally, so
+//! a column named `"a.b"` is addressed as `"a\\.b"`. Fields are strings unless
+//! [`CsvOptions`] infers other types. Fields may be quoted, with `""` standing for a quote and
+//! line breaks allowed inside; blank lines are skipped.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+
+use serde_json::{Map, Number, Value};
+
+use crate::ObjMatcher;
+
+/// Options controlling how CSV fields are turned into values.
+///
+/// Types are only inferred for unquoted fields, so that quoting, as in `"007"`, keeps a field a
+/// string.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    delimiter: char,
+    infer_numbers: bool,
+    infer_booleans: bool,
+    empty_as_null: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            infer_numbers: true,
+            infer_booleans: false,
+            empty_as_null: false,
+        }
+    }
+}
+
+impl CsvOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Separates fields with `delimiter`, e.g. `b'\t'` or `b';'`, instead of a comma.
+    #[must_use]
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = char::from(delimiter);
+        self
+    }
+
+    /// Reads fields written as JSON numbers, such as `42` or `-1.5e3` but not `007` or `.5`, as
+    /// numbers. On by default.
+    #[must_use]
+    pub fn infer_numbers(mut self, infer: bool) -> Self {
+        self.infer_numbers = infer;
+        self
+    }
+
+    /// Reads `true` and `false` as booleans.
+    #[must_use]
+    pub fn infer_booleans(mut self, infer: bool) -> Self {
+        self.infer_booleans = infer;
+        self
+    }
+
+    /// Reads empty fields as `null`, so that `{"field": null}` matches them.
+    #[must_use]
+    pub fn empty_as_null(mut self, empty_as_null: bool) -> Self {
+        self.empty_as_null = empty_as_null;
+        self
+    }
+
+    fn value(&self, field: Field) -> Value {
+        if field.quoted {
+            return Value::String(field.text);
+        }
+        let text = field.text;
+        if text.is_empty() && self.empty_as_null {
+            return Value::Null;
+        }
+        if self.infer_booleans && (text == "true" || text == "false") {
+            return Value::Bool(text == "true");
+        }
+        if self.infer_numbers && text.trim() == text {
+            if let Ok(n) = serde_json::from_str::<Number>(&text) {
+                return Value::Number(n);
+            }
+        }
+        Value::String(text)
+    }
+}
+
+/// Error reading a CSV row.
+#[derive(Debug, Clone)]
+pub struct CsvError {
+    /// Line on which the row starts, from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+/// Iterator over the rows of a CSV file as objects, created by [`csv_rows`].
+///
+/// Rows that cannot be read, such as those with more or fewer fields than the header, are
+/// yielded as errors; an I/O error ends the iteration.
+pub struct CsvRows<R> {
+    reader: BufReader<R>,
+    options: CsvOptions,
+    headers: Option<Vec<String>>,
+    line: usize,
+    done: bool,
+}
+
+/// Reads the rows of the CSV file `reader` holds, whose first row names the columns.
+pub fn csv_rows<R: io::Read>(reader: R, options: CsvOptions) -> CsvRows<R> {
+    CsvRows {
+        reader: BufReader::new(reader),
+        options,
+        headers: None,
+        line: 0,
+        done: false,
+    }
+}
+
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+impl<R: io::Read> CsvRows<R> {
+    /// The fields of the next non-blank row, and the line it starts on.
+    fn record(&mut self) -> Option<Result<(usize, Vec<Field>), CsvError>> {
+        let mut record = String::new();
+        loop {
+            if self.done {
+                return None;
+            }
+            record.clear();
+            let start = self.line + 1;
+            // A quoted field may span lines, in which case the quotes read so far are unbalanced.
+            loop {
+                match self.reader.read_line(&mut record) {
+                    Ok(0) => {
+                        self.done = true;
+                        break;
+                    }
+                    Ok(_) => self.line += 1,
+                    Err(err) => {
+                        self.done = true;
+                        return Some(Err(CsvError {
+                            line: self.line + 1,
+                            message: err.to_string(),
+                        }));
+                    }
+                }
+                if record.matches('"').count() % 2 == 0 {
+                    break;
+                }
+            }
+            if record.ends_with('\n') {
+                record.pop();
+                if record.ends_with('\r') {
+                    record.pop();
+                }
+            }
+            if !record.is_empty() {
+                let fields = split(&record, self.options.delimiter).map_err(|message| CsvError {
+                    line: start,
+                    message,
+                });
+                return Some(fields.map(|fields| (start, fields)));
+            }
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for CsvRows<R> {
+    type Item = Result<Value, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.headers.is_none() {
+            match self.record()? {
+                Ok((_, fields)) => {
+                    self.headers = Some(fields.into_iter().map(|field| field.text).collect());
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        let (line, fields) = match self.record()? {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err)),
+        };
+        let headers = self.headers.as_ref().unwrap();
+        if fields.len() != headers.len() {
+            return Some(Err(CsvError {
+                line,
+                message: format!("expected {} fields, found {}", headers.len(), fields.len()),
+            }));
+        }
+        let row = headers
+            .iter()
+            .cloned()
+            .zip(fields.into_iter().map(|field| self.options.value(field)))
+            .collect::<Map<_, _>>();
+        Some(Ok(Value::Object(row)))
+    }
+}
+
+fn split(record: &str, delimiter: char) -> Result<Vec<Field>, String> {
+    let mut fields = Vec::new();
+    let mut chars = record.chars().peekable();
+    loop {
+        let mut field = Field {
+            text: String::new(),
+            quoted: chars.peek() == Some(&'"'),
+        };
+        if field.quoted {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.text.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.text.push(c),
+                    None => return Err("unterminated quoted field".to_string()),
+                }
+            }
+            match chars.next() {
+                None => {
+                    fields.push(field);
+                    return Ok(fields);
+                }
+                Some(c) if c == delimiter => fields.push(field),
+                Some(c) => return Err(format!("unexpected `{c}` after a quoted field")),
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    None => {
+                        fields.push(field);
+                        return Ok(fields);
+                    }
+                    Some(c) if c == delimiter => break,
+                    Some(c) => field.text.push(c),
+                }
+            }
+            fields.push(field);
+        }
+    }
+}
+
+/// Iterator over the rows of a CSV file matching a matcher, created by [`filter_csv`].
+pub struct MatchingCsv<'m, R> {
+    rows: CsvRows<R>,
+    matcher: &'m ObjMatcher,
+}
+
+/// Filters the rows of the CSV file `reader` holds down to those matching `matcher`, reading one
+/// row at a time. Rows that cannot be read are yielded as errors.
+pub fn filter_csv<R: io::Read>(
+    reader: R,
+    matcher: &ObjMatcher,
+    options: CsvOptions,
+) -> MatchingCsv<'_, R> {
+    MatchingCsv {
+        rows: csv_rows(reader, options),
+        matcher,
+    }
+}
+
+impl<R: io::Read> Iterator for MatchingCsv<'_, R> {
+    type Item = Result<Value, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.rows.next()? {
+                Ok(row) if !self.matcher.matches(&row) => continue,
+                item => return Some(item),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_csv() {
+        let input = "id,name,zip,active,score\r\n\
+            1,\"Smith, Ann\",\"02134\",true,\r\n\
+            \n\
+            2,\"say \"\"hi\"\"\nthere\",02134,false,4.5\n\
+            3,Bob,10001,true,12";
+        let rows = csv_rows(input.as_bytes(), CsvOptions::new())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                json!({"id": 1, "name": "Smith, Ann", "zip": "02134", "active": "true", "score": ""}),
+                json!({"id": 2, "name": "say \"hi\"\nthere", "zip": "02134", "active": "false", "score": 4.5}),
+                json!({"id": 3, "name": "Bob", "zip": 10001, "active": "true", "score": 12}),
+            ]
+        );
+
+        let matcher = crate::from_str(
+            r#"{"active": true, "score": {"$ne": null}, "zip": {"$type": ["string"]}}"#,
+        )
+        .unwrap();
+        let options = CsvOptions::new().infer_booleans(true).empty_as_null(true);
+        let matching = filter_csv(input.as_bytes(), &matcher, options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(matching.is_empty());
+        let options = CsvOptions::new().infer_numbers(false).infer_booleans(true);
+        let ids = filter_csv(input.as_bytes(), &matcher, options)
+            .map(|row| row.unwrap()["id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![json!("1"), json!("3")]);
+
+        let input = "a;b\n1;2;3\n\"x\"y;2\n\"open;2\n";
+        let mut rows = csv_rows(input.as_bytes(), CsvOptions::new().delimiter(b';'));
+        let err = rows.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: expected 2 fields, found 3");
+        assert_eq!(rows.next().unwrap().unwrap_err().line, 3);
+        assert_eq!(rows.next().unwrap().unwrap_err().line, 4);
+        assert!(rows.next().is_none());
+        assert_eq!(csv_rows(&b""[..], CsvOptions::new()).count(), 0);
+    }
+}
@@ -0,0 +1,99 @@
+//! User-defined operators.
+//!
+//! An application registers its operators with an [`OperatorRegistry`] and passes the registry
+//! both to [`ParseOptions::registry`](crate::ParseOptions::registry), which validates operands
+//! when a query is parsed, and to [`MatchOptions::registry`](crate::MatchOptions::registry),
+//! which evaluates them. Built-in operators always take precedence over registered ones.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{MatchOptions, MatchesValue};
+
+/// An operator implemented outside of this crate, such as `$isBusinessDay`.
+pub trait CustomOperator: Send + Sync {
+    /// Name of the operator, including the leading `$`.
+    fn name(&self) -> &str;
+
+    /// Validates the operand of the operator, returning a description of the problem if it is
+    /// not acceptable.
+    fn parse(&self, operand: &Value) -> Result<(), String>;
+
+    /// Returns whether `value` satisfies the operator.
+    fn evaluate(&self, operand: &Value, value: &Value) -> bool;
+}
+
+/// Set of [`CustomOperator`]s, keyed by name.
+#[derive(Clone, Default)]
+pub struct OperatorRegistry {
+    operators: HashMap<String, Arc<dyn CustomOperator>>,
+}
+
+impl OperatorRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an operator, replacing any operator previously registered under the same name.
+    pub fn register<O: CustomOperator + 'static>(&mut self, operator: O) {
+        self.operators
+            .insert(operator.name().to_string(), Arc::new(operator));
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&dyn CustomOperator> {
+        self.operators.get(name).map(|operator| &**operator)
+    }
+}
+
+impl fmt::Debug for OperatorRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.operators.keys()).finish()
+    }
+}
+
+/// Use of a registered operator within a query, e.g. `{"$isBusinessDay": true}`.
+#[derive(Debug, Clone)]
+pub struct CustomOperatorCall {
+    name: String,
+    operand: Value,
+}
+
+impl CustomOperatorCall {
+    pub(crate) fn new(name: String, operand: Value) -> Self {
+        CustomOperatorCall { name, operand }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn operand(&self) -> &Value {
+        &self.operand
+    }
+}
+
+impl Serialize for CustomOperatorCall {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(&self.name, &self.operand)?;
+        map.end()
+    }
+}
+
+impl MatchesValue for CustomOperatorCall {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        options
+            .operator_registry()
+            .and_then(|registry| registry.get(&self.name))
+            .map_or(false, |operator| operator.evaluate(&self.operand, other))
+    }
+}
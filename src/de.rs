@@ -0,0 +1,188 @@
+//! Deserializers for embedding matchers in configuration structs.
+//!
+//! Deriving `Deserialize` for a struct with an [`ObjMatcher`] field recognizes operators and
+//! checks their operands, even through `#[serde(flatten)]` or an untagged enum, except the
+//! operands of unknown `$` operators, which may be custom ones. These functions parse matchers
+//! like [`from_json`] in every case, so errors are reported with the rest of the configuration:
+//!
+//! ```
+//! # use serde::Deserialize;
+//! # use serde_json_matcher::ObjMatcher;
+//! #[derive(Deserialize)]
+//! struct Route {
+//!     #[serde(default, deserialize_with = "serde_json_matcher::de::matcher")]
+//!     filter: ObjMatcher,
+//!     #[serde(default, deserialize_with = "serde_json_matcher::de::option")]
+//!     sample: Option<ObjMatcher>,
+//!     #[serde(default, deserialize_with = "serde_json_matcher::de::matchers")]
+//!     deny: Vec<ObjMatcher>,
+//! }
+//!
+//! let route: Route = serde_json::from_str(r#"{"filter": {"level": "error"}}"#).unwrap();
+//! assert!(route.sample.is_none() && route.deny.is_empty());
+//! assert!(serde_json::from_str::<Route>(r#"{"filter": {"$in": 1}}"#).is_err());
+//! ```
+//!
+//! A missing field with `#[serde(default)]` is [`ObjMatcher::default`], which matches every
+//! value.
+
+use std::iter::FromIterator;
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde_json::Value;
+
+use crate::{from_json, ObjMatcher};
+
+fn parse<E: Error>(value: Value) -> Result<ObjMatcher, E> {
+    from_json(value).map_err(E::custom)
+}
+
+/// Deserializes a matcher.
+pub fn matcher<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ObjMatcher, D::Error> {
+    parse(Value::deserialize(deserializer)?)
+}
+
+/// Deserializes an optional matcher, `null` being `None`.
+pub fn option<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ObjMatcher>, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(None),
+        value => parse(value).map(Some),
+    }
+}
+
+/// Deserializes an array of matchers into any collection, such as a `Vec`.
+pub fn matchers<'de, D, C>(deserializer: D) -> Result<C, D::Error>
+where
+    D: Deserializer<'de>,
+    C: FromIterator<ObjMatcher>,
+{
+    Vec::<Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(parse)
+        .collect()
+}
+
+/// Deserializes an object of named matchers into any map, such as a `HashMap<String, _>`.
+pub fn named_matchers<'de, D, C>(deserializer: D) -> Result<C, D::Error>
+where
+    D: Deserializer<'de>,
+    C: FromIterator<(String, ObjMatcher)>,
+{
+    serde_json::Map::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, value)| Ok((name, parse(value)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use crate::ObjMatcher;
+
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(default, deserialize_with = "super::matcher")]
+        filter: ObjMatcher,
+        #[serde(default, deserialize_with = "super::option")]
+        sample: Option<ObjMatcher>,
+        #[serde(default, deserialize_with = "super::named_matchers")]
+        routes: HashMap<String, ObjMatcher>,
+    }
+
+    #[test]
+    pub fn test_config() {
+        let config: Config = serde_json::from_value(json!({
+            "sample": null,
+            "routes": {"errors": {"level": {"$in": ["error", "fatal"]}}},
+        }))
+        .unwrap();
+        assert!(config.filter.matches(&json!({"anything": 1})));
+        assert!(config.filter.matches(&json!("not an object")));
+        assert!(config.sample.is_none());
+        assert!(config.routes["errors"].matches(&json!({"level": "fatal"})));
+
+        let err = serde_json::from_value::<Config>(json!({"routes": {"x": {"level": {"$in": 1}}}}));
+        assert!(err.is_err());
+    }
+
+    #[derive(Deserialize)]
+    struct Flattened {
+        name: String,
+        #[serde(flatten)]
+        rule: Rule,
+    }
+
+    #[derive(Deserialize)]
+    struct Rule {
+        when: ObjMatcher,
+        unless: Option<ObjMatcher>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Condition {
+        Matcher(ObjMatcher),
+    }
+
+    #[test]
+    pub fn test_derived() {
+        let flattened: Flattened = serde_json::from_value(json!({
+            "name": "large",
+            "when": {"$gte": 100, "$comment": "bytes"},
+            "unless": {"$in": [1, 2]},
+        }))
+        .unwrap();
+        assert_eq!(flattened.name, "large");
+        match flattened.rule.when {
+            ObjMatcher::Gte(_) => {}
+            other => panic!("expected `$gte`, got {:?}", other),
+        }
+        match flattened.rule.unless {
+            Some(ObjMatcher::In(_)) => {}
+            other => panic!("expected `$in`, got {:?}", other),
+        }
+
+        match serde_json::from_value(json!({"$gt": 1, "$lt": 5})).unwrap() {
+            Condition::Matcher(ObjMatcher::And(and)) => assert_eq!(and.val.len(), 2),
+            Condition::Matcher(other) => panic!("expected `$and`, got {:?}", other),
+        }
+        match serde_json::from_value(json!({"level": "error"})).unwrap() {
+            Condition::Matcher(ObjMatcher::Value(_)) => {}
+            Condition::Matcher(other) => panic!("expected a value, got {:?}", other),
+        }
+        // Unknown operators may be custom ones, recognized when matching with a registry.
+        match serde_json::from_value(json!({"$custom": {"$in": 1}})).unwrap() {
+            Condition::Matcher(ObjMatcher::Value(_)) => {}
+            Condition::Matcher(other) => panic!("expected a value, got {:?}", other),
+        }
+        assert!(serde_json::from_value::<ObjMatcher>(json!({"a": {"$custom": 1}})).is_ok());
+
+        let err = serde_json::from_value::<Flattened>(json!({"name": "x", "when": {"$in": 1}}));
+        assert!(err.is_err());
+        let err = serde_json::from_value::<Condition>(json!({"$gt": 1, "level": 2}));
+        assert!(err.is_err());
+
+        // Invalid operands under fields are reported too, rather than when matching.
+        for query in &[
+            json!({"a": {"$gt": 1, "typo": 2}}),
+            json!({"a": {"$in": 1}}),
+            json!({"$or": [{"a": {"$in": 1}}]}),
+            json!({"$custom": 1, "$gt": 2}),
+            json!({"a": {"$gt": 1, "$bogus": 1}}),
+            json!({"a": {"$custom": 1}, "b": {"$in": 1}}),
+        ] {
+            assert!(
+                serde_json::from_value::<ObjMatcher>(query.clone()).is_err(),
+                "{}",
+                query
+            );
+        }
+        let unchecked = ObjMatcher::Value(json!({"a": {"$in": 1}}));
+        assert!(!unchecked.matches(&json!({"a": 1})));
+        assert!(unchecked.compile().is_err());
+    }
+}
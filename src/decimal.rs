@@ -0,0 +1,171 @@
+//! Exact decimal numbers, with which `{"$numberDecimal": "..."}` literals are compared when the
+//! `decimal` feature is enabled, rather than as the nearest `f64`, so that `{"$numberDecimal":
+//! "0.1"}` is less than `0.10000000000000001` and `{"$numberDecimal": "19.90"}` equals `19.9`.
+//!
+//! A decimal literal compares with numbers, other decimal literals and strings holding a decimal
+//! number, such as `"19.90"`, by their values.
+
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+/// `0.d₁d₂…dₙ × 10^exponent`, with no leading or trailing zero digits, zero having none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Decimal {
+    negative: bool,
+    digits: Vec<u8>,
+    exponent: i64,
+}
+
+impl Decimal {
+    /// Parses numbers such as `-12`, `19.90`, `.5` or `1.5E-3`.
+    pub(crate) fn parse(text: &str) -> Option<Decimal> {
+        let (negative, unsigned) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(i) => (&unsigned[..i], unsigned[i + 1..].parse::<i64>().ok()?),
+            None => (unsigned, 0),
+        };
+        let (integer, fraction) = match mantissa.find('.') {
+            Some(i) => (&mantissa[..i], &mantissa[i + 1..]),
+            None => (mantissa, ""),
+        };
+        let all = integer.bytes().chain(fraction.bytes());
+        if integer.is_empty() && fraction.is_empty() || !all.clone().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let all = all.map(|b| b - b'0').collect::<Vec<_>>();
+        let leading = all.iter().take_while(|&&d| d == 0).count();
+        let mut digits = all[leading..].to_vec();
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let exponent = if digits.is_empty() {
+            0
+        } else {
+            exponent.checked_add(integer.len() as i64 - leading as i64)?
+        };
+        Some(Decimal {
+            negative: negative && !digits.is_empty(),
+            digits,
+            exponent,
+        })
+    }
+
+    fn sign(&self) -> i8 {
+        match (self.digits.is_empty(), self.negative) {
+            (true, _) => 0,
+            (false, true) => -1,
+            (false, false) => 1,
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        self.sign().cmp(&other.sign()).then_with(|| {
+            let magnitude = self
+                .exponent
+                .cmp(&other.exponent)
+                .then_with(|| self.digits.cmp(&other.digits));
+            if self.negative {
+                magnitude.reverse()
+            } else {
+                magnitude
+            }
+        })
+    }
+}
+
+/// The value of `value` if it is a `{"$numberDecimal": "..."}` literal.
+pub(crate) fn literal(value: &Value) -> Option<Decimal> {
+    let o = value.as_object().filter(|o| o.len() == 1)?;
+    Decimal::parse(o.get("$numberDecimal")?.as_str()?)
+}
+
+fn operand(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Number(n) => Decimal::parse(&n.to_string()),
+        Value::String(s) => Decimal::parse(s),
+        _ => literal(value),
+    }
+}
+
+/// Orders `a` relative to `b` if one is a decimal literal and the other has a decimal value.
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (literal(a), literal(b)) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        (Some(a), None) => Some(a.cmp(&operand(b)?)),
+        (None, Some(b)) => Some(operand(a)?.cmp(&b)),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_json, BorrowedMatcher};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_decimal() {
+        let parse = |text| Decimal::parse(text).unwrap();
+        assert_eq!(parse("19.90"), parse("1.99e1"));
+        assert_eq!(parse("-0.0"), parse("0"));
+        assert!(parse("0.1") < parse("0.10000000000000001"));
+        assert!(parse("-100") < parse("-99.999"));
+        assert!(parse("12345678901234567890.01") > parse("12345678901234567890"));
+        for text in &["", ".", "-", "1.2.3", "1e", "NaN", "Infinity", "0x10"] {
+            assert_eq!(Decimal::parse(text), None, "{text}");
+        }
+
+        let matcher = from_json(json!({
+            "price": {"$gte": {"$numberDecimal": "19.99"}, "$lt": {"$numberDecimal": "20.00"}},
+            "discount": {"$numberDecimal": "0.10"},
+        }))
+        .unwrap();
+        for (doc, expected) in &[
+            (json!({"price": 19.99, "discount": 0.1}), true),
+            (
+                json!({"price": {"$numberDecimal": "19.995"}, "discount": "0.1"}),
+                true,
+            ),
+            (json!({"price": 19.98, "discount": 0.1}), false),
+            (
+                json!({"price": 20, "discount": {"$numberDecimal": "1E-1"}}),
+                false,
+            ),
+            (json!({"price": "cheap", "discount": 0.1}), false),
+        ] {
+            assert_eq!(matcher.matches(doc), *expected, "{doc}");
+        }
+
+        // Decimal documents compared with plain numbers, by every kind of matcher.
+        let doc = json!({"n": {"$numberDecimal": "7.50"}});
+        for query in &[
+            json!({"n": {"$gt": 7, "$lte": 7.5}}),
+            json!({"n": {"$in": [1, 7.5]}}),
+            json!({"n": 7.5}),
+        ] {
+            let matcher = from_json(query.clone()).unwrap();
+            assert!(matcher.matches(&doc), "{}", query);
+            assert!(matcher.compile().unwrap().matches(&doc), "{}", query);
+            let text = query.to_string();
+            assert!(
+                BorrowedMatcher::parse(&text).unwrap().matches(&doc),
+                "{}",
+                query
+            );
+        }
+        assert!(!from_json(json!({"n": {"$gt": 7.5}})).unwrap().matches(&doc));
+    }
+}
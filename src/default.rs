@@ -0,0 +1,105 @@
+//! `$default`, giving a missing field a value before the operators next to it apply, e.g.
+//! `{"retries": {"$default": 0, "$lt": 3}}` matches documents without `retries` as if it were
+//! `0`.
+//!
+//! As everywhere in field conditions, a missing field is `null`, so a field set to `null` takes
+//! the default too.
+
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::{parse_operator, MatchOptions, MatchesValue, ObjMatcher, OperatorRegistry};
+
+/// `$default` and the operators next to it.
+#[derive(Debug, Clone)]
+pub struct DefaultOperator {
+    source: Map<String, Value>,
+    default: Value,
+    matcher: Box<ObjMatcher>,
+}
+
+impl DefaultOperator {
+    pub(crate) fn parse(
+        source: Map<String, Value>,
+        registry: Option<&OperatorRegistry>,
+    ) -> Result<Self, serde_json::Error> {
+        let mut rest = source.clone();
+        let default = rest.remove("$default").unwrap_or(Value::Null);
+        let matcher = parse_operator(Value::Object(rest), registry)?
+            .ok_or_else(|| serde::de::Error::custom("`$default` needs an operator to apply to"))?;
+        Ok(DefaultOperator {
+            source,
+            default,
+            matcher: Box::new(matcher),
+        })
+    }
+
+    pub(crate) fn matcher(&self) -> &ObjMatcher {
+        &self.matcher
+    }
+
+    /// `value`, or the default if it is `null`.
+    pub(crate) fn apply<'a>(&'a self, value: &'a Value) -> &'a Value {
+        if value.is_null() {
+            &self.default
+        } else {
+            value
+        }
+    }
+}
+
+impl Serialize for DefaultOperator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.source.serialize(serializer)
+    }
+}
+
+impl MatchesValue for DefaultOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        self.matcher.evaluate(self.apply(other), options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_str, BorrowedMatcher, Compatibility, MatchOptions};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_default() {
+        let query = r#"{"retries": {"$default": 0, "$lt": 3}}"#;
+        let matcher = from_str(query).unwrap();
+        let compiled = matcher.compile().unwrap();
+        let borrowed = BorrowedMatcher::parse(query).unwrap();
+        let strict = MatchOptions::new().compatibility(Compatibility::StrictMongo);
+        for (doc, expected) in &[
+            (json!({}), true),
+            (json!({"retries": null}), true),
+            (json!({"retries": 2}), true),
+            (json!({"retries": 3}), false),
+            (json!({"retries": "0"}), false),
+        ] {
+            assert_eq!(matcher.matches(doc), *expected, "{doc}");
+            assert_eq!(compiled.matches(doc), *expected, "{doc}");
+            assert_eq!(borrowed.matches(doc), *expected, "{doc}");
+            assert_eq!(
+                matcher.matches_with_options(doc, &strict),
+                *expected,
+                "{doc}"
+            );
+        }
+
+        // Several operators all see the default.
+        let matcher =
+            from_str(r#"{"tags": {"$default": [], "$len": 0, "$type": ["array"]}}"#).unwrap();
+        assert!(matcher.matches(&json!({"name": "ann"})));
+        assert!(!matcher.matches(&json!({"tags": ["a"]})));
+        let top = json!({"$default": [], "$len": 0, "$comment": "untagged"});
+        let matcher = from_str(&top.to_string()).unwrap();
+        assert!(matcher.matches(&json!(null)));
+        assert_eq!(serde_json::to_value(&matcher).unwrap(), top);
+
+        assert!(from_str(r#"{"retries": {"$default": 0}}"#).is_err());
+        assert!(from_str(r#"{"retries": {"$default": 0, "$lt": 3, "a": 1}}"#).is_err());
+    }
+}
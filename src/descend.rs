@@ -0,0 +1,121 @@
+//! `$descend`, matching fields at any depth, as the `..` of `JSONPath`.
+//!
+//! `{"$descend": {"error_code": {"$ne": 0}}}` matches when some `error_code` field, in the value
+//! itself or in any object nested in it through objects and arrays, is not 0. Each field of the
+//! operand must be satisfied by one of its occurrences, independently of the others. Only fields
+//! that are present count, so a value without any `error_code` does not match.
+//!
+//! Dotted names such as `"error.code"` are looked up from every nested object in turn.
+
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{path, DescendOperator, MatchOptions, MatchesValue, ObjMatcher};
+
+/// Operand of `$descend`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Map<String, Value>", into = "Map<String, Value>")]
+pub struct Descend {
+    source: Map<String, Value>,
+    fields: Vec<String>,
+    matchers: Vec<ObjMatcher>,
+}
+
+impl TryFrom<Map<String, Value>> for Descend {
+    type Error = String;
+
+    fn try_from(source: Map<String, Value>) -> Result<Self, String> {
+        if source.is_empty() {
+            return Err("`$descend` needs at least one field".to_string());
+        }
+        let mut fields = Vec::with_capacity(source.len());
+        let mut matchers = Vec::with_capacity(source.len());
+        for (field, value) in &source {
+            if field.starts_with('$') {
+                return Err(format!("`{field}` is not a field name"));
+            }
+            fields.push(field.clone());
+            matchers.push(serde_json::from_value(value.clone()).map_err(|err| err.to_string())?);
+        }
+        Ok(Descend {
+            source,
+            fields,
+            matchers,
+        })
+    }
+}
+
+impl From<Descend> for Map<String, Value> {
+    fn from(descend: Descend) -> Map<String, Value> {
+        descend.source
+    }
+}
+
+impl Descend {
+    pub(crate) fn matchers(&self) -> &[ObjMatcher] {
+        &self.matchers
+    }
+}
+
+/// Whether `field` satisfies `matcher` in `value` or any of its descendants.
+fn occurs(value: &Value, field: &str, matcher: &ObjMatcher, options: &MatchOptions) -> bool {
+    match value {
+        Value::Object(o) => {
+            path::get(value, field).map_or(false, |found| matcher.evaluate(found, options))
+                || o.values()
+                    .any(|child| occurs(child, field, matcher, options))
+        }
+        Value::Array(a) => a.iter().any(|child| occurs(child, field, matcher, options)),
+        _ => false,
+    }
+}
+
+impl MatchesValue for DescendOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        self.val
+            .fields
+            .iter()
+            .zip(&self.val.matchers)
+            .all(|(field, matcher)| occurs(other, field, matcher, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_descend() {
+        let failed = from_str(r#"{"$descend": {"error_code": {"$ne": 0}}}"#).unwrap();
+        assert!(failed.matches(&json!({"error_code": 3})));
+        assert!(failed.matches(&json!({
+            "status": "ok",
+            "steps": [{"result": {"error_code": 0}}, {"result": {"inner": {"error_code": 7}}}]
+        })));
+        assert!(!failed.matches(&json!({"steps": [{"result": {"error_code": 0}}]})));
+        assert!(!failed.matches(&json!({"status": "ok"})));
+        assert!(failed
+            .compile()
+            .unwrap()
+            .matches(&json!([{"error_code": 1}])));
+
+        let nested =
+            from_str(r#"{"payload": {"$descend": {"error.code": 500, "service": "db"}}}"#).unwrap();
+        assert!(nested.matches(&json!({
+            "payload": {"a": {"error": {"code": 500}}, "b": [{"service": "db"}]}
+        })));
+        assert!(!nested.matches(&json!({"payload": {"a": {"error": {"code": 500}}}})));
+        assert!(!nested.matches(&json!({"error": {"code": 500}, "service": "db"})));
+        assert_eq!(
+            serde_json::to_value(&failed).unwrap(),
+            json!({"$descend": {"error_code": {"$ne": 0}}})
+        );
+
+        assert!(from_str(r#"{"$descend": {}}"#).is_err());
+        assert!(from_str(r#"{"$descend": {"$gt": 1}}"#).is_err());
+        assert!(from_str(r#"{"$descend": {"a": {"$gt": 1, "$nope": 1}}}"#).is_err());
+    }
+}
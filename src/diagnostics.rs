@@ -0,0 +1,320 @@
+//! Reporting every problem in a query at once, for editors that show all errors together rather
+//! than the first one `from_str` fails on.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    context, from_json_with_options, is_operator, params, parse_single_operator, ParseOptions,
+};
+
+/// Category of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// The query is not valid JSON.
+    Syntax,
+    /// A `$`-prefixed key that is not an operator. Fields starting with `$` must be escaped.
+    UnknownOperator,
+    /// A field next to an operator other than `$and` and `$or`.
+    UnexpectedKey,
+    /// An operator whose operand has the wrong type or value.
+    InvalidOperand,
+    /// An operator rejected by [`ParseOptions::deny_operator`].
+    DeniedOperator,
+    /// A violation of one of the [`ParseOptions`] limits.
+    Limit,
+    /// A `$ref` that cannot be resolved.
+    Reference,
+}
+
+/// A problem found in a query by [`validate`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// JSON Pointer to the offending part of the query, e.g. `"/$or/0/age/$gt"`, or `""` for the
+    /// whole query.
+    pub pointer: String,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+/// Returns every problem in the query `s`, in document order: whatever makes
+/// [`from_str`](crate::from_str) fail, as well as `$`-prefixed keys that are not operators, which
+/// `from_str` compares literally but which are almost always misspelled operators. An empty list
+/// means the query parses.
+#[must_use]
+pub fn validate(s: &str) -> Vec<Diagnostic> {
+    validate_with_options(s, &ParseOptions::default())
+}
+
+#[must_use]
+pub fn validate_with_options(s: &str, options: &ParseOptions) -> Vec<Diagnostic> {
+    let mut validator = Validator::new(options);
+    validator.size(s.len());
+    match serde_json::from_str(s) {
+        Ok(v) => validator.query(v),
+        Err(err) => validator.push("", DiagnosticKind::Syntax, err.to_string()),
+    }
+    validator.diagnostics
+}
+
+#[must_use]
+pub fn validate_json(v: Value) -> Vec<Diagnostic> {
+    validate_json_with_options(v, &ParseOptions::default())
+}
+
+#[must_use]
+pub fn validate_json_with_options(v: Value, options: &ParseOptions) -> Vec<Diagnostic> {
+    let mut validator = Validator::new(options);
+    if options.limits().size.is_some() {
+        if let Ok(s) = serde_json::to_string(&v) {
+            validator.size(s.len());
+        }
+    }
+    validator.query(v);
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    options: &'a ParseOptions,
+    diagnostics: Vec<Diagnostic>,
+    operators: usize,
+}
+
+impl<'a> Validator<'a> {
+    fn new(options: &'a ParseOptions) -> Self {
+        Validator {
+            options,
+            diagnostics: Vec::new(),
+            operators: 0,
+        }
+    }
+
+    fn push(&mut self, pointer: &str, kind: DiagnosticKind, message: String) {
+        self.diagnostics.push(Diagnostic {
+            pointer: pointer.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    fn size(&mut self, size: usize) {
+        match self.options.limits().size {
+            Some(max) if size > max => self.push(
+                "",
+                DiagnosticKind::Limit,
+                format!("query of {size} bytes exceeds the limit of {max}"),
+            ),
+            _ => {}
+        }
+    }
+
+    fn query(&mut self, v: Value) {
+        let v = match self.options.matcher_registry() {
+            Some(registry) => match registry.resolve(&v) {
+                Ok(resolved) => resolved,
+                Err(err) => {
+                    self.push("", DiagnosticKind::Reference, err.to_string());
+                    v
+                }
+            },
+            None => v,
+        };
+        self.matcher(&v, "");
+        // Anything the walk above does not anticipate is still reported, so that an empty list
+        // always means the query parses.
+        if self.diagnostics.is_empty() {
+            if let Err(err) = from_json_with_options(v, self.options) {
+                self.push("", DiagnosticKind::InvalidOperand, err.to_string());
+            }
+        }
+    }
+
+    /// A query, or the condition on a field.
+    fn matcher(&mut self, value: &Value, pointer: &str) {
+        let o = match value {
+            Value::Object(o) if !context::is_reference(value) && !params::is_placeholder(value) => {
+                o
+            }
+            _ => return self.literal(value, pointer),
+        };
+        let registry = self.options.operator_registry();
+        let operators: Vec<&String> = o.keys().filter(|key| is_operator(key, registry)).collect();
+        let logical = operators.iter().all(|key| *key == "$and" || *key == "$or");
+        for (key, operand) in o {
+            let pointer = join(pointer, key);
+            self.string(key, &pointer);
+            if key == "$comment" || key == "$ref" {
+                self.literal(operand, &pointer);
+            } else if is_operator(key, registry) {
+                self.operator(key, operand, &pointer);
+            } else if key.starts_with('$') {
+                self.push(
+                    &pointer,
+                    DiagnosticKind::UnknownOperator,
+                    format!("unknown operator `{key}`"),
+                );
+            } else if let (Some(first), false) = (operators.first(), logical) {
+                self.push(
+                    &pointer,
+                    DiagnosticKind::UnexpectedKey,
+                    format!("unexpected key `{key}` next to operator `{first}`"),
+                );
+            } else {
+                self.matcher(operand, &pointer);
+            }
+        }
+    }
+
+    fn operator(&mut self, key: &str, operand: &Value, pointer: &str) {
+        let limits = self.options.limits();
+        self.operators += 1;
+        if let Some(max) = limits.operators.filter(|max| self.operators == *max + 1) {
+            self.push(
+                pointer,
+                DiagnosticKind::Limit,
+                format!("query exceeds the limit of {max} operators"),
+            );
+        }
+        if self.options.is_denied(key) {
+            self.push(
+                pointer,
+                DiagnosticKind::DeniedOperator,
+                format!("operator `{key}` is not allowed"),
+            );
+        }
+        match (key, operand) {
+            ("$and" | "$or" | "$in" | "$nin", Value::Array(a)) => {
+                if let Some(max) = limits.arity.filter(|max| a.len() > *max) {
+                    self.push(
+                        pointer,
+                        DiagnosticKind::Limit,
+                        format!(
+                            "`{}` with {} elements exceeds the limit of {}",
+                            key,
+                            a.len(),
+                            max
+                        ),
+                    );
+                }
+                for (i, value) in a.iter().enumerate() {
+                    self.matcher(value, &join(pointer, &i.to_string()));
+                }
+            }
+            ("$not" | "$eq" | "$ne" | "$len", _) => self.matcher(operand, pointer),
+            _ => {
+                let registry = self.options.operator_registry();
+                let result = if let Some(custom) = registry.and_then(|r| r.get(key)) {
+                    custom.parse(operand)
+                } else {
+                    let mut single = Map::new();
+                    single.insert(key.to_string(), operand.clone());
+                    parse_single_operator(Value::Object(single), registry)
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                };
+                if let Err(err) = result {
+                    self.push(
+                        pointer,
+                        DiagnosticKind::InvalidOperand,
+                        format!("invalid `{key}`: {err}"),
+                    );
+                }
+                self.literal(operand, pointer);
+            }
+        }
+    }
+
+    /// Checks the strings of a value compared as is.
+    fn literal(&mut self, value: &Value, pointer: &str) {
+        match value {
+            Value::String(s) => self.string(s, pointer),
+            Value::Array(a) => {
+                for (i, value) in a.iter().enumerate() {
+                    self.literal(value, &join(pointer, &i.to_string()));
+                }
+            }
+            Value::Object(o) => {
+                for (key, value) in o {
+                    let pointer = join(pointer, key);
+                    self.string(key, &pointer);
+                    self.literal(value, &pointer);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn string(&mut self, s: &str, pointer: &str) {
+        if let Some(max) = self
+            .options
+            .limits()
+            .string_len
+            .filter(|max| s.len() > *max)
+        {
+            self.push(
+                pointer,
+                DiagnosticKind::Limit,
+                format!("string of {} bytes exceeds the limit of {}", s.len(), max),
+            );
+        }
+    }
+}
+
+/// Appends a reference token to a JSON Pointer, escaping it as RFC 6901 requires.
+fn join(pointer: &str, token: &str) -> String {
+    format!(
+        "{}/{}",
+        pointer,
+        token.replace('~', "~0").replace('/', "~1")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_validate() {
+        assert!(validate(r#"{"$or": [{"a": {"$in": [1, 2]}}, {"b": "x"}]}"#).is_empty());
+
+        let query = r#"{"$or": [{"age": {"$gt": 1, "typo": 2}}, {"n": {"$type": 5}}],
+                        "a/b": {"$foo": 1}, "c": {"$bitsAllSet": "x"}}"#;
+        assert!(from_str(query).is_err());
+        let diagnostics = validate(query);
+        let found: Vec<(&str, DiagnosticKind)> = diagnostics
+            .iter()
+            .map(|d| (d.pointer.as_str(), d.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("/$or/0/age/typo", DiagnosticKind::UnexpectedKey),
+                ("/$or/1/n/$type", DiagnosticKind::InvalidOperand),
+                ("/a~1b/$foo", DiagnosticKind::UnknownOperator),
+                ("/c/$bitsAllSet", DiagnosticKind::InvalidOperand),
+            ]
+        );
+
+        let options = ParseOptions::new()
+            .deny_operator("$nin")
+            .max_arity(2)
+            .max_string_len(4);
+        let diagnostics = validate_with_options(r#"{"name": {"$nin": [1, 2, "long!"]}}"#, &options);
+        assert_eq!(
+            serde_json::to_value(&diagnostics).unwrap(),
+            json!([
+                {"pointer": "/name/$nin", "kind": "denied_operator", "message": "operator `$nin` is not allowed"},
+                {"pointer": "/name/$nin", "kind": "limit", "message": "`$nin` with 3 elements exceeds the limit of 2"},
+                {"pointer": "/name/$nin/2", "kind": "limit", "message": "string of 5 bytes exceeds the limit of 4"},
+            ])
+        );
+
+        let diagnostics = validate(r#"{"a": "#);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::Syntax);
+        assert!(validate_json(json!({"a": {"$eq": 1}})).is_empty());
+    }
+}
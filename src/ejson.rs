@@ -0,0 +1,390 @@
+//! `MongoDB` Extended JSON v2 literals, such as `{"$date": "2024-01-01T00:00:00Z"}` or
+//! `{"$numberLong": "42"}`, which tools exporting from `MongoDB` write for values that plain JSON
+//! cannot hold.
+//!
+//! Queries have them replaced by their values when parsed, and documents when compared:
+//!
+//! - `$numberInt`, `$numberLong`, `$numberDouble` and `$numberDecimal` are numbers, except that
+//!   with the `decimal` feature, decimals are kept and compared exactly.
+//! - `$date`, given as an ISO 8601 string or as `{"$numberLong": "..."}`, is the number of
+//!   milliseconds since the Unix epoch, like `{"$now": {}}`.
+//! - `$oid`, like the shell's `"ObjectId(\"...\")"` strings, is the object id in lowercase
+//!   hexadecimal, which orders object ids by their bytes.
+//! - `$binary`, in the v2 form `{"$binary": {"base64": "...", "subType": "00"}}` or the legacy
+//!   form `{"$binary": "...", "$type": "00"}`, and `$uuid`, which is binary of subtype 4, are
+//!   rewritten into the v2 form with padded, standard base64. Binaries order by length, then
+//!   subtype, then bytes, as in `MongoDB`.
+//!
+//! Malformed literals, and numbers JSON cannot hold such as `{"$numberDouble": "NaN"}`, are left
+//! as they are.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use serde_json::{Map, Number, Value};
+
+/// The value of `value` if it is an Extended JSON literal other than a binary already in the
+/// form binaries are rewritten into.
+pub(crate) fn decode(value: &Value) -> Option<Value> {
+    let o = match value {
+        Value::Object(o) => o,
+        Value::String(s) if s.starts_with("ObjectId(") => {
+            let hex = s["ObjectId(".len()..].trim_end_matches(')');
+            let hex = hex.trim_matches(|c| c == '"' || c == '\'');
+            return object_id(hex).filter(|_| s.len() == "ObjectId(\"\")".len() + 24);
+        }
+        _ => return None,
+    };
+    if o.len() == 2 {
+        return match (o.get("$binary"), o.get("$type")) {
+            (Some(Value::String(base64)), Some(Value::String(subtype))) => binary(base64, subtype),
+            _ => None,
+        };
+    }
+    let (key, operand) = o.iter().next().filter(|_| o.len() == 1)?;
+    let text = operand.as_str();
+    match key.as_str() {
+        "$numberInt" => text?.parse::<i32>().ok().map(Value::from),
+        "$numberLong" => text?.parse::<i64>().ok().map(Value::from),
+        "$numberDouble" => {
+            let f = text?.parse::<f64>().ok().filter(|f| f.is_finite())?;
+            Number::from_f64(f).map(Value::Number)
+        }
+        #[cfg(not(feature = "decimal"))]
+        "$numberDecimal" => serde_json::from_str::<Number>(text?)
+            .ok()
+            .map(Value::Number),
+        "$date" => match operand {
+            Value::String(s) => parse_date(s).map(Value::from),
+            _ => decode(operand).filter(Value::is_i64),
+        },
+        "$oid" => object_id(text?),
+        "$binary" => {
+            let fields = operand.as_object()?;
+            let base64 = fields.get("base64")?.as_str()?;
+            let subtype = fields.get("subType")?.as_str()?;
+            binary(base64, subtype).filter(|decoded| decoded != value)
+        }
+        "$uuid" => {
+            let hex = text?.replace('-', "");
+            if text?.len() != 36 || hex.len() != 32 {
+                return None;
+            }
+            let bytes = (0..16)
+                .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok())
+                .collect::<Option<Vec<_>>>()?;
+            Some(binary_value(&bytes, 4))
+        }
+        _ => None,
+    }
+}
+
+fn object_id(hex: &str) -> Option<Value> {
+    if hex.len() == 24 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(Value::String(hex.to_ascii_lowercase()))
+    } else {
+        None
+    }
+}
+
+fn binary(base64: &str, subtype: &str) -> Option<Value> {
+    if subtype.is_empty() || subtype.len() > 2 {
+        return None;
+    }
+    let subtype = u8::from_str_radix(subtype, 16).ok()?;
+    Some(binary_value(&decode_base64(base64)?, subtype))
+}
+
+fn binary_value(bytes: &[u8], subtype: u8) -> Value {
+    let mut fields = Map::new();
+    fields.insert("base64".to_string(), Value::String(encode_base64(bytes)));
+    fields.insert(
+        "subType".to_string(),
+        Value::String(format!("{subtype:02x}")),
+    );
+    let mut binary = Map::new();
+    binary.insert("$binary".to_string(), Value::Object(fields));
+    Value::Object(binary)
+}
+
+/// The bytes and subtype of a binary in the form binaries are rewritten into.
+fn parse_binary(value: &Value) -> Option<(Vec<u8>, &str)> {
+    let fields = value.as_object()?.get("$binary")?.as_object()?;
+    let bytes = decode_base64(fields.get("base64")?.as_str()?)?;
+    Some((bytes, fields.get("subType")?.as_str()?))
+}
+
+/// The bytes of a binary in the form binaries are rewritten into.
+pub(crate) fn binary_bytes(value: &Value) -> Option<Vec<u8>> {
+    parse_binary(value).map(|(bytes, _)| bytes)
+}
+
+/// Whether `value` is a literal left as it is when decoding, which equals only values it stands
+/// for rather than being taken for field conditions.
+pub(crate) fn is_literal(value: &Value) -> bool {
+    #[cfg(feature = "decimal")]
+    {
+        if crate::decimal::literal(value).is_some() {
+            return true;
+        }
+    }
+    parse_binary(value).is_some()
+}
+
+/// Orders `a` relative to `b`, if both are binaries or, with the `decimal` feature, one is a
+/// decimal and the other a number.
+pub(crate) fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    #[cfg(feature = "decimal")]
+    {
+        if let Some(ordering) = crate::decimal::compare(a, b) {
+            return Some(ordering);
+        }
+    }
+    let (a, a_subtype) = parse_binary(a)?;
+    let (b, b_subtype) = parse_binary(b)?;
+    Some(
+        a.len()
+            .cmp(&b.len())
+            .then_with(|| a_subtype.cmp(b_subtype))
+            .then_with(|| a.cmp(&b)),
+    )
+}
+
+/// Decodes base64 in the standard or URL-safe alphabet, with or without padding, ignoring
+/// whitespace.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3 + 2);
+    let (mut buffer, mut bits) = (0_u32, 0);
+    let mut padding = false;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => {
+                padding = true;
+                continue;
+            }
+            _ => return None,
+        };
+        if padding {
+            return None;
+        }
+        buffer = buffer << 6 | u32::from(digit);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // A single digit left over holds less than a byte.
+    if bits >= 6 {
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Encodes `bytes` in padded, standard base64.
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(char::from(DIGITS[(n >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// `value`, or its value if it is an Extended JSON literal.
+pub(crate) fn decoded(value: &Value) -> Cow<'_, Value> {
+    match decode(value) {
+        Some(decoded) => Cow::Owned(decoded),
+        None => Cow::Borrowed(value),
+    }
+}
+
+/// Replaces every Extended JSON literal in `value`.
+pub(crate) fn decode_all(value: Value) -> Value {
+    if let Some(decoded) = decode(&value) {
+        return decoded;
+    }
+    match value {
+        Value::Object(o) => Value::Object(o.into_iter().map(|(k, v)| (k, decode_all(v))).collect()),
+        Value::Array(a) => Value::Array(a.into_iter().map(decode_all).collect()),
+        value => value,
+    }
+}
+
+/// Milliseconds since the Unix epoch of a date such as `2024-01-31`, `2024-01-31T08:00:00Z` or
+/// `2024-01-31T08:00:00.250+01:00`.
+fn parse_date(s: &str) -> Option<i64> {
+    let number = |s: &str| -> Option<i64> {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (date, time) = match s.find('T') {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(parts.next()?)?,
+        number(parts.next()?)?,
+        number(parts.next()?)?,
+    );
+    if date.len() != 10 || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month)
+    {
+        return None;
+    }
+    let mut millis = days_from_civil(year, month, day) * 86_400_000;
+    let time = match time {
+        Some(time) => time,
+        None => return Some(millis),
+    };
+    let (clock, offset) = match time.find(['Z', '+', '-']) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => return None,
+    };
+    let (clock, fraction) = match clock.find('.') {
+        Some(i) => (&clock[..i], Some(&clock[i + 1..])),
+        None => (clock, None),
+    };
+    let mut fields = clock.splitn(3, ':');
+    let (hour, minute, second) = (
+        number(fields.next()?)?,
+        number(fields.next()?)?,
+        number(fields.next().unwrap_or("00"))?,
+    );
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    millis += ((hour * 60 + minute) * 60 + second) * 1000;
+    if let Some(fraction) = fraction {
+        number(fraction)?;
+        let digits = format!("{:0<3}", &fraction[..fraction.len().min(3)]);
+        millis += number(&digits)?;
+    }
+    if offset != "Z" {
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let offset = offset[1..].replace(':', "");
+        if offset.len() != 4 {
+            return None;
+        }
+        let minutes = number(&offset[..2])? * 60 + number(&offset[2..])?;
+        millis -= sign * minutes * 60_000;
+    }
+    Some(millis)
+}
+
+pub(crate) fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_json;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_extended_json() {
+        assert_eq!(parse_date("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_date("2024-02-29T12:30:15.5+02:00"),
+            Some(1_709_202_615_500)
+        );
+        assert_eq!(parse_date("1969-12-31"), Some(-86_400_000));
+        assert_eq!(parse_date("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_date("2024-01-01T00:00:00"), None);
+
+        let query = json!({
+            "_id": {"$oid": "5F2A0C9B8E4D3A1B2C3D4E5F"},
+            "createdAt": {"$gte": {"$date": "2024-01-01T00:00:00Z"}},
+            "views": {"$gt": {"$numberLong": "1000"}},
+            "price": {"$in": [{"$numberDecimal": "9.99"}, {"$numberDecimal": "19.99"}]},
+        });
+        let matcher = from_json(query).unwrap();
+        let exported = json!({
+            "_id": {"$oid": "5f2a0c9b8e4d3a1b2c3d4e5f"},
+            "createdAt": {"$date": {"$numberLong": "1717200000000"}},
+            "views": {"$numberLong": "5000"},
+            "price": {"$numberDecimal": "19.99"},
+        });
+        assert!(matcher.matches(&exported));
+        let plain = json!({
+            "_id": "5f2a0c9b8e4d3a1b2c3d4e5f",
+            "createdAt": 1_717_200_000_000_i64,
+            "views": 5000,
+            "price": 19.99,
+        });
+        assert!(matcher.matches(&plain));
+        assert!(matcher.compile().unwrap().matches(&exported));
+        let text =
+            r#"{"_id": {"$ne": {"$oid": "5f2a0c9b8e4d3a1b2c3d4e5f"}}, "views": {"$lte": 5000}}"#;
+        let borrowed = crate::BorrowedMatcher::parse(text).unwrap();
+        assert!(!borrowed.matches(&exported));
+        assert!(borrowed.matches(&json!({"_id": {"$oid": "000000000000000000000000"}, "views": 1})));
+        let mut old = exported;
+        old["createdAt"] = json!({"$date": "2023-06-01T00:00:00Z"});
+        assert!(!matcher.matches(&old));
+        // Object ids and binaries in any of their forms.
+        let matcher = from_json(json!({
+            "_id": {"$lt": {"$oid": "65A0000000000000000000FF"}},
+            "key": {"$binary": {"base64": "+/8", "subType": "0"}},
+            "uuid": {"$uuid": "123e4567-e89b-12d3-a456-426614174000"},
+        }))
+        .unwrap();
+        let doc = json!({
+            "_id": "ObjectId(\"65a0000000000000000000f0\")",
+            "key": {"$binary": "-_8=", "$type": "00"},
+            "uuid": {"$binary": {"base64": "Ej5FZ+ibEtOkVkJmFBdAAA==", "subType": "04"}},
+        });
+        assert!(matcher.matches(&doc));
+        let shorter =
+            from_json(json!({"k": {"$lt": {"$binary": {"base64": "AAAA", "subType": "00"}}}}))
+                .unwrap();
+        assert!(shorter.matches(&json!({"k": {"$binary": {"base64": "/w==", "subType": "80"}}})));
+        assert!(!shorter.matches(&json!({"k": {"$binary": {"base64": "AAAB", "subType": "00"}}})));
+        assert_eq!(
+            encode_base64(&decode_base64("aGVsbG8").unwrap()),
+            "aGVsbG8="
+        );
+        assert_eq!(decode_base64("a"), None);
+
+        // Malformed literals are compared as written.
+        assert!(from_json(json!({"n": {"$numberLong": "x"}}))
+            .unwrap()
+            .matches(&json!({"n": {"$numberLong": "x"}})));
+    }
+}
@@ -0,0 +1,113 @@
+//! Matching documents held as their top-level fields, such as a borrowed `Map` or a slice of
+//! key-value pairs, without first building a `Value::Object`.
+
+use serde_json::{Map, Value};
+
+use crate::walk::{self, Condition};
+use crate::{path, values_equal, Compatibility, MatchOptions, ObjMatcher};
+
+/// A document given by its top-level fields.
+pub(crate) trait Fields {
+    fn field(&self, key: &str) -> Option<&Value>;
+
+    fn to_object(&self) -> Value;
+}
+
+impl Fields for Map<String, Value> {
+    fn field(&self, key: &str) -> Option<&Value> {
+        self.get(key)
+    }
+
+    fn to_object(&self) -> Value {
+        Value::Object(self.clone())
+    }
+}
+
+/// Like a `Map` built from the pairs, a later pair replaces an earlier one of the same key.
+impl Fields for [(&str, &Value)] {
+    fn field(&self, key: &str) -> Option<&Value> {
+        self.iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| *value)
+    }
+
+    fn to_object(&self) -> Value {
+        Value::Object(
+            self.iter()
+                .map(|(key, value)| ((*key).to_string(), (*value).clone()))
+                .collect(),
+        )
+    }
+}
+
+/// Evaluates `matcher` on the document made of `fields`, looking the conditions on fields up in
+/// place. Only the operators applying to the document as a whole, such as `$exact` or `$expr`,
+/// need it built as a `Value`.
+pub(crate) fn matches<F: Fields + ?Sized>(
+    matcher: &ObjMatcher,
+    fields: &F,
+    options: &MatchOptions,
+) -> bool {
+    match matcher {
+        ObjMatcher::And(op) => op.val.iter().all(|m| matches(m, fields, options)),
+        ObjMatcher::Or(op) => op.val.iter().any(|m| matches(m, fields, options)),
+        ObjMatcher::Not(op) => !matches(&op.val, fields, options),
+        ObjMatcher::Value(value) => match walk::value(value, options.operator_registry()) {
+            walk::Clause::Operator(matcher) => matches(&matcher, fields, options),
+            walk::Clause::Fields(conditions) if options.profile() == Compatibility::Native => {
+                walk::conditions(conditions, options.operator_registry()).all(|(key, condition)| {
+                    let actual = path::lookup_field(|key| fields.field(key), key);
+                    match condition {
+                        Condition::Operator(matcher) => matcher.evaluate(actual, options),
+                        Condition::Literal(value) => values_equal(value, actual, options),
+                    }
+                })
+            }
+            _ => matcher.evaluate(&fields.to_object(), options),
+        },
+        _ => matcher.evaluate(&fields.to_object(), options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_entries() {
+        let matcher = from_str(
+            r#"{"level": {"$in": ["warn", "error"]}, "http.status": {"$gte": 500}, "$or": [{"retry": true}, {"attempt": {"$gt": 2}}]}"#,
+        )
+        .unwrap();
+        let doc = json!({"level": "error", "http": {"status": 503}, "attempt": 3});
+        let map = doc.as_object().unwrap();
+        assert!(matcher.matches_map(map));
+        assert_eq!(matcher.matches_map(map), matcher.matches(&doc));
+
+        let level = json!("error");
+        let http = json!({"status": 404});
+        let retry = json!(true);
+        let entries = [("level", &level), ("http", &http), ("retry", &retry)];
+        assert!(!matcher.matches_entries(&entries));
+        let http = json!({"status": 500});
+        let entries = [("level", &level), ("http", &http), ("retry", &retry)];
+        assert!(matcher.matches_entries(&entries));
+
+        // A later pair replaces an earlier one, and missing fields are `null`.
+        let warn = json!("warn");
+        let entries = [("level", &level), ("level", &warn)];
+        assert!(from_str(r#"{"level": "warn", "other": null}"#)
+            .unwrap()
+            .matches_entries(&entries));
+
+        // Operators on the whole document see the fields as an object.
+        let has_keys = from_str(r#"{"$hasKeys": ["level", "http"]}"#).unwrap();
+        assert!(has_keys.matches_map(map));
+        assert!(!has_keys.matches_entries(&[("level", &level)]));
+        assert!(from_str(r#"{"$not": {"level": "warn"}}"#)
+            .unwrap()
+            .matches_map(map));
+    }
+}
@@ -0,0 +1,502 @@
+//! Explaining why a document does or does not match, as a serializable tree of clauses.
+
+use std::borrow::Cow;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::compat::{self, Candidate};
+use crate::walk::{self, Condition};
+use crate::{values_equal, Compatibility, MatchOptions, ObjMatcher};
+
+/// Outcome of one clause of a matcher against a document, with the outcomes of its sub-clauses.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExplainReport {
+    /// Fields leading from the document root to the value the clause was applied to.
+    pub path: Vec<String>,
+    /// Operator of the clause, or `None` for a literal comparison or a group of field clauses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    pub outcome: bool,
+    /// Value the clause was applied to, or `None` if the field is missing.
+    pub actual: Option<Value>,
+    /// Operand of the clause, for clauses without sub-clauses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ExplainReport>,
+}
+
+impl ObjMatcher {
+    /// Evaluates the matcher against `value`, recording the outcome of every clause.
+    ///
+    /// Unlike [`matches`](Self::matches), every clause is evaluated, even when the outcome is
+    /// already decided.
+    #[must_use]
+    pub fn explain(&self, value: &Value) -> ExplainReport {
+        self.explain_with_options(value, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn explain_with_options(&self, value: &Value, options: &MatchOptions) -> ExplainReport {
+        Explainer { options }.matcher(self, Some(value), &[])
+    }
+
+    /// Returns `None` if `value` does not match, and otherwise the paths of the values that
+    /// satisfied the positive clauses of the matcher, for highlighting or redacting them.
+    ///
+    /// Paths are listed once, in the order of the clauses. Clauses under `$not`, `$ne` or `$nin`
+    /// hold because of values that are absent or different, and contribute no path, nor do the
+    /// clauses of an `$or` that did not hold. A clause on the value itself, such as a top-level
+    /// `$expr`, contributes the empty path.
+    #[must_use]
+    pub fn matches_with_paths(&self, value: &Value) -> Option<Vec<Vec<String>>> {
+        self.matches_with_paths_with_options(value, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_with_paths_with_options(
+        &self,
+        value: &Value,
+        options: &MatchOptions,
+    ) -> Option<Vec<Vec<String>>> {
+        let report = self.explain_with_options(value, options);
+        if !report.outcome {
+            return None;
+        }
+        let mut paths = Vec::new();
+        satisfied_paths(&report, &mut paths);
+        Some(paths)
+    }
+
+    /// Like [`matches_with_paths`](Self::matches_with_paths), but follows the array elements
+    /// that satisfied each clause, for highlighting the offending element rather than the whole
+    /// array.
+    ///
+    /// Under the [`Compatibility`] profiles that traverse arrays, a clause on `items.sku` that
+    /// held for the second item contributes `["items", "1", "sku"]`, and a clause on `tags` that
+    /// held for its first element contributes `["tags", "0"]`. A clause that held for no single
+    /// element, such as `{"$gt": 1, "$lt": 5}` satisfied by two different elements, contributes
+    /// the path of the field. `$countMatching` contributes the elements its matcher held for.
+    #[must_use]
+    pub fn matches_with_locations(&self, value: &Value) -> Option<Vec<Vec<String>>> {
+        self.matches_with_locations_with_options(value, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_with_locations_with_options(
+        &self,
+        value: &Value,
+        options: &MatchOptions,
+    ) -> Option<Vec<Vec<String>>> {
+        if !self.matches_with_options(value, options) {
+            return None;
+        }
+        let mut locator = Locator {
+            options,
+            locations: Vec::new(),
+        };
+        locator.matcher(self, value, &[]);
+        Some(locator.locations)
+    }
+}
+
+fn satisfied_paths(report: &ExplainReport, paths: &mut Vec<Vec<String>>) {
+    if !report.outcome {
+        return;
+    }
+    match report.operator.as_deref() {
+        Some("$not" | "$ne" | "$nin") => {}
+        _ if report.expected.is_some() => {
+            if !paths.contains(&report.path) {
+                paths.push(report.path.clone());
+            }
+        }
+        _ => {
+            for child in &report.children {
+                satisfied_paths(child, paths);
+            }
+        }
+    }
+}
+
+/// Collects the locations of [`ObjMatcher::matches_with_locations`] from a matching document.
+struct Locator<'a> {
+    options: &'a MatchOptions,
+    locations: Vec<Vec<String>>,
+}
+
+impl Locator<'_> {
+    fn push(&mut self, location: Vec<String>) {
+        if !self.locations.contains(&location) {
+            self.locations.push(location);
+        }
+    }
+
+    fn matcher(&mut self, matcher: &ObjMatcher, actual: &Value, path: &[String]) {
+        match matcher {
+            ObjMatcher::Not(_) | ObjMatcher::Ne(_) | ObjMatcher::Nin(_) => {}
+            ObjMatcher::And(op) => {
+                for matcher in &op.val {
+                    self.matcher(matcher, actual, path);
+                }
+            }
+            ObjMatcher::Or(op) => {
+                for matcher in &op.val {
+                    if matcher.evaluate(actual, self.options) {
+                        self.matcher(matcher, actual, path);
+                    }
+                }
+            }
+            ObjMatcher::CountMatching(op) => {
+                let elements = match actual {
+                    Value::Array(elements) => elements.as_slice(),
+                    _ => &[],
+                };
+                let before = self.locations.len();
+                for (index, element) in elements.iter().enumerate() {
+                    if op.val.matcher().evaluate(element, self.options) {
+                        let mut location = path.to_vec();
+                        location.push(index.to_string());
+                        self.push(location);
+                    }
+                }
+                if self.locations.len() == before {
+                    self.push(path.to_vec());
+                }
+            }
+            ObjMatcher::Value(value) => {
+                let registry = self.options.operator_registry();
+                match walk::value(value, registry) {
+                    walk::Clause::Operator(matcher) => self.matcher(&matcher, actual, path),
+                    walk::Clause::Fields(fields) => {
+                        for (key, condition) in walk::conditions(fields, registry) {
+                            self.field(key, &condition, actual, path);
+                        }
+                    }
+                    walk::Clause::Literal(_) => self.push(path.to_vec()),
+                }
+            }
+            _ => self.push(path.to_vec()),
+        }
+    }
+
+    fn field(&mut self, key: &str, condition: &Condition<'_>, actual: &Value, path: &[String]) {
+        if self.options.profile() == Compatibility::Native {
+            let mut path = path.to_vec();
+            path.extend(crate::path::segments(key).map(Cow::into_owned));
+            match condition {
+                Condition::Operator(matcher) => {
+                    self.matcher(matcher, crate::path::lookup(actual, key), &path);
+                }
+                Condition::Literal(_) => self.push(path),
+            }
+            return;
+        }
+        if let Condition::Operator(ObjMatcher::Not(_) | ObjMatcher::Ne(_) | ObjMatcher::Nin(_)) =
+            condition
+        {
+            return;
+        }
+        let options = self.options;
+        let holds = |candidate: Candidate<'_>| match condition {
+            Condition::Operator(matcher) => {
+                compat::matches_candidates(matcher, &[candidate], options)
+            }
+            Condition::Literal(value) => {
+                values_equal(value, candidate.unwrap_or(&Value::Null), options)
+            }
+        };
+        let before = self.locations.len();
+        for (location, candidate) in compat::located_candidates(actual, key, self.options) {
+            if !holds(candidate) {
+                continue;
+            }
+            let location = [path, &location].concat();
+            match (condition, candidate) {
+                (Condition::Operator(matcher), Some(candidate)) => {
+                    self.matcher(matcher, candidate, &location);
+                }
+                _ => self.push(location),
+            }
+        }
+        if self.locations.len() == before {
+            let mut path = path.to_vec();
+            path.extend(crate::path::segments(key).map(Cow::into_owned));
+            self.push(path);
+        }
+    }
+}
+
+struct Explainer<'a> {
+    options: &'a MatchOptions,
+}
+
+impl Explainer<'_> {
+    fn report(path: &[String], operator: &str, actual: Option<&Value>) -> ExplainReport {
+        ExplainReport {
+            path: path.to_vec(),
+            operator: Some(operator.to_string()),
+            outcome: false,
+            actual: actual.cloned(),
+            expected: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn group(
+        &self,
+        operator: &str,
+        matchers: &[ObjMatcher],
+        actual: Option<&Value>,
+        path: &[String],
+    ) -> ExplainReport {
+        let mut report = Self::report(path, operator, actual);
+        report.children = matchers
+            .iter()
+            .map(|m| self.matcher(m, actual, path))
+            .collect();
+        report
+    }
+
+    fn matcher(
+        &self,
+        matcher: &ObjMatcher,
+        actual: Option<&Value>,
+        path: &[String],
+    ) -> ExplainReport {
+        let any = |report: &ExplainReport| report.children.iter().any(|c| c.outcome);
+        let all = |report: &ExplainReport| report.children.iter().all(|c| c.outcome);
+        match matcher {
+            ObjMatcher::Eq(op) => self.wrap("$eq", &op.val, actual, path, false),
+            ObjMatcher::Ne(op) => self.wrap("$ne", &op.val, actual, path, true),
+            ObjMatcher::Not(op) => self.wrap("$not", &op.val, actual, path, true),
+            ObjMatcher::In(op) => {
+                let mut report = self.group("$in", &op.val, actual, path);
+                report.outcome = any(&report);
+                report
+            }
+            ObjMatcher::Nin(op) => {
+                let mut report = self.group("$nin", &op.val, actual, path);
+                report.outcome = !any(&report);
+                report
+            }
+            ObjMatcher::And(op) => {
+                let mut report = self.group("$and", &op.val, actual, path);
+                report.outcome = all(&report);
+                report
+            }
+            ObjMatcher::Or(op) => {
+                let mut report = self.group("$or", &op.val, actual, path);
+                report.outcome = any(&report);
+                report
+            }
+            ObjMatcher::Value(value) => self.value(value, actual, path),
+            other => {
+                let expected = serde_json::to_value(other).unwrap_or(Value::Null);
+                let operator = match &expected {
+                    Value::Object(o) => o.keys().find(|k| *k != "$comment").cloned(),
+                    _ => None,
+                };
+                let expected = match (&operator, &expected) {
+                    (Some(operator), Value::Object(o)) => o.get(operator).cloned(),
+                    _ => Some(expected),
+                };
+                ExplainReport {
+                    path: path.to_vec(),
+                    operator,
+                    outcome: other.evaluate(actual.unwrap_or(&Value::Null), self.options),
+                    actual: actual.cloned(),
+                    expected,
+                    children: Vec::new(),
+                }
+            }
+        }
+    }
+
+    fn wrap(
+        &self,
+        operator: &str,
+        matcher: &ObjMatcher,
+        actual: Option<&Value>,
+        path: &[String],
+        negate: bool,
+    ) -> ExplainReport {
+        let child = self.matcher(matcher, actual, path);
+        let mut report = Self::report(path, operator, actual);
+        report.outcome = child.outcome != negate;
+        report.children.push(child);
+        report
+    }
+
+    fn value(&self, value: &Value, actual: Option<&Value>, path: &[String]) -> ExplainReport {
+        let registry = self.options.operator_registry();
+        let fields = match walk::value(value, registry) {
+            walk::Clause::Operator(matcher) => return self.matcher(&matcher, actual, path),
+            walk::Clause::Fields(fields) => fields,
+            walk::Clause::Literal(value) => return self.literal(value, actual, path),
+        };
+        let mut children = Vec::new();
+        for (key, condition) in walk::conditions(fields, registry) {
+            let mut path = path.to_vec();
+            path.extend(crate::path::segments(key).map(Cow::into_owned));
+            let actual = actual.and_then(|actual| crate::path::get(actual, key));
+            children.push(match condition {
+                Condition::Operator(matcher) => self.matcher(&matcher, actual, &path),
+                Condition::Literal(value) => self.literal(value, actual, &path),
+            });
+        }
+        ExplainReport {
+            path: path.to_vec(),
+            operator: None,
+            outcome: children.iter().all(|c| c.outcome),
+            actual: actual.cloned(),
+            expected: None,
+            children,
+        }
+    }
+
+    fn literal(&self, expected: &Value, actual: Option<&Value>, path: &[String]) -> ExplainReport {
+        ExplainReport {
+            path: path.to_vec(),
+            operator: None,
+            outcome: values_equal(expected, actual.unwrap_or(&Value::Null), self.options),
+            actual: actual.cloned(),
+            expected: Some(expected.clone()),
+            children: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_str, Compatibility, MatchOptions};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_explain() {
+        let matcher = from_str(r#"{"type": "order", "total": {"$nin": [0, null]}}"#).unwrap();
+        let doc = json!({"type": "order"});
+        let report = matcher.explain(&doc);
+        assert_eq!(report.outcome, matcher.matches(&doc));
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "path": [],
+                "outcome": false,
+                "actual": {"type": "order"},
+                "children": [
+                    {
+                        "path": ["total"],
+                        "operator": "$nin",
+                        "outcome": false,
+                        "actual": null,
+                        "children": [
+                            {"path": ["total"], "outcome": false, "actual": null, "expected": 0},
+                            {"path": ["total"], "outcome": true, "actual": null, "expected": null}
+                        ]
+                    },
+                    {"path": ["type"], "outcome": true, "actual": "order", "expected": "order"}
+                ]
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_explain_agrees_with_matches() {
+        let matcher = from_str(
+            r#"{"$or": [{"a": {"$type": ["number"]}}, {"b": {"$not": {"$eq": 1}}, "c": [1, 2]}]}"#,
+        )
+        .unwrap();
+        let docs = [
+            json!({"a": 1}),
+            json!({"a": "x", "b": 2, "c": [1, 2]}),
+            json!({"a": "x", "b": 1, "c": [1, 2]}),
+            json!({"b": 2, "c": [2, 1]}),
+        ];
+        for doc in &docs {
+            assert_eq!(matcher.explain(doc).outcome, matcher.matches(doc), "{doc}");
+        }
+        let report = matcher.explain(&docs[0]);
+        let type_clause = &report.children[0].children[0];
+        assert_eq!(type_clause.operator.as_deref(), Some("$type"));
+        assert_eq!(type_clause.expected, Some(json!(["number"])));
+    }
+
+    #[test]
+    pub fn test_matches_with_paths() {
+        let matcher = from_str(
+            r#"{"$or": [{"user.email": {"$type": ["string"]}}, {"phone": {"$ne": null}}],
+                "status": {"$in": ["open", "new"]}, "tags": {"$nin": ["spam"]}}"#,
+        )
+        .unwrap();
+        let doc = json!({"user": {"email": "a@b.c"}, "phone": "1", "status": "open", "tags": []});
+        assert_eq!(
+            matcher.matches_with_paths(&doc),
+            Some(vec![
+                vec!["user".to_string(), "email".to_string()],
+                vec!["status".to_string()],
+            ])
+        );
+        assert_eq!(
+            matcher.matches_with_paths(&json!({"phone": "1", "status": "new"})),
+            Some(vec![vec!["status".to_string()]])
+        );
+        assert_eq!(matcher.matches_with_paths(&json!({"status": "open"})), None);
+        let whole = from_str(r#"{"$expr": {"$gt": ["$a", 1]}}"#).unwrap();
+        assert_eq!(
+            whole.matches_with_paths(&json!({"a": 2})),
+            Some(vec![Vec::<String>::new()])
+        );
+    }
+
+    #[test]
+    pub fn test_matches_with_locations() {
+        let location = |path: &[&str]| {
+            path.iter()
+                .map(std::string::ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        let mongo = MatchOptions::new().compatibility(Compatibility::StrictMongo);
+        let doc = json!({
+            "tags": ["a", "b"],
+            "items": [{"sku": "A1", "qty": 2}, {"sku": "B2", "qty": 7}],
+            "reviews": [{"stars": 5}, {"stars": 1}, {"stars": 5}]
+        });
+
+        let matcher = from_str(
+            r#"{"tags": "b", "items.sku": "B2", "items.qty": {"$gt": 1, "$lt": 5},
+                "reviews": {"$countMatching": {"matcher": {"stars": 5}, "gte": 2}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            matcher.matches_with_locations_with_options(&doc, &mongo),
+            Some(vec![
+                location(&["items", "0", "qty"]),
+                location(&["items", "1", "sku"]),
+                location(&["reviews", "0"]),
+                location(&["reviews", "2"]),
+                location(&["tags", "1"]),
+            ])
+        );
+
+        // Satisfied by no single element, or by an absence.
+        let matcher =
+            from_str(r#"{"items.qty": {"$gt": 5, "$lt": 3}, "tags": {"$ne": "c"}}"#).unwrap();
+        assert_eq!(
+            matcher.matches_with_locations_with_options(&doc, &mongo),
+            Some(vec![location(&["items", "qty"])])
+        );
+        assert_eq!(
+            matcher.matches_with_locations_with_options(&json!({"tags": ["c"]}), &mongo),
+            None
+        );
+
+        // Without array traversal, locations are the paths of the clauses.
+        let matcher = from_str(r#"{"tags": ["a", "b"], "items.1.qty": {"$gte": 7}}"#).unwrap();
+        assert_eq!(
+            matcher.matches_with_locations(&doc),
+            Some(vec![location(&["items", "1", "qty"]), location(&["tags"])])
+        );
+    }
+}
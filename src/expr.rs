@@ -0,0 +1,436 @@
+//! `$expr`, evaluating an aggregation expression over the fields of the candidate value.
+//!
+//! `{"$expr": {"$gt": [{"$add": ["$price", "$tax"]}, 100]}}` matches values whose `price` and
+//! `tax` add up to more than 100. Strings starting with `$` refer to fields of the value, and
+//! `{"$literal": value}` stands for `value` as written. The value matches when the expression
+//! evaluates to anything but `false`, `null` or zero; missing fields evaluate to `null`.
+//!
+//! Supported functions:
+//!
+//! - comparison: `$eq`, `$ne`, `$gt`, `$gte`, `$lt`, `$lte`, comparing any two values in the
+//!   order of `$sort`;
+//! - logic: `$and`, `$or`, `$not`, and `$cond`, written `{"$cond": {"if": .., "then": ..,
+//!   "else": ..}}` or `{"$cond": [if, then, else]}`, which evaluates only the branch taken;
+//! - arithmetic: `$add`, `$subtract`, `$multiply`, `$divide`, `$mod`, which evaluate to `null`
+//!   unless all their arguments are numbers;
+//! - strings: `$toLower`, `$toUpper`, `$trim`, `$concat`, `$strLen`, which evaluate to `null`
+//!   unless all their arguments are strings;
+//! - arrays: `$size`, and `$arrayElemAt` with negative indexes counting from the end.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use crate::{order, path, values_equal, ExprOperator, MatchOptions, MatchesValue};
+
+/// Operand of `$expr`, checked when the matcher is parsed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Value", into = "Value")]
+pub struct Expression {
+    source: Value,
+    expr: Expr,
+}
+
+impl TryFrom<Value> for Expression {
+    type Error = String;
+
+    fn try_from(source: Value) -> Result<Self, String> {
+        let expr = Expr::parse(&source)?;
+        Ok(Expression { source, expr })
+    }
+}
+
+impl From<Expression> for Value {
+    fn from(expression: Expression) -> Value {
+        expression.source
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Field(String),
+    Array(Vec<Expr>),
+    Object(Vec<(String, Expr)>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    And,
+    Or,
+    Not,
+    Cond,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Mod,
+    ToLower,
+    ToUpper,
+    Trim,
+    Concat,
+    StrLen,
+    Size,
+    ArrayElemAt,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        Some(match name {
+            "$eq" => Function::Eq,
+            "$ne" => Function::Ne,
+            "$gt" => Function::Gt,
+            "$gte" => Function::Gte,
+            "$lt" => Function::Lt,
+            "$lte" => Function::Lte,
+            "$and" => Function::And,
+            "$or" => Function::Or,
+            "$not" => Function::Not,
+            "$cond" => Function::Cond,
+            "$add" => Function::Add,
+            "$subtract" => Function::Subtract,
+            "$multiply" => Function::Multiply,
+            "$divide" => Function::Divide,
+            "$mod" => Function::Mod,
+            "$toLower" => Function::ToLower,
+            "$toUpper" => Function::ToUpper,
+            "$trim" => Function::Trim,
+            "$concat" => Function::Concat,
+            "$strLen" => Function::StrLen,
+            "$size" => Function::Size,
+            "$arrayElemAt" => Function::ArrayElemAt,
+            _ => return None,
+        })
+    }
+
+    /// Number of arguments taken, or `None` for any number.
+    fn arity(self) -> Option<usize> {
+        match self {
+            Function::Not
+            | Function::ToLower
+            | Function::ToUpper
+            | Function::Trim
+            | Function::StrLen
+            | Function::Size => Some(1),
+            Function::And
+            | Function::Or
+            | Function::Add
+            | Function::Multiply
+            | Function::Concat => None,
+            Function::Cond => Some(3),
+            _ => Some(2),
+        }
+    }
+}
+
+impl Expr {
+    fn parse(value: &Value) -> Result<Expr, String> {
+        match value {
+            Value::String(s) if s.starts_with('$') => Ok(Expr::Field(s[1..].to_string())),
+            Value::Array(a) => a
+                .iter()
+                .map(Expr::parse)
+                .collect::<Result<_, _>>()
+                .map(Expr::Array),
+            Value::Object(o) if o.len() == 1 && o.keys().all(|key| key.starts_with('$')) => {
+                let (name, operand) = o.iter().next().expect("one entry");
+                if name == "$literal" {
+                    return Ok(Expr::Literal(operand.clone()));
+                }
+                let function = Function::from_name(name)
+                    .ok_or_else(|| format!("unknown expression operator `{name}`"))?;
+                let args = match operand {
+                    Value::Object(branches) if function == Function::Cond => {
+                        return Self::parse_cond(branches);
+                    }
+                    Value::Array(a) => a.iter().map(Expr::parse).collect::<Result<Vec<_>, _>>()?,
+                    _ => vec![Expr::parse(operand)?],
+                };
+                match function.arity() {
+                    Some(arity) if arity != args.len() => Err(format!(
+                        "`{}` takes {} argument{}, found {}",
+                        name,
+                        arity,
+                        if arity == 1 { "" } else { "s" },
+                        args.len()
+                    )),
+                    _ => Ok(Expr::Call(function, args)),
+                }
+            }
+            Value::Object(o) => match o.keys().find(|key| key.starts_with('$')) {
+                Some(key) => Err(format!("unexpected key `{key}` in expression object")),
+                None => o
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), Expr::parse(value)?)))
+                    .collect::<Result<_, String>>()
+                    .map(Expr::Object),
+            },
+            _ => Ok(Expr::Literal(value.clone())),
+        }
+    }
+
+    /// Parses the `{"if": .., "then": .., "else": ..}` form of `$cond`.
+    fn parse_cond(branches: &Map<String, Value>) -> Result<Expr, String> {
+        if let Some(key) = branches
+            .keys()
+            .find(|key| !["if", "then", "else"].contains(&key.as_str()))
+        {
+            return Err(format!("unexpected key `{key}` in `$cond`"));
+        }
+        let branch = |key: &str| match branches.get(key) {
+            Some(value) => Expr::parse(value),
+            None => Err(format!("`$cond` is missing `{key}`")),
+        };
+        Ok(Expr::Call(
+            Function::Cond,
+            vec![branch("if")?, branch("then")?, branch("else")?],
+        ))
+    }
+
+    fn evaluate(&self, root: &Value, options: &MatchOptions) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(field) => path::lookup(root, field).clone(),
+            Expr::Array(a) => Value::Array(a.iter().map(|e| e.evaluate(root, options)).collect()),
+            Expr::Object(o) => Value::Object(
+                o.iter()
+                    .map(|(key, e)| (key.clone(), e.evaluate(root, options)))
+                    .collect::<Map<_, _>>(),
+            ),
+            Expr::Call(function, args) => call(*function, args, root, options),
+        }
+    }
+}
+
+fn call(function: Function, args: &[Expr], root: &Value, options: &MatchOptions) -> Value {
+    let eval = |i: usize| args[i].evaluate(root, options);
+    let compare = |accept: &[Ordering]| {
+        let ordering = order::compare_values(&eval(0), &eval(1), options);
+        Value::Bool(accept.contains(&ordering))
+    };
+    match function {
+        Function::Eq => Value::Bool(values_equal(&eval(1), &eval(0), options)),
+        Function::Ne => Value::Bool(!values_equal(&eval(1), &eval(0), options)),
+        Function::Gt => compare(&[Ordering::Greater]),
+        Function::Gte => compare(&[Ordering::Greater, Ordering::Equal]),
+        Function::Lt => compare(&[Ordering::Less]),
+        Function::Lte => compare(&[Ordering::Less, Ordering::Equal]),
+        Function::And => Value::Bool(args.iter().all(|e| truthy(&e.evaluate(root, options)))),
+        Function::Or => Value::Bool(args.iter().any(|e| truthy(&e.evaluate(root, options)))),
+        Function::Not => Value::Bool(!truthy(&eval(0))),
+        Function::Cond => {
+            if truthy(&eval(0)) {
+                eval(1)
+            } else {
+                eval(2)
+            }
+        }
+        Function::Add => arithmetic(args, root, options, i64::checked_add, |a, b| a + b),
+        Function::Subtract => arithmetic(args, root, options, i64::checked_sub, |a, b| a - b),
+        Function::Multiply => arithmetic(args, root, options, i64::checked_mul, |a, b| a * b),
+        Function::Divide => match (eval(0).as_f64(), eval(1).as_f64()) {
+            (Some(a), Some(b)) if b != 0.0 => number(a / b),
+            _ => Value::Null,
+        },
+        Function::Mod => match (eval(0), eval(1)) {
+            (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+                (Some(a), Some(b)) => a.checked_rem(b).map_or(Value::Null, Value::from),
+                _ => match (a.as_f64(), b.as_f64()) {
+                    (Some(a), Some(b)) if b != 0.0 => number(a % b),
+                    _ => Value::Null,
+                },
+            },
+            _ => Value::Null,
+        },
+        Function::ToLower => map_str(eval(0), str::to_lowercase),
+        Function::ToUpper => map_str(eval(0), str::to_uppercase),
+        Function::Trim => map_str(eval(0), |s| s.trim().to_string()),
+        Function::Concat => {
+            let mut concatenated = String::new();
+            for e in args {
+                match e.evaluate(root, options) {
+                    Value::String(s) => concatenated.push_str(&s),
+                    _ => return Value::Null,
+                }
+            }
+            Value::String(concatenated)
+        }
+        Function::StrLen => match eval(0) {
+            Value::String(s) => Value::from(s.chars().count()),
+            _ => Value::Null,
+        },
+        Function::Size => match eval(0) {
+            Value::Array(a) => Value::from(a.len()),
+            _ => Value::Null,
+        },
+        Function::ArrayElemAt => match (eval(0), eval(1).as_i64()) {
+            (Value::Array(mut a), Some(index)) => match crate::path::position(index, a.len()) {
+                Some(index) => a.swap_remove(index),
+                None => Value::Null,
+            },
+            _ => Value::Null,
+        },
+    }
+}
+
+/// Folds the arguments with integer arithmetic while they are all integers and it does not
+/// overflow, and with floating-point arithmetic otherwise.
+fn arithmetic(
+    args: &[Expr],
+    root: &Value,
+    options: &MatchOptions,
+    integer: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Value {
+    let mut values = args.iter().map(|e| e.evaluate(root, options));
+    let mut acc = match values.next() {
+        Some(Value::Number(n)) => n,
+        _ => return Value::Null,
+    };
+    for value in values {
+        let n = match value {
+            Value::Number(n) => n,
+            _ => return Value::Null,
+        };
+        acc = match (acc.as_i64(), n.as_i64()) {
+            (Some(a), Some(b)) if integer(a, b).is_some() => Number::from(integer(a, b).unwrap()),
+            _ => match (acc.as_f64(), n.as_f64()) {
+                (Some(a), Some(b)) => match Number::from_f64(float(a, b)) {
+                    Some(n) => n,
+                    None => return Value::Null,
+                },
+                _ => return Value::Null,
+            },
+        };
+    }
+    Value::Number(acc)
+}
+
+fn number(n: f64) -> Value {
+    Number::from_f64(n).map_or(Value::Null, Value::Number)
+}
+
+fn map_str(value: Value, f: impl Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(s) => Value::String(f(&s)),
+        _ => Value::Null,
+    }
+}
+
+/// Whether an expression result counts as true: anything but `false`, `null` and zero.
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        _ => true,
+    }
+}
+
+impl MatchesValue for ExprOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        truthy(&self.val.expr.evaluate(other, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_expr() {
+        let doc = json!({
+            "price": 80, "tax": 25.5, "qty": 3, "name": "  Widget ", "first": "Ada",
+            "last": "Lovelace", "tags": ["a", "b", "c"], "limit": 250,
+        });
+        let holds = |expr: &str| {
+            from_str(&format!(r#"{{"$expr": {expr}}}"#))
+                .unwrap()
+                .matches(&doc)
+        };
+        assert!(holds(r#"{"$gt": [{"$add": ["$price", "$tax"]}, 100]}"#));
+        assert!(holds(
+            r#"{"$lte": [{"$multiply": ["$price", "$qty"]}, "$limit"]}"#
+        ));
+        assert!(holds(
+            r#"{"$eq": [{"$subtract": ["$limit", "$price"]}, 170]}"#
+        ));
+        assert!(holds(r#"{"$eq": [{"$divide": ["$price", 8]}, 10.0]}"#));
+        assert!(holds(r#"{"$eq": [{"$mod": ["$price", 7]}, 3]}"#));
+        assert!(holds(
+            r#"{"$eq": [{"$toLower": {"$trim": "$name"}}, "widget"]}"#
+        ));
+        assert!(holds(r#"{"$eq": [{"$toUpper": "$first"}, "ADA"]}"#));
+        assert!(holds(
+            r#"{"$eq": [{"$concat": ["$first", " ", "$last"]}, "Ada Lovelace"]}"#
+        ));
+        assert!(holds(r#"{"$eq": [{"$strLen": "$last"}, 8]}"#));
+        assert!(holds(r#"{"$eq": [{"$size": "$tags"}, 3]}"#));
+        assert!(holds(r#"{"$eq": [{"$arrayElemAt": ["$tags", -1]}, "c"]}"#));
+        assert!(holds(
+            r#"{"$and": [{"$not": "$missing"}, {"$or": [0, "$qty"]}]}"#
+        ));
+        assert!(!holds(r#"{"$eq": [{"$literal": "$price"}, "$price"]}"#));
+        assert!(holds(r#"{"$eq": [{"$add": ["$price", "$name"]}, null]}"#));
+        assert!(!holds(r#"{"$arrayElemAt": ["$tags", 5]}"#));
+        assert!(!holds(r#"{"$divide": ["$price", 0]}"#));
+        assert!(holds(r#"{"$eq": [{"a": "$qty"}, {"a": 3}]}"#));
+        assert!(holds(
+            r#"{"$eq": [{"$cond": [{"$gt": ["$qty", 5]}, "many", "few"]}, "few"]}"#
+        ));
+
+        assert!(from_str(r#"{"$expr": {"$gt": [1]}}"#).is_err());
+        assert!(from_str(r#"{"$expr": {"$nope": [1, 2]}}"#).is_err());
+        assert!(from_str(r#"{"$expr": {"$size": ["$a", "$b"]}}"#).is_err());
+        assert!(from_str(r#"{"$expr": {"$cond": [true, 1]}}"#).is_err());
+        assert!(from_str(r#"{"$expr": {"$cond": {"if": true, "then": 1}}}"#).is_err());
+        assert!(
+            from_str(r#"{"$expr": {"$cond": {"if": 1, "then": 1, "else": 2, "x": 3}}}"#).is_err()
+        );
+        let matcher = from_str(r#"{"$expr": {"$gt": ["$a", 1]}}"#).unwrap();
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"$expr": {"$gt": ["$a", 1]}})
+        );
+        assert!(matcher.compile().unwrap().matches(&json!({"a": 2})));
+    }
+
+    #[test]
+    pub fn test_cond() {
+        let matcher = from_str(
+            r#"{"$expr": {"$lte": [
+                "$amount",
+                {"$cond": {
+                    "if": {"$eq": ["$type", "refund"]},
+                    "then": "$refund_limit",
+                    "else": "$charge_limit"
+                }}
+            ]}}"#,
+        )
+        .unwrap();
+        let limits = |doc: serde_json::Value| {
+            let mut doc = doc;
+            doc["refund_limit"] = json!(50);
+            doc["charge_limit"] = json!(500);
+            matcher.matches(&doc)
+        };
+        assert!(limits(json!({"type": "refund", "amount": 40})));
+        assert!(!limits(json!({"type": "refund", "amount": 100})));
+        assert!(limits(json!({"type": "charge", "amount": 100})));
+        assert!(!limits(json!({"type": "charge", "amount": 800})));
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap()["$expr"]["$lte"][1]["$cond"]["if"],
+            json!({"$eq": ["$type", "refund"]})
+        );
+    }
+}
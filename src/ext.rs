@@ -0,0 +1,40 @@
+//! Matching from the value's side, e.g. `value.matches(&matcher)`.
+
+use serde_json::Value;
+
+use crate::{from_str, ObjMatcher};
+
+/// Extension methods on [`Value`] for matching it against a matcher or a query.
+pub trait ValueMatchExt {
+    /// Returns whether the value matches `matcher`.
+    fn matches(&self, matcher: &ObjMatcher) -> bool;
+
+    /// Parses `query` and returns whether the value matches it.
+    fn matches_query(&self, query: &str) -> Result<bool, serde_json::Error>;
+}
+
+impl ValueMatchExt for Value {
+    fn matches(&self, matcher: &ObjMatcher) -> bool {
+        matcher.matches(self)
+    }
+
+    fn matches_query(&self, query: &str) -> Result<bool, serde_json::Error> {
+        Ok(from_str(query)?.matches(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_value_match_ext() {
+        let value = json!({"a": 1, "b": [1, 2]});
+        let matcher = from_str(r#"{"a": {"$in": [1, 2]}}"#).unwrap();
+        assert!(value.matches(&matcher));
+        assert!(value.matches_query(r#"{"b": {"$len": 2}}"#).unwrap());
+        assert!(!value.matches_query(r#"{"a": 2}"#).unwrap());
+        assert!(value.matches_query(r#"{"a": "#).is_err());
+    }
+}
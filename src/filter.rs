@@ -0,0 +1,150 @@
+//! Filtering `tracing` events with matchers.
+//!
+//! Each event is turned into a document holding its fields, with the event's level (e.g.
+//! `"WARN"`) under `level` and its target under `target`; fields of the same names are
+//! overridden. The event's message is the `message` field. Floats, integers, booleans and strings
+//! keep their types; any other field is recorded as its `Debug` representation.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter, Layer};
+
+use crate::ObjMatcher;
+
+/// Keeps the `tracing` events whose fields match a matcher, which can be replaced at any time.
+///
+/// Used as a [`Layer`], it filters events for every layer of the subscriber; used as a per-layer
+/// [`Filter`], only for the layer it is attached to. Spans are never filtered. Clones share the
+/// same matcher.
+#[derive(Debug, Clone)]
+pub struct MatcherFilter {
+    matcher: Arc<RwLock<ObjMatcher>>,
+}
+
+impl MatcherFilter {
+    #[must_use]
+    pub fn new(matcher: ObjMatcher) -> Self {
+        MatcherFilter {
+            matcher: Arc::new(RwLock::new(matcher)),
+        }
+    }
+
+    /// Replaces the matcher used for subsequent events.
+    pub fn set_matcher(&self, matcher: ObjMatcher) {
+        *self.matcher.write().unwrap() = matcher;
+    }
+
+    /// Returns whether the event would be kept.
+    #[must_use]
+    pub fn matches_event(&self, event: &Event<'_>) -> bool {
+        let document = event_document(event);
+        self.matcher.read().unwrap().matches(&document)
+    }
+}
+
+fn event_document(event: &Event<'_>) -> Value {
+    let mut fields = Fields(Map::new());
+    event.record(&mut fields);
+    let mut document = fields.0;
+    let metadata = event.metadata();
+    document.insert("level".to_string(), Value::from(metadata.level().as_str()));
+    document.insert("target".to_string(), Value::from(metadata.target()));
+    Value::Object(document)
+}
+
+struct Fields(Map<String, Value>);
+
+impl Fields {
+    fn insert<V: Into<Value>>(&mut self, field: &Field, value: V) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+impl Visit for Fields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{value:?}"));
+    }
+}
+
+impl<S: Subscriber> Layer<S> for MatcherFilter {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        self.matches_event(event)
+    }
+}
+
+impl<S> Filter<S> for MatcherFilter {
+    fn enabled(&self, _meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        true
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, _cx: &Context<'_, S>) -> bool {
+        self.matches_event(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use std::sync::Mutex;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Records the message of every event it sees.
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Messages {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            if let Value::String(message) = &event_document(event)["message"] {
+                self.0.lock().unwrap().push(message.clone());
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_matcher_filter() {
+        let filter = MatcherFilter::new(
+            from_str(r#"{"$or": [{"level": "ERROR"}, {"user": "alice"}]}"#).unwrap(),
+        );
+        let messages = Messages::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(filter.clone())
+            .with(messages.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(user = "alice", "kept");
+            tracing::info!(user = "bob", "dropped");
+            tracing::error!("failed");
+            filter.set_matcher(from_str(r#"{"attempt": {"$in": [2, 3]}}"#).unwrap());
+            tracing::warn!(attempt = 3, "retried");
+            tracing::error!(attempt = 1, "first");
+        });
+        assert_eq!(
+            *messages.0.lock().unwrap(),
+            vec!["kept", "failed", "retried"]
+        );
+    }
+}
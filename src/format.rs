@@ -0,0 +1,295 @@
+//! `$format`, matching strings in one of the formats of the JSON Schema vocabulary, e.g.
+//! `{"email": {"$format": "email"}}`.
+//!
+//! The formats are `email` (an address such as `ann@example.com`, without comments or quoted
+//! local parts), `uri` (an absolute URI, as in RFC 3986), `ipv4`, `ipv6`, `uuid` and `date-time`
+//! (as in RFC 3339, e.g. `2024-01-31T08:00:00.250+01:00`). Unlike in JSON Schema, values other
+//! than strings never match.
+
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{ejson, FormatOperator, MatchOptions, MatchesValue};
+
+/// Operand of `$format`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    Email,
+    Uri,
+    Ipv4,
+    Ipv6,
+    Uuid,
+    DateTime,
+}
+
+impl Format {
+    fn accepts(self, s: &str) -> bool {
+        match self {
+            Format::Email => is_email(s),
+            Format::Uri => is_uri(s),
+            Format::Ipv4 => is_ipv4(s),
+            Format::Ipv6 => Ipv6Addr::from_str(s).is_ok(),
+            Format::Uuid => is_uuid(s),
+            Format::DateTime => is_date_time(s),
+        }
+    }
+}
+
+impl MatchesValue for FormatOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        other.as_str().map_or(false, |s| self.val.accepts(s))
+    }
+}
+
+fn is_email(s: &str) -> bool {
+    let at = match s.rfind('@') {
+        Some(at) => at,
+        None => return false,
+    };
+    let (local, domain) = (&s[..at], &s[at + 1..]);
+    let atom = |atom: &str| {
+        !atom.is_empty()
+            && atom
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c))
+    };
+    let domain = if domain.starts_with('[') && domain.ends_with(']') {
+        let literal = &domain[1..domain.len() - 1];
+        if let Some(address) = literal.strip_prefix("IPv6:") {
+            Ipv6Addr::from_str(address).is_ok()
+        } else {
+            is_ipv4(literal)
+        }
+    } else {
+        is_hostname(domain)
+    };
+    local.len() <= 64 && local.split('.').all(atom) && domain
+}
+
+fn is_hostname(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 253
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn is_uri(s: &str) -> bool {
+    let colon = match s.find(':') {
+        Some(colon) => colon,
+        None => return false,
+    };
+    let scheme = &s[..colon];
+    let scheme_valid = scheme
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    let bytes = &s.as_bytes()[colon + 1..];
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'%' {
+            let escaped = bytes.get(i + 1..i + 3);
+            if !escaped.map_or(false, |hex| hex.iter().all(u8::is_ascii_hexdigit)) {
+                return false;
+            }
+            i += 3;
+            continue;
+        }
+        // Unreserved and reserved characters.
+        if !(b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=".contains(&b)) {
+            return false;
+        }
+        i += 1;
+    }
+    scheme_valid
+}
+
+fn is_ipv4(s: &str) -> bool {
+    let parts = s.split('.').collect::<Vec<_>>();
+    parts.len() == 4
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part.len() <= 3
+                && part.bytes().all(|b| b.is_ascii_digit())
+                && (part.len() == 1 || !part.starts_with('0'))
+                && part.parse::<u16>().ok().map_or(false, |n| n <= 255)
+        })
+}
+
+fn is_uuid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+fn is_date_time(s: &str) -> bool {
+    let digits = |s: &str, n: usize| -> Option<i64> {
+        if s.len() == n && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    };
+    let valid = || -> Option<()> {
+        let b = s.as_bytes();
+        if s.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+        if b[10] != b'T' && b[10] != b't' {
+            return None;
+        }
+        let (year, month, day) = (
+            digits(&s[..4], 4)?,
+            digits(&s[5..7], 2)?,
+            digits(&s[8..10], 2)?,
+        );
+        let (hour, minute, second) = (
+            digits(&s[11..13], 2)?,
+            digits(&s[14..16], 2)?,
+            digits(&s[17..19], 2)?,
+        );
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > ejson::days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return None;
+        }
+        let mut rest = &s[19..];
+        if rest.starts_with('.') {
+            let end = rest[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(rest.len(), |i| i + 1);
+            if end == 1 {
+                return None;
+            }
+            rest = &rest[end..];
+        }
+        match rest {
+            "Z" | "z" => Some(()),
+            _ if rest.len() == 6 && (rest.starts_with('+') || rest.starts_with('-')) => {
+                let (hours, minutes) = (digits(&rest[1..3], 2)?, digits(&rest[4..6], 2)?);
+                if &rest[3..4] == ":" && hours <= 23 && minutes <= 59 {
+                    Some(())
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    };
+    s.is_ascii() && valid().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_format() {
+        let cases = [
+            (
+                "email",
+                &[
+                    "ann@example.com",
+                    "a.b+tag@sub.example.co",
+                    "x@localhost",
+                    "ops@[10.0.0.1]",
+                ][..],
+                &[
+                    "ann",
+                    "@example.com",
+                    "ann@",
+                    "a..b@example.com",
+                    "ann@-example.com",
+                    "a b@c.d",
+                ][..],
+            ),
+            (
+                "uri",
+                &[
+                    "https://example.com/a?b=c#d",
+                    "urn:isbn:0451450523",
+                    "mailto:ann@example.com",
+                    "http://x/%20",
+                ][..],
+                &[
+                    "/relative/path",
+                    "example.com",
+                    "1http://x",
+                    "http://x/a b",
+                    "http://x/%zz",
+                ][..],
+            ),
+            (
+                "ipv4",
+                &["192.168.0.1", "0.0.0.0", "255.255.255.255"][..],
+                &["256.1.1.1", "1.2.3", "01.2.3.4", "1.2.3.4.5", "a.b.c.d"][..],
+            ),
+            (
+                "ipv6",
+                &["::1", "2001:db8::8a2e:370:7334", "::ffff:192.168.0.1"][..],
+                &["12345::", "1:2:3:4:5:6:7:8:9", "192.168.0.1", ":::"][..],
+            ),
+            (
+                "uuid",
+                &[
+                    "123e4567-e89b-12d3-a456-426614174000",
+                    "00000000-0000-0000-0000-000000000000",
+                ][..],
+                &[
+                    "123e4567e89b12d3a456426614174000",
+                    "123e4567-e89b-12d3-a456-42661417400g",
+                ][..],
+            ),
+            (
+                "date-time",
+                &[
+                    "2024-01-31T08:00:00Z",
+                    "2024-02-29t08:00:00.250+01:00",
+                    "2016-12-31T23:59:60z",
+                ][..],
+                &[
+                    "2024-01-31",
+                    "2023-02-29T08:00:00Z",
+                    "2024-01-31T08:00:00",
+                    "2024-01-31 08:00:00Z",
+                    "2024-01-31T24:00:00Z",
+                    "2024-01-31T08:00:00.Z",
+                ][..],
+            ),
+        ];
+        for (format, valid, invalid) in &cases {
+            let matcher = from_str(&format!(r#"{{"$format": "{format}"}}"#)).unwrap();
+            for s in *valid {
+                assert!(matcher.matches(&json!(s)), "{} {}", format, s);
+            }
+            for s in *invalid {
+                assert!(!matcher.matches(&json!(s)), "{} {}", format, s);
+            }
+            assert!(!matcher.matches(&json!(1)));
+        }
+
+        let matcher = from_str(r#"{"contact.email": {"$format": "email"}}"#).unwrap();
+        assert!(matcher.matches(&json!({"contact": {"email": "ann@example.com"}})));
+        assert!(!matcher.matches(&json!({"contact": {}})));
+        assert!(from_str(r#"{"$format": "hostname-ish"}"#).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! `$geoWithin` and `$near` over GeoJSON points.
+//!
+//! Candidate values are either GeoJSON points (`{"type": "Point", "coordinates": [lng, lat]}`) or
+//! legacy coordinate pairs (`[lng, lat]`). Any other value never matches.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{GeoWithinOperator, MatchOptions, MatchesValue, NearOperator};
+
+/// Mean Earth radius in meters, as used by MongoDB for spherical distances.
+const EARTH_RADIUS_METERS: f64 = 6_378_100.0;
+
+type Position = [f64; 2];
+
+/// Area accepted by `$geoWithin`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GeoShape {
+    #[serde(rename = "$geometry")]
+    Geometry(Geometry),
+    /// Bottom-left and top-right corners.
+    #[serde(rename = "$box")]
+    Box([Position; 2]),
+    #[serde(rename = "$polygon")]
+    Polygon(Vec<Position>),
+    /// Planar circle: center and radius in coordinate units.
+    #[serde(rename = "$center")]
+    Center((Position, f64)),
+    /// Spherical circle: center and radius in radians.
+    #[serde(rename = "$centerSphere")]
+    CenterSphere((Position, f64)),
+}
+
+/// GeoJSON geometry accepted by `$geoWithin.$geometry`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    Polygon(Vec<Vec<Position>>),
+    MultiPolygon(Vec<Vec<Vec<Position>>>),
+}
+
+/// GeoJSON point used as the reference of `$near`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Point {
+    Point(Position),
+}
+
+/// Operand of `$near`; distances are in meters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Near {
+    #[serde(rename = "$geometry")]
+    geometry: Point,
+    #[serde(
+        rename = "$maxDistance",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    max_distance: Option<f64>,
+    #[serde(
+        rename = "$minDistance",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    min_distance: Option<f64>,
+}
+
+fn as_position(value: &Value) -> Option<Position> {
+    let coordinates = match value {
+        Value::Array(_) => value,
+        Value::Object(o) if o.get("type") == Some(&Value::from("Point")) => o.get("coordinates")?,
+        _ => return None,
+    };
+    match coordinates.as_array()?.as_slice() {
+        [lng, lat] => Some([lng.as_f64()?, lat.as_f64()?]),
+        _ => None,
+    }
+}
+
+fn in_ring(point: Position, ring: &[Position]) -> bool {
+    let [x, y] = point;
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let [xi, yi] = ring[i];
+        let [xj, yj] = ring[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn in_polygon(point: Position, rings: &[Vec<Position>]) -> bool {
+    match rings.split_first() {
+        Some((exterior, holes)) => {
+            in_ring(point, exterior) && !holes.iter().any(|hole| in_ring(point, hole))
+        }
+        None => false,
+    }
+}
+
+/// Great-circle distance in radians.
+fn angular_distance(a: Position, b: Position) -> f64 {
+    let (lng1, lat1) = (a[0].to_radians(), a[1].to_radians());
+    let (lng2, lat2) = (b[0].to_radians(), b[1].to_radians());
+    let h = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lng2 - lng1) / 2.0).sin().powi(2);
+    2.0 * h.sqrt().min(1.0).asin()
+}
+
+impl GeoShape {
+    fn contains(&self, point: Position) -> bool {
+        match self {
+            GeoShape::Geometry(Geometry::Polygon(rings)) => in_polygon(point, rings),
+            GeoShape::Geometry(Geometry::MultiPolygon(polygons)) => {
+                polygons.iter().any(|rings| in_polygon(point, rings))
+            }
+            GeoShape::Box([[x1, y1], [x2, y2]]) => {
+                point[0] >= *x1 && point[0] <= *x2 && point[1] >= *y1 && point[1] <= *y2
+            }
+            GeoShape::Polygon(ring) => in_ring(point, ring),
+            GeoShape::Center(([cx, cy], radius)) => (point[0] - cx).hypot(point[1] - cy) <= *radius,
+            GeoShape::CenterSphere((center, radius)) => angular_distance(*center, point) <= *radius,
+        }
+    }
+}
+
+impl MatchesValue for GeoWithinOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        match as_position(other) {
+            Some(point) => self.val.contains(point),
+            None => false,
+        }
+    }
+}
+
+impl MatchesValue for NearOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        let point = match as_position(other) {
+            Some(point) => point,
+            None => return false,
+        };
+        let Point::Point(reference) = self.val.geometry;
+        let distance = angular_distance(reference, point) * EARTH_RADIUS_METERS;
+        self.val.max_distance.map_or(true, |max| distance <= max)
+            && self.val.min_distance.map_or(true, |min| distance >= min)
+    }
+}
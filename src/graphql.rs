@@ -0,0 +1,311 @@
+//! Converting between matchers and the `where` inputs of GraphQL APIs in the style of Hasura,
+//! such as
+//! `{"age": {"_gt": 21}, "_or": [{"role": {"_eq": "admin"}}, {"verified": {"_eq": true}}]}`.
+//!
+//! Fields hold either a comparison, whose keys are `_eq`, `_neq`, `_gt`, `_gte`, `_lt`, `_lte`,
+//! `_in`, `_nin` and `_is_null`, or a nested `where` input applying to the fields of the nested
+//! object, so `{"author": {"name": {"_eq": "Ann"}}}` is `{"author.name": "Ann"}`. `_and`, `_or`
+//! and `_not` combine `where` inputs, at the top or in a nested one. Comparisons with `null`, and
+//! `_is_null: true`, also hold for missing fields.
+
+use serde_json::{Map, Value};
+
+use crate::{
+    context, escape_field, from_json, parse_operator, path, ObjMatcher, UnsupportedClause,
+};
+
+const COMBINATIONS: &[&str] = &["_and", "_or", "_not"];
+
+/// Parses a GraphQL `where` input into a matcher.
+pub fn from_graphql_where(input: &Value) -> Result<ObjMatcher, serde_json::Error> {
+    from_json(query(input, "")?)
+}
+
+fn invalid(message: String) -> serde_json::Error {
+    serde::de::Error::custom(message)
+}
+
+/// The query requiring all of `clauses`.
+fn all(mut clauses: Vec<Value>) -> Value {
+    match clauses.len() {
+        0 => Value::Object(Map::new()),
+        1 => clauses.pop().unwrap(),
+        _ => condition("$and", Value::Array(clauses)),
+    }
+}
+
+/// The query equivalent to the `where` input `input`, whose fields are under `prefix`.
+fn query(input: &Value, prefix: &str) -> Result<Value, serde_json::Error> {
+    let fields = match input {
+        Value::Object(fields) => fields,
+        _ => {
+            return Err(invalid(format!(
+                "expected a `where` input, found `{input}`"
+            )))
+        }
+    };
+    let mut clauses = Vec::new();
+    for (key, value) in fields {
+        match key.as_str() {
+            "_and" | "_or" => {
+                let inputs = match value {
+                    Value::Array(inputs) => inputs,
+                    _ => return Err(invalid(format!("`{key}` expects an array"))),
+                };
+                let children = inputs
+                    .iter()
+                    .map(|input| query(input, prefix))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let operator = if key == "_and" { "$and" } else { "$or" };
+                clauses.push(condition(operator, Value::Array(children)));
+            }
+            "_not" => clauses.push(condition("$not", query(value, prefix)?)),
+            field => {
+                let path = if prefix.is_empty() {
+                    escape_field(field)
+                } else {
+                    format!("{}.{}", prefix, escape_field(field))
+                };
+                let comparison = value.as_object().map_or(false, |o| {
+                    o.keys()
+                        .any(|key| key.starts_with('_') && !COMBINATIONS.contains(&key.as_str()))
+                });
+                if comparison {
+                    for (operator, operand) in value.as_object().unwrap() {
+                        clauses.push(compare(&path, operator, operand)?);
+                    }
+                } else {
+                    clauses.push(query(value, &path)?);
+                }
+            }
+        }
+    }
+    Ok(all(clauses))
+}
+
+fn compare(path: &str, operator: &str, operand: &Value) -> Result<Value, serde_json::Error> {
+    let condition = match operator {
+        "_eq" => operand.clone(),
+        "_is_null" => match operand {
+            Value::Bool(true) => Value::Null,
+            Value::Bool(false) => condition("$ne", Value::Null),
+            _ => return Err(invalid(format!("`_is_null` on `{path}` expects a boolean"))),
+        },
+        "_neq" => condition("$ne", operand.clone()),
+        "_gt" => condition("$gt", operand.clone()),
+        "_gte" => condition("$gte", operand.clone()),
+        "_lt" => condition("$lt", operand.clone()),
+        "_lte" => condition("$lte", operand.clone()),
+        "_in" => condition("$in", operand.clone()),
+        "_nin" => condition("$nin", operand.clone()),
+        _ => {
+            return Err(invalid(format!(
+                "unsupported comparison `{operator}` on `{path}`"
+            )))
+        }
+    };
+    let mut clause = Map::new();
+    clause.insert(path.to_string(), condition);
+    Ok(Value::Object(clause))
+}
+
+fn condition(operator: &str, operand: Value) -> Value {
+    let mut condition = Map::new();
+    condition.insert(operator.to_string(), operand);
+    Value::Object(condition)
+}
+
+impl ObjMatcher {
+    /// Converts the matcher into a GraphQL `where` input, the reverse of [`from_graphql_where`].
+    ///
+    /// Field conditions, `$eq`, `$ne`, `$in`, `$nin`, `$and`, `$or`, `$not` and comparisons are
+    /// converted; any other operator, or a comparison applying to the document itself, is
+    /// returned as an error.
+    pub fn to_graphql_where(&self) -> Result<Value, UnsupportedClause> {
+        where_input(self, "")
+    }
+}
+
+fn where_input(matcher: &ObjMatcher, path: &str) -> Result<Value, UnsupportedClause> {
+    let comparison = |operator: &str, operand: Value| {
+        if path.is_empty() {
+            Err(UnsupportedClause::new(matcher, path))
+        } else {
+            Ok(nest(path, condition(operator, operand)))
+        }
+    };
+    match matcher {
+        ObjMatcher::Eq(op) => where_input(&op.val, path),
+        ObjMatcher::Ne(op) => match literal(&op.val) {
+            Some(Value::Null) => comparison("_is_null", Value::Bool(false)),
+            Some(value) => comparison("_neq", value.clone()),
+            None => Ok(condition("_not", where_input(&op.val, path)?)),
+        },
+        ObjMatcher::And(op) => Ok(merge(children(&op.val, path)?)),
+        ObjMatcher::Or(op) => Ok(condition("_or", Value::Array(children(&op.val, path)?))),
+        ObjMatcher::Not(op) => Ok(condition("_not", where_input(&op.val, path)?)),
+        ObjMatcher::Gt(op) => comparison("_gt", op.val.clone()),
+        ObjMatcher::Gte(op) => comparison("_gte", op.val.clone()),
+        ObjMatcher::Lt(op) => comparison("_lt", op.val.clone()),
+        ObjMatcher::Lte(op) => comparison("_lte", op.val.clone()),
+        ObjMatcher::In(op) => match literals(&op.val) {
+            Some(values) => comparison("_in", Value::Array(values)),
+            None => Ok(condition("_or", Value::Array(children(&op.val, path)?))),
+        },
+        ObjMatcher::Nin(op) => match literals(&op.val) {
+            Some(values) => comparison("_nin", Value::Array(values)),
+            None => Ok(condition(
+                "_not",
+                condition("_or", Value::Array(children(&op.val, path)?)),
+            )),
+        },
+        ObjMatcher::Value(value) => {
+            if let Ok(Some(matcher)) = parse_operator(value.clone(), None) {
+                return where_input(&matcher, path);
+            }
+            match value {
+                _ if context::is_reference(value) => Err(UnsupportedClause::new(matcher, path)),
+                Value::Object(o) => {
+                    let mut fields = Vec::with_capacity(o.len());
+                    for (key, value) in o.iter().filter(|(key, _)| *key != "$comment") {
+                        let path = if path.is_empty() {
+                            key.clone()
+                        } else {
+                            format!("{path}.{key}")
+                        };
+                        fields.push(match parse_operator(value.clone(), None) {
+                            Ok(Some(matcher)) => where_input(&matcher, &path)?,
+                            _ => equal(value, &path)?,
+                        });
+                    }
+                    Ok(merge(fields))
+                }
+                _ => equal(value, path),
+            }
+        }
+        _ => Err(UnsupportedClause::new(matcher, path)),
+    }
+}
+
+fn equal(value: &Value, path: &str) -> Result<Value, UnsupportedClause> {
+    if path.is_empty() {
+        return Err(UnsupportedClause::new(
+            &ObjMatcher::Value(value.clone()),
+            path,
+        ));
+    }
+    Ok(match value {
+        Value::Null => nest(path, condition("_is_null", Value::Bool(true))),
+        _ => nest(path, condition("_eq", value.clone())),
+    })
+}
+
+fn children(matchers: &[ObjMatcher], path: &str) -> Result<Vec<Value>, UnsupportedClause> {
+    matchers.iter().map(|m| where_input(m, path)).collect()
+}
+
+/// The value `matcher` compares with for equality, if it is a literal. Objects in place of a
+/// matcher are field conditions.
+fn literal(matcher: &ObjMatcher) -> Option<&Value> {
+    match matcher {
+        ObjMatcher::Value(value) if !value.is_object() && !context::is_reference(value) => {
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+fn literals(members: &[ObjMatcher]) -> Option<Vec<Value>> {
+    members
+        .iter()
+        .map(|member| literal(member).cloned())
+        .collect()
+}
+
+/// `comparison` on the field at `path`, as nested inputs for each field along it.
+fn nest(path: &str, comparison: Value) -> Value {
+    let segments = path::segments(path).collect::<Vec<_>>();
+    segments.into_iter().rev().fold(comparison, |input, field| {
+        let mut nested = Map::new();
+        nested.insert(field.into_owned(), input);
+        Value::Object(nested)
+    })
+}
+
+/// A single input requiring all of `inputs`, combining their fields when they do not overlap.
+fn merge(inputs: Vec<Value>) -> Value {
+    let mut merged = Map::new();
+    for input in &inputs {
+        if let Value::Object(fields) = input {
+            if fields.keys().any(|key| merged.contains_key(key)) {
+                return condition("_and", Value::Array(inputs));
+            }
+            merged.extend(fields.clone());
+        }
+    }
+    Value::Object(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_graphql_where() {
+        let input = json!({
+            "age": {"_gte": 21, "_lt": 65},
+            "author": {"name": {"_eq": "Ann"}, "_or": [{"verified": {"_eq": true}}, {"karma": {"_gt": 100}}]},
+            "_not": {"status": {"_in": ["banned", "deleted"]}},
+            "deleted_at": {"_is_null": true},
+        });
+        let matcher = from_graphql_where(&input).unwrap();
+        let doc = json!({"age": 30, "author": {"name": "Ann", "karma": 150}, "status": "active"});
+        assert!(matcher.matches(&doc));
+        assert!(!matcher.matches(&json!({"age": 30, "author": {"name": "Ann"}})));
+        assert!(!matcher.matches(
+            &json!({"age": 30, "author": {"name": "Ann", "verified": true}, "status": "banned"})
+        ));
+
+        let output = matcher.to_graphql_where().unwrap();
+        let reparsed = from_graphql_where(&output).unwrap();
+        for doc in &[
+            doc.clone(),
+            json!({"age": 70, "author": {"name": "Ann", "verified": true}}),
+            json!({"age": 30, "author": {"name": "Ann", "verified": true}, "deleted_at": 1}),
+        ] {
+            assert_eq!(reparsed.matches(doc), matcher.matches(doc), "{doc}");
+        }
+
+        assert_eq!(
+            from_str(r#"{"a.b": {"$ne": null}, "tags": ["x"], "n": {"$nin": [1, 2]}, "$or": [{"c": 1}, {"d": {"$not": {"$gt": 2}}}]}"#)
+                .unwrap()
+                .to_graphql_where()
+                .unwrap(),
+            json!({
+                "a": {"b": {"_is_null": false}},
+                "tags": {"_eq": ["x"]},
+                "n": {"_nin": [1, 2]},
+                "_or": [{"c": {"_eq": 1}}, {"_not": {"d": {"_gt": 2}}}],
+            })
+        );
+        // Conditions on the same field are kept apart.
+        assert_eq!(
+            from_str(r#"{"$and": [{"a": {"$gt": 1}}, {"a": {"$lt": 5}}]}"#)
+                .unwrap()
+                .to_graphql_where()
+                .unwrap(),
+            json!({"_and": [{"a": {"_gt": 1}}, {"a": {"_lt": 5}}]})
+        );
+
+        let err = from_str(r#"{"name": {"$len": 3}}"#)
+            .unwrap()
+            .to_graphql_where()
+            .unwrap_err();
+        assert_eq!(err.path, "name");
+        assert!(from_graphql_where(&json!({"name": {"_like": "A%"}})).is_err());
+        assert!(from_graphql_where(&json!({"_or": {"a": {"_eq": 1}}})).is_err());
+        assert!(from_graphql_where(&json!({"a": {"_is_null": "yes"}})).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! Counting and grouping documents by field path.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use serde_json::{Map, Number, Value};
+
+use crate::{order, path, update, MatchOptions, ObjMatcher};
+
+/// Number of `values` matching `matcher`.
+pub fn count_matching<'a, I>(values: I, matcher: &ObjMatcher) -> usize
+where
+    I: IntoIterator<Item = &'a Value>,
+{
+    values.into_iter().filter(|v| matcher.matches(v)).count()
+}
+
+/// Counts `values` by the value at `path`, ordered by that value. Documents missing the field
+/// are counted under `null`.
+pub fn group_by<'a, I>(values: I, path: &str) -> Vec<(Value, usize)>
+where
+    I: IntoIterator<Item = &'a Value>,
+{
+    let group = Group {
+        key: Operand::Path(path.to_string()),
+        fields: vec![(
+            "count".to_string(),
+            Accumulator::Sum(Operand::Literal(1.into())),
+        )],
+    };
+    group
+        .aggregate(values)
+        .into_iter()
+        .map(|mut group| {
+            let count = group["count"]
+                .as_u64()
+                .and_then(|count| usize::try_from(count).ok())
+                .unwrap_or(0);
+            (group["_id"].take(), count)
+        })
+        .collect()
+}
+
+/// `value` with integral floating-point numbers written as integers, so that keys
+/// [`order::compare_values`] treats as equal, such as `1` and `1.0`, land in the same group.
+#[allow(clippy::cast_possible_truncation)]
+fn normalize_key(value: &Value) -> Value {
+    match value {
+        Value::Number(n) if order::as_i128(n).is_none() => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => Value::from(f as i64),
+            _ => value.clone(),
+        },
+        Value::Array(a) => Value::Array(a.iter().map(normalize_key).collect()),
+        Value::Object(o) => Value::Object(
+            o.iter()
+                .map(|(key, value)| (key.clone(), normalize_key(value)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Path(String),
+    Literal(Value),
+}
+
+impl Operand {
+    fn parse(value: Value) -> Operand {
+        match value {
+            Value::String(ref s) if s.starts_with('$') => Operand::Path(s[1..].to_string()),
+            value => Operand::Literal(value),
+        }
+    }
+
+    fn resolve<'a>(&'a self, document: &'a Value) -> Option<&'a Value> {
+        match self {
+            Operand::Path(p) => path::get(document, p),
+            Operand::Literal(value) => Some(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Accumulator {
+    Sum(Operand),
+    Avg(Operand),
+    Min(Operand),
+    Max(Operand),
+    First(Operand),
+    Last(Operand),
+    Push(Operand),
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Sum(Number),
+    Avg(f64, u64),
+    Extreme(Option<Value>),
+    First(Option<Value>),
+    Last(Option<Value>),
+    Push(Vec<Value>),
+}
+
+impl Accumulator {
+    fn parse(name: &str, value: Value) -> Result<Accumulator, serde_json::Error> {
+        let (operator, operand) = match value {
+            Value::Object(o) if o.len() == 1 => o.into_iter().next().unwrap(),
+            _ => {
+                return Err(serde::de::Error::custom(format!(
+                    "`$group` field `{name}` must be an accumulator object"
+                )))
+            }
+        };
+        let operand = Operand::parse(operand);
+        Ok(match operator.as_str() {
+            "$sum" => Accumulator::Sum(operand),
+            "$avg" => Accumulator::Avg(operand),
+            "$min" => Accumulator::Min(operand),
+            "$max" => Accumulator::Max(operand),
+            "$first" => Accumulator::First(operand),
+            "$last" => Accumulator::Last(operand),
+            "$push" => Accumulator::Push(operand),
+            _ => {
+                return Err(serde::de::Error::custom(format!(
+                    "unknown accumulator `{operator}`"
+                )))
+            }
+        })
+    }
+
+    fn operand(&self) -> &Operand {
+        match self {
+            Accumulator::Sum(operand)
+            | Accumulator::Avg(operand)
+            | Accumulator::Min(operand)
+            | Accumulator::Max(operand)
+            | Accumulator::First(operand)
+            | Accumulator::Last(operand)
+            | Accumulator::Push(operand) => operand,
+        }
+    }
+
+    fn init(&self) -> State {
+        match self {
+            Accumulator::Sum(_) => State::Sum(0.into()),
+            Accumulator::Avg(_) => State::Avg(0.0, 0),
+            Accumulator::Min(_) | Accumulator::Max(_) => State::Extreme(None),
+            Accumulator::First(_) => State::First(None),
+            Accumulator::Last(_) => State::Last(None),
+            Accumulator::Push(_) => State::Push(Vec::new()),
+        }
+    }
+
+    fn accumulate(&self, state: &mut State, document: &Value) {
+        let value = match self.operand().resolve(document) {
+            Some(value) => value,
+            None => return,
+        };
+        match (self, state) {
+            (_, State::Sum(sum)) => {
+                if let Value::Number(n) = value {
+                    if let Some(total) = update::add(sum, n) {
+                        *sum = total;
+                    }
+                }
+            }
+            (_, State::Avg(sum, count)) => {
+                if let Some(n) = value.as_f64() {
+                    *sum += n;
+                    *count += 1;
+                }
+            }
+            (accumulator, State::Extreme(extreme)) if !value.is_null() => {
+                let wanted = match accumulator {
+                    Accumulator::Min(_) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                let replace = match extreme {
+                    Some(current) => {
+                        order::compare_values(value, current, &MatchOptions::default()) == wanted
+                    }
+                    None => true,
+                };
+                if replace {
+                    *extreme = Some(value.clone());
+                }
+            }
+            (_, State::First(first)) if first.is_none() => *first = Some(value.clone()),
+            (_, State::Last(last)) => *last = Some(value.clone()),
+            (_, State::Push(values)) => values.push(value.clone()),
+            _ => {}
+        }
+    }
+}
+
+impl State {
+    #[allow(clippy::cast_precision_loss)]
+    fn finish(self) -> Value {
+        match self {
+            State::Sum(sum) => Value::Number(sum),
+            State::Avg(_, 0) => Value::Null,
+            State::Avg(sum, count) => {
+                Number::from_f64(sum / count as f64).map_or(Value::Null, Value::Number)
+            }
+            State::Extreme(value) | State::First(value) | State::Last(value) => {
+                value.unwrap_or(Value::Null)
+            }
+            State::Push(values) => Value::Array(values),
+        }
+    }
+}
+
+/// A `$group` stage, e.g. `{"_id": "$status", "count": {"$sum": 1}, "total": {"$sum": "$amount"}}`.
+///
+/// `_id` is the grouping key: a `"$path"` string groups by the value at that path, any other
+/// value puts every document in one group. The other fields are computed with `$sum`, `$avg`,
+/// `$min`, `$max`, `$first`, `$last` or `$push`, applied to a `"$path"` or a literal value.
+/// Groups are returned ordered by key.
+#[derive(Debug, Clone)]
+pub struct Group {
+    key: Operand,
+    fields: Vec<(String, Accumulator)>,
+}
+
+impl Group {
+    pub fn from_json(v: Value) -> Result<Self, serde_json::Error> {
+        let mut fields = match v {
+            Value::Object(o) => o,
+            _ => return Err(serde::de::Error::custom("`$group` must be an object")),
+        };
+        let key = match fields.remove("_id") {
+            Some(key) => Operand::parse(key),
+            None => return Err(serde::de::Error::custom("`$group` requires an `_id`")),
+        };
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| {
+                let accumulator = Accumulator::parse(&name, value)?;
+                Ok((name, accumulator))
+            })
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        Ok(Group { key, fields })
+    }
+
+    /// One document per group, with the group key as `_id`.
+    pub fn aggregate<'a, I>(&self, values: I) -> Vec<Value>
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        let mut index = HashMap::new();
+        let mut groups: Vec<(Value, Vec<State>)> = Vec::new();
+        for document in values {
+            let key = self.key.resolve(document).unwrap_or(&Value::Null);
+            let i = *index
+                .entry(normalize_key(key).to_string())
+                .or_insert_with(|| {
+                    let states = self.fields.iter().map(|(_, a)| a.init()).collect();
+                    groups.push((key.clone(), states));
+                    groups.len() - 1
+                });
+            for ((_, accumulator), state) in self.fields.iter().zip(groups[i].1.iter_mut()) {
+                accumulator.accumulate(state, document);
+            }
+        }
+        let options = MatchOptions::default();
+        groups.sort_by(|(a, _), (b, _)| order::compare_values(a, b, &options));
+        groups
+            .into_iter()
+            .map(|(key, states)| {
+                let mut group = Map::new();
+                group.insert("_id".to_string(), key);
+                for ((name, _), state) in self.fields.iter().zip(states) {
+                    group.insert(name.clone(), state.finish());
+                }
+                Value::Object(group)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_group() {
+        let orders = vec![
+            json!({"status": "paid", "total": 10, "region": "eu"}),
+            json!({"status": "open", "total": 5}),
+            json!({"status": "paid", "total": 2.5, "region": "us"}),
+            json!({"total": 1}),
+        ];
+        assert_eq!(
+            group_by(&orders, "status"),
+            vec![(json!(null), 1), (json!("open"), 1), (json!("paid"), 2)]
+        );
+        let paid = from_str(r#"{"status": "paid"}"#).unwrap();
+        assert_eq!(count_matching(&orders, &paid), 2);
+
+        let group = Group::from_json(json!({
+            "_id": "$status",
+            "total": {"$sum": "$total"},
+            "avg": {"$avg": "$total"},
+            "max": {"$max": "$total"},
+            "regions": {"$push": "$region"},
+            "first": {"$first": "$region"},
+        }))
+        .unwrap();
+        assert_eq!(
+            group.aggregate(&orders)[2],
+            json!({
+                "_id": "paid",
+                "total": 12.5,
+                "avg": 6.25,
+                "max": 10,
+                "regions": ["eu", "us"],
+                "first": "eu",
+            })
+        );
+        let all = Group::from_json(json!({"_id": null, "n": {"$sum": 1}})).unwrap();
+        assert_eq!(all.aggregate(&orders), vec![json!({"_id": null, "n": 4})]);
+
+        let amounts = vec![
+            json!({"qty": 1}),
+            json!({"qty": 1.0}),
+            json!({"qty": [2.0, 1]}),
+            json!({"qty": [2, 1.0]}),
+            json!({"qty": 1.5}),
+        ];
+        assert_eq!(
+            group_by(&amounts, "qty"),
+            vec![(json!(1), 2), (json!(1.5), 1), (json!([2.0, 1]), 2)]
+        );
+
+        assert!(Group::from_json(json!({"n": {"$sum": 1}})).is_err());
+        assert!(Group::from_json(json!({"_id": null, "n": {"$median": 1}})).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Numeric intervals, into which compiled matchers coalesce the range clauses stacked on one
+//! value, such as `{"$gte": 5, "$lt": 100, "$ne": 7}`.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use serde_json::{Number, Value};
+
+use crate::compat::Candidate;
+use crate::{coercion, ejson, order, MatchOptions, ObjMatcher};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Bound {
+    pub(crate) value: Number,
+    pub(crate) inclusive: bool,
+}
+
+/// Numbers between the bounds, except the excluded ones. Values other than numbers are outside.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Interval {
+    pub(crate) lower: Option<Bound>,
+    pub(crate) upper: Option<Bound>,
+    /// Numbers excluded by `$ne`, which like any literal equal only numbers written the same way,
+    /// so excluding `7` leaves `7.0` in.
+    pub(crate) excluded: Vec<Number>,
+}
+
+impl Interval {
+    /// The numbers satisfying `matcher`, if it is a comparison with a number.
+    pub(crate) fn of(matcher: &ObjMatcher) -> Option<Interval> {
+        let (operand, lower, inclusive) = match matcher {
+            ObjMatcher::Gt(op) => (&op.val, true, false),
+            ObjMatcher::Gte(op) => (&op.val, true, true),
+            ObjMatcher::Lt(op) => (&op.val, false, false),
+            ObjMatcher::Lte(op) => (&op.val, false, true),
+            _ => return None,
+        };
+        let bound = match operand {
+            Value::Number(n) => Some(Bound {
+                value: n.clone(),
+                inclusive,
+            }),
+            _ => return None,
+        };
+        Some(if lower {
+            Interval {
+                lower: bound,
+                ..Interval::default()
+            }
+        } else {
+            Interval {
+                upper: bound,
+                ..Interval::default()
+            }
+        })
+    }
+
+    /// All numbers but `n`.
+    pub(crate) fn excluding(n: Number) -> Interval {
+        Interval {
+            excluded: vec![n],
+            ..Interval::default()
+        }
+    }
+
+    pub(crate) fn is_bounded(&self) -> bool {
+        self.lower.is_some() || self.upper.is_some()
+    }
+
+    /// Narrows this interval to the numbers also in `other`.
+    pub(crate) fn intersect(&mut self, other: &Interval) {
+        if let Some(bound) = &other.lower {
+            self.lower = Some(tighter(self.lower.take(), bound.clone(), Ordering::Greater));
+        }
+        if let Some(bound) = &other.upper {
+            self.upper = Some(tighter(self.upper.take(), bound.clone(), Ordering::Less));
+        }
+        for n in &other.excluded {
+            if !self.excluded.contains(n) {
+                self.excluded.push(n.clone());
+            }
+        }
+    }
+
+    /// Whether no number can be inside, judging by the bounds alone.
+    pub(crate) fn is_empty(&self) -> bool {
+        match (&self.lower, &self.upper) {
+            (Some(lower), Some(upper)) => {
+                match order::compare_numbers(&lower.value, &upper.value) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => !(lower.inclusive && upper.inclusive),
+                    Ordering::Less => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn contains(&self, value: &Value, options: &MatchOptions) -> bool {
+        let operand = Operand::new(value, options);
+        is_numeric(&operand.value)
+            && within(&operand.value, self.lower.as_ref(), Ordering::Less)
+            && within(&operand.value, self.upper.as_ref(), Ordering::Greater)
+            && !self.excludes(&operand)
+    }
+
+    /// Whether the candidates of a field condition satisfy the clauses this interval was made of,
+    /// each bound by any of the numbers among them and each exclusion by all of them.
+    pub(crate) fn contains_any(
+        &self,
+        candidates: &[Candidate<'_>],
+        options: &MatchOptions,
+    ) -> bool {
+        let numbers = candidates
+            .iter()
+            .filter_map(|candidate| Some(Operand::new((*candidate)?, options)))
+            .filter(|operand| is_numeric(&operand.value))
+            .collect::<Vec<_>>();
+        let bound_met = |bound: Option<&Bound>, outside: Ordering| {
+            numbers.iter().any(|n| within(&n.value, bound, outside))
+        };
+        bound_met(self.lower.as_ref(), Ordering::Less)
+            && bound_met(self.upper.as_ref(), Ordering::Greater)
+            && !numbers.iter().any(|n| self.excludes(n))
+    }
+
+    fn excludes(&self, operand: &Operand<'_>) -> bool {
+        match &*operand.value {
+            Value::Number(n) if !operand.coerced => self.excluded.contains(n),
+            value => self
+                .excluded
+                .iter()
+                .any(|n| compare_number(value, n) == Some(Ordering::Equal)),
+        }
+    }
+}
+
+/// A value checked against an interval: decoded if it is an Extended JSON literal, or the number
+/// a string stands for under the coercion rules of the options, which unlike a number written
+/// in the document equals the excluded numbers of the same value.
+struct Operand<'a> {
+    value: Cow<'a, Value>,
+    coerced: bool,
+}
+
+impl<'a> Operand<'a> {
+    fn new(value: &'a Value, options: &MatchOptions) -> Operand<'a> {
+        match coercion::number(value, options) {
+            Some(n) => Operand {
+                value: Cow::Owned(Value::Number(n)),
+                coerced: true,
+            },
+            None => Operand {
+                value: ejson::decoded(value),
+                coerced: false,
+            },
+        }
+    }
+}
+
+/// Whether `value` is a number, or with the `decimal` feature a decimal literal.
+fn is_numeric(value: &Value) -> bool {
+    compare_number(value, &Number::from(0)).is_some()
+}
+
+fn compare_number(value: &Value, n: &Number) -> Option<Ordering> {
+    match value {
+        Value::Number(value) => Some(order::compare_numbers(value, n)),
+        #[cfg(feature = "decimal")]
+        _ => crate::decimal::compare(value, &Value::Number(n.clone())),
+        #[cfg(not(feature = "decimal"))]
+        _ => None,
+    }
+}
+
+fn within(value: &Value, bound: Option<&Bound>, outside: Ordering) -> bool {
+    match bound {
+        Some(bound) => match compare_number(value, &bound.value) {
+            Some(Ordering::Equal) => bound.inclusive,
+            Some(ordering) => ordering != outside,
+            None => false,
+        },
+        None => true,
+    }
+}
+
+/// The more restrictive of two bounds on the same side, `further` being the ordering of a bound
+/// that restricts more relative to one that restricts less.
+fn tighter(current: Option<Bound>, bound: Bound, further: Ordering) -> Bound {
+    let current = match current {
+        Some(current) => current,
+        None => return bound,
+    };
+    match order::compare_numbers(&bound.value, &current.value) {
+        Ordering::Equal => Bound {
+            inclusive: current.inclusive && bound.inclusive,
+            ..current
+        },
+        ordering if ordering == further => bound,
+        _ => current,
+    }
+}
@@ -0,0 +1,142 @@
+//! Matching documents against a secondary in-memory collection, such as orders whose customer
+//! satisfies a matcher, with a hash join.
+//!
+//! A [`JoinIndex`] holds the keys of the secondary documents that match a matcher. Registered in
+//! a map under a name and set as the [`Resolver`] of the options, it answers `$lookup` clauses
+//! of that name:
+//!
+//! ```
+//! # use std::collections::HashMap;
+//! # use std::sync::Arc;
+//! # use serde_json::json;
+//! # use serde_json_matcher::{from_str, JoinIndex, MatchOptions};
+//! let customers = vec![json!({"id": 1, "tier": "vip"}), json!({"id": 2, "tier": "basic"})];
+//! let vip = JoinIndex::new(&customers, "id", &from_str(r#"{"tier": "vip"}"#).unwrap());
+//! let mut joins = HashMap::new();
+//! joins.insert("vip_customers".to_string(), vip);
+//! let options = MatchOptions::new().resolver(Arc::new(joins));
+//!
+//! let matcher = from_str(r#"{"customer_id": {"$lookup": "vip_customers"}}"#).unwrap();
+//! assert!(matcher.matches_with_options(&json!({"customer_id": 1}), &options));
+//! assert!(!matcher.matches_with_options(&json!({"customer_id": 2}), &options));
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+
+use serde_json::Value;
+
+use crate::{path, MatchOptions, ObjMatcher, Resolver};
+
+/// The keys of the documents of a collection that match a matcher.
+///
+/// Keys are compared exactly, as JSON: the number `1` and the string `"1"` are different keys.
+/// Documents without the key field are left out.
+#[derive(Debug, Clone, Default)]
+pub struct JoinIndex {
+    keys: HashSet<String>,
+}
+
+impl JoinIndex {
+    /// Indexes the field `key` of the documents matching `matcher`.
+    #[must_use]
+    pub fn new<'a, I>(documents: I, key: &str, matcher: &ObjMatcher) -> Self
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        Self::new_with_options(documents, key, matcher, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn new_with_options<'a, I>(
+        documents: I,
+        key: &str,
+        matcher: &ObjMatcher,
+        options: &MatchOptions,
+    ) -> Self
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        let keys = documents
+            .into_iter()
+            .filter(|document| matcher.matches_with_options(document, options))
+            .filter_map(|document| path::get(document, key))
+            .map(Value::to_string)
+            .collect();
+        JoinIndex { keys }
+    }
+
+    /// Whether a matching document has the key `value`.
+    #[must_use]
+    pub fn contains(&self, value: &Value) -> bool {
+        self.keys.contains(&value.to_string())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Answers `$lookup` clauses with the index registered under the set's name. Unknown sets
+/// contain nothing.
+impl<S: BuildHasher + Send + Sync> Resolver for HashMap<String, JoinIndex, S> {
+    fn contains(&self, set: &str, value: &Value) -> bool {
+        self.get(set).map_or(false, |index| index.contains(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    pub fn test_join_index() {
+        let customers = vec![
+            json!({"id": 1, "address": {"country": "DE"}}),
+            json!({"id": "2", "address": {"country": "DE"}}),
+            json!({"id": 3, "address": {"country": "FR"}}),
+            json!({"address": {"country": "DE"}}),
+        ];
+        let german = JoinIndex::new(
+            &customers,
+            "id",
+            &from_str(r#"{"address.country": "DE"}"#).unwrap(),
+        );
+        assert_eq!(german.len(), 2);
+        assert!(german.contains(&json!(1)));
+        assert!(german.contains(&json!("2")));
+        assert!(!german.contains(&json!(2)));
+        assert!(!german.contains(&json!(3)));
+
+        let mut joins = HashMap::new();
+        joins.insert("german_customers".to_string(), german);
+        let options = MatchOptions::new().resolver(Arc::new(joins));
+        let matcher =
+            from_str(r#"{"status": "open", "order.customer_id": {"$lookup": "german_customers"}}"#)
+                .unwrap();
+        let orders = [
+            json!({"status": "open", "order": {"customer_id": 1}}),
+            json!({"status": "open", "order": {"customer_id": 3}}),
+            json!({"status": "closed", "order": {"customer_id": "2"}}),
+            json!({"status": "open", "order": {"customer_id": "2"}}),
+        ];
+        let indexes: Vec<usize> = orders
+            .iter()
+            .enumerate()
+            .filter(|(_, order)| matcher.matches_with_options(order, &options))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(indexes, vec![0, 3]);
+        let unknown = from_str(r#"{"order.customer_id": {"$lookup": "nope"}}"#).unwrap();
+        assert!(!unknown.matches_with_options(&orders[0], &options));
+    }
+}
@@ -0,0 +1,173 @@
+//! `$anyKey`, `$allKeys`, `$hasKeys` and `$exact`, matching objects by their keys rather than by
+//! known field names, as for dynamic maps of headers or labels.
+//!
+//! `{"headers": {"$anyKey": {"$gte": "x-", "$lt": "x."}}}` matches when some key of `headers`
+//! starts with `x-`, `$allKeys` when every key satisfies the matcher, and `{"labels": {"$hasKeys":
+//! ["team", "env"]}}` when `labels` has both keys. The keys are matched as JSON strings. Values
+//! other than objects never match.
+//!
+//! Field conditions ignore the keys they do not mention. `{"$exact": {"id": 1, "name": {"$type":
+//! ["string"]}}}` additionally requires the object to have exactly the keys `id` and `name`, for
+//! detecting schema drift. A dotted field such as `"address.city"` requires the key `address`;
+//! nested objects are checked only when wrapped in `$exact` themselves.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    path, AllKeysOperator, AnyKeyOperator, ExactOperator, HasKeysOperator, MatchOptions,
+    MatchesValue, ObjMatcher,
+};
+
+/// Operand of `$exact`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Map<String, Value>", into = "Map<String, Value>")]
+pub struct Exact {
+    source: Map<String, Value>,
+    keys: BTreeSet<String>,
+    matcher: Box<ObjMatcher>,
+}
+
+impl TryFrom<Map<String, Value>> for Exact {
+    type Error = String;
+
+    fn try_from(source: Map<String, Value>) -> Result<Self, String> {
+        let mut keys = BTreeSet::new();
+        for field in source.keys().filter(|key| *key != "$comment") {
+            if field.starts_with('$') {
+                return Err(format!("`{field}` is not a field name"));
+            }
+            keys.extend(
+                path::segments(field)
+                    .next()
+                    .map(std::borrow::Cow::into_owned),
+            );
+        }
+        let matcher =
+            serde_json::from_value(Value::Object(source.clone())).map_err(|err| err.to_string())?;
+        Ok(Exact {
+            source,
+            keys,
+            matcher: Box::new(matcher),
+        })
+    }
+}
+
+impl From<Exact> for Map<String, Value> {
+    fn from(exact: Exact) -> Map<String, Value> {
+        exact.source
+    }
+}
+
+impl Exact {
+    pub(crate) fn matcher(&self) -> &ObjMatcher {
+        &self.matcher
+    }
+}
+
+impl MatchesValue for AnyKeyOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Object(o) => o
+                .keys()
+                .any(|key| self.val.evaluate(&Value::String(key.clone()), options)),
+            _ => false,
+        }
+    }
+}
+
+/// Holds for empty objects.
+impl MatchesValue for AllKeysOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Object(o) => o
+                .keys()
+                .all(|key| self.val.evaluate(&Value::String(key.clone()), options)),
+            _ => false,
+        }
+    }
+}
+
+impl MatchesValue for HasKeysOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        match other {
+            Value::Object(o) => self.val.iter().all(|key| o.contains_key(key)),
+            _ => false,
+        }
+    }
+}
+
+impl MatchesValue for ExactOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Object(o) => {
+                o.len() == self.val.keys.len()
+                    && o.keys().all(|key| self.val.keys.contains(key))
+                    && self.val.matcher.evaluate(other, options)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_key_matchers() {
+        let custom = from_str(r#"{"headers": {"$anyKey": {"$gte": "x-", "$lt": "x."}}}"#).unwrap();
+        assert!(custom.matches(&json!({"headers": {"accept": "*/*", "x-trace": "1"}})));
+        assert!(!custom.matches(&json!({"headers": {"accept": "*/*"}})));
+        assert!(!custom.matches(&json!({"headers": ["x-trace"]})));
+
+        let short = from_str(r#"{"labels": {"$allKeys": {"$len": {"$lte": 3}}}}"#).unwrap();
+        assert!(short.matches(&json!({"labels": {"env": "prod", "app": "api"}})));
+        assert!(!short.matches(&json!({"labels": {"env": "prod", "team": "core"}})));
+        assert!(short.matches(&json!({"labels": {}})));
+        assert!(!short.matches(&json!({})));
+
+        let required = from_str(r#"{"labels": {"$hasKeys": ["team", "env"]}}"#).unwrap();
+        assert!(required.matches(&json!({"labels": {"env": "prod", "team": null, "x": 1}})));
+        assert!(!required.matches(&json!({"labels": {"env": "prod"}})));
+        assert!(required
+            .compile()
+            .unwrap()
+            .matches(&json!({"labels": {"env": 1, "team": 2}})));
+
+        assert!(from_str(r#"{"a": {"$hasKeys": "team"}}"#).is_err());
+        assert!(from_str(r#"{"a": {"$anyKey": {"$gt": 1, "$nope": 1}}}"#).is_err());
+    }
+
+    #[test]
+    pub fn test_exact() {
+        let matcher = from_str(
+            r#"{"$exact": {"id": {"$gt": 0}, "address.city": "Paris", "$comment": "v2"}}"#,
+        )
+        .unwrap();
+        assert!(matcher.matches(&json!({"id": 1, "address": {"city": "Paris", "zip": "75001"}})));
+        assert!(!matcher.matches(&json!({"id": 1, "address": {"city": "Paris"}, "extra": true})));
+        assert!(!matcher.matches(&json!({"address": {"city": "Paris"}})));
+        assert!(!matcher.matches(&json!({"id": 0, "address": {"city": "Paris"}})));
+
+        let nested = from_str(
+            r#"{"user": {"$exact": {"name": {"$type": ["string"]}, "tags": {"$exact": {}}}}}"#,
+        )
+        .unwrap();
+        assert!(nested.matches(&json!({"user": {"name": "ann", "tags": {}}, "other": 1})));
+        assert!(!nested.matches(&json!({"user": {"name": "ann", "tags": {"a": 1}}})));
+        assert!(!nested.matches(&json!({"user": "ann"})));
+        assert!(nested
+            .compile()
+            .unwrap()
+            .matches(&json!({"user": {"name": "ann", "tags": {}}})));
+
+        assert!(from_str(r#"{"$exact": {"$or": [{"a": 1}]}}"#).is_err());
+        assert!(from_str(r#"{"$exact": [1]}"#).is_err());
+        assert!(from_str(r#"{"$exact": {"a": {"$gt": 1, "$nope": 1}}}"#).is_err());
+    }
+}
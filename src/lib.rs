@@ -1,12 +1,172 @@
 //! Implements a matcher for `serde_json::Value`s using the Mongo Query Language.
 //!
-//! Currently supports `$eq`, `$in`, `$ne`, `$nin`, `$and`, `$not`, `$or`, `$type` and `$nor`.
+//! Currently supports:
+//!
+//! - comparison: `$eq`, `$ne`, `$gt`, `$gte`, `$lt`, `$lte`, `$in` and `$nin`
+//! - logic: `$and`, `$or` and `$not`
+//! - types and shapes: `$type`, `$len`, `$format`, `$exact`, `$bytes`, `$truthy`, `$falsy` and
+//!   `$default`
+//! - keys and nesting: `$hasKeys`, `$anyKey`, `$allKeys`, `$descend` and `$countMatching`
+//! - sets: `$setEquals`, `$setIsSubset` and `$setIntersects`
+//! - bits: `$bitsAllSet`, `$bitsAnySet`, `$bitsAllClear` and `$bitsAnyClear`
+//! - everything else: `$sampleRate`, `$bucket`, `$lookup` and `$expr`
+
+// Fallible functions fail only on the invalid queries, documents or files they are given.
+#![allow(clippy::missing_errors_doc)]
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+#[cfg(all(test, feature = "derive"))]
+extern crate self as serde_json_matcher;
+#[cfg(feature = "tokio")]
+extern crate tokio_runtime as tokio;
+
+mod analysis;
+mod arena;
+mod borrowed;
+mod builder;
+mod bytes;
+mod cache;
+mod coercion;
+#[cfg(feature = "icu")]
+mod collation;
+mod collector;
+mod compat;
+mod compiled;
+#[cfg(feature = "tokio")]
+#[clippy::msrv = "1.64"]
+mod concurrent;
+mod conformance;
+mod constraints;
+mod context;
+mod count;
+mod coverage;
+#[cfg(feature = "csv")]
+mod csv;
+mod custom;
+pub mod de;
+#[cfg(feature = "decimal")]
+mod decimal;
+mod default;
+mod descend;
+mod diagnostics;
+mod ejson;
+mod entries;
+mod explain;
+mod expr;
+mod ext;
+#[cfg(feature = "tracing-filter")]
+mod filter;
+mod format;
+#[cfg(feature = "geo")]
+pub mod geo;
+mod graphql;
+mod group;
+mod interval;
+mod join;
+mod keys;
+#[cfg(feature = "log")]
+mod logger;
+mod lookup;
+mod meilisearch;
+mod membership;
+mod merge;
+mod observe;
+mod options;
+mod order;
+mod params;
+mod patch;
+mod path;
+mod pipeline;
+mod planner;
+mod projection;
+mod refs;
+mod rules;
+mod sampling;
+#[cfg(feature = "rhai")]
+mod script;
+mod sets;
+mod shape;
+mod sort;
+mod spelling;
+mod stats;
+mod stream;
+mod subscriptions;
+#[cfg(feature = "tracing")]
+mod trace;
+mod translate;
+mod truthy;
+mod typed;
+mod update;
+mod walk;
+#[cfg(feature = "notify")]
+mod watch;
+#[cfg(feature = "xml")]
+mod xml;
+
+pub use analysis::{are_disjoint, equivalent};
+pub use arena::{ArenaMatcher, MatcherArena};
+pub use borrowed::BorrowedMatcher;
+pub use builder::{Field, FieldPath, Matchable};
+pub use cache::{CachedMatcher, CompileCache};
+#[cfg(feature = "icu")]
+pub use collation::{Collation, CollationError, CollationStrength};
+pub use collector::{ClauseStatistics, StatisticsCollector};
+pub use compiled::{evaluate_all, evaluate_all_with_options, CompiledMatcher, Interner};
+#[cfg(feature = "tokio")]
+pub use concurrent::ConcurrentMatcher;
+pub use conformance::{Spec, SpecFailure, SpecReport, SpecSuite};
+pub use constraints::Constraint;
+pub use context::{ContextProvider, SystemContext};
+pub use coverage::{ClauseCoverage, Coverage};
+#[cfg(feature = "csv")]
+pub use csv::{csv_rows, filter_csv, CsvError, CsvOptions, CsvRows, MatchingCsv};
+pub use custom::{CustomOperator, CustomOperatorCall, OperatorRegistry};
+pub use default::DefaultOperator;
+pub use diagnostics::{
+    validate, validate_json, validate_json_with_options, validate_with_options, Diagnostic,
+    DiagnosticKind,
+};
+pub use explain::ExplainReport;
+pub use ext::ValueMatchExt;
+#[cfg(feature = "tracing-filter")]
+pub use filter::MatcherFilter;
+pub use graphql::from_graphql_where;
+pub use group::{count_matching, group_by, Group};
+pub use join::JoinIndex;
+#[cfg(feature = "log")]
+pub use logger::MatcherLogger;
+pub use lookup::{AsyncResolver, Resolver};
+pub use meilisearch::MeilisearchFilter;
+pub use merge::{merge, Conflict, MergeError, MergeStrategy};
+pub use observe::{Clause, Observer};
+#[cfg(feature = "normalization")]
+pub use options::Normalization;
+pub use options::{ArrayComparison, Coercion, Compatibility, MatchOptions, ParseOptions};
+pub use patch::PatchOperation;
+pub use path::escape_field;
+pub use pipeline::{Pipeline, Stage};
+pub use planner::{Optimization, PathStatistics, Plan, Statistics};
+pub use projection::Projection;
+pub use refs::MatcherRegistry;
+pub use rules::{LoadError, MatcherSet, Rule, RuleError};
+#[cfg(feature = "derive")]
+pub use serde_json_matcher_derive::{lazy_matcher, path, Matchable};
+pub use sort::{sort_values, sort_values_with_options, top_k, Sort};
+pub use stats::MatcherStats;
+pub use stream::{filter_array, filter_stream, MatchingArray, MatchingStream};
+pub use subscriptions::{SubscriptionId, Subscriptions};
+pub use translate::UnsupportedClause;
+pub use typed::TypedMatcher;
+pub use update::{delete_matching, find_and_modify, update_matching, Update, UpdateError};
+#[cfg(feature = "notify")]
+pub use watch::{WatchError, WatchedMatcherSet};
+#[cfg(feature = "xml")]
+pub use xml::{from_xml, XmlError};
+
 trait MatchesValue {
-    fn matches(&self, other: &Value) -> bool;
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool;
 }
 
 macro_rules! operator_struct {
@@ -20,9 +180,12 @@ macro_rules! operator_struct {
     };
     ($obj_matcher_case:ident, $struct_name:ident, $json_operator:expr, $type:ty) => {
         #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[serde(deny_unknown_fields)]
         pub struct $struct_name {
             #[serde(rename = $json_operator)]
             val: $type,
+            #[serde(rename = "$comment", default, skip_serializing_if = "Option::is_none")]
+            comment: Option<Value>,
         }
 
         impl From<$struct_name> for ObjMatcher {
@@ -37,23 +200,17 @@ operator_struct!(Eq, EqOperator, "$eq");
 
 impl MatchesValue for EqOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
-        self.val.matches(other)
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        self.val.evaluate(other, options)
     }
 }
 
-operator_struct!(In, InOperator, "$in", Vec<ObjMatcher>);
+operator_struct!(In, InOperator, "$in", membership::Members);
 
 impl MatchesValue for InOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
-        for v in &self.val {
-            if v.matches(other) {
-                return true;
-            }
-        }
-
-        false
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        self.val.any(other, options)
     }
 }
 
@@ -61,23 +218,17 @@ operator_struct!(Ne, NeOperator, "$ne");
 
 impl MatchesValue for NeOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
-        !self.val.matches(other)
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        !self.val.evaluate(other, options)
     }
 }
 
-operator_struct!(Nin, NinOperator, "$nin", Vec<ObjMatcher>);
+operator_struct!(Nin, NinOperator, "$nin", membership::Members);
 
 impl MatchesValue for NinOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
-        for v in &self.val {
-            if v.matches(other) {
-                return false;
-            }
-        }
-
-        true
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        !self.val.any(other, options)
     }
 }
 
@@ -85,9 +236,9 @@ operator_struct!(And, AndOperator, "$and", Vec<ObjMatcher>);
 
 impl MatchesValue for AndOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
         for v in &self.val {
-            if !v.matches(other) {
+            if !v.evaluate(other, options) {
                 return false;
             }
         }
@@ -100,8 +251,8 @@ operator_struct!(Not, NotOperator, "$not");
 
 impl MatchesValue for NotOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
-        if self.val.matches(other) {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        if self.val.evaluate(other, options) {
             return false;
         }
 
@@ -115,9 +266,9 @@ operator_struct!(Or, OrOperator, "$or", Vec<ObjMatcher>);
 
 impl MatchesValue for OrOperator {
     #[inline]
-    fn matches(&self, other: &Value) -> bool {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
         for v in &self.val {
-            if v.matches(other) {
+            if v.evaluate(other, options) {
                 return true;
             }
         }
@@ -126,10 +277,123 @@ impl MatchesValue for OrOperator {
     }
 }
 
+macro_rules! comparison_operator {
+    ($obj_matcher_case:ident, $struct_name:ident, $json_operator:expr, $($ordering:ident)|+) => {
+        operator_struct!($obj_matcher_case, $struct_name, $json_operator, Value);
+
+        impl MatchesValue for $struct_name {
+            fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+                match values_compare(&self.val, other, options) {
+                    Some($(std::cmp::Ordering::$ordering)|+) => true,
+                    _ => false,
+                }
+            }
+        }
+    };
+}
+
+// Orderings are those of the candidate relative to the operand.
+comparison_operator!(Gt, GtOperator, "$gt", Greater);
+comparison_operator!(Gte, GteOperator, "$gte", Greater | Equal);
+comparison_operator!(Lt, LtOperator, "$lt", Less);
+comparison_operator!(Lte, LteOperator, "$lte", Less | Equal);
+
+operator_struct!(Len, LenOperator, "$len");
+
+impl MatchesValue for LenOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        let len = match other {
+            Value::String(s) => s.chars().count(),
+            Value::Array(a) => a.len(),
+            _ => return false,
+        };
+        self.val.evaluate(&Value::from(len), options)
+    }
+}
+
+/// Bits tested by the `$bits*` operators, given either as a numeric mask or as a list of bit
+/// positions, 0 being the least significant bit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BitMask {
+    Mask(u64),
+    Positions(Vec<u32>),
+}
+
+impl BitMask {
+    /// Returns how many of the selected bits exist and how many of them are set in `value`, or
+    /// `None` if `value` is not an integer. Positions past 63 read the sign bit, as if the
+    /// integer were sign-extended.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn count(&self, value: &Value) -> Option<(u32, u32)> {
+        let n = value.as_number()?;
+        let bits = match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => i as u64,
+            (None, Some(u), _) => u,
+            (None, None, Some(f)) if f.fract() == 0.0 && f.abs() < 9.2e18 => f as i64 as u64,
+            _ => return None,
+        };
+        let (mask, high) =
+            match self {
+                BitMask::Mask(mask) => (*mask, false),
+                BitMask::Positions(positions) => positions.iter().fold((0, false), |(m, h), &p| {
+                    if p < 64 {
+                        (m | 1 << p, h)
+                    } else {
+                        (m, true)
+                    }
+                }),
+            };
+        let negative = n.as_i64().map_or(false, |i| i < 0) || n.as_f64().map_or(false, |f| f < 0.0);
+        Some((
+            mask.count_ones() + u32::from(high),
+            (bits & mask).count_ones() + u32::from(high && negative),
+        ))
+    }
+}
+
+operator_struct!(BitsAllSet, BitsAllSetOperator, "$bitsAllSet", BitMask);
+
+impl MatchesValue for BitsAllSetOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        self.val
+            .count(other)
+            .map_or(false, |(selected, set)| set == selected)
+    }
+}
+
+operator_struct!(BitsAnySet, BitsAnySetOperator, "$bitsAnySet", BitMask);
+
+impl MatchesValue for BitsAnySetOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        self.val.count(other).map_or(false, |(_, set)| set > 0)
+    }
+}
+
+operator_struct!(BitsAllClear, BitsAllClearOperator, "$bitsAllClear", BitMask);
+
+impl MatchesValue for BitsAllClearOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        self.val.count(other).map_or(false, |(_, set)| set == 0)
+    }
+}
+
+operator_struct!(BitsAnyClear, BitsAnyClearOperator, "$bitsAnyClear", BitMask);
+
+impl MatchesValue for BitsAnyClearOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        self.val
+            .count(other)
+            .map_or(false, |(selected, set)| set < selected)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TypeOperator {
     #[serde(rename = "$type")]
     val: Vec<TypeOperatorMatcher>,
+    #[serde(rename = "$comment", default, skip_serializing_if = "Option::is_none")]
+    comment: Option<Value>,
 }
 
 // impl MatchesValue for TypeOperator {
@@ -148,7 +412,7 @@ pub struct TypeOperator {
 // }
 
 impl MatchesValue for TypeOperator {
-    fn matches(&self, other: &Value) -> bool {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
         let other_value_type = match other {
             Value::Null => TypeOperatorMatcher::Null,
             Value::Bool(_) => TypeOperatorMatcher::Bool,
@@ -167,6 +431,72 @@ impl MatchesValue for TypeOperator {
     }
 }
 
+operator_struct!(
+    SampleRate,
+    SampleRateOperator,
+    "$sampleRate",
+    sampling::SampleRate
+);
+
+operator_struct!(Bucket, BucketOperator, "$bucket", sampling::Bucket);
+
+operator_struct!(Lookup, LookupOperator, "$lookup", String);
+
+operator_struct!(Expr, ExprOperator, "$expr", expr::Expression);
+
+operator_struct!(
+    CountMatching,
+    CountMatchingOperator,
+    "$countMatching",
+    count::CountMatching
+);
+
+operator_struct!(AnyKey, AnyKeyOperator, "$anyKey");
+
+operator_struct!(AllKeys, AllKeysOperator, "$allKeys");
+
+operator_struct!(HasKeys, HasKeysOperator, "$hasKeys", Vec<String>);
+
+operator_struct!(Exact, ExactOperator, "$exact", keys::Exact);
+
+operator_struct!(Descend, DescendOperator, "$descend", descend::Descend);
+
+operator_struct!(SetEquals, SetEqualsOperator, "$setEquals", Vec<Value>);
+
+operator_struct!(SetIsSubset, SetIsSubsetOperator, "$setIsSubset", Vec<Value>);
+
+operator_struct!(
+    SetIntersects,
+    SetIntersectsOperator,
+    "$setIntersects",
+    Vec<Value>
+);
+
+operator_struct!(Bytes, BytesOperator, "$bytes", bytes::Bytes);
+
+operator_struct!(Format, FormatOperator, "$format", format::Format);
+
+operator_struct!(Truthy, TruthyOperator, "$truthy", bool);
+
+operator_struct!(Falsy, FalsyOperator, "$falsy", bool);
+
+impl MatchesValue for LookupOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        options
+            .lookup_resolver()
+            .map_or(false, |resolver| resolver.contains(&self.val, other))
+    }
+}
+
+#[cfg(feature = "rhai")]
+operator_struct!(Where, WhereOperator, "$where", script::Script);
+
+#[cfg(feature = "geo")]
+operator_struct!(GeoWithin, GeoWithinOperator, "$geoWithin", geo::GeoShape);
+
+#[cfg(feature = "geo")]
+operator_struct!(Near, NearOperator, "$near", geo::Near);
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 enum TypeOperatorMatcher {
@@ -178,7 +508,12 @@ enum TypeOperatorMatcher {
     Number,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A parsed query.
+///
+/// Operators nested in field conditions are kept as written and interpreted each time the
+/// matcher is evaluated. [`compile`](ObjMatcher::compile) flattens the whole query into the node
+/// buffer of a [`CompiledMatcher`], which is faster to evaluate many times.
+#[derive(Debug, Clone, Serialize)]
 #[serde(untagged)]
 pub enum ObjMatcher {
     Eq(EqOperator),
@@ -188,93 +523,770 @@ pub enum ObjMatcher {
     And(AndOperator),
     Not(NotOperator),
     Or(OrOperator),
+    Gt(GtOperator),
+    Gte(GteOperator),
+    Lt(LtOperator),
+    Lte(LteOperator),
+    Len(LenOperator),
     Type(TypeOperator),
+    BitsAllSet(BitsAllSetOperator),
+    BitsAnySet(BitsAnySetOperator),
+    BitsAllClear(BitsAllClearOperator),
+    BitsAnyClear(BitsAnyClearOperator),
+    SampleRate(SampleRateOperator),
+    Bucket(BucketOperator),
+    Lookup(LookupOperator),
+    Expr(ExprOperator),
+    CountMatching(CountMatchingOperator),
+    AnyKey(AnyKeyOperator),
+    AllKeys(AllKeysOperator),
+    HasKeys(HasKeysOperator),
+    Exact(ExactOperator),
+    Descend(DescendOperator),
+    SetEquals(SetEqualsOperator),
+    SetIsSubset(SetIsSubsetOperator),
+    SetIntersects(SetIntersectsOperator),
+    Bytes(BytesOperator),
+    Format(FormatOperator),
+    Truthy(TruthyOperator),
+    Falsy(FalsyOperator),
+    Default(DefaultOperator),
+    #[cfg(feature = "rhai")]
+    Where(WhereOperator),
+    #[cfg(feature = "geo")]
+    GeoWithin(GeoWithinOperator),
+    #[cfg(feature = "geo")]
+    Near(NearOperator),
+    Custom(CustomOperatorCall),
     Value(Value),
 }
 
+/// Recognizes operators by their keys, as [`from_json`] does, rather than by trying each variant
+/// in turn, so that the outcome does not depend on how the surrounding type buffers its input,
+/// e.g. through `#[serde(flatten)]` or an untagged enum.
+///
+/// Objects whose `$` keys are all unknown are kept as values, as they may call custom operators
+/// that are recognized when matching with a registry, and their operands are left unchecked.
+/// Unknown `$` keys next to known operators are errors, as in `from_json`, and the operands of
+/// the other operators are checked, so that invalid ones are reported here rather than never
+/// matching.
+impl<'de> Deserialize<'de> for ObjMatcher {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = ejson::decode_all(Value::deserialize(deserializer)?);
+        let matcher = match parse_operator(value.clone(), None).map_err(serde::de::Error::custom)? {
+            Some(matcher) => matcher,
+            None => ObjMatcher::Value(value),
+        };
+        check_operands(&matcher, None, "").map_err(serde::de::Error::custom)?;
+        Ok(matcher)
+    }
+}
+
+/// The empty query `{}`, which matches every value.
+impl Default for ObjMatcher {
+    fn default() -> Self {
+        ObjMatcher::Value(Value::Object(serde_json::Map::new()))
+    }
+}
+
 impl ObjMatcher {
     #[must_use]
     pub fn matches(&self, other: &Value) -> bool {
-        MatchesValue::matches(self, other)
-    }
-}
-
-fn try_into_operator(value: Value) -> Option<ObjMatcher> {
-    if let Some(obj) = value.as_object() {
-        if obj.contains_key("$eq") {
-            return Some(ObjMatcher::Eq(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$in") {
-            return Some(ObjMatcher::In(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$ne") {
-            return Some(ObjMatcher::Ne(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$nin") {
-            return Some(ObjMatcher::Nin(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$and") {
-            return Some(ObjMatcher::And(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$not") {
-            return Some(ObjMatcher::Not(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$or") {
-            return Some(ObjMatcher::Or(serde_json::from_value(value).unwrap()));
-        } else if obj.contains_key("$type") {
-            return Some(ObjMatcher::Type(serde_json::from_value(value).unwrap()));
+        self.matches_with_options(other, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_with_options(&self, other: &Value, options: &MatchOptions) -> bool {
+        #[cfg(feature = "tracing")]
+        let span = trace::evaluate_span(self);
+        let outcome = match options.evaluation_observer() {
+            Some(observer) => observe::evaluate(self, other, options, observer),
+            None => self.evaluate(other, options),
+        };
+        #[cfg(feature = "tracing")]
+        span.record("outcome", outcome);
+        outcome
+    }
+
+    /// Matches any serializable value, such as a `toml::Value` or a struct deriving `Serialize`,
+    /// through the `Value` it serializes to. TOML dates and times, which `toml` serializes as a
+    /// private single-field object, become their RFC 3339 strings, which compare in time order
+    /// when written alike.
+    ///
+    /// Decoded Avro records, `apache_avro::types::Value`s or the structs `apache_avro::from_value`
+    /// returns, are matched without going through JSON text. Their logical types keep their Avro
+    /// encoding, so timestamps are integers since the epoch, in the unit of the logical type, and
+    /// are compared with numbers.
+    pub fn matches_serialize<T: Serialize + ?Sized>(
+        &self,
+        value: &T,
+    ) -> Result<bool, serde_json::Error> {
+        let mut value = serde_json::to_value(value)?;
+        unwrap_toml_datetimes(&mut value);
+        Ok(self.matches(&value))
+    }
+
+    /// Matches the object with the fields of `map`, without cloning it into a `Value`.
+    #[must_use]
+    pub fn matches_map(&self, map: &serde_json::Map<String, Value>) -> bool {
+        self.matches_map_with_options(map, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_map_with_options(
+        &self,
+        map: &serde_json::Map<String, Value>,
+        options: &MatchOptions,
+    ) -> bool {
+        self.matches_fields(map, options)
+    }
+
+    /// Matches the object with the fields `entries`, a later entry replacing an earlier one of
+    /// the same key, without building a `Value`.
+    #[must_use]
+    pub fn matches_entries(&self, entries: &[(&str, &Value)]) -> bool {
+        self.matches_entries_with_options(entries, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn matches_entries_with_options(
+        &self,
+        entries: &[(&str, &Value)],
+        options: &MatchOptions,
+    ) -> bool {
+        self.matches_fields(entries, options)
+    }
+
+    /// Only operators applying to the whole document, or an observer, need it built as a
+    /// `Value`.
+    fn matches_fields<F: entries::Fields + ?Sized>(
+        &self,
+        fields: &F,
+        options: &MatchOptions,
+    ) -> bool {
+        if options.evaluation_observer().is_some() {
+            return self.matches_with_options(&fields.to_object(), options);
         }
+        #[cfg(feature = "tracing")]
+        let span = trace::evaluate_span(self);
+        let outcome = entries::matches(self, fields, options);
+        #[cfg(feature = "tracing")]
+        span.record("outcome", outcome);
+        outcome
+    }
+
+    /// Evaluates a clause of a larger matcher, without instrumentation.
+    pub(crate) fn evaluate(&self, other: &Value, options: &MatchOptions) -> bool {
+        MatchesValue::matches(self, other, options)
     }
-    None
+}
+
+fn unwrap_toml_datetimes(value: &mut Value) {
+    const DATETIME: &str = "$__toml_private_datetime";
+    match value {
+        Value::Object(o) if o.len() == 1 && o.get(DATETIME).map_or(false, Value::is_string) => {
+            *value = o.remove(DATETIME).unwrap();
+        }
+        Value::Object(o) => o.values_mut().for_each(unwrap_toml_datetimes),
+        Value::Array(a) => a.iter_mut().for_each(unwrap_toml_datetimes),
+        _ => {}
+    }
+}
+
+fn values_equal(expected: &Value, actual: &Value, options: &MatchOptions) -> bool {
+    if let Some(provider) = options.context_provider() {
+        if let Some(resolved) = context::resolve(expected, provider) {
+            return literal_equal(&resolved, actual, options);
+        }
+    }
+    literal_equal(expected, actual, options)
+}
+
+/// Orders `actual` relative to `expected`, or `None` if they are of different types and so not
+/// comparable.
+fn values_compare(
+    expected: &Value,
+    actual: &Value,
+    options: &MatchOptions,
+) -> Option<std::cmp::Ordering> {
+    if let Some(provider) = options.context_provider() {
+        if let Some(resolved) = context::resolve(expected, provider) {
+            return literal_compare(&resolved, actual, options);
+        }
+    }
+    literal_compare(expected, actual, options)
+}
+
+fn literal_compare(
+    expected: &Value,
+    actual: &Value,
+    options: &MatchOptions,
+) -> Option<std::cmp::Ordering> {
+    let (expected, actual) = (ejson::decoded(expected), ejson::decoded(actual));
+    ejson::compare(&actual, &expected)
+        .or_else(|| coercion::compare(&actual, &expected, options))
+        .or_else(|| compat::compare(&expected, &actual, options))
+}
+
+fn literal_equal(expected: &Value, actual: &Value, options: &MatchOptions) -> bool {
+    if let Some(decoded) = ejson::decode(actual) {
+        return literal_equal(expected, &decoded, options);
+    }
+    if let Some(decoded) = ejson::decode(expected) {
+        return literal_equal(&decoded, actual, options);
+    }
+    #[cfg(feature = "decimal")]
+    {
+        if let Some(ordering) = decimal::compare(actual, expected) {
+            return ordering == std::cmp::Ordering::Equal;
+        }
+    }
+    if let Some(ordering) = coercion::compare(actual, expected, options) {
+        return ordering == std::cmp::Ordering::Equal;
+    }
+    match (expected, actual) {
+        (Value::String(a), Value::String(b)) => options.str_eq(a, b),
+        (Value::Array(a), Value::Array(b)) => match options.array_order() {
+            ArrayComparison::Ordered => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| literal_equal(a, b, options))
+            }
+            ArrayComparison::Unordered => {
+                a.iter()
+                    .all(|a| b.iter().any(|b| literal_equal(a, b, options)))
+                    && b.iter()
+                        .all(|b| a.iter().any(|a| literal_equal(a, b, options)))
+            }
+            ArrayComparison::Multiset => {
+                // Equality is an equivalence, so pairing each element with the first unpaired
+                // equal one finds a pairing whenever there is one.
+                let mut paired = vec![false; b.len()];
+                a.len() == b.len()
+                    && a.iter().all(|a| {
+                        let found = b
+                            .iter()
+                            .enumerate()
+                            .position(|(i, b)| !paired[i] && literal_equal(a, b, options));
+                        found.map(|i| paired[i] = true).is_some()
+                    })
+            }
+        },
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, a)| match b.get(key) {
+                    Some(b) => literal_equal(a, b, options),
+                    None => false,
+                })
+        }
+        _ => expected == actual,
+    }
+}
+
+/// Interprets `value` as an operator object. Invalid operands, which `from_json` rejects but
+/// matchers built by hand may hold, give a matcher that matches nothing.
+///
+/// Values without operator keys, such as literal field conditions, are recognized without being
+/// copied.
+fn try_into_operator(value: &Value, registry: Option<&OperatorRegistry>) -> Option<ObjMatcher> {
+    let has_operators = value
+        .as_object()
+        .map_or(false, |o| o.keys().any(|key| is_operator(key, registry)));
+    if !has_operators {
+        return None;
+    }
+    parse_operator(value.clone(), registry).unwrap_or_else(|_| {
+        Some(ObjMatcher::Not(NotOperator {
+            val: Box::new(ObjMatcher::default()),
+            comment: None,
+        }))
+    })
+}
+
+const OPERATORS: &[&str] = &[
+    "$eq",
+    "$in",
+    "$ne",
+    "$nin",
+    "$and",
+    "$not",
+    "$or",
+    "$gt",
+    "$gte",
+    "$lt",
+    "$lte",
+    "$len",
+    "$type",
+    "$bitsAllSet",
+    "$bitsAnySet",
+    "$bitsAllClear",
+    "$bitsAnyClear",
+    "$sampleRate",
+    "$bucket",
+    "$lookup",
+    "$expr",
+    "$countMatching",
+    "$anyKey",
+    "$allKeys",
+    "$hasKeys",
+    "$exact",
+    "$descend",
+    "$setEquals",
+    "$setIsSubset",
+    "$setIntersects",
+    "$bytes",
+    "$format",
+    "$truthy",
+    "$falsy",
+    "$default",
+    #[cfg(feature = "rhai")]
+    "$where",
+    #[cfg(feature = "geo")]
+    "$geoWithin",
+    #[cfg(feature = "geo")]
+    "$near",
+];
+
+fn is_operator(key: &str, registry: Option<&OperatorRegistry>) -> bool {
+    OPERATORS.contains(&key) || registry.map_or(false, |r| r.get(key).is_some())
+}
+
+/// Interprets `value` as an operator object, failing if it is one but its operand is invalid.
+///
+/// Several operators in one object, e.g. `{"$gte": 5, "$lt": 100}`, must all hold. `$and` and
+/// `$or` may sit next to plain fields, which must then match too; any other key next to an
+/// operator is an error.
+fn parse_operator(
+    value: Value,
+    registry: Option<&OperatorRegistry>,
+) -> Result<Option<ObjMatcher>, serde_json::Error> {
+    let obj = match value {
+        Value::Object(ref obj) if !params::is_placeholder(&value) => obj,
+        _ => return Ok(None),
+    };
+    let operators = obj
+        .keys()
+        .filter(|key| is_operator(key, registry))
+        .collect::<Vec<_>>();
+    let first = match operators.first() {
+        Some(first) => *first,
+        None => return Ok(None),
+    };
+    let fields = obj
+        .iter()
+        .filter(|(key, _)| *key != "$comment" && !is_operator(key, registry))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect::<serde_json::Map<_, _>>();
+    let logical = operators.iter().all(|key| *key == "$and" || *key == "$or");
+    if let Some(key) = fields.keys().find(|key| !logical || key.starts_with('$')) {
+        return Err(serde::de::Error::custom(format!(
+            "unexpected key `{key}` next to operator `{first}`"
+        )));
+    }
+    if obj.contains_key("$default") {
+        return DefaultOperator::parse(obj.clone(), registry)
+            .map(|op| Some(ObjMatcher::Default(op)));
+    }
+    if operators.len() == 1 && fields.is_empty() {
+        return parse_single_operator(value, registry);
+    }
+    let mut clauses = Vec::with_capacity(operators.len() + 1);
+    for key in &operators {
+        let mut single = serde_json::Map::new();
+        single.insert((*key).clone(), obj[key.as_str()].clone());
+        clauses.extend(parse_single_operator(Value::Object(single), registry)?);
+    }
+    if !fields.is_empty() {
+        clauses.push(ObjMatcher::Value(Value::Object(fields)));
+    }
+    Ok(Some(ObjMatcher::And(AndOperator {
+        val: clauses,
+        comment: obj.get("$comment").cloned(),
+    })))
+}
+
+fn parse_single_operator(
+    value: Value,
+    registry: Option<&OperatorRegistry>,
+) -> Result<Option<ObjMatcher>, serde_json::Error> {
+    let obj = match value.as_object() {
+        Some(obj) => obj,
+        None => return Ok(None),
+    };
+    macro_rules! parse_keyed {
+        ($($key:literal => $variant:ident,)*) => {
+            $(
+                if obj.contains_key($key) {
+                    return Ok(Some(ObjMatcher::$variant(serde_json::from_value(value)?)));
+                }
+            )*
+        };
+    }
+    parse_keyed! {
+        "$eq" => Eq,
+        "$in" => In,
+        "$ne" => Ne,
+        "$nin" => Nin,
+        "$and" => And,
+        "$not" => Not,
+        "$or" => Or,
+        "$gt" => Gt,
+        "$gte" => Gte,
+        "$lt" => Lt,
+        "$lte" => Lte,
+        "$len" => Len,
+        "$type" => Type,
+        "$bitsAllSet" => BitsAllSet,
+        "$bitsAnySet" => BitsAnySet,
+        "$bitsAllClear" => BitsAllClear,
+        "$bitsAnyClear" => BitsAnyClear,
+        "$sampleRate" => SampleRate,
+        "$bucket" => Bucket,
+        "$lookup" => Lookup,
+        "$expr" => Expr,
+        "$countMatching" => CountMatching,
+        "$anyKey" => AnyKey,
+        "$allKeys" => AllKeys,
+        "$hasKeys" => HasKeys,
+        "$exact" => Exact,
+        "$descend" => Descend,
+        "$setEquals" => SetEquals,
+        "$setIsSubset" => SetIsSubset,
+        "$setIntersects" => SetIntersects,
+        "$bytes" => Bytes,
+        "$format" => Format,
+        "$truthy" => Truthy,
+        "$falsy" => Falsy,
+    }
+    #[cfg(feature = "rhai")]
+    {
+        parse_keyed! {
+            "$where" => Where,
+        }
+    }
+    #[cfg(feature = "geo")]
+    {
+        parse_keyed! {
+            "$geoWithin" => GeoWithin,
+            "$near" => Near,
+        }
+    }
+    if let Some(registry) = registry {
+        for (key, operand) in obj {
+            if registry.get(key).is_some() {
+                return Ok(Some(ObjMatcher::Custom(CustomOperatorCall::new(
+                    key.clone(),
+                    operand.clone(),
+                ))));
+            }
+        }
+    }
+    Ok(None)
 }
 
 impl MatchesValue for ObjMatcher {
-    fn matches(&self, other: &Value) -> bool {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
         match self {
-            ObjMatcher::Eq(op) => op.matches(other),
-            ObjMatcher::In(op) => op.matches(other),
-            ObjMatcher::Ne(op) => op.matches(other),
-            ObjMatcher::Nin(op) => op.matches(other),
-            ObjMatcher::And(op) => op.matches(other),
-            ObjMatcher::Not(op) => op.matches(other),
-            ObjMatcher::Or(op) => op.matches(other),
-            ObjMatcher::Type(op) => op.matches(other),
-            ObjMatcher::Value(value) => match try_into_operator(value.clone()) {
-                Some(obj_matcher) => obj_matcher.matches(other),
-                None => match value {
-                    Value::Number(n) => match other {
-                        Value::Number(n2) => n == n2,
-                        _ => false,
-                    },
-                    Value::Object(o) => {
-                        for (key, value) in o {
-                            if let Some(obj_matcher) = try_into_operator(value.clone()) {
-                                if !obj_matcher.matches(&other[key]) {
-                                    return false;
-                                }
-                            } else if value != &other[key] {
-                                return false;
+            ObjMatcher::Eq(op) => op.matches(other, options),
+            ObjMatcher::In(op) => op.matches(other, options),
+            ObjMatcher::Ne(op) => op.matches(other, options),
+            ObjMatcher::Nin(op) => op.matches(other, options),
+            ObjMatcher::And(op) => op.matches(other, options),
+            ObjMatcher::Not(op) => op.matches(other, options),
+            ObjMatcher::Or(op) => op.matches(other, options),
+            ObjMatcher::Gt(op) => op.matches(other, options),
+            ObjMatcher::Gte(op) => op.matches(other, options),
+            ObjMatcher::Lt(op) => op.matches(other, options),
+            ObjMatcher::Lte(op) => op.matches(other, options),
+            ObjMatcher::Len(op) => op.matches(other, options),
+            ObjMatcher::Type(op) => op.matches(other, options),
+            ObjMatcher::BitsAllSet(op) => op.matches(other, options),
+            ObjMatcher::BitsAnySet(op) => op.matches(other, options),
+            ObjMatcher::BitsAllClear(op) => op.matches(other, options),
+            ObjMatcher::BitsAnyClear(op) => op.matches(other, options),
+            ObjMatcher::SampleRate(op) => op.matches(other, options),
+            ObjMatcher::Bucket(op) => op.matches(other, options),
+            ObjMatcher::Lookup(op) => op.matches(other, options),
+            ObjMatcher::Expr(op) => op.matches(other, options),
+            ObjMatcher::CountMatching(op) => op.matches(other, options),
+            ObjMatcher::AnyKey(op) => op.matches(other, options),
+            ObjMatcher::AllKeys(op) => op.matches(other, options),
+            ObjMatcher::HasKeys(op) => op.matches(other, options),
+            ObjMatcher::Exact(op) => op.matches(other, options),
+            ObjMatcher::Descend(op) => op.matches(other, options),
+            ObjMatcher::SetEquals(op) => op.matches(other, options),
+            ObjMatcher::SetIsSubset(op) => op.matches(other, options),
+            ObjMatcher::SetIntersects(op) => op.matches(other, options),
+            ObjMatcher::Bytes(op) => op.matches(other, options),
+            ObjMatcher::Format(op) => op.matches(other, options),
+            ObjMatcher::Truthy(op) => op.matches(other, options),
+            ObjMatcher::Falsy(op) => op.matches(other, options),
+            ObjMatcher::Default(op) => op.matches(other, options),
+            #[cfg(feature = "rhai")]
+            ObjMatcher::Where(op) => op.matches(other, options),
+            #[cfg(feature = "geo")]
+            ObjMatcher::GeoWithin(op) => op.matches(other, options),
+            #[cfg(feature = "geo")]
+            ObjMatcher::Near(op) => op.matches(other, options),
+            ObjMatcher::Custom(op) => op.matches(other, options),
+            ObjMatcher::Value(value) => match walk::value(value, options.operator_registry()) {
+                walk::Clause::Operator(obj_matcher) => obj_matcher.evaluate(other, options),
+                walk::Clause::Fields(fields) if options.profile() != Compatibility::Native => {
+                    fields
+                        .iter()
+                        .filter(|(key, _)| *key != "$comment")
+                        .all(|(key, value)| {
+                            let candidates = compat::candidates(other, key, options);
+                            compat::field_matches(value, &candidates, options)
+                        })
+                }
+                walk::Clause::Fields(fields) => {
+                    walk::conditions(fields, options.operator_registry()).all(|(key, condition)| {
+                        let actual = path::lookup(other, key);
+                        match condition {
+                            walk::Condition::Operator(obj_matcher) => {
+                                obj_matcher.evaluate(actual, options)
                             }
+                            walk::Condition::Literal(value) => values_equal(value, actual, options),
                         }
-                        true
+                    })
+                }
+                walk::Clause::Literal(value) => values_equal(value, other, options),
+            },
+        }
+    }
+}
+
+fn check_policy(value: &Value, options: &ParseOptions) -> Result<(), serde_json::Error> {
+    match value {
+        Value::Object(o) => {
+            for (key, value) in o {
+                if options.is_denied(key) {
+                    return Err(serde::de::Error::custom(format!(
+                        "operator `{key}` is not allowed"
+                    )));
+                }
+                if let Some(operator) = options.operator_registry().and_then(|r| r.get(key)) {
+                    operator.parse(value).map_err(|err| {
+                        serde::de::Error::custom(format!("invalid `{key}`: {err}"))
+                    })?;
+                }
+                check_policy(value, options)?;
+            }
+        }
+        Value::Array(a) => {
+            for value in a {
+                check_policy(value, options)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check_limits(
+    value: &Value,
+    options: &ParseOptions,
+    operators: &mut usize,
+) -> Result<(), serde_json::Error> {
+    let limits = options.limits();
+    let check_len = |s: &str| match limits.string_len {
+        Some(max) if s.len() > max => Err(serde::de::Error::custom(format!(
+            "string of {} bytes exceeds the limit of {}",
+            s.len(),
+            max
+        ))),
+        _ => Ok(()),
+    };
+    match value {
+        Value::String(s) => check_len(s)?,
+        Value::Object(o) => {
+            for (key, value) in o {
+                check_len(key)?;
+                if is_operator(key, options.operator_registry()) {
+                    *operators += 1;
+                    if let Some(max) = limits.operators.filter(|max| *operators > *max) {
+                        return Err(serde::de::Error::custom(format!(
+                            "query exceeds the limit of {max} operators"
+                        )));
                     }
-                    _ => {
-                        todo!("not implemented value match {:?}", other)
+                }
+                match (key.as_str(), value, limits.arity) {
+                    ("$in" | "$nin" | "$and" | "$or", Value::Array(a), Some(max))
+                        if a.len() > max =>
+                    {
+                        return Err(serde::de::Error::custom(format!(
+                            "`{}` with {} elements exceeds the limit of {}",
+                            key,
+                            a.len(),
+                            max
+                        )));
                     }
-                },
-            },
+                    _ => {}
+                }
+                check_limits(value, options, operators)?;
+            }
         }
+        Value::Array(a) => {
+            for value in a {
+                check_limits(value, options, operators)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn check_size(size: usize, options: &ParseOptions) -> Result<(), serde_json::Error> {
+    match options.limits().size {
+        Some(max) if size > max => Err(serde::de::Error::custom(format!(
+            "query of {size} bytes exceeds the limit of {max}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Counts the bytes written to it, measuring the size of a value as JSON without buffering it.
+struct ByteCount(usize);
+
+impl std::io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
 pub fn from_str(s: &str) -> Result<ObjMatcher, serde_json::Error> {
+    from_str_with_options(s, &ParseOptions::default())
+}
+
+pub fn from_json(v: Value) -> Result<ObjMatcher, serde_json::Error> {
+    from_json_with_options(v, &ParseOptions::default())
+}
+
+pub fn from_str_with_options(
+    s: &str,
+    options: &ParseOptions,
+) -> Result<ObjMatcher, serde_json::Error> {
+    check_size(s.len(), options)?;
     let v: Value = serde_json::from_str(s)?;
-    if let Some(obj_matcher) = try_into_operator(v.clone()) {
-        Ok(obj_matcher)
+    from_json_with_options(v, options)
+}
+
+pub fn from_json_with_options(
+    v: Value,
+    options: &ParseOptions,
+) -> Result<ObjMatcher, serde_json::Error> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        "parse",
+        clauses = tracing::field::Empty,
+        ok = tracing::field::Empty
+    )
+    .entered();
+    let result = parse(v, options);
+    #[cfg(feature = "tracing")]
+    trace::record_parse(&span, &result);
+    result
+}
+
+fn parse(v: Value, options: &ParseOptions) -> Result<ObjMatcher, serde_json::Error> {
+    let v = if options.accepts_bare_operators() {
+        spelling::add_prefixes(v)
     } else {
-        Ok(ObjMatcher::Value(v))
+        v
+    };
+    let v = ejson::decode_all(v);
+    // Limits apply to the query as resolved, so that `$ref`s cannot multiply it past them.
+    let v = match options.matcher_registry() {
+        Some(registry) => registry.resolve(&v)?,
+        None => v,
+    };
+    if options.limits().size.is_some() {
+        let mut size = ByteCount(0);
+        serde_json::to_writer(&mut size, &v)?;
+        check_size(size.0, options)?;
     }
+    check_limits(&v, options, &mut 0)?;
+    check_policy(&v, options)?;
+    let matcher = match parse_operator(v.clone(), options.operator_registry())? {
+        Some(obj_matcher) => obj_matcher,
+        None => ObjMatcher::Value(v),
+    };
+    check_operands(&matcher, options.operator_registry(), "")?;
+    Ok(matcher)
 }
 
-pub fn from_json(v: Value) -> Result<ObjMatcher, serde_json::Error> {
-    if let Some(obj_matcher) = try_into_operator(v.clone()) {
-        Ok(obj_matcher)
+/// Parses the operators nested in field conditions, which are otherwise only parsed when
+/// matching, so that invalid operands are reported by `from_json` along with their field path.
+fn check_operands(
+    matcher: &ObjMatcher,
+    registry: Option<&OperatorRegistry>,
+    path: &str,
+) -> Result<(), serde_json::Error> {
+    let children: &[ObjMatcher] = match matcher {
+        ObjMatcher::Eq(op) => std::slice::from_ref(&*op.val),
+        ObjMatcher::Ne(op) => std::slice::from_ref(&*op.val),
+        ObjMatcher::Not(op) => std::slice::from_ref(&*op.val),
+        ObjMatcher::Len(op) => std::slice::from_ref(&*op.val),
+        ObjMatcher::CountMatching(op) => std::slice::from_ref(op.val.matcher()),
+        ObjMatcher::AnyKey(op) => std::slice::from_ref(&*op.val),
+        ObjMatcher::AllKeys(op) => std::slice::from_ref(&*op.val),
+        ObjMatcher::Descend(op) => op.val.matchers(),
+        ObjMatcher::Exact(op) => std::slice::from_ref(op.val.matcher()),
+        ObjMatcher::Bytes(op) => op.val.length().map_or(&[], std::slice::from_ref),
+        ObjMatcher::Default(op) => std::slice::from_ref(op.matcher()),
+        ObjMatcher::In(op) => &op.val,
+        ObjMatcher::Nin(op) => &op.val,
+        ObjMatcher::And(op) => &op.val,
+        ObjMatcher::Or(op) => &op.val,
+        ObjMatcher::Value(value) => {
+            if let Some(matcher) =
+                parse_operator(value.clone(), registry).map_err(|err| at_path(path, err))?
+            {
+                return check_operands(&matcher, registry, path);
+            }
+            if let Value::Object(o) = value {
+                if !context::is_reference(value) {
+                    for (key, value) in o {
+                        // Unknown `$` keys may call custom operators, whose operands are theirs
+                        // to check.
+                        if key.starts_with('$') {
+                            continue;
+                        }
+                        let path = if path.is_empty() {
+                            key.clone()
+                        } else {
+                            format!("{path}.{key}")
+                        };
+                        if let Some(matcher) = parse_operator(value.clone(), registry)
+                            .map_err(|err| at_path(&path, err))?
+                        {
+                            check_operands(&matcher, registry, &path)?;
+                        }
+                    }
+                }
+            }
+            return Ok(());
+        }
+        _ => return Ok(()),
+    };
+    for child in children {
+        check_operands(child, registry, path)?;
+    }
+    Ok(())
+}
+
+fn at_path(path: &str, err: serde_json::Error) -> serde_json::Error {
+    if path.is_empty() {
+        err
     } else {
-        Ok(ObjMatcher::Value(v))
+        serde::de::Error::custom(format!("invalid condition for `{path}`: {err}"))
     }
 }
 
@@ -283,6 +1295,31 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    pub fn test_matches_serialize() {
+        #[derive(Serialize)]
+        struct Deployment {
+            name: &'static str,
+            replicas: u32,
+            // As `toml::value::Datetime` serializes.
+            released: serde_json::Map<String, Value>,
+        }
+        let released = json!({"$__toml_private_datetime": "2024-03-01T12:00:00Z"});
+        let deployment = Deployment {
+            name: "api",
+            replicas: 3,
+            released: released.as_object().unwrap().clone(),
+        };
+        let matcher =
+            from_str(r#"{"replicas": {"$gte": 2}, "released": {"$gt": "2024-01-01T00:00:00Z"}}"#)
+                .unwrap();
+        assert!(matcher.matches_serialize(&deployment).unwrap());
+        assert!(!from_str(r#"{"name": "web"}"#)
+            .unwrap()
+            .matches_serialize(&deployment)
+            .unwrap());
+    }
+
     #[test]
     pub fn test_operator_type() {
         let matcher = from_str(r#"{"a":{"$type":["number", "bool"]}}"#).unwrap();
@@ -308,6 +1345,42 @@ mod tests {
         assert!(!matcher.matches(&json!({"a": 3})));
     }
 
+    #[test]
+    pub fn test_in_hashed() {
+        let mut allowed: Vec<Value> = (0..1_000).map(|i| json!(format!("user-{}", i))).collect();
+        allowed.extend(vec![
+            json!(-0.0),
+            json!({"$gt": 1_000_000}),
+            json!({"$type": ["bool"]}),
+        ]);
+        let query = json!({"a": {"$in": allowed.clone()}, "b": {"$nin": allowed}});
+        let matcher = from_json(query.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&matcher).unwrap(), query);
+        let compiled = matcher.compile().unwrap();
+        for (a, matches) in [
+            (json!("user-999"), true),
+            (json!("user-1000"), false),
+            (json!(0.0), true),
+            (json!(0), false),
+            (json!(2_000_000), true),
+            (json!(true), true),
+            (json!({"x": 1}), false),
+        ] {
+            let doc = json!({"a": a, "b": "other"});
+            assert_eq!(matcher.matches(&doc), matches, "{doc}");
+            assert_eq!(compiled.matches(&doc), matches, "{doc}");
+            let doc = json!({"a": "user-1", "b": a});
+            assert_eq!(matcher.matches(&doc), !matches, "{doc}");
+        }
+        let lowercased = MatchOptions::new().lowercase(true);
+        assert!(matcher.matches_with_options(&json!({"a": "USER-7", "b": 1}), &lowercased));
+        assert!(!matcher.matches(&json!({"a": "USER-7", "b": 1})));
+        // Lowercasing is not case folding.
+        let street = from_str(r#"{"a": "stra\u00dfe"}"#).unwrap();
+        assert!(!street.matches_with_options(&json!({"a": "STRASSE"}), &lowercased));
+        assert!(street.matches_with_options(&json!({"a": "STRAẞE"}), &lowercased));
+    }
+
     #[test]
     pub fn test_in() {
         let matcher = from_str(r#"{"a":{"$in":[1, 2]}}"#).unwrap();
@@ -374,4 +1447,578 @@ mod tests {
         let val = json!({"b": 2});
         assert!(matcher.matches(&val));
     }
+
+    #[test]
+    pub fn test_dotted_path() {
+        let matcher =
+            from_str(r#"{"address.city": "London", "address.zip": {"$ne": "N1"}}"#).unwrap();
+        assert!(matcher.matches(&json!({"address": {"city": "London", "zip": "E1"}})));
+        assert!(!matcher.matches(&json!({"address": {"city": "London", "zip": "N1"}})));
+        assert!(!matcher.matches(&json!({"address": "London"})));
+        let compiled = matcher.compile().unwrap();
+        assert!(compiled.matches(&json!({"address": {"city": "London"}})));
+    }
+
+    #[test]
+    pub fn test_escaped_field() {
+        let matcher = from_str(r#"{"\\$type": "x", "a\\.b": {"$ne": 1}}"#).unwrap();
+        assert!(matcher.matches(&json!({"$type": "x", "a.b": 2, "a": {"b": 1}})));
+        assert!(!matcher.matches(&json!({"$type": "x", "a.b": 1})));
+        assert!(!matcher.matches(&json!({"type": "x"})));
+        let compiled = matcher.compile().unwrap();
+        assert!(compiled.matches(&json!({"$type": "x"})));
+    }
+
+    #[test]
+    pub fn test_array_index_path() {
+        let matcher = from_str(r#"{"items.0.sku": "a", "items.-1.status": "shipped"}"#).unwrap();
+        assert!(matcher.matches(&json!({"items": [{"sku": "a"}, {"status": "shipped"}]})));
+        assert!(!matcher.matches(&json!({"items": [{"sku": "a", "status": "shipped"}, {}]})));
+        assert!(!matcher.matches(&json!({"items": {"0": {"sku": "a"}}})));
+    }
+
+    #[test]
+    pub fn test_comparison() {
+        let matcher = from_str(r#"{"n": {"$gt": 1}, "s": {"$lte": "b"}}"#).unwrap();
+        assert!(matcher.matches(&json!({"n": 1.5, "s": "a"})));
+        assert!(matcher.matches(&json!({"n": 2, "s": "b"})));
+        assert!(!matcher.matches(&json!({"n": 1, "s": "a"})));
+        assert!(!matcher.matches(&json!({"n": "2", "s": "a"})));
+        assert!(!matcher.matches(&json!({"s": "a"})));
+        let matcher = from_str(r#"{"n": {"$not": {"$gte": 0}}}"#).unwrap();
+        assert!(matcher.matches(&json!({"n": -1})));
+        assert!(matcher.matches(&json!({"n": null})));
+        let matcher = from_str(r#"{"n": {"$lt": 3}}"#).unwrap();
+        assert!(matcher.compile().unwrap().matches(&json!({"n": 2})));
+    }
+
+    #[test]
+    pub fn test_len() {
+        let matcher = from_str(r#"{"name": {"$len": {"$lte": 4}}, "tags": {"$len": 2}}"#).unwrap();
+        assert!(matcher.matches(&json!({"name": "café", "tags": [1, 2]})));
+        assert!(!matcher.matches(&json!({"name": "cafés", "tags": [1, 2]})));
+        assert!(!matcher.matches(&json!({"name": "cafe", "tags": [1]})));
+        assert!(!matcher.matches(&json!({"name": 4, "tags": [1, 2]})));
+    }
+
+    #[test]
+    pub fn test_operator_siblings() {
+        let matcher = from_str(r#"{"a": {"$gte": 5, "$lt": 100}}"#).unwrap();
+        assert!(matcher.matches(&json!({"a": 5})));
+        assert!(!matcher.matches(&json!({"a": 100})));
+        let matcher = from_str(r#"{"$or": [{"a": 1}, {"a": 2}], "b": true}"#).unwrap();
+        assert!(matcher.matches(&json!({"a": 2, "b": true})));
+        assert!(!matcher.matches(&json!({"a": 2, "b": false})));
+
+        let err = from_str(r#"{"a": {"$in": [1], "typo": 2}}"#).unwrap_err();
+        assert!(err.to_string().contains("`typo`"));
+        assert!(err.to_string().contains("`a`"));
+        let err = from_str(r#"{"a.b": {"$not": {"$gt": 1, "$foo": 2}}}"#).unwrap_err();
+        assert!(err.to_string().contains("`$foo`"));
+        assert!(err.to_string().contains("`a.b`"));
+        assert!(from_str(r#"{"$in": [1], "typo": 2}"#).is_err());
+        assert!(serde_json::from_str::<InOperator>(r#"{"$in": [1], "typo": 2}"#).is_err());
+    }
+
+    #[test]
+    pub fn test_string_literal() {
+        let matcher = from_str(r#"{"a":{"$in":["x", "y"]}}"#).unwrap();
+        assert!(matcher.matches(&json!({"a": "x"})));
+        assert!(!matcher.matches(&json!({"a": "z"})));
+
+        let matcher = from_str(r#""hello""#).unwrap();
+        assert!(matcher.matches(&json!("hello")));
+        assert!(!matcher.matches(&json!({"a": "hello"})));
+    }
+
+    #[cfg(feature = "normalization")]
+    #[test]
+    pub fn test_normalization() {
+        let matcher = from_str(r#"{"name":"caf\u00e9"}"#).unwrap();
+        let decomposed = json!({"name": "cafe\u{301}"});
+        assert!(!matcher.matches(&decomposed));
+
+        let options = MatchOptions::new().normalization(Normalization::Nfc);
+        assert!(matcher.matches_with_options(&decomposed, &options));
+        assert!(!matcher.matches_with_options(&json!({"name": "Caf\u{e9}"}), &options));
+
+        let options = options.lowercase(true);
+        assert!(matcher.matches_with_options(&json!({"name": "CAFE\u{301}"}), &options));
+
+        let matcher = from_str(r#"{"a":{"$in":["fi"]}}"#).unwrap();
+        let ligature = json!({"a": "\u{fb01}"});
+        assert!(!matcher.matches_with_options(
+            &ligature,
+            &MatchOptions::new().normalization(Normalization::Nfc)
+        ));
+        assert!(matcher.matches_with_options(
+            &ligature,
+            &MatchOptions::new().normalization(Normalization::Nfkc)
+        ));
+    }
+
+    #[test]
+    pub fn test_array_comparison() {
+        let matcher = from_str(r#"{"tags": ["a", "b", "a"], "rows": [[1, 2]]}"#).unwrap();
+        let reordered = json!({"tags": ["b", "a", "a"], "rows": [[2, 1]]});
+        let deduplicated = json!({"tags": ["b", "a"], "rows": [[2, 1, 1]]});
+        assert!(!matcher.matches(&reordered));
+        assert!(matcher.matches(&json!({"tags": ["a", "b", "a"], "rows": [[1, 2]]})));
+
+        let unordered = MatchOptions::new().array_comparison(ArrayComparison::Unordered);
+        assert!(matcher.matches_with_options(&reordered, &unordered));
+        assert!(matcher.matches_with_options(&deduplicated, &unordered));
+        assert!(
+            !matcher.matches_with_options(&json!({"tags": ["a"], "rows": [[1, 2]]}), &unordered)
+        );
+
+        let multiset = MatchOptions::new().array_comparison(ArrayComparison::Multiset);
+        assert!(matcher.matches_with_options(&reordered, &multiset));
+        assert!(!matcher.matches_with_options(&deduplicated, &multiset));
+        assert!(!matcher.matches_with_options(
+            &json!({"tags": ["a", "b", "b"], "rows": [[1, 2]]}),
+            &multiset
+        ));
+        assert!(matcher
+            .compile()
+            .unwrap()
+            .matches_with_options(&reordered, &multiset));
+    }
+
+    #[cfg(feature = "icu")]
+    #[test]
+    pub fn test_collation() {
+        let matcher = from_str(r#"{"name":"resume"}"#).unwrap();
+        let doc = json!({"name": "R\u{e9}sum\u{e9}"});
+        assert!(!matcher.matches(&doc));
+
+        let primary = Collation::new("en", CollationStrength::Primary).unwrap();
+        assert!(matcher.matches_with_options(&doc, &MatchOptions::new().collation(primary)));
+        let secondary = Collation::new("en", CollationStrength::Secondary).unwrap();
+        assert!(!matcher.matches_with_options(&doc, &MatchOptions::new().collation(secondary)));
+
+        let matcher = from_str(r#"{"city":"\u0131sparta"}"#).unwrap();
+        let doc = json!({"city": "Isparta"});
+        let turkish = Collation::new("tr", CollationStrength::Secondary).unwrap();
+        assert!(matcher.matches_with_options(&doc, &MatchOptions::new().collation(turkish)));
+        let english = Collation::new("en", CollationStrength::Secondary).unwrap();
+        assert!(!matcher.matches_with_options(&doc, &MatchOptions::new().collation(english)));
+
+        assert!(Collation::new("not a locale!", CollationStrength::Primary).is_err());
+    }
+
+    #[test]
+    pub fn test_bits() {
+        // 54 == 0b110110
+        let doc = json!({"status": 54});
+
+        let matcher = from_str(r#"{"status":{"$bitsAllSet":[1, 2]}}"#).unwrap();
+        assert!(matcher.matches(&doc));
+        let matcher = from_str(r#"{"status":{"$bitsAllSet":3}}"#).unwrap();
+        assert!(!matcher.matches(&doc));
+
+        let matcher = from_str(r#"{"status":{"$bitsAnySet":[0, 1]}}"#).unwrap();
+        assert!(matcher.matches(&doc));
+        let matcher = from_str(r#"{"status":{"$bitsAnySet":9}}"#).unwrap();
+        assert!(!matcher.matches(&doc));
+
+        let matcher = from_str(r#"{"status":{"$bitsAllClear":[0, 3]}}"#).unwrap();
+        assert!(matcher.matches(&doc));
+        let matcher = from_str(r#"{"status":{"$bitsAllClear":[0, 1]}}"#).unwrap();
+        assert!(!matcher.matches(&doc));
+
+        let matcher = from_str(r#"{"status":{"$bitsAnyClear":6}}"#).unwrap();
+        assert!(!matcher.matches(&doc));
+        let matcher = from_str(r#"{"status":{"$bitsAnyClear":7}}"#).unwrap();
+        assert!(matcher.matches(&doc));
+
+        let matcher = from_str(r#"{"status":{"$bitsAllSet":[63, 100]}}"#).unwrap();
+        assert!(matcher.matches(&json!({"status": -1})));
+        assert!(!matcher.matches(&json!({"status": 1})));
+        assert!(!matcher.matches(&json!({"status": "54"})));
+        assert!(!matcher.matches(&json!({"status": 5.5})));
+    }
+
+    #[test]
+    pub fn test_denied_operator() {
+        let options = ParseOptions::new().deny_operator("$nin");
+        assert!(from_str_with_options(r#"{"a":{"$in":[1]}}"#, &options).is_ok());
+        let err = from_str_with_options(r#"{"$or":[{"a":{"$nin":[1]}}]}"#, &options).unwrap_err();
+        assert!(err.to_string().contains("`$nin`"));
+        assert!(from_str(r#"{"$or":[{"a":{"$nin":[1]}}]}"#).is_ok());
+    }
+
+    #[test]
+    pub fn test_parse_limits() {
+        let query = r#"{"$or": [{"a": {"$in": [1, 2, 3]}}, {"name": "abcdef"}]}"#;
+        let parse = |options: ParseOptions| from_str_with_options(query, &options);
+        assert!(parse(ParseOptions::new().max_operators(2).max_arity(3)).is_ok());
+        let err = parse(ParseOptions::new().max_operators(1)).unwrap_err();
+        assert!(err.to_string().contains("limit of 1 operators"));
+        let err = parse(ParseOptions::new().max_arity(2)).unwrap_err();
+        assert!(err.to_string().contains("`$in` with 3 elements"));
+        assert!(parse(ParseOptions::new().max_string_len(6)).is_ok());
+        assert!(parse(ParseOptions::new().max_string_len(5)).is_err());
+        assert!(parse(ParseOptions::new().max_size(query.len())).is_ok());
+        assert!(parse(ParseOptions::new().max_size(query.len() - 1)).is_err());
+        let options = ParseOptions::new().max_size(20);
+        assert!(from_json_with_options(json!({"a": {"$in": [1, 2, 3, 4, 5]}}), &options).is_err());
+    }
+
+    struct DivisibleBy;
+
+    impl CustomOperator for DivisibleBy {
+        fn name(&self) -> &'static str {
+            "$divisibleBy"
+        }
+
+        fn parse(&self, operand: &Value) -> Result<(), String> {
+            match operand.as_u64() {
+                Some(n) if n > 0 => Ok(()),
+                _ => Err("expected a positive integer".to_string()),
+            }
+        }
+
+        fn evaluate(&self, operand: &Value, value: &Value) -> bool {
+            match (value.as_u64(), operand.as_u64()) {
+                (Some(value), Some(divisor)) => value % divisor == 0,
+                _ => false,
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_custom_operator() {
+        let mut registry = OperatorRegistry::new();
+        registry.register(DivisibleBy);
+        let registry = std::sync::Arc::new(registry);
+        let parse_options = ParseOptions::new().registry(registry.clone());
+        let match_options = MatchOptions::new().registry(registry);
+
+        let matcher = from_str_with_options(r#"{"a":{"$divisibleBy":3}}"#, &parse_options).unwrap();
+        assert!(matcher.matches_with_options(&json!({"a": 9}), &match_options));
+        assert!(!matcher.matches_with_options(&json!({"a": 10}), &match_options));
+        assert!(!matcher.matches(&json!({"a": 9})));
+
+        let matcher = from_str_with_options(
+            r#"{"$or":[{"$divisibleBy":5},{"$divisibleBy":7}]}"#,
+            &parse_options,
+        )
+        .unwrap();
+        assert!(matcher.matches_with_options(&json!(14), &match_options));
+        assert!(!matcher.matches_with_options(&json!(3), &match_options));
+
+        let matcher = from_str_with_options(r#"{"$divisibleBy":2}"#, &parse_options).unwrap();
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"$divisibleBy": 2})
+        );
+
+        let err = from_str_with_options(r#"{"a":{"$divisibleBy":0}}"#, &parse_options).unwrap_err();
+        assert!(err.to_string().contains("expected a positive integer"));
+    }
+
+    #[test]
+    pub fn test_comment() {
+        let query =
+            r#"{"$comment":"active admins","role":"admin","age":{"$in":[1,2],"$comment":"small"}}"#;
+        let matcher = from_str(query).unwrap();
+        assert!(matcher.matches(&json!({"role": "admin", "age": 1})));
+        assert!(!matcher.matches(&json!({"role": "user", "age": 1})));
+
+        let matcher =
+            from_str(r#"{"$and":[{"$comment":"anything"},{"a":{"$ne":1,"$comment":"not one"}}]}"#)
+                .unwrap();
+        assert!(matcher.matches(&json!({"a": 2})));
+        assert!(!matcher.matches(&json!({"a": 1})));
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"$and":[{"$comment":"anything"},{"a":{"$ne":1,"$comment":"not one"}}]})
+        );
+
+        let matcher = from_str(r#"{"$type":["number"],"$comment":"numbers only"}"#).unwrap();
+        assert!(matcher.matches(&json!(1)));
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"$type":["number"],"$comment":"numbers only"})
+        );
+    }
+
+    #[test]
+    pub fn test_refs() {
+        let mut registry = MatcherRegistry::new();
+        registry
+            .define_str(
+                "common/active_user",
+                r#"{"active":true,"deleted":{"$ne":true}}"#,
+            )
+            .unwrap();
+        registry
+            .define_str(
+                "common/active_admin",
+                r#"{"$and":[{"$ref":"common/active_user"},{"role":"admin"}]}"#,
+            )
+            .unwrap();
+        let options = ParseOptions::new().matchers(std::sync::Arc::new(registry.clone()));
+
+        let matcher = from_str_with_options(
+            r#"{"$or":[{"$ref":"common/active_admin"},{"owner":true}]}"#,
+            &options,
+        )
+        .unwrap();
+        assert!(matcher.matches(&json!({"active": true, "role": "admin"})));
+        assert!(!matcher.matches(&json!({"active": true, "deleted": true, "role": "admin"})));
+        assert!(!matcher.matches(&json!({"active": true, "role": "user"})));
+        assert!(matcher.matches(&json!({"owner": true})));
+
+        let err = from_str_with_options(r#"{"$ref":"missing"}"#, &options).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown matcher reference `missing`"));
+
+        registry.define("a", json!({"$or": [{"$ref": "b"}, {"x": 1}]}));
+        registry.define("b", json!({"$ref": "a"}));
+        let options = ParseOptions::new().matchers(std::sync::Arc::new(registry));
+        let err = from_str_with_options(r#"{"$ref":"a"}"#, &options).unwrap_err();
+        assert!(err.to_string().contains("a -> b -> a"));
+
+        // Limits apply to the resolved query, as they do in `validate`.
+        let mut registry = MatcherRegistry::new();
+        registry.define("large", json!({"a": {"$in": [1, 2, 3]}, "b": {"$gt": 0}}));
+        let options = ParseOptions::new()
+            .matchers(std::sync::Arc::new(registry))
+            .max_operators(5);
+        let query = r#"{"$and":[{"$ref":"large"},{"$ref":"large"},{"$ref":"large"}]}"#;
+        let err = from_str_with_options(query, &options).unwrap_err();
+        assert!(err.to_string().contains("limit of 5 operators"));
+        assert!(!validate_with_options(query, &options).is_empty());
+        let options = options.max_operators(7).max_size(query.len() + 10);
+        assert!(from_str_with_options(query, &options).is_err());
+    }
+
+    #[test]
+    pub fn test_bind() {
+        let template =
+            from_str(r#"{"$and":[{"tenant":{"$param":"tenant"}},{"level":{"$in":[{"$param":"level"},"error"]}}]}"#)
+                .unwrap();
+        assert_eq!(template.params(), vec!["tenant", "level"]);
+        assert!(!template.matches(&json!({"tenant": "acme", "level": "warn"})));
+
+        let params = json!({"tenant": "acme", "level": "warn"});
+        let matcher = template.bind(params.as_object().unwrap()).unwrap();
+        assert!(matcher.params().is_empty());
+        assert!(matcher.matches(&json!({"tenant": "acme", "level": "warn"})));
+        assert!(matcher.matches(&json!({"tenant": "acme", "level": "error"})));
+        assert!(!matcher.matches(&json!({"tenant": "other", "level": "warn"})));
+
+        let params = json!({"tenant": "acme"});
+        let err = template.bind(params.as_object().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("missing parameter `level`"));
+
+        // A whole `$in`/`$nin` operand can be a parameter.
+        let template = from_str(r#"{"id": {"$in": {"$param": "ids"}}}"#).unwrap();
+        assert_eq!(template.params(), vec!["ids"]);
+        assert!(!template.matches(&json!({"id": 1})));
+        let excluded = from_str(r#"{"id": {"$nin": {"$param": "ids"}}}"#).unwrap();
+        assert!(!excluded.matches(&json!({"id": 1})));
+        let params = json!({"ids": [1, 2]});
+        let matcher = template.bind(params.as_object().unwrap()).unwrap();
+        assert!(matcher.matches(&json!({"id": 2})));
+        assert!(!matcher.matches(&json!({"id": 3})));
+        let matcher = excluded.bind(params.as_object().unwrap()).unwrap();
+        assert!(!matcher.matches(&json!({"id": 2})));
+        assert!(matcher.matches(&json!({"id": 3})));
+        let params = json!({"ids": 1});
+        assert!(template.bind(params.as_object().unwrap()).is_err());
+
+        // The options the template was parsed with apply to the bound matcher.
+        let options = ParseOptions::new().deny_operator("$nin");
+        let params = json!({"ids": [1, 2]});
+        assert!(excluded
+            .bind_with_options(params.as_object().unwrap(), &options)
+            .is_err());
+        assert!(template
+            .bind_with_options(params.as_object().unwrap(), &options)
+            .is_ok());
+    }
+
+    struct Deployment;
+
+    impl ContextProvider for Deployment {
+        fn now(&self) -> Value {
+            json!(1_700_000_000_000u64)
+        }
+
+        fn get(&self, key: &str) -> Option<Value> {
+            match key {
+                "region" => Some(json!("eu-west-1")),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_context() {
+        let matcher = from_str(r#"{"region":{"$context":"region"}}"#).unwrap();
+        let options = MatchOptions::new().context(std::sync::Arc::new(Deployment));
+        assert!(matcher.matches_with_options(&json!({"region": "eu-west-1"}), &options));
+        assert!(!matcher.matches_with_options(&json!({"region": "us-east-1"}), &options));
+        assert!(!matcher.matches(&json!({"region": "eu-west-1"})));
+
+        let matcher = from_str(r#"{"at":{"$in":[{"$now":{}}, 0]}}"#).unwrap();
+        assert!(matcher.matches_with_options(&json!({"at": 1_700_000_000_000u64}), &options));
+        assert!(matcher.matches_with_options(&json!({"at": 0}), &options));
+
+        let matcher = from_str(r#"{"zone":{"$context":"zone"}}"#).unwrap();
+        assert!(matcher.matches_with_options(&json!({"other": 1}), &options));
+
+        std::env::set_var("SERDE_JSON_MATCHER_TEST_STAGE", "prod");
+        let matcher = from_str(r#"{"stage":{"$env":"SERDE_JSON_MATCHER_TEST_STAGE"}}"#).unwrap();
+        let options = MatchOptions::new().context(std::sync::Arc::new(SystemContext));
+        assert!(matcher.matches_with_options(&json!({"stage": "prod"}), &options));
+        // Other providers only expose the variables they choose to.
+        let options = MatchOptions::new().context(std::sync::Arc::new(Deployment));
+        assert!(!matcher.matches_with_options(&json!({"stage": "prod"}), &options));
+        assert!(matcher.matches_with_options(&json!({}), &options));
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    pub fn test_where() {
+        let matcher = from_str(r#"{"$where":"doc.a + doc.b == 3"}"#).unwrap();
+        assert!(matcher.matches(&json!({"a": 1, "b": 2})));
+        assert!(!matcher.matches(&json!({"a": 2, "b": 2})));
+        assert!(!matcher.matches(&json!({"a": "x"})));
+
+        let matcher = from_str(r#"{"items":{"$where":"doc.len() > 1"}}"#).unwrap();
+        assert!(matcher.matches(&json!({"items": [1, 2]})));
+        assert!(!matcher.matches(&json!({"items": [1]})));
+
+        let matcher = from_str(r#"{"$where":"loop {}"}"#).unwrap();
+        assert!(!matcher.matches(&json!({})));
+        let matcher = from_str(r#"{"$where":"let s = \"x\"; loop { s += s; }"}"#).unwrap();
+        assert!(!matcher.matches(&json!({})));
+        let matcher = from_str(r#"{"$where":"fn f(n) { f(n + 1) } f(0)"}"#).unwrap();
+        assert!(!matcher.matches(&json!({})));
+
+        let err = from_str(r#"{"$where":"doc.a +"}"#).unwrap_err();
+        assert!(err.to_string().contains("invalid `$where` script"));
+        let matcher = from_str(r#"{"a":{"$where":"doc > 1"}}"#).unwrap();
+        assert_eq!(
+            serde_json::to_value(&matcher).unwrap(),
+            json!({"a": {"$where": "doc > 1"}})
+        );
+
+        let options = ParseOptions::new().deny_operator("$where");
+        assert!(from_str_with_options(r#"{"$where":"true"}"#, &options).is_err());
+    }
+
+    #[cfg(feature = "geo")]
+    #[test]
+    pub fn test_geo_within() {
+        let matcher = from_str(
+            r#"{"loc":{"$geoWithin":{"$geometry":{"type":"Polygon","coordinates":[[[0,0],[10,0],[10,10],[0,10],[0,0]],[[4,4],[6,4],[6,6],[4,6],[4,4]]]}}}}"#,
+        )
+        .unwrap();
+        assert!(matcher.matches(&json!({"loc": {"type": "Point", "coordinates": [1, 1]}})));
+        assert!(matcher.matches(&json!({"loc": [9.5, 2]})));
+        assert!(!matcher.matches(&json!({"loc": [5, 5]})));
+        assert!(!matcher.matches(&json!({"loc": [11, 5]})));
+        assert!(!matcher.matches(&json!({"loc": "somewhere"})));
+
+        let matcher = from_str(r#"{"loc":{"$geoWithin":{"$box":[[0,0],[2,2]]}}}"#).unwrap();
+        assert!(matcher.matches(&json!({"loc": [1, 2]})));
+        assert!(!matcher.matches(&json!({"loc": [3, 1]})));
+
+        let matcher = from_str(r#"{"loc":{"$geoWithin":{"$center":[[0,0],5]}}}"#).unwrap();
+        assert!(matcher.matches(&json!({"loc": [3, 4]})));
+        assert!(!matcher.matches(&json!({"loc": [4, 4]})));
+    }
+
+    #[cfg(feature = "geo")]
+    #[test]
+    pub fn test_near() {
+        // Paris to London is roughly 344km.
+        let matcher = from_str(
+            r#"{"loc":{"$near":{"$geometry":{"type":"Point","coordinates":[2.3522,48.8566]},"$maxDistance":350000}}}"#,
+        )
+        .unwrap();
+        assert!(matcher.matches(&json!({"loc": [-0.1276, 51.5072]})));
+        assert!(!matcher.matches(&json!({"loc": [13.405, 52.52]})));
+
+        let matcher = from_str(
+            r#"{"loc":{"$near":{"$geometry":{"type":"Point","coordinates":[2.3522,48.8566]},"$minDistance":1000}}}"#,
+        )
+        .unwrap();
+        assert!(!matcher.matches(&json!({"loc": [2.3522, 48.8566]})));
+        assert!(matcher.matches(&json!({"loc": [-0.1276, 51.5072]})));
+    }
+
+    #[cfg(feature = "arbitrary-precision")]
+    #[test]
+    pub fn test_wide_integers() {
+        // Token ids past `u64::MAX`, and past `f64`'s 53 bits of precision.
+        let matcher = from_str(
+            r#"{"token": {"$gt": 340282366920938463463374607431768211000, "$lte": 340282366920938463463374607431768211455}, "owner": {"$in": [-170141183460469231731687303715884105728, 18446744073709551617]}}"#,
+        )
+        .unwrap();
+        let doc = |token: &str, owner: &str| {
+            serde_json::from_str::<Value>(&format!(r#"{{"token": {token}, "owner": {owner}}}"#))
+                .unwrap()
+        };
+        let compiled = CompiledMatcher::from_bytes(&matcher.compile().unwrap().to_bytes()).unwrap();
+        for (doc, expected) in &[
+            (
+                doc(
+                    "340282366920938463463374607431768211455",
+                    "18446744073709551617",
+                ),
+                true,
+            ),
+            (
+                doc(
+                    "340282366920938463463374607431768211001",
+                    "-170141183460469231731687303715884105728",
+                ),
+                true,
+            ),
+            (
+                doc(
+                    "340282366920938463463374607431768211000",
+                    "18446744073709551617",
+                ),
+                false,
+            ),
+            (
+                doc(
+                    "340282366920938463463374607431768211455",
+                    "18446744073709551616",
+                ),
+                false,
+            ),
+            (doc("3.4e38", "18446744073709551617"), false),
+        ] {
+            assert_eq!(matcher.matches(doc), *expected, "{doc}");
+            assert_eq!(compiled.matches(doc), *expected, "{doc}");
+        }
+        let borrowed = BorrowedMatcher::parse(r#"{"id": 18446744073709551617}"#).unwrap();
+        assert!(borrowed.matches(&serde_json::from_str(r#"{"id": 18446744073709551617}"#).unwrap()));
+        assert!(
+            !borrowed.matches(&serde_json::from_str(r#"{"id": 18446744073709551616}"#).unwrap())
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    pub fn test_lazy_matcher() {
+        lazy_matcher! {
+            /// Adult users.
+            static ADULTS = r#"{"age": {"$gte": 18}}"#;
+            pub(crate) static ADMINS = r#"{"roles": {"$in": ["admin"]}}"#;
+        }
+
+        assert!(ADULTS.matches(&json!({"age": 21})));
+        assert!(!ADULTS.matches(&json!({"age": 17})));
+        assert!(ADMINS.matches(&json!({"roles": "admin"})));
+    }
 }
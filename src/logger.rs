@@ -0,0 +1,150 @@
+//! Filtering `log` records with matchers.
+//!
+//! Each record is turned into a document holding its key-value pairs, with the record's level
+//! (e.g. `"WARN"`) under `level`, its target under `target` and its formatted message under
+//! `message`; pairs of the same names are overridden. Integers, floats, booleans and strings keep
+//! their types; any other value is recorded as its `Display` representation.
+
+use std::sync::{Arc, RwLock};
+
+use log::kv::{self, VisitSource};
+use log::{Log, Metadata, Record};
+use serde_json::{Map, Value};
+
+use crate::ObjMatcher;
+
+/// A [`Log`] that forwards to another logger only the records matching a matcher, which can be
+/// replaced at any time.
+#[derive(Debug)]
+pub struct MatcherLogger<L> {
+    inner: L,
+    matcher: Arc<RwLock<ObjMatcher>>,
+}
+
+impl<L: Log> MatcherLogger<L> {
+    #[must_use]
+    pub fn new(inner: L, matcher: ObjMatcher) -> Self {
+        MatcherLogger {
+            inner,
+            matcher: Arc::new(RwLock::new(matcher)),
+        }
+    }
+
+    /// Handle for replacing the matcher once the logger has been installed.
+    #[must_use]
+    pub fn handle(&self) -> Arc<RwLock<ObjMatcher>> {
+        self.matcher.clone()
+    }
+
+    /// Returns whether the record would be forwarded, for use as a filter in other pipelines.
+    #[must_use]
+    pub fn matches_record(&self, record: &Record<'_>) -> bool {
+        let document = record_document(record);
+        self.matcher.read().unwrap().matches(&document)
+    }
+}
+
+impl<L: Log> Log for MatcherLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.inner.enabled(record.metadata()) && self.matches_record(record) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn record_document(record: &Record<'_>) -> Value {
+    let mut pairs = Pairs(Map::new());
+    let _ = record.key_values().visit(&mut pairs);
+    let mut document = pairs.0;
+    document.insert("level".to_string(), Value::from(record.level().as_str()));
+    document.insert("target".to_string(), Value::from(record.target()));
+    document.insert(
+        "message".to_string(),
+        Value::from(record.args().to_string()),
+    );
+    Value::Object(document)
+}
+
+struct Pairs(Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Pairs {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(v) = value.to_u64() {
+            Value::from(v)
+        } else if let Some(v) = value.to_i64() {
+            Value::from(v)
+        } else if let Some(v) = value.to_f64() {
+            Value::from(v)
+        } else if let Some(v) = value.to_bool() {
+            Value::from(v)
+        } else if let Some(v) = value.to_borrowed_str() {
+            Value::from(v)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use std::sync::Mutex;
+
+    /// Records the message of every record it receives.
+    #[derive(Default)]
+    struct Messages(Mutex<Vec<String>>);
+
+    impl Log for Messages {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn log(logger: &dyn Log, level: log::Level, pairs: &[(&str, i64)], message: &str) {
+        let pairs: Vec<(&str, kv::Value<'_>)> = pairs
+            .iter()
+            .map(|(k, v)| (*k, kv::Value::from(*v)))
+            .collect();
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target("app")
+                .key_values(&&pairs[..])
+                .args(format_args!("{message}"))
+                .build(),
+        );
+    }
+
+    #[test]
+    pub fn test_matcher_logger() {
+        let matcher = from_str(r#"{"$or": [{"level": "ERROR"}, {"attempt": {"$in": [2, 3]}}]}"#);
+        let logger = MatcherLogger::new(Messages::default(), matcher.unwrap());
+        log(&logger, log::Level::Info, &[("attempt", 1)], "first");
+        log(&logger, log::Level::Info, &[("attempt", 2)], "retried");
+        log(&logger, log::Level::Error, &[], "failed");
+        *logger.handle().write().unwrap() = from_str(r#"{"message": "first"}"#).unwrap();
+        log(&logger, log::Level::Error, &[], "failed");
+        log(&logger, log::Level::Info, &[("attempt", 1)], "first");
+        assert_eq!(
+            *logger.inner.0.lock().unwrap(),
+            vec!["retried", "failed", "first"]
+        );
+    }
+}
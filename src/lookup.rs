@@ -0,0 +1,197 @@
+//! `$lookup`, checking a value against reference data held outside the query.
+//!
+//! `{"user_id": {"$lookup": "blocked_users"}}` holds when the resolver set with
+//! [`MatchOptions::resolver`] reports that the set named `blocked_users` contains the value of
+//! `user_id`. Without a resolver, `$lookup` never matches.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde_json::Value;
+
+use crate::{MatchOptions, ObjMatcher};
+
+/// Answers `$lookup` clauses, set with [`MatchOptions::resolver`].
+pub trait Resolver: Send + Sync {
+    /// Whether the set named `set` contains `value`.
+    fn contains(&self, set: &str, value: &Value) -> bool;
+}
+
+/// Answers `$lookup` clauses asynchronously, for reference data behind a network call, in
+/// [`ObjMatcher::matches_async`].
+pub trait AsyncResolver: Send + Sync {
+    /// Whether the set named `set` contains `value`.
+    fn contains<'a>(
+        &'a self,
+        set: &'a str,
+        value: &'a Value,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+}
+
+impl ObjMatcher {
+    /// Returns whether `other` matches, answering `$lookup` clauses with `resolver`.
+    ///
+    /// Each distinct lookup is resolved once, one after the other, in evaluation order. Lookups
+    /// in clauses that do not affect the outcome, such as the remaining clauses of an `$or` after
+    /// one matched, are not resolved.
+    ///
+    /// The matcher is evaluated again after each answer, so a document needing `k` lookups costs
+    /// `k + 1` evaluations. Matchers with many lookups per document are better served by a
+    /// synchronous [`Resolver`] over prefetched data.
+    pub async fn matches_async(&self, other: &Value, resolver: &dyn AsyncResolver) -> bool {
+        self.matches_async_with_options(other, resolver, &MatchOptions::default())
+            .await
+    }
+
+    pub async fn matches_async_with_options(
+        &self,
+        other: &Value,
+        resolver: &dyn AsyncResolver,
+        options: &MatchOptions,
+    ) -> bool {
+        // Only the final evaluation is reported to the observer, not the probing ones.
+        let probing = options.unobserved();
+        let mut answers = Vec::new();
+        loop {
+            // Evaluates with the lookups answered so far, recording the first one that is not.
+            // Every clause before it was evaluated exactly, so the real evaluation needs it too;
+            // once a pass needs no new lookup, its outcome is final.
+            let known = Arc::new(Known {
+                answers: answers.clone(),
+                missing: Mutex::new(None),
+            });
+            let outcome =
+                self.matches_with_options(other, &probing.clone().resolver(known.clone()));
+            let missing = known
+                .missing
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            let (set, value) = match missing {
+                Some(missing) => missing,
+                None if options.evaluation_observer().is_none() => return outcome,
+                None => return self.matches_with_options(other, &options.clone().resolver(known)),
+            };
+            let contained = resolver.contains(&set, &value).await;
+            answers.push((set, value, contained));
+        }
+    }
+}
+
+/// Lookups answered so far by an [`AsyncResolver`].
+struct Known {
+    answers: Vec<(String, Value, bool)>,
+    missing: Mutex<Option<(String, Value)>>,
+}
+
+impl Resolver for Known {
+    fn contains(&self, set: &str, value: &Value) -> bool {
+        let answer = self.answers.iter().find(|(s, v, _)| s == set && v == value);
+        if let Some((_, _, contained)) = answer {
+            return *contained;
+        }
+        let mut missing = self.missing.lock().unwrap();
+        if missing.is_none() {
+            *missing = Some((set.to_string(), value.clone()));
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    struct Blocked;
+
+    impl Resolver for Blocked {
+        fn contains(&self, set: &str, value: &Value) -> bool {
+            set == "blocked_users" && (value == &json!(7) || value == &json!(9))
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingBlocked(AtomicUsize);
+
+    impl AsyncResolver for CountingBlocked {
+        fn contains<'a>(
+            &'a self,
+            set: &'a str,
+            value: &'a Value,
+        ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Blocked.contains(set, value) })
+        }
+    }
+
+    /// Polls a future that never waits, as the resolver in these tests.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn raw() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                raw()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let waker = unsafe { Waker::from_raw(raw()) };
+        let mut future = Box::pin(future);
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future is pending"),
+        }
+    }
+
+    #[test]
+    pub fn test_lookup() {
+        let matcher = from_str(
+            r#"{"$or": [{"user_id": {"$lookup": "blocked_users"}}, {"owner": {"$lookup": "blocked_users"}}]}"#,
+        )
+        .unwrap();
+        let options = MatchOptions::new().resolver(Arc::new(Blocked));
+        assert!(matcher.matches_with_options(&json!({"user_id": 7}), &options));
+        assert!(matcher.matches_with_options(&json!({"user_id": 1, "owner": 9}), &options));
+        assert!(!matcher.matches_with_options(&json!({"user_id": 1}), &options));
+        assert!(!matcher.matches(&json!({"user_id": 7})));
+        let compiled = matcher.compile().unwrap();
+        assert!(compiled.matches_with_options(&json!({"owner": 7}), &options));
+        assert!(from_str(r#"{"a": {"$lookup": 1}}"#).is_err());
+
+        let resolver = CountingBlocked::default();
+        assert!(block_on(
+            matcher.matches_async(&json!({"user_id": 7}), &resolver)
+        ));
+        assert_eq!(resolver.0.load(Ordering::SeqCst), 1);
+        assert!(block_on(
+            matcher.matches_async(&json!({"user_id": 1, "owner": 9}), &resolver)
+        ));
+        assert_eq!(resolver.0.load(Ordering::SeqCst), 3);
+        assert!(!block_on(matcher.matches_async(&json!({}), &resolver)));
+
+        // The observer sees the final evaluation only, not the ones waiting for lookups.
+        let exits = Arc::new(Exits::default());
+        let options = MatchOptions::new().observer(exits.clone());
+        let doc = json!({"user_id": 1, "owner": 9});
+        assert!(block_on(
+            matcher.matches_async_with_options(&doc, &resolver, &options)
+        ));
+        let evaluated = exits.0.swap(0, Ordering::SeqCst);
+        let options = options.resolver(Arc::new(Blocked));
+        assert!(matcher.matches_with_options(&doc, &options));
+        assert_eq!(evaluated, exits.0.load(Ordering::SeqCst));
+    }
+
+    #[derive(Default)]
+    struct Exits(AtomicUsize);
+
+    impl crate::Observer for Exits {
+        fn exit(&self, _: &crate::Clause<'_>, _: bool) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
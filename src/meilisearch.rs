@@ -0,0 +1,306 @@
+//! Translating matchers into Meilisearch filter expressions.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{context, parse_operator, path, ObjMatcher, UnsupportedClause};
+
+/// A matcher translated by [`ObjMatcher::to_meilisearch_filter`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MeilisearchFilter {
+    /// The filter expression, or `None` if nothing could be translated, in which case every
+    /// document passes.
+    pub filter: Option<String>,
+    /// The clauses Meilisearch cannot express, in query order. They were left out of `filter`,
+    /// together with any `$or` or `$not` containing them, so that `filter` keeps every matching
+    /// document.
+    pub unsupported: Vec<UnsupportedClause>,
+}
+
+impl ObjMatcher {
+    /// Translates the matcher into the filter syntax of Meilisearch, e.g. `{"genre": {"$in":
+    /// ["horror", "comedy"]}, "year": {"$gte": 2000}}` into
+    /// `genre IN ["horror", "comedy"] AND year >= 2000`, for applying the same rules at the search
+    /// layer.
+    ///
+    /// Field conditions, `$eq`, `$ne`, `$in`, `$nin`, `$and`, `$or`, `$not` and comparisons with
+    /// numbers are translated; fields compared with `null` also match when missing, as here. The
+    /// filter is exact when [`MeilisearchFilter::unsupported`] is empty, except that Meilisearch
+    /// compares each element of an array attribute and does not tell numbers from their string
+    /// forms, so it may keep more documents than the matcher. Otherwise, it keeps a superset of
+    /// the matching documents, which the matcher must then filter.
+    #[must_use]
+    pub fn to_meilisearch_filter(&self) -> MeilisearchFilter {
+        let mut translator = Translator {
+            unsupported: Vec::new(),
+        };
+        let filter = translator.matcher(self, "").map(|expr| expr.text);
+        MeilisearchFilter {
+            filter,
+            unsupported: translator.unsupported,
+        }
+    }
+}
+
+struct Expr {
+    text: String,
+    /// Whether the expression joins others with `AND` or `OR`, and so needs parentheses inside
+    /// another.
+    compound: bool,
+}
+
+impl Expr {
+    fn simple(text: String) -> Expr {
+        Expr {
+            text,
+            compound: false,
+        }
+    }
+
+    fn grouped(self) -> String {
+        if self.compound {
+            format!("({})", self.text)
+        } else {
+            self.text
+        }
+    }
+}
+
+/// Each translation returns `None` when it does not restrict the documents, either because the
+/// matcher matches everything or because it was left out.
+struct Translator {
+    unsupported: Vec<UnsupportedClause>,
+}
+
+impl Translator {
+    fn matcher(&mut self, matcher: &ObjMatcher, path: &str) -> Option<Expr> {
+        match matcher {
+            ObjMatcher::Eq(op) => self.matcher(&op.val, path),
+            ObjMatcher::Ne(op) => self.not(&op.val, path),
+            ObjMatcher::And(op) => {
+                let children = op.val.iter().map(|m| self.matcher(m, path)).collect();
+                join(children, " AND ")
+            }
+            ObjMatcher::Or(op) if !op.val.is_empty() => {
+                let children = op.val.iter().map(|m| self.matcher(m, path)).collect();
+                join(children, " OR ")
+            }
+            ObjMatcher::Not(op) => self.not(&op.val, path),
+            ObjMatcher::Gt(op) => self.compare(matcher, path, ">", &op.val),
+            ObjMatcher::Gte(op) => self.compare(matcher, path, ">=", &op.val),
+            ObjMatcher::Lt(op) => self.compare(matcher, path, "<", &op.val),
+            ObjMatcher::Lte(op) => self.compare(matcher, path, "<=", &op.val),
+            ObjMatcher::In(op) => self.member(matcher, path, &op.val, "IN"),
+            ObjMatcher::Nin(op) => self.member(matcher, path, &op.val, "NOT IN"),
+            ObjMatcher::Value(value) => {
+                if let Ok(Some(matcher)) = parse_operator(value.clone(), None) {
+                    return self.matcher(&matcher, path);
+                }
+                match value {
+                    Value::Object(o) if !context::is_reference(value) => {
+                        let children = o
+                            .iter()
+                            .filter(|(key, _)| *key != "$comment")
+                            .map(|(key, value)| {
+                                let path = if path.is_empty() {
+                                    key.clone()
+                                } else {
+                                    format!("{path}.{key}")
+                                };
+                                match parse_operator(value.clone(), None) {
+                                    Ok(Some(matcher)) => self.matcher(&matcher, &path),
+                                    _ => self.equal(value, &path),
+                                }
+                            })
+                            .collect();
+                        join(children, " AND ")
+                    }
+                    _ => self.equal(value, path),
+                }
+            }
+            _ => self.unsupported(matcher, path),
+        }
+    }
+
+    fn not(&mut self, matcher: &ObjMatcher, path: &str) -> Option<Expr> {
+        let unsupported = self.unsupported.len();
+        let expr = self.matcher(matcher, path)?;
+        // Negating a filter that keeps too many documents would drop matching ones.
+        if self.unsupported.len() > unsupported {
+            return None;
+        }
+        Some(Expr::simple(format!("NOT {}", expr.grouped())))
+    }
+
+    fn equal(&mut self, value: &Value, path: &str) -> Option<Expr> {
+        let literal = ObjMatcher::Value(value.clone());
+        let attribute = match attribute(path) {
+            Some(attribute) => attribute,
+            None => return self.unsupported(&literal, path),
+        };
+        if value.is_null() {
+            return Some(Expr {
+                text: format!("{attribute} IS NULL OR {attribute} NOT EXISTS"),
+                compound: true,
+            });
+        }
+        match scalar(value) {
+            Some(scalar) => Some(Expr::simple(format!("{attribute} = {scalar}"))),
+            None => self.unsupported(&literal, path),
+        }
+    }
+
+    fn compare(
+        &mut self,
+        matcher: &ObjMatcher,
+        path: &str,
+        operator: &str,
+        operand: &Value,
+    ) -> Option<Expr> {
+        match (attribute(path), operand) {
+            (Some(attribute), Value::Number(n)) => {
+                Some(Expr::simple(format!("{attribute} {operator} {n}")))
+            }
+            _ => self.unsupported(matcher, path),
+        }
+    }
+
+    fn member(
+        &mut self,
+        matcher: &ObjMatcher,
+        path: &str,
+        members: &[ObjMatcher],
+        operator: &str,
+    ) -> Option<Expr> {
+        let scalars = members
+            .iter()
+            .map(|member| match member {
+                ObjMatcher::Value(value) if !value.is_null() => scalar(value),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>();
+        match (attribute(path), scalars) {
+            (Some(attribute), Some(scalars)) => Some(Expr::simple(format!(
+                "{attribute} {operator} [{}]",
+                scalars.join(", ")
+            ))),
+            _ => self.unsupported(matcher, path),
+        }
+    }
+
+    fn unsupported(&mut self, matcher: &ObjMatcher, path: &str) -> Option<Expr> {
+        self.unsupported.push(UnsupportedClause::new(matcher, path));
+        None
+    }
+}
+
+/// Joins the expressions that restrict the documents. A conjunction ignores the others; a
+/// disjunction with one of them does not restrict the documents either.
+fn join(children: Vec<Option<Expr>>, separator: &str) -> Option<Expr> {
+    let conjunction = separator == " AND ";
+    let mut exprs = Vec::new();
+    for child in children {
+        match child {
+            Some(expr) => exprs.push(expr),
+            None if conjunction => {}
+            None => return None,
+        }
+    }
+    match exprs.len() {
+        0 => None,
+        1 => exprs.pop(),
+        _ => Some(Expr {
+            text: exprs
+                .into_iter()
+                .map(Expr::grouped)
+                .collect::<Vec<_>>()
+                .join(separator),
+            compound: true,
+        }),
+    }
+}
+
+/// The attribute at `path` in Meilisearch's dot notation, if its field names need no quoting.
+fn attribute(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    let segments = path::segments(path)
+        .map(|segment| {
+            let plain = !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if plain {
+                Some(segment.into_owned())
+            } else {
+                None
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(segments.join("."))
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(format!(
+            "\"{}\"",
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        )),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    fn filter(query: &str) -> Option<String> {
+        let translated = from_str(query).unwrap().to_meilisearch_filter();
+        assert!(translated.unsupported.is_empty(), "{:?}", translated);
+        translated.filter
+    }
+
+    #[test]
+    pub fn test_to_meilisearch_filter() {
+        assert_eq!(
+            filter(
+                r#"{"genre": {"$in": ["horror", "comedy"]}, "year": {"$gte": 2000, "$lt": 2010}}"#
+            )
+            .unwrap(),
+            r#"genre IN ["horror", "comedy"] AND (year >= 2000 AND year < 2010)"#
+        );
+        assert_eq!(
+            filter(r#"{"$or": [{"author.name": "O\"Brien"}, {"rating": {"$not": {"$lte": 3}}}], "draft": {"$ne": true}}"#)
+                .unwrap(),
+            r#"(author.name = "O\"Brien" OR NOT rating <= 3) AND NOT draft = true"#
+        );
+        assert_eq!(
+            filter(r#"{"deleted_at": null, "tags": {"$nin": ["spam", 1]}}"#).unwrap(),
+            r#"(deleted_at IS NULL OR deleted_at NOT EXISTS) AND tags NOT IN ["spam", 1]"#
+        );
+        assert_eq!(filter("{}"), None);
+
+        let translated = from_str(r#"{"year": {"$gt": 1990}, "title": {"$len": 5}}"#)
+            .unwrap()
+            .to_meilisearch_filter();
+        assert_eq!(translated.filter.unwrap(), "year > 1990");
+        assert_eq!(translated.unsupported.len(), 1);
+        assert_eq!(translated.unsupported[0].path, "title");
+        assert_eq!(translated.unsupported[0].clause, json!({"$len": 5}));
+
+        // Leaving a clause out of a disjunction or a negation would drop matching documents.
+        for query in &[
+            r#"{"$or": [{"year": 1990}, {"tags": ["a", "b"]}]}"#,
+            r#"{"$not": {"year": 1990, "title": {"$gt": "m"}}}"#,
+            r#"{"a\\.b": 1}"#,
+        ] {
+            let translated = from_str(query).unwrap().to_meilisearch_filter();
+            assert_eq!(translated.filter, None, "{query}");
+            assert_eq!(translated.unsupported.len(), 1, "{query}");
+        }
+    }
+}
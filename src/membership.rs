@@ -0,0 +1,117 @@
+//! Operand of `$in` and `$nin`, with its literal elements hashed for long allow- and deny-lists.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{ejson, MatchOptions, ObjMatcher};
+
+/// The elements of `$in` or `$nin`. Scalars and arrays are literals compared for equality, which
+/// with the default [`MatchOptions`] is a lookup in a hash set; only the other elements, such as
+/// operators or field conditions, are evaluated one after the other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<ObjMatcher>", into = "Vec<ObjMatcher>")]
+pub struct Members {
+    elements: Vec<ObjMatcher>,
+    literals: HashSet<String>,
+    /// Positions of the elements that are not literals.
+    matchers: Vec<usize>,
+}
+
+impl From<Vec<ObjMatcher>> for Members {
+    fn from(elements: Vec<ObjMatcher>) -> Self {
+        let mut literals = HashSet::new();
+        let mut matchers = Vec::new();
+        for (i, element) in elements.iter().enumerate() {
+            match element {
+                ObjMatcher::Value(value) if !value.is_object() => {
+                    literals.insert(key(value));
+                }
+                _ => matchers.push(i),
+            }
+        }
+        Members {
+            elements,
+            literals,
+            matchers,
+        }
+    }
+}
+
+impl From<Members> for Vec<ObjMatcher> {
+    fn from(members: Members) -> Vec<ObjMatcher> {
+        members.elements
+    }
+}
+
+impl Deref for Members {
+    type Target = [ObjMatcher];
+
+    fn deref(&self) -> &[ObjMatcher] {
+        &self.elements
+    }
+}
+
+impl Members {
+    /// Number of literal elements that are looked up rather than scanned.
+    pub(crate) fn hashed(&self) -> usize {
+        self.literals.len()
+    }
+
+    /// Whether some element matches `value`.
+    pub(crate) fn any(&self, value: &Value, options: &MatchOptions) -> bool {
+        let value = &*ejson::decoded(value);
+        if !options.exact_literals() || ejson::is_literal(value) {
+            return self.elements.iter().any(|m| m.evaluate(value, options));
+        }
+        (!value.is_object() && self.literals.contains(&key(value)))
+            || self
+                .matchers
+                .iter()
+                .any(|&i| self.elements[i].evaluate(value, options))
+    }
+}
+
+/// Text identifying a scalar or array up to literal equality: the JSON text, except that `-0.0`
+/// is written like `0.0`, which it equals.
+fn key(value: &Value) -> String {
+    let mut key = String::new();
+    write_key(&mut key, value);
+    key
+}
+
+fn write_key(key: &mut String, value: &Value) {
+    match value {
+        Value::Number(n) if n.is_f64() && n.as_f64() == Some(0.0) => key.push_str("0.0"),
+        Value::Array(a) => {
+            key.push('[');
+            for (i, element) in a.iter().enumerate() {
+                if i > 0 {
+                    key.push(',');
+                }
+                write_key(key, element);
+            }
+            key.push(']');
+        }
+        Value::Object(o) => {
+            // Sorted, as objects equal whatever the order of their fields.
+            let mut fields: Vec<_> = o.iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            key.push('{');
+            for (i, (field, element)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    key.push(',');
+                }
+                let _ = write!(key, "{}:", Value::String(field.clone()));
+                write_key(key, element);
+            }
+            key.push('}');
+        }
+        _ => {
+            let _ = write!(key, "{value}");
+        }
+    }
+}
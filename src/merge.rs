@@ -0,0 +1,177 @@
+//! Combining matchers, e.g. tenant base rules with user overrides.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::{context, parse_operator, AndOperator, Constraint, ObjMatcher};
+
+/// How [`merge`] combines two matchers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Documents must match both matchers.
+    All,
+    /// Documents must match both matchers, except that the second matcher's top-level field
+    /// conditions replace the first's conditions on the same field, or on fields nested in or
+    /// containing it.
+    Override,
+}
+
+/// Field constrained to disjoint sets of values by the two merged matchers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub path: Vec<String>,
+    pub left: Vec<Value>,
+    pub right: Vec<Value>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` must be one of {} and one of {}",
+            self.path.join("."),
+            Value::from(self.left.clone()),
+            Value::from(self.right.clone())
+        )
+    }
+}
+
+/// Error returned when merged matchers contradict each other, so no document could match.
+#[derive(Debug)]
+pub struct MergeError {
+    conflicts: Vec<Conflict>,
+}
+
+impl MergeError {
+    #[must_use]
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, conflict) in self.conflicts.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{conflict}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+/// Top-level field conditions of a matcher, if it is a plain field object.
+fn fields(matcher: &ObjMatcher) -> Option<&Map<String, Value>> {
+    let value = match matcher {
+        ObjMatcher::Value(value) => value,
+        _ => return None,
+    };
+    match parse_operator(value.clone(), None) {
+        Ok(None) if !context::is_reference(value) => value.as_object(),
+        _ => None,
+    }
+}
+
+/// Whether one dotted path equals the other or addresses a field nested in it.
+fn overlaps(a: &str, b: &str) -> bool {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    long.starts_with(short) && (long.len() == short.len() || long[short.len()..].starts_with('.'))
+}
+
+fn conflicts(left: &[Constraint], right: &[Constraint]) -> Vec<Conflict> {
+    left.iter()
+        .filter_map(|l| {
+            let r = right.iter().find(|r| r.path == l.path)?;
+            if l.values.iter().any(|v| r.values.contains(v)) {
+                return None;
+            }
+            Some(Conflict {
+                path: l.path.clone(),
+                left: l.values.clone(),
+                right: r.values.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Combines two matchers into one, failing if their equality constraints on some field
+/// contradict each other. Contradictions the constraints cannot express, such as between
+/// `$ne` and `$eq`, are not detected.
+pub fn merge(
+    a: &ObjMatcher,
+    b: &ObjMatcher,
+    strategy: MergeStrategy,
+) -> Result<ObjMatcher, MergeError> {
+    let base = match (strategy, fields(a), fields(b)) {
+        (MergeStrategy::Override, Some(base), Some(overrides)) => {
+            let base: Map<String, Value> = base
+                .iter()
+                .filter(|(key, _)| !overrides.keys().any(|o| overlaps(key, o)))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            ObjMatcher::Value(Value::Object(base))
+        }
+        _ => a.clone(),
+    };
+    let conflicts = conflicts(&base.constraints(), &b.constraints());
+    if !conflicts.is_empty() {
+        return Err(MergeError { conflicts });
+    }
+    if let (Some(base), Some(other)) = (fields(&base), fields(b)) {
+        if !base
+            .keys()
+            .any(|key| other.keys().any(|o| overlaps(key, o)))
+        {
+            let mut merged = base.clone();
+            merged.extend(other.iter().map(|(k, v)| (k.clone(), v.clone())));
+            return Ok(ObjMatcher::Value(Value::Object(merged)));
+        }
+    }
+    Ok(ObjMatcher::And(AndOperator {
+        val: vec![base, b.clone()],
+        comment: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_merge() {
+        let base = from_str(r#"{"tenant": "acme", "region": {"$in": ["eu", "us"]}}"#).unwrap();
+        let user = from_str(r#"{"region": "eu", "level": "error"}"#).unwrap();
+
+        let merged = merge(&base, &user, MergeStrategy::All).unwrap();
+        assert!(merged.matches(&json!({"tenant": "acme", "region": "eu", "level": "error"})));
+        assert!(!merged.matches(&json!({"tenant": "acme", "region": "us", "level": "error"})));
+
+        let user = from_str(r#"{"region": "apac"}"#).unwrap();
+        let err = merge(&base, &user, MergeStrategy::All).unwrap_err();
+        assert_eq!(
+            err.conflicts(),
+            &[Conflict {
+                path: vec!["region".to_string()],
+                left: vec![json!("eu"), json!("us")],
+                right: vec![json!("apac")],
+            }]
+        );
+
+        let merged = merge(&base, &user, MergeStrategy::Override).unwrap();
+        assert!(merged.matches(&json!({"tenant": "acme", "region": "apac"})));
+        assert!(!merged.matches(&json!({"tenant": "other", "region": "apac"})));
+
+        let nested = from_str(r#"{"region.code": 1}"#).unwrap();
+        let merged = merge(&user, &nested, MergeStrategy::Override).unwrap();
+        assert!(merged.matches(&json!({"region": {"code": 1}})));
+
+        let any = from_str(r#"{"$or": [{"tenant": "a"}, {"tenant": "b"}]}"#).unwrap();
+        assert!(merge(&any, &base, MergeStrategy::Override).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Hooks called as each clause of a matcher is evaluated, for step-through debuggers and live
+//! tracing.
+
+use std::borrow::Cow;
+
+use serde_json::Value;
+
+use crate::compiled::{Node, NodeId, Nodes};
+use crate::walk::{self, Condition};
+use crate::{compiled, path, planner, values_equal, MatchOptions, ObjMatcher};
+
+/// A clause of a matcher as it is evaluated against a document.
+#[derive(Debug, Clone, Copy)]
+pub struct Clause<'a> {
+    /// Fields leading from the document root to the value the clause is applied to.
+    pub path: &'a [String],
+    /// Operator of the clause, or `None` for a literal comparison or a group of field clauses.
+    pub operator: Option<&'a str>,
+    /// Value the clause is applied to, or `None` if the field is missing.
+    pub value: Option<&'a Value>,
+}
+
+/// Receives the clauses of a matcher as they are evaluated, set with
+/// [`MatchOptions::observer`].
+///
+/// Clauses are reported in evaluation order, each `enter` followed by the `enter` and `exit` of
+/// its sub-clauses and then by its own `exit`. Clauses skipped because the outcome was already
+/// decided, such as the remaining clauses of an `$or` after one matched, are not reported.
+///
+/// A [`CompiledMatcher`](crate::CompiledMatcher) reports the clauses it was compiled into, in the
+/// order it evaluates them, with the numeric ranges on one field merged into a `$range`.
+pub trait Observer: Send + Sync {
+    fn enter(&self, _clause: &Clause<'_>) {}
+
+    fn exit(&self, _clause: &Clause<'_>, _outcome: bool) {}
+}
+
+pub(crate) fn evaluate(
+    matcher: &ObjMatcher,
+    value: &Value,
+    options: &MatchOptions,
+    observer: &dyn Observer,
+) -> bool {
+    Observed { options, observer }.matcher(matcher, Some(value), &[])
+}
+
+pub(crate) fn evaluate_compiled(
+    nodes: &Nodes,
+    root: NodeId,
+    value: &Value,
+    options: &MatchOptions,
+    observer: &dyn Observer,
+) -> bool {
+    Observed { options, observer }.node(nodes, root, Some(value), &[])
+}
+
+struct Observed<'a> {
+    options: &'a MatchOptions,
+    observer: &'a dyn Observer,
+}
+
+impl Observed<'_> {
+    fn clause<F: FnOnce() -> bool>(
+        &self,
+        path: &[String],
+        operator: Option<&str>,
+        value: Option<&Value>,
+        evaluate: F,
+    ) -> bool {
+        let clause = Clause {
+            path,
+            operator,
+            value,
+        };
+        self.observer.enter(&clause);
+        let outcome = evaluate();
+        self.observer.exit(&clause, outcome);
+        outcome
+    }
+
+    fn matcher(&self, matcher: &ObjMatcher, actual: Option<&Value>, path: &[String]) -> bool {
+        let any = |matchers: &[ObjMatcher]| matchers.iter().any(|m| self.matcher(m, actual, path));
+        match matcher {
+            ObjMatcher::Eq(op) => self.clause(path, Some("$eq"), actual, || {
+                self.matcher(&op.val, actual, path)
+            }),
+            ObjMatcher::Ne(op) => self.clause(path, Some("$ne"), actual, || {
+                !self.matcher(&op.val, actual, path)
+            }),
+            ObjMatcher::Not(op) => self.clause(path, Some("$not"), actual, || {
+                !self.matcher(&op.val, actual, path)
+            }),
+            ObjMatcher::In(op) => self.clause(path, Some("$in"), actual, || any(&op.val)),
+            ObjMatcher::Nin(op) => self.clause(path, Some("$nin"), actual, || !any(&op.val)),
+            ObjMatcher::And(op) => self.clause(path, Some("$and"), actual, || {
+                op.val.iter().all(|m| self.matcher(m, actual, path))
+            }),
+            ObjMatcher::Or(op) => self.clause(path, Some("$or"), actual, || any(&op.val)),
+            ObjMatcher::Value(value) => self.value(value, actual, path),
+            other => self.clause(path, compiled::operator_name(other), actual, || {
+                other.evaluate(actual.unwrap_or(&Value::Null), self.options)
+            }),
+        }
+    }
+
+    fn value(&self, value: &Value, actual: Option<&Value>, path: &[String]) -> bool {
+        let registry = self.options.operator_registry();
+        let fields = match walk::value(value, registry) {
+            walk::Clause::Operator(matcher) => return self.matcher(&matcher, actual, path),
+            walk::Clause::Fields(fields) => fields,
+            walk::Clause::Literal(value) => return self.literal(value, actual, path),
+        };
+        self.clause(path, None, actual, || {
+            walk::conditions(fields, registry).all(|(key, condition)| {
+                let mut path = path.to_vec();
+                path.extend(crate::path::segments(key).map(Cow::into_owned));
+                let actual = actual.and_then(|actual| crate::path::get(actual, key));
+                match condition {
+                    Condition::Operator(matcher) => self.matcher(&matcher, actual, &path),
+                    Condition::Literal(value) => self.literal(value, actual, &path),
+                }
+            })
+        })
+    }
+
+    fn node(&self, nodes: &Nodes, id: NodeId, actual: Option<&Value>, path: &[String]) -> bool {
+        let value = actual.unwrap_or(&Value::Null);
+        match nodes.get(id) {
+            Node::And(children) => self.clause(path, Some("$and"), actual, || {
+                children.iter().all(|&c| self.node(nodes, c, actual, path))
+            }),
+            Node::Or(children) => self.clause(path, Some("$or"), actual, || {
+                children.iter().any(|&c| self.node(nodes, c, actual, path))
+            }),
+            Node::Not(child) => self.clause(path, Some("$not"), actual, || {
+                !self.node(nodes, *child, actual, path)
+            }),
+            Node::Field(key, child) => {
+                let mut path = path.to_vec();
+                path.extend(path::segments(key).map(Cow::into_owned));
+                let actual = actual.and_then(|actual| path::get(actual, key));
+                self.node(nodes, *child, actual, &path)
+            }
+            Node::Literal(expected) => self.literal(expected, actual, path),
+            Node::Range(interval) => self.clause(path, Some("$range"), actual, || {
+                interval.contains(value, self.options)
+            }),
+            Node::Interpreted(matcher) => {
+                let operator = planner::interpreted_name(matcher);
+                self.clause(path, Some(operator), actual, || {
+                    matcher.evaluate(value, self.options)
+                })
+            }
+        }
+    }
+
+    fn literal(&self, expected: &Value, actual: Option<&Value>, path: &[String]) -> bool {
+        self.clause(path, None, actual, || {
+            values_equal(expected, actual.unwrap_or(&Value::Null), self.options)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Recorder {
+        fn enter(&self, clause: &Clause<'_>) {
+            let line = format!(
+                "enter {} {}",
+                clause.path.join("."),
+                clause.operator.unwrap_or("-")
+            );
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn exit(&self, clause: &Clause<'_>, outcome: bool) {
+            let line = format!("exit {} {}", clause.path.join("."), outcome);
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[test]
+    pub fn test_observer() {
+        let matcher = from_str(r#"{"$or": [{"a": {"$gt": 1}}, {"b": 2}, {"c": 3}]}"#).unwrap();
+        let recorder = Arc::new(Recorder::default());
+        let options = MatchOptions::new().observer(recorder.clone());
+        let doc = json!({"a": 1, "b": 2});
+        assert!(matcher.matches_with_options(&doc, &options));
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "enter  $or",
+                "enter  -",
+                "enter a $gt",
+                "exit a false",
+                "exit  false",
+                "enter  -",
+                "enter b -",
+                "exit b true",
+                "exit  true",
+                "exit  true",
+            ]
+        );
+        for doc in &[json!({"c": 3}), json!({"a": 2}), json!({})] {
+            assert_eq!(
+                matcher.matches_with_options(doc, &options),
+                matcher.matches(doc)
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_operands() {
+        // Every walk over a matcher built by hand with invalid operands agrees that it matches
+        // nothing, rather than panicking.
+        let matcher = ObjMatcher::Value(json!({"a": {"$gt": 1, "typo": 2}, "b": {"$in": 1}}));
+        let doc = json!({"a": 2, "b": 1});
+        let recorder = Arc::new(Recorder::default());
+        let options = MatchOptions::new().observer(recorder.clone());
+        assert!(!matcher.matches(&doc));
+        assert!(!matcher.matches_with_options(&doc, &options));
+        assert!(!recorder.0.lock().unwrap().is_empty());
+        assert!(!matcher.explain(&doc).outcome);
+        assert!(matcher.mismatch_patch(&doc).is_none());
+        assert_eq!(matcher.stats().operators["$not"], 2);
+    }
+}
@@ -0,0 +1,509 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+#[cfg(feature = "normalization")]
+use unicode_normalization::UnicodeNormalization;
+
+#[cfg(feature = "icu")]
+use crate::collation::Collation;
+use crate::{ContextProvider, MatcherRegistry, Observer, OperatorRegistry, Resolver, Statistics};
+
+/// Unicode normalization form applied to strings before they are compared.
+#[cfg(feature = "normalization")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Canonical composition, e.g. `"e\u{301}"` becomes `"é"`.
+    Nfc,
+    /// Compatibility composition, which additionally folds characters such as `"ﬁ"` into `"fi"`.
+    Nfkc,
+}
+
+/// How array literals are compared with arrays, as in `{"tags": ["a", "b"]}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayComparison {
+    /// Same elements in the same order. The default.
+    #[default]
+    Ordered,
+    /// Same elements in any order, ignoring how many times each occurs, so `["a", "b"]` equals
+    /// `["b", "a", "a"]`.
+    Unordered,
+    /// Same elements in any order, each occurring as many times, so `["a", "b", "a"]` equals
+    /// `["b", "a", "a"]` but not `["a", "b"]`.
+    Multiset,
+}
+
+/// Which engine's corner-case semantics field conditions follow, for migrating from another
+/// engine without changing results. The conformance specs pinning down each profile are in the
+/// `conformance` directory of the repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Compatibility {
+    /// This crate's semantics, the default. A field condition applies to the field's value as a
+    /// whole, a missing field is `null` for every operator, and `$gt`, `$gte`, `$lt` and `$lte`
+    /// only order values of the same JSON type.
+    #[default]
+    Native,
+    /// `MongoDB`'s semantics. A field condition holds if it holds for an array field or for any of
+    /// its elements, and paths go through arrays of objects, so `{"items.sku": "A1"}` holds if
+    /// any item has that sku. `$ne`, `$nin` and `$not` hold if the condition they negate holds
+    /// for none of them. A missing field is `null` for equality and comparisons, but not for
+    /// `$type`.
+    StrictMongo,
+    /// sift.js's semantics. Arrays are traversed as with `StrictMongo`, except that looking a
+    /// field up on an array, or on any element that is not an object, also finds it missing.
+    /// Comparisons follow JavaScript's relational operators between values of the same
+    /// `typeof`: strings compare by UTF-16 code units, and `null`, arrays and objects compare
+    /// with each other through their string or numeric forms. A missing field is only `null`
+    /// for equality.
+    SiftJs,
+}
+
+/// Which strings equal, and compare with, values of other types, for matching stringly-typed
+/// data such as form submissions and query strings. No string does by default.
+///
+/// Coercion applies between a string and a value of another type, in either the query or the
+/// document, and only to strings written exactly as the other value would be, without
+/// surrounding whitespace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Coercion {
+    numbers: bool,
+    booleans: bool,
+    nulls: bool,
+}
+
+impl Coercion {
+    /// No coercion.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every coercion.
+    #[must_use]
+    pub fn all() -> Self {
+        Coercion {
+            numbers: true,
+            booleans: true,
+            nulls: true,
+        }
+    }
+
+    /// Strings holding a JSON number, such as `"5"` or `"2.50"`, are that number, so `"5"`
+    /// equals `5` and `5.0` and is less than `10`.
+    #[must_use]
+    pub fn numbers(mut self, coerce: bool) -> Self {
+        self.numbers = coerce;
+        self
+    }
+
+    /// `"true"` and `"false"` are booleans.
+    #[must_use]
+    pub fn booleans(mut self, coerce: bool) -> Self {
+        self.booleans = coerce;
+        self
+    }
+
+    /// `"null"` and the empty string, as left by an empty form field, are `null`.
+    #[must_use]
+    pub fn nulls(mut self, coerce: bool) -> Self {
+        self.nulls = coerce;
+        self
+    }
+
+    pub(crate) fn is_none(self) -> bool {
+        self == Coercion::default()
+    }
+
+    pub(crate) fn coerces_numbers(self) -> bool {
+        self.numbers
+    }
+
+    pub(crate) fn coerces_booleans(self) -> bool {
+        self.booleans
+    }
+
+    pub(crate) fn coerces_nulls(self) -> bool {
+        self.nulls
+    }
+}
+
+/// Options controlling how a matcher compares values.
+///
+/// The default options compare strings code point by code point, exactly as they appear in the
+/// query and in the matched document.
+#[derive(Clone, Default)]
+pub struct MatchOptions {
+    #[cfg(feature = "normalization")]
+    normalization: Option<Normalization>,
+    lowercase: bool,
+    array_comparison: ArrayComparison,
+    compatibility: Compatibility,
+    coercion: Coercion,
+    #[cfg(feature = "icu")]
+    collation: Option<Collation>,
+    registry: Option<Arc<OperatorRegistry>>,
+    context: Option<Arc<dyn ContextProvider>>,
+    observer: Option<Arc<dyn Observer>>,
+    resolver: Option<Arc<dyn Resolver>>,
+}
+
+impl fmt::Debug for MatchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("MatchOptions");
+        #[cfg(feature = "normalization")]
+        debug.field("normalization", &self.normalization);
+        debug
+            .field("lowercase", &self.lowercase)
+            .field("array_comparison", &self.array_comparison)
+            .field("compatibility", &self.compatibility)
+            .field("coercion", &self.coercion);
+        #[cfg(feature = "icu")]
+        debug.field("collation", &self.collation);
+        debug
+            .field("registry", &self.registry)
+            .field("context", &self.context.is_some())
+            .field("observer", &self.observer.is_some())
+            .field("resolver", &self.resolver.is_some())
+            .finish()
+    }
+}
+
+impl MatchOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Normalizes both sides of every string comparison to the given form.
+    #[cfg(feature = "normalization")]
+    #[must_use]
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = Some(normalization);
+        self
+    }
+
+    /// Lowercases both sides of every string comparison, after normalization, with
+    /// [`str::to_lowercase`]. This is not full Unicode case folding: `"STRASSE"` does not equal
+    /// `"straße"`, and a final `"ς"` does not equal `"σ"`.
+    #[must_use]
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// Compares array literals with arrays as `comparison` says, including arrays nested in
+    /// them.
+    #[must_use]
+    pub fn array_comparison(mut self, comparison: ArrayComparison) -> Self {
+        self.array_comparison = comparison;
+        self
+    }
+
+    pub(crate) fn array_order(&self) -> ArrayComparison {
+        self.array_comparison
+    }
+
+    /// Follows the corner-case semantics of another engine, see [`Compatibility`].
+    #[must_use]
+    pub fn compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    pub(crate) fn profile(&self) -> Compatibility {
+        self.compatibility
+    }
+
+    /// Lets strings equal, and compare with, values of other types, see [`Coercion`].
+    #[must_use]
+    pub fn coercion(mut self, coercion: Coercion) -> Self {
+        self.coercion = coercion;
+        self
+    }
+
+    pub(crate) fn coercion_rules(&self) -> Coercion {
+        self.coercion
+    }
+
+    /// Compares strings using locale-aware collation rules instead of code points.
+    #[cfg(feature = "icu")]
+    #[must_use]
+    pub fn collation(mut self, collation: Collation) -> Self {
+        self.collation = Some(collation);
+        self
+    }
+
+    /// Evaluates user-defined operators with the given registry.
+    #[must_use]
+    pub fn registry(mut self, registry: Arc<OperatorRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub(crate) fn operator_registry(&self) -> Option<&OperatorRegistry> {
+        self.registry.as_deref()
+    }
+
+    /// Resolves `$now`, `$env` and `$context` literals with the given provider.
+    #[must_use]
+    pub fn context(mut self, provider: Arc<dyn ContextProvider>) -> Self {
+        self.context = Some(provider);
+        self
+    }
+
+    pub(crate) fn context_provider(&self) -> Option<&dyn ContextProvider> {
+        self.context.as_deref()
+    }
+
+    /// Reports every clause evaluated by [`ObjMatcher::matches_with_options`] to `observer`.
+    ///
+    /// [`ObjMatcher::matches_with_options`]: crate::ObjMatcher::matches_with_options
+    #[must_use]
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// These options without the observer, for evaluations whose clauses are not reported.
+    pub(crate) fn unobserved(&self) -> MatchOptions {
+        MatchOptions {
+            observer: None,
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn evaluation_observer(&self) -> Option<&dyn Observer> {
+        self.observer.as_deref()
+    }
+
+    /// Answers `$lookup` clauses with the given resolver. Without one they never match.
+    #[must_use]
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    pub(crate) fn lookup_resolver(&self) -> Option<&dyn Resolver> {
+        self.resolver.as_deref()
+    }
+
+    /// Options for compiling matchers that are then evaluated with these options.
+    pub(crate) fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            registry: self.registry.clone(),
+            ..ParseOptions::default()
+        }
+    }
+
+    pub(crate) fn prepare_str<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        #[cfg(feature = "normalization")]
+        let s: Cow<'a, str> = match self.normalization {
+            None => Cow::Borrowed(s),
+            Some(Normalization::Nfc) => Cow::Owned(s.nfc().collect()),
+            Some(Normalization::Nfkc) => Cow::Owned(s.nfkc().collect()),
+        };
+        #[cfg(not(feature = "normalization"))]
+        let s = Cow::Borrowed(s);
+        if self.lowercase {
+            Cow::Owned(s.to_lowercase())
+        } else {
+            s
+        }
+    }
+
+    /// Whether literals equal only literals with the same JSON text, up to the order of object
+    /// fields and the sign of zero.
+    pub(crate) fn exact_literals(&self) -> bool {
+        #[cfg(feature = "icu")]
+        {
+            if self.collation.is_some() {
+                return false;
+            }
+        }
+        !self.transforms_strings()
+            && self.array_comparison == ArrayComparison::Ordered
+            && self.coercion.is_none()
+    }
+
+    /// Whether strings are normalized or lowercased before they are compared.
+    fn transforms_strings(&self) -> bool {
+        #[cfg(feature = "normalization")]
+        {
+            if self.normalization.is_some() {
+                return true;
+            }
+        }
+        self.lowercase
+    }
+
+    pub(crate) fn str_eq(&self, a: &str, b: &str) -> bool {
+        #[cfg(feature = "icu")]
+        {
+            if let Some(collation) = &self.collation {
+                let (a, b) = (self.prepare_str(a), self.prepare_str(b));
+                return collation.compare(&a, &b) == Ordering::Equal;
+            }
+        }
+        if !self.transforms_strings() {
+            return a == b;
+        }
+        self.prepare_str(a) == self.prepare_str(b)
+    }
+
+    pub(crate) fn str_cmp(&self, a: &str, b: &str) -> Ordering {
+        #[cfg(feature = "icu")]
+        {
+            if let Some(collation) = &self.collation {
+                let (a, b) = (self.prepare_str(a), self.prepare_str(b));
+                return collation.compare(&a, &b);
+            }
+        }
+        if !self.transforms_strings() {
+            return a.cmp(b);
+        }
+        self.prepare_str(a).cmp(&self.prepare_str(b))
+    }
+}
+
+/// Options controlling which queries are accepted when parsing a matcher.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    denied_operators: Vec<String>,
+    registry: Option<Arc<OperatorRegistry>>,
+    matchers: Option<Arc<MatcherRegistry>>,
+    costs: HashMap<String, u32>,
+    statistics: Option<Arc<Statistics>>,
+    max_operators: Option<usize>,
+    max_arity: Option<usize>,
+    max_string_len: Option<usize>,
+    max_size: Option<usize>,
+    bare_operators: bool,
+}
+
+impl ParseOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects queries using the given operator anywhere, e.g. `"$where"` for untrusted input.
+    #[must_use]
+    pub fn deny_operator<S: Into<String>>(mut self, operator: S) -> Self {
+        self.denied_operators.push(operator.into());
+        self
+    }
+
+    /// Recognizes and validates user-defined operators from the given registry.
+    #[must_use]
+    pub fn registry(mut self, registry: Arc<OperatorRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub(crate) fn operator_registry(&self) -> Option<&OperatorRegistry> {
+        self.registry.as_deref()
+    }
+
+    /// Resolves `$ref` references against the given named matchers.
+    #[must_use]
+    pub fn matchers(mut self, matchers: Arc<MatcherRegistry>) -> Self {
+        self.matchers = Some(matchers);
+        self
+    }
+
+    pub(crate) fn matcher_registry(&self) -> Option<&MatcherRegistry> {
+        self.matchers.as_deref()
+    }
+
+    /// Sets the relative cost of evaluating an operator, which compiled matchers use to run
+    /// cheaper clauses of `$and` and `$or` first. Comparing a literal costs 1.
+    #[must_use]
+    pub fn operator_cost<S: Into<String>>(mut self, operator: S, cost: u32) -> Self {
+        self.costs.insert(operator.into(), cost);
+        self
+    }
+
+    pub(crate) fn cost(&self, operator: &str) -> Option<u32> {
+        self.costs.get(operator).copied()
+    }
+
+    /// Orders the clauses of compiled matchers using statistics about the documents they run
+    /// against, rather than by cost alone.
+    #[must_use]
+    pub fn statistics(mut self, statistics: Arc<Statistics>) -> Self {
+        self.statistics = Some(statistics);
+        self
+    }
+
+    pub(crate) fn planner_statistics(&self) -> Option<&Statistics> {
+        self.statistics.as_deref()
+    }
+
+    /// Rejects queries using more than `max` operators in total.
+    #[must_use]
+    pub fn max_operators(mut self, max: usize) -> Self {
+        self.max_operators = Some(max);
+        self
+    }
+
+    /// Rejects queries with more than `max` elements in an `$in`, `$nin`, `$and` or `$or`.
+    #[must_use]
+    pub fn max_arity(mut self, max: usize) -> Self {
+        self.max_arity = Some(max);
+        self
+    }
+
+    /// Rejects queries containing a string, field names included, longer than `max` bytes.
+    #[must_use]
+    pub fn max_string_len(mut self, max: usize) -> Self {
+        self.max_string_len = Some(max);
+        self
+    }
+
+    /// Rejects queries longer than `max` bytes as JSON. `from_str_with_options` checks the input
+    /// before parsing it.
+    #[must_use]
+    pub fn max_size(mut self, max: usize) -> Self {
+        self.max_size = Some(max);
+        self
+    }
+
+    /// Also reads the comparison and logical operators spelled without `$`, as JavaScript ORMs
+    /// write them: `eq`, `ne`, `gt`, `gte`, `lt`, `lte`, `in`, `nin`, `and`, `or` and `not`,
+    /// along with Prisma's `equals`, `notIn`, `AND`, `OR` and `NOT`. Fields with these names must
+    /// then be escaped, e.g. `"\\in"`.
+    #[must_use]
+    pub fn bare_operators(mut self, accept: bool) -> Self {
+        self.bare_operators = accept;
+        self
+    }
+
+    pub(crate) fn accepts_bare_operators(&self) -> bool {
+        self.bare_operators
+    }
+
+    pub(crate) fn limits(&self) -> Limits {
+        Limits {
+            operators: self.max_operators,
+            arity: self.max_arity,
+            string_len: self.max_string_len,
+            size: self.max_size,
+        }
+    }
+
+    pub(crate) fn is_denied(&self, operator: &str) -> bool {
+        self.denied_operators
+            .iter()
+            .any(|denied| denied == operator)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) operators: Option<usize>,
+    pub(crate) arity: Option<usize>,
+    pub(crate) string_len: Option<usize>,
+    pub(crate) size: Option<usize>,
+}
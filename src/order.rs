@@ -0,0 +1,80 @@
+//! Total order over JSON values, following `MongoDB`'s comparison order across types:
+//! `null` < numbers < strings < objects < arrays < booleans.
+
+use std::cmp::Ordering;
+
+use serde_json::{Number, Value};
+
+use crate::MatchOptions;
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Number(_) => 1,
+        Value::String(_) => 2,
+        Value::Object(_) => 3,
+        Value::Array(_) => 4,
+        Value::Bool(_) => 5,
+    }
+}
+
+pub(crate) fn compare_numbers(a: &Number, b: &Number) -> Ordering {
+    if let (Some(a), Some(b)) = (as_i128(a), as_i128(b)) {
+        return a.cmp(&b);
+    }
+    if let (Some(a), Some(b)) = (as_u128(a), as_u128(b)) {
+        return a.cmp(&b);
+    }
+    let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+/// The integer `n`, which may exceed 64 bits when `serde_json` keeps the text of numbers, as it
+/// does with the `arbitrary-precision` feature.
+pub(crate) fn as_i128(n: &Number) -> Option<i128> {
+    match (n.as_i64(), n.as_u64()) {
+        (Some(i), _) => Some(i128::from(i)),
+        (None, Some(u)) => Some(i128::from(u)),
+        _ if cfg!(feature = "arbitrary-precision") => n.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_u128(n: &Number) -> Option<u128> {
+    match n.as_u64() {
+        Some(u) => Some(u128::from(u)),
+        None if cfg!(feature = "arbitrary-precision") => n.to_string().parse().ok(),
+        None => None,
+    }
+}
+
+/// Compares two values of the same type, as the `$gt`, `$gte`, `$lt` and `$lte` operators do.
+pub(crate) fn compare_same_type(a: &Value, b: &Value, options: &MatchOptions) -> Option<Ordering> {
+    if type_rank(a) == type_rank(b) {
+        Some(compare_values(a, b, options))
+    } else {
+        None
+    }
+}
+
+/// Compares two values, comparing strings with the collation rules of `options`.
+pub(crate) fn compare_values(a: &Value, b: &Value, options: &MatchOptions) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => compare_numbers(a, b),
+        (Value::String(a), Value::String(b)) => options.str_cmp(a, b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| compare_values(a, b, options))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|((ka, a), (kb, b))| ka.cmp(kb).then_with(|| compare_values(a, b, options)))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
@@ -0,0 +1,100 @@
+//! `$param` placeholders, replaced by concrete values with [`ObjMatcher::bind`].
+//!
+//! A placeholder is an object with a single `$param` key naming the parameter, e.g.
+//! `{"tenant": {"$param": "tenant_id"}}`. A placeholder can also stand for a whole operand, e.g.
+//! `{"id": {"$in": {"$param": "ids"}}}` binds `ids` to the array of candidates. Unbound
+//! placeholders are compared literally and so never match real documents.
+
+use serde_json::{Map, Value};
+
+use crate::{from_json_with_options, ObjMatcher, ParseOptions};
+
+fn placeholder(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(o) if o.len() == 1 => o.get("$param").and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+/// Whether `value` is a placeholder, or an operator whose operand is one, which can only be
+/// checked once bound.
+pub(crate) fn is_placeholder(value: &Value) -> bool {
+    match value {
+        Value::Object(o) if o.len() == 1 && placeholder(value).is_none() => o
+            .iter()
+            .any(|(key, value)| key.starts_with('$') && placeholder(value).is_some()),
+        _ => placeholder(value).is_some(),
+    }
+}
+
+fn substitute(value: &mut Value, params: &Map<String, Value>) -> Result<(), serde_json::Error> {
+    if let Some(name) = placeholder(value) {
+        *value = match params.get(name) {
+            Some(param) => param.clone(),
+            None => {
+                return Err(serde::de::Error::custom(format!(
+                    "missing parameter `{name}`"
+                )))
+            }
+        };
+        return Ok(());
+    }
+    match value {
+        Value::Object(o) => {
+            for value in o.values_mut() {
+                substitute(value, params)?;
+            }
+        }
+        Value::Array(a) => {
+            for value in a {
+                substitute(value, params)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn collect(value: &Value, names: &mut Vec<String>) {
+    if let Some(name) = placeholder(value) {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        return;
+    }
+    match value {
+        Value::Object(o) => o.values().for_each(|value| collect(value, names)),
+        Value::Array(a) => a.iter().for_each(|value| collect(value, names)),
+        _ => {}
+    }
+}
+
+impl ObjMatcher {
+    /// Returns a copy of this matcher with every `$param` placeholder replaced by the value of
+    /// the corresponding entry in `params`, failing if a parameter is missing.
+    pub fn bind(&self, params: &Map<String, Value>) -> Result<ObjMatcher, serde_json::Error> {
+        self.bind_with_options(params, &ParseOptions::default())
+    }
+
+    /// Like [`ObjMatcher::bind`], parsing the bound matcher with `options`, so that the registry,
+    /// limits and deny list the template was parsed with apply to it too.
+    pub fn bind_with_options(
+        &self,
+        params: &Map<String, Value>,
+        options: &ParseOptions,
+    ) -> Result<ObjMatcher, serde_json::Error> {
+        let mut value = serde_json::to_value(self)?;
+        substitute(&mut value, params)?;
+        from_json_with_options(value, options)
+    }
+
+    /// Names of the `$param` placeholders in this matcher, in order of first appearance.
+    #[must_use]
+    pub fn params(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Ok(value) = serde_json::to_value(self) {
+            collect(&value, &mut names);
+        }
+        names
+    }
+}
@@ -0,0 +1,290 @@
+//! Describing what a document lacks to match, as an RFC 6902 JSON Patch.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::walk::{self, Condition};
+use crate::{path, values_equal, MatchOptions, ObjMatcher, TypeOperatorMatcher};
+
+/// An operation of an RFC 6902 JSON Patch. Paths are JSON Pointers, such as `/user/email`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+}
+
+impl ObjMatcher {
+    /// Returns a patch turning `value` into a document that matches, empty if `value` already
+    /// matches, or `None` if no patch could be derived.
+    ///
+    /// Failing equalities and `$gte`/`$lte` bounds set the field to the operand, `$gt`/`$lt` on
+    /// integers to the nearest integer past it, `$type` to an empty value of the first type, and
+    /// failing `$ne`, `$nin` or `$not` remove the field. Of the alternatives of an `$or` or `$in`,
+    /// the first one needing the fewest operations is taken. Other operators, such as `$len` or
+    /// `$expr`, cannot be repaired. The patched document is checked to match before the patch is
+    /// returned.
+    #[must_use]
+    pub fn mismatch_patch(&self, value: &Value) -> Option<Vec<PatchOperation>> {
+        self.mismatch_patch_with_options(value, &MatchOptions::default())
+    }
+
+    #[must_use]
+    pub fn mismatch_patch_with_options(
+        &self,
+        value: &Value,
+        options: &MatchOptions,
+    ) -> Option<Vec<PatchOperation>> {
+        let mut repair = Repair {
+            options,
+            document: value.clone(),
+        };
+        if !repair.matcher(self, "") || !self.matches_with_options(&repair.document, options) {
+            return None;
+        }
+        let mut operations = Vec::new();
+        diff(value, &repair.document, &mut String::new(), &mut operations);
+        Some(operations)
+    }
+}
+
+/// A document being changed until it satisfies a matcher.
+struct Repair<'a> {
+    options: &'a MatchOptions,
+    document: Value,
+}
+
+impl Repair<'_> {
+    fn get(&self, path: &str) -> &Value {
+        if path.is_empty() {
+            &self.document
+        } else {
+            path::lookup(&self.document, path)
+        }
+    }
+
+    fn holds(&self, matcher: &ObjMatcher, path: &str) -> bool {
+        matcher.evaluate(self.get(path), self.options)
+    }
+
+    /// Changes the document so that `matcher` holds at `path`, returning `false` if it cannot.
+    fn matcher(&mut self, matcher: &ObjMatcher, path: &str) -> bool {
+        if self.holds(matcher, path) {
+            return true;
+        }
+        match matcher {
+            ObjMatcher::Eq(op) => self.matcher(&op.val, path),
+            ObjMatcher::And(op) => op.val.iter().all(|m| self.matcher(m, path)),
+            ObjMatcher::Or(op) => self.any(&op.val, path),
+            ObjMatcher::In(op) => self.any(&op.val, path),
+            ObjMatcher::Ne(_) | ObjMatcher::Nin(_) | ObjMatcher::Not(_) => self.remove(path),
+            ObjMatcher::Gte(op) => self.set(path, op.val.clone()),
+            ObjMatcher::Lte(op) => self.set(path, op.val.clone()),
+            ObjMatcher::Gt(op) => match op.val.as_i64().and_then(|n| n.checked_add(1)) {
+                Some(n) => self.set(path, Value::from(n)),
+                None => false,
+            },
+            ObjMatcher::Lt(op) => match op.val.as_i64().and_then(|n| n.checked_sub(1)) {
+                Some(n) => self.set(path, Value::from(n)),
+                None => false,
+            },
+            ObjMatcher::Type(op) => match op.val.first() {
+                Some(ty) => self.set(path, empty(ty)),
+                None => false,
+            },
+            ObjMatcher::Value(value) => self.value(value, path),
+            _ => false,
+        }
+    }
+
+    fn value(&mut self, value: &Value, path: &str) -> bool {
+        let registry = self.options.operator_registry();
+        let fields = match walk::value(value, registry) {
+            walk::Clause::Operator(matcher) => return self.matcher(&matcher, path),
+            walk::Clause::Fields(fields) => fields,
+            walk::Clause::Literal(value) => return self.set(path, value.clone()),
+        };
+        walk::conditions(fields, registry).all(|(key, condition)| {
+            let path = if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{path}.{key}")
+            };
+            match condition {
+                Condition::Operator(matcher) => self.matcher(&matcher, &path),
+                Condition::Literal(value) if values_equal(value, self.get(&path), self.options) => {
+                    true
+                }
+                Condition::Literal(value) => self.set(&path, value.clone()),
+            }
+        })
+    }
+
+    /// Takes the alternative changing the fewest values.
+    fn any(&mut self, matchers: &[ObjMatcher], path: &str) -> bool {
+        let mut best: Option<(usize, Value)> = None;
+        for matcher in matchers {
+            let mut attempt = Repair {
+                options: self.options,
+                document: self.document.clone(),
+            };
+            if !attempt.matcher(matcher, path) || !attempt.holds(matcher, path) {
+                continue;
+            }
+            let mut operations = Vec::new();
+            diff(
+                &self.document,
+                &attempt.document,
+                &mut String::new(),
+                &mut operations,
+            );
+            if best
+                .as_ref()
+                .map_or(true, |(fewest, _)| operations.len() < *fewest)
+            {
+                best = Some((operations.len(), attempt.document));
+            }
+        }
+        match best {
+            Some((_, document)) => {
+                self.document = document;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set(&mut self, path: &str, value: Value) -> bool {
+        if path.is_empty() {
+            self.document = value;
+            return true;
+        }
+        path::insert(&mut self.document, path, value)
+    }
+
+    fn remove(&mut self, path: &str) -> bool {
+        if path.is_empty() {
+            return false;
+        }
+        path::remove(&mut self.document, path);
+        true
+    }
+}
+
+fn empty(ty: &TypeOperatorMatcher) -> Value {
+    match ty {
+        TypeOperatorMatcher::Null => Value::Null,
+        TypeOperatorMatcher::Bool => Value::Bool(false),
+        TypeOperatorMatcher::Number => Value::from(0),
+        TypeOperatorMatcher::String => Value::String(String::new()),
+        TypeOperatorMatcher::Array => Value::Array(Vec::new()),
+        TypeOperatorMatcher::Object => Value::Object(Map::new()),
+    }
+}
+
+/// Appends the operations turning `from` into `to`, recursing into objects and into arrays of
+/// the same length, and replacing other values as a whole.
+fn diff(from: &Value, to: &Value, pointer: &mut String, operations: &mut Vec<PatchOperation>) {
+    if from == to {
+        return;
+    }
+    let len = pointer.len();
+    match (from, to) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, from) in a {
+                push_token(pointer, key);
+                match b.get(key) {
+                    Some(to) => diff(from, to, pointer, operations),
+                    None => operations.push(PatchOperation::Remove {
+                        path: pointer.clone(),
+                    }),
+                }
+                pointer.truncate(len);
+            }
+            for (key, to) in b.iter().filter(|(key, _)| !a.contains_key(*key)) {
+                push_token(pointer, key);
+                operations.push(PatchOperation::Add {
+                    path: pointer.clone(),
+                    value: to.clone(),
+                });
+                pointer.truncate(len);
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (i, (from, to)) in a.iter().zip(b).enumerate() {
+                push_token(pointer, &i.to_string());
+                diff(from, to, pointer, operations);
+                pointer.truncate(len);
+            }
+        }
+        _ => operations.push(PatchOperation::Replace {
+            path: pointer.clone(),
+            value: to.clone(),
+        }),
+    }
+}
+
+fn push_token(pointer: &mut String, token: &str) {
+    pointer.push('/');
+    pointer.push_str(&token.replace('~', "~0").replace('/', "~1"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_mismatch_patch() {
+        let matcher = from_str(
+            r#"{"status": "active", "age": {"$gte": 18}, "deleted": {"$ne": true},
+                "$or": [{"team.lead": {"$in": ["ada", "grace"]}, "team.size": {"$gt": 2}}, {"role": "admin"}],
+                "a/b": {"$type": ["array"]}}"#,
+        )
+        .unwrap();
+        let doc = json!({"status": "inactive", "age": 12, "deleted": true, "team": {"size": 1}});
+        let patch = matcher.mismatch_patch(&doc).unwrap();
+        assert_eq!(
+            serde_json::to_value(&patch).unwrap(),
+            json!([
+                {"op": "replace", "path": "/age", "value": 18},
+                {"op": "remove", "path": "/deleted"},
+                {"op": "replace", "path": "/status", "value": "active"},
+                {"op": "add", "path": "/a~1b", "value": []},
+                {"op": "add", "path": "/role", "value": "admin"},
+            ])
+        );
+        let doc = json!({"status": "active", "age": 30, "team": {"lead": "bob", "size": 2}, "role": "dev"});
+        assert_eq!(
+            matcher.mismatch_patch(&doc),
+            Some(vec![
+                PatchOperation::Replace {
+                    path: "/role".to_string(),
+                    value: json!("admin"),
+                },
+                PatchOperation::Add {
+                    path: "/a~1b".to_string(),
+                    value: json!([]),
+                },
+            ])
+        );
+        let doc =
+            json!({"status": "active", "age": 30, "team": {"lead": "ada", "size": 1}, "a/b": []});
+        assert_eq!(
+            matcher.mismatch_patch(&doc),
+            Some(vec![PatchOperation::Replace {
+                path: "/team/size".to_string(),
+                value: json!(3),
+            }])
+        );
+        let doc = json!({"status": "active", "age": 30, "role": "admin", "a/b": []});
+        assert_eq!(matcher.mismatch_patch(&doc), Some(Vec::new()));
+
+        let unrepairable = from_str(r#"{"name": {"$len": 3}}"#).unwrap();
+        assert_eq!(unrepairable.mismatch_patch(&json!({"name": "ab"})), None);
+        let contradictory = from_str(r#"{"n": {"$gte": 5, "$lte": 3}}"#).unwrap();
+        assert_eq!(contradictory.mismatch_patch(&json!({"n": 4})), None);
+    }
+}
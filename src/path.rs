@@ -0,0 +1,228 @@
+//! Dotted field paths such as `"address.city"`, shared by matching, projection and updates.
+//!
+//! A backslash escapes the next character of a path, so `"a\\.b"` addresses the field named
+//! `"a.b"` and `"\\$type"` the field named `"$type"`, which would otherwise be read as an
+//! operator.
+//!
+//! Numeric segments index into arrays, negative indexes counting from the end, so
+//! `"items.-1.sku"` addresses the `sku` of the last item.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use serde_json::{Map, Value};
+
+static NULL: Value = Value::Null;
+
+/// Escapes a field name so that it is addressed literally, e.g. `"a.b"` becomes `"a\\.b"`.
+#[must_use]
+pub fn escape_field(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        if c == '.' || c == '\\' || (i == 0 && c == '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Field names along a dotted path.
+pub(crate) struct Segments<'a> {
+    rest: Option<&'a str>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        let rest = self.rest?;
+        let mut escaped = false;
+        let mut end = None;
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '.' => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let segment = if let Some(end) = end {
+            self.rest = Some(&rest[end + 1..]);
+            &rest[..end]
+        } else {
+            self.rest = None;
+            rest
+        };
+        Some(unescape(segment))
+    }
+}
+
+fn unescape(segment: &str) -> Cow<'_, str> {
+    if !segment.contains('\\') {
+        return Cow::Borrowed(segment);
+    }
+    let mut unescaped = String::with_capacity(segment.len());
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next().or(Some('\\'))),
+            c => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+pub(crate) fn segments(path: &str) -> Segments<'_> {
+    Segments { rest: Some(path) }
+}
+
+/// Position of the array element addressed by `key`, if it is an index within `len`.
+pub(crate) fn index(key: &str, len: usize) -> Option<usize> {
+    position(key.parse().ok()?, len)
+}
+
+/// Position of the array element at `index` within `len`, counting from the end if negative.
+pub(crate) fn position(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 {
+        len.checked_sub(usize::try_from(index.unsigned_abs()).ok()?)?
+    } else {
+        usize::try_from(index).ok()?
+    };
+    if index < len {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+fn child<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(o) => o.get(key),
+        Value::Array(a) => a.get(index(key, a.len())?),
+        _ => None,
+    }
+}
+
+fn child_mut<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Value> {
+    match value {
+        Value::Object(o) => o.get_mut(key),
+        Value::Array(a) => {
+            let index = index(key, a.len())?;
+            a.get_mut(index)
+        }
+        _ => None,
+    }
+}
+
+/// The value at `path`, or `None` if a field along it is missing.
+pub(crate) fn get<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    segments(path).try_fold(value, |value, key| child(value, &key))
+}
+
+/// The value at `path`, treating a missing field as `null` like the query language does.
+pub(crate) fn lookup<'a>(value: &'a Value, path: &str) -> &'a Value {
+    get(value, path).unwrap_or(&NULL)
+}
+
+/// Like [`lookup`], in a document given by a function returning its top-level fields.
+pub(crate) fn lookup_field<'a, F>(field: F, path: &str) -> &'a Value
+where
+    F: FnOnce(&str) -> Option<&'a Value>,
+{
+    let mut segments = segments(path);
+    segments
+        .next()
+        .and_then(|key| field(&key))
+        .and_then(|value| segments.try_fold(value, |value, key| child(value, &key)))
+        .unwrap_or(&NULL)
+}
+
+pub(crate) fn get_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    segments(path).try_fold(value, |value, key| child_mut(value, &key))
+}
+
+/// Sets the value at `path`, creating missing objects along it. Returns `false`, leaving `value`
+/// unchanged, if the path runs through something other than an object or past the end of an
+/// array.
+pub(crate) fn insert(value: &mut Value, path: &str, new: Value) -> bool {
+    let mut segments = segments(path).peekable();
+    let mut current = value;
+    while let Some(key) = segments.next() {
+        let last = segments.peek().is_none();
+        current = match current {
+            Value::Object(object) if last => {
+                object.insert(key.into_owned(), new);
+                return true;
+            }
+            Value::Object(object) => object
+                .entry(key.into_owned())
+                .or_insert_with(|| Value::Object(Map::new())),
+            Value::Array(array) => match index(&key, array.len()) {
+                Some(index) if last => {
+                    array[index] = new;
+                    return true;
+                }
+                Some(index) => &mut array[index],
+                None => return false,
+            },
+            _ => return false,
+        };
+    }
+    false
+}
+
+/// Removes and returns the value at `path`. Array elements are replaced with `null` rather than
+/// removed, so that the indexes of the following elements do not change.
+pub(crate) fn remove(value: &mut Value, path: &str) -> Option<Value> {
+    let mut segments: Vec<Cow<'_, str>> = segments(path).collect();
+    let key = segments.pop()?;
+    let parent = segments
+        .iter()
+        .try_fold(value, |value, key| child_mut(value, key))?;
+    match parent {
+        Value::Object(object) => object.remove(&*key),
+        Value::Array(array) => {
+            let index = index(&key, array.len())?;
+            Some(array[index].take())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_escaped_segments() {
+        let segments: Vec<_> = segments(r"a\.b.\$type.c\\.d").collect();
+        assert_eq!(segments, vec!["a.b", "$type", r"c\", "d"]);
+        assert_eq!(escape_field("$a.b"), r"\$a\.b");
+        let doc = json!({"a.b": {"$type": 1}});
+        assert_eq!(get(&doc, &escape_field("a.b")), Some(&json!({"$type": 1})));
+        assert_eq!(lookup(&doc, r"a\.b.\$type"), &json!(1));
+        assert_eq!(lookup(&doc, "a.b"), &Value::Null);
+    }
+
+    #[test]
+    pub fn test_array_indexes() {
+        let mut doc = json!({"items": [{"sku": "a"}, {"sku": "b"}, {"sku": "c"}]});
+        assert_eq!(lookup(&doc, "items.0.sku"), &json!("a"));
+        assert_eq!(lookup(&doc, "items.-1.sku"), &json!("c"));
+        assert_eq!(get(&doc, "items.3.sku"), None);
+        assert_eq!(get(&doc, "items.-4"), None);
+        assert!(insert(&mut doc, "items.1.qty", json!(2)));
+        assert!(insert(&mut doc, "items.-1", json!("last")));
+        assert!(!insert(&mut doc, "items.5", json!(1)));
+        assert_eq!(remove(&mut doc, "items.0"), Some(json!({"sku": "a"})));
+        assert_eq!(
+            doc,
+            json!({"items": [null, {"sku": "b", "qty": 2}, "last"]})
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! A small subset of `MongoDB`'s aggregation pipeline, run over in-memory documents.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::Value;
+
+use crate::{from_json, sort_values, Group, ObjMatcher, Projection, Sort};
+
+/// A stage of a [`Pipeline`].
+#[derive(Debug, Clone)]
+pub enum Stage {
+    /// `{"$match": query}` keeps the documents matching the query.
+    Match(ObjMatcher),
+    /// `{"$project": projection}` trims each document.
+    Project(Projection),
+    /// `{"$sort": sort}` sorts all documents reaching the stage.
+    Sort(Sort),
+    /// `{"$limit": n}` keeps the first `n` documents.
+    Limit(usize),
+    /// `{"$skip": n}` drops the first `n` documents.
+    Skip(usize),
+    /// `{"$group": group}` replaces all documents reaching the stage with one per group.
+    Group(Group),
+}
+
+/// Stages applied in order to a stream of documents, e.g.
+/// `[{"$match": {"type": "order"}}, {"$sort": {"total": -1}}, {"$limit": 10}]`.
+///
+/// `$sort` and `$group` buffer every document reaching them; every other stage processes documents
+/// one at a time, so pipelines without either run in constant memory.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    #[must_use]
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Pipeline { stages }
+    }
+
+    /// Builds a pipeline from a `Value`. `$sort` stages on several keys must list them as an
+    /// array, as [`Sort::from_json`](crate::Sort::from_json) requires.
+    pub fn from_json(v: Value) -> Result<Self, serde_json::Error> {
+        if let Value::Array(stages) = &v {
+            for stage in stages {
+                if let Some(sort) = stage.get("$sort") {
+                    crate::sort::check_key_order(sort)?;
+                }
+            }
+        }
+        Pipeline::deserialize(v)
+    }
+
+    #[must_use]
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Runs the pipeline over `values`. Stages are applied lazily as the returned iterator is
+    /// consumed, except `$sort` and `$group`, which consume all of `values` reaching them before
+    /// this returns.
+    pub fn run<'a, I>(&'a self, values: I) -> Box<dyn Iterator<Item = Value> + 'a>
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'a,
+    {
+        let mut values: Box<dyn Iterator<Item = Value> + 'a> = Box::new(values.into_iter());
+        for stage in &self.stages {
+            values = match stage {
+                Stage::Match(matcher) => Box::new(values.filter(move |v| matcher.matches(v))),
+                Stage::Project(projection) => Box::new(values.map(move |v| projection.apply(&v))),
+                Stage::Sort(sort) => {
+                    let mut sorted: Vec<Value> = values.collect();
+                    sort_values(&mut sorted, sort);
+                    Box::new(sorted.into_iter())
+                }
+                Stage::Limit(n) => Box::new(values.take(*n)),
+                Stage::Skip(n) => Box::new(values.skip(*n)),
+                Stage::Group(group) => {
+                    let values: Vec<Value> = values.collect();
+                    Box::new(group.aggregate(&values).into_iter())
+                }
+            };
+        }
+        values
+    }
+}
+
+impl<'de> Deserialize<'de> for Stage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StageVisitor;
+
+        impl<'de> Visitor<'de> for StageVisitor {
+            type Value = Stage;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a pipeline stage object with a single key")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Stage, A::Error> {
+                let name: String = match map.next_key()? {
+                    Some(name) => name,
+                    None => return Err(de::Error::custom("empty pipeline stage")),
+                };
+                let stage = match name.as_str() {
+                    "$match" => {
+                        Stage::Match(from_json(map.next_value()?).map_err(de::Error::custom)?)
+                    }
+                    "$project" => Stage::Project(
+                        Projection::from_json(map.next_value()?).map_err(de::Error::custom)?,
+                    ),
+                    "$sort" => Stage::Sort(map.next_value()?),
+                    "$limit" => Stage::Limit(map.next_value()?),
+                    "$skip" => Stage::Skip(map.next_value()?),
+                    "$group" => Stage::Group(
+                        Group::from_json(map.next_value()?).map_err(de::Error::custom)?,
+                    ),
+                    _ => {
+                        return Err(de::Error::custom(format!(
+                            "unknown pipeline stage `{name}`"
+                        )))
+                    }
+                };
+                if map.next_key::<String>()?.is_some() {
+                    return Err(de::Error::custom(format!(
+                        "pipeline stage `{name}` must be the only key of its object"
+                    )));
+                }
+                Ok(stage)
+            }
+        }
+
+        deserializer.deserialize_map(StageVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pipeline {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Pipeline::new)
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_pipeline() {
+        let orders = vec![
+            json!({"id": 1, "type": "order", "total": 10, "items": [1]}),
+            json!({"id": 2, "type": "refund", "total": 50}),
+            json!({"id": 3, "type": "order", "total": 30, "items": [1, 2]}),
+            json!({"id": 4, "type": "order", "total": 20}),
+            json!({"id": 5, "type": "order", "total": 40}),
+        ];
+        let pipeline: Pipeline = r#"[
+            {"$match": {"type": "order"}},
+            {"$sort": {"total": -1}},
+            {"$skip": 1},
+            {"$limit": 2},
+            {"$project": {"id": 1, "total": 1}}
+        ]"#
+        .parse()
+        .unwrap();
+        let results: Vec<Value> = pipeline.run(orders.clone()).collect();
+        assert_eq!(
+            results,
+            vec![json!({"id": 3, "total": 30}), json!({"id": 4, "total": 20})]
+        );
+
+        let pipeline: Pipeline = r#"[
+            {"$match": {"type": "order"}},
+            {"$group": {"_id": null, "revenue": {"$sum": "$total"}}}
+        ]"#
+        .parse()
+        .unwrap();
+        let results: Vec<Value> = pipeline.run(orders).collect();
+        assert_eq!(results, vec![json!({"_id": null, "revenue": 100})]);
+
+        assert!(r#"[{"$bucket": {}}]"#.parse::<Pipeline>().is_err());
+        assert!(r#"[{"$limit": 1, "$skip": 1}]"#.parse::<Pipeline>().is_err());
+        assert!(r#"[{"$limit": -1}]"#.parse::<Pipeline>().is_err());
+
+        assert!(Pipeline::from_json(json!([{"$sort": {"b": 1, "a": 1}}])).is_err());
+        let pipeline = Pipeline::from_json(json!([{"$sort": [{"b": 1}, {"a": 1}]}])).unwrap();
+        let values = vec![json!({"a": 1, "b": 2}), json!({"a": 2, "b": 1})];
+        let results: Vec<Value> = pipeline.run(values).collect();
+        assert_eq!(
+            results,
+            vec![json!({"a": 2, "b": 1}), json!({"a": 1, "b": 2})]
+        );
+    }
+}
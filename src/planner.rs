@@ -0,0 +1,431 @@
+//! Planning the evaluation order of compiled matchers.
+//!
+//! The clauses of `$and` and `$or` are ordered from cheapest to most expensive. Given
+//! [`Statistics`] about the documents a matcher runs against, through
+//! [`ParseOptions::statistics`], they are instead ordered by their cost relative to how likely
+//! they are to decide the outcome: a conjunction first runs the cheap clauses that rarely hold,
+//! a disjunction the cheap clauses that often do.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::compiled::{operator_name, Node, NodeId, Nodes};
+use crate::interval::{Bound, Interval};
+use crate::{ObjMatcher, ParseOptions};
+
+/// Fraction of documents assumed to satisfy a clause nothing is known about.
+const UNKNOWN_SELECTIVITY: f64 = 0.5;
+
+/// What is known of the values at one field path.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PathStatistics {
+    /// Fraction of documents, between 0 and 1, that the clauses on this path hold for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_rate: Option<f64>,
+    /// Number of distinct values at this path; equality with a literal is then assumed to hold
+    /// for one document in `cardinality`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cardinality: Option<u64>,
+}
+
+/// Statistics about the documents matchers run against, by dotted field path.
+///
+/// ```
+/// use serde_json_matcher::{PathStatistics, Statistics};
+///
+/// let statistics = Statistics::new()
+///     .path("status", PathStatistics { cardinality: Some(4), ..Default::default() })
+///     .path("user.verified", PathStatistics { match_rate: Some(0.99), ..Default::default() });
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Statistics {
+    paths: BTreeMap<String, PathStatistics>,
+}
+
+impl Statistics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the statistics of a dotted field path, such as `"user.email"`.
+    #[must_use]
+    pub fn path<S: Into<String>>(mut self, path: S, statistics: PathStatistics) -> Self {
+        self.paths.insert(path.into(), statistics);
+        self
+    }
+
+    #[must_use]
+    pub fn get(&self, path: &str) -> Option<&PathStatistics> {
+        self.paths.get(path)
+    }
+}
+
+/// One step of the evaluation of a compiled matcher, as returned by
+/// [`CompiledMatcher::explain_plan`](crate::CompiledMatcher::explain_plan).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Plan {
+    /// `$and`, `$or`, `$not`, `$eq` for a comparison with a literal, `$range` for numeric clauses
+    /// checked together, or the operator evaluated by the interpreter.
+    pub step: String,
+    /// Dotted path of the value the step is applied to, empty for the document itself.
+    pub path: String,
+    /// Literal, operator or bounds the value is compared with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operand: Option<Value>,
+    /// Estimated cost, in the units of [`ParseOptions::operator_cost`].
+    pub cost: u64,
+    /// Estimated fraction of documents the step holds for, if statistics were given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selectivity: Option<f64>,
+    /// How compiling changed the step from the query as written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimization: Option<Optimization>,
+    /// Sub-steps, in the order they are evaluated.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Plan>,
+}
+
+/// How compiling changed a step of a [`Plan`] from the query as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Optimization {
+    /// A `$in` or `$nin` whose literals are looked up in a hash set rather than compared one by
+    /// one.
+    Hashed { literals: usize },
+    /// Numeric `$gt`, `$gte`, `$lt`, `$lte` and `$ne` clauses on one field, checked together as
+    /// a `$range`.
+    Coalesced,
+    /// Numeric clauses on one field that no number satisfies, so that the step never holds.
+    Unsatisfiable,
+}
+
+impl fmt::Display for Plan {
+    /// Writes one step per line, sub-steps indented under their parent.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Plan {
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", self.step, indent = depth * 2)?;
+        if !self.path.is_empty() {
+            write!(f, " {}", self.path)?;
+        }
+        match (&self.operand, self.optimization) {
+            (_, Some(Optimization::Hashed { literals })) => {
+                write!(f, " (hashed, {literals} literals)")?;
+            }
+            (Some(operand), _) => write!(f, " {operand}")?,
+            (None, _) => {}
+        }
+        write!(f, ", cost {}", self.cost)?;
+        if let Some(selectivity) = self.selectivity {
+            write!(f, ", selectivity {selectivity:.3}")?;
+        }
+        match self.optimization {
+            Some(Optimization::Coalesced) => write!(f, ", coalesced")?,
+            Some(Optimization::Unsatisfiable) => write!(f, ", coalesced, never holds")?,
+            _ => {}
+        }
+        for child in &self.children {
+            writeln!(f)?;
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct Planner<'a> {
+    pub(crate) nodes: &'a Nodes,
+    pub(crate) options: &'a ParseOptions,
+}
+
+impl Planner<'_> {
+    /// Estimated cost of evaluating a node.
+    pub(crate) fn cost(&self, id: NodeId) -> u64 {
+        match self.nodes.get(id) {
+            Node::And(children) | Node::Or(children) => children
+                .iter()
+                .fold(1, |cost, &c| cost.saturating_add(self.cost(c))),
+            Node::Not(child) => self.cost(*child),
+            Node::Field(_, child) => self.cost(*child).saturating_add(1),
+            Node::Literal(_) => 1,
+            // Checked first, as nothing satisfies it.
+            Node::Range(interval) if interval.is_empty() => 0,
+            Node::Range(_) => 2,
+            Node::Interpreted(matcher) => {
+                let operator = operator_name(matcher);
+                match operator.and_then(|operator| self.options.cost(operator)) {
+                    Some(cost) => u64::from(cost),
+                    None => default_cost(operator),
+                }
+            }
+        }
+    }
+
+    /// Estimated fraction of the values at `path` a node holds for, if statistics were given.
+    pub(crate) fn selectivity(&self, id: NodeId, path: &str) -> Option<f64> {
+        let statistics = self.options.planner_statistics()?;
+        Some(self.estimate(statistics, id, path))
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn estimate(&self, statistics: &Statistics, id: NodeId, path: &str) -> f64 {
+        match self.nodes.get(id) {
+            Node::And(children) => children
+                .iter()
+                .map(|&c| self.estimate(statistics, c, path))
+                .product(),
+            Node::Or(children) => {
+                1.0 - children
+                    .iter()
+                    .map(|&c| 1.0 - self.estimate(statistics, c, path))
+                    .product::<f64>()
+            }
+            Node::Not(child) => 1.0 - self.estimate(statistics, *child, path),
+            Node::Field(key, child) => {
+                let path = join(path, key);
+                match statistics.get(&path).and_then(|s| s.match_rate) {
+                    Some(rate) => rate.clamp(0.0, 1.0),
+                    None => self.estimate(statistics, *child, &path),
+                }
+            }
+            Node::Literal(_) => match statistics.get(path).and_then(|s| s.cardinality) {
+                Some(cardinality) if cardinality > 0 => 1.0 / cardinality as f64,
+                _ => UNKNOWN_SELECTIVITY,
+            },
+            Node::Range(_) | Node::Interpreted(_) => UNKNOWN_SELECTIVITY,
+        }
+    }
+
+    /// Orders the clauses of a conjunction or disjunction of the values at `path`, so that the
+    /// ones most likely to decide its outcome for the least work run first. Both short-circuit,
+    /// so this only changes how much work is done, not the result.
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn order(&self, children: &mut [NodeId], path: &str, conjunction: bool) {
+        let statistics = match self.options.planner_statistics() {
+            Some(statistics) => statistics,
+            None => return children.sort_by_key(|&c| self.cost(c)),
+        };
+        let rank = |c: NodeId| {
+            let holds = self.estimate(statistics, c, path);
+            let decides = if conjunction { 1.0 - holds } else { holds };
+            self.cost(c) as f64 / decides.max(1e-6)
+        };
+        children.sort_by(|&a, &b| {
+            rank(a)
+                .partial_cmp(&rank(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    pub(crate) fn explain(&self, id: NodeId, path: &str) -> Plan {
+        let mut optimization = None;
+        let (step, operand, children): (&str, _, &[NodeId]) = match self.nodes.get(id) {
+            Node::And(children) => ("$and", None, children),
+            Node::Or(children) => ("$or", None, children),
+            Node::Not(child) => ("$not", None, std::slice::from_ref(child)),
+            Node::Field(key, child) => {
+                let mut plan = self.explain(*child, &join(path, key));
+                plan.cost = self.cost(id);
+                return plan;
+            }
+            Node::Literal(value) => ("$eq", Some((**value).clone()), &[]),
+            Node::Range(interval) => {
+                optimization = Some(if interval.is_empty() {
+                    Optimization::Unsatisfiable
+                } else {
+                    Optimization::Coalesced
+                });
+                ("$range", Some(bounds(interval)), &[])
+            }
+            Node::Interpreted(matcher) => {
+                optimization = match matcher {
+                    ObjMatcher::In(op) => Some(op.val.hashed()),
+                    ObjMatcher::Nin(op) => Some(op.val.hashed()),
+                    _ => None,
+                }
+                .map(|literals| Optimization::Hashed { literals });
+                let operand = serde_json::to_value(matcher).ok();
+                (interpreted_name(matcher), operand, &[])
+            }
+        };
+        Plan {
+            step: step.to_string(),
+            path: path.to_string(),
+            operand,
+            cost: self.cost(id),
+            selectivity: self.selectivity(id, path),
+            optimization,
+            children: children.iter().map(|&c| self.explain(c, path)).collect(),
+        }
+    }
+}
+
+pub(crate) fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+pub(crate) fn interpreted_name(matcher: &ObjMatcher) -> &str {
+    match matcher {
+        ObjMatcher::In(_) => "$in",
+        ObjMatcher::Nin(_) => "$nin",
+        _ => operator_name(matcher).unwrap_or("$matcher"),
+    }
+}
+
+/// The clauses an interval was merged from, such as `{"$gte": 5, "$lt": 100, "$ne": 7}`.
+fn bounds(interval: &Interval) -> Value {
+    let mut bounds = Map::new();
+    let mut bound = |bound: &Option<Bound>, inclusive: &str, exclusive: &str| {
+        if let Some(bound) = bound {
+            let operator = if bound.inclusive {
+                inclusive
+            } else {
+                exclusive
+            };
+            bounds.insert(operator.to_string(), Value::Number(bound.value.clone()));
+        }
+    };
+    bound(&interval.lower, "$gte", "$gt");
+    bound(&interval.upper, "$lte", "$lt");
+    match interval.excluded.as_slice() {
+        [] => {}
+        [n] => {
+            bounds.insert("$ne".to_string(), Value::Number(n.clone()));
+        }
+        excluded => {
+            let excluded = excluded.iter().cloned().map(Value::Number).collect();
+            bounds.insert("$nin".to_string(), Value::Array(excluded));
+        }
+    }
+    Value::Object(bounds)
+}
+
+/// Cost of an operator without a hint in [`ParseOptions::operator_cost`].
+fn default_cost(operator: Option<&str>) -> u64 {
+    match operator {
+        Some(
+            "$gt" | "$gte" | "$lt" | "$lte" | "$type" | "$bitsAllSet" | "$bitsAnySet"
+            | "$bitsAllClear" | "$bitsAnyClear" | "$sampleRate" | "$truthy" | "$falsy",
+        ) => 2,
+        Some("$bucket" | "$expr" | "$hasKeys" | "$format") => 5,
+        Some(
+            "$exact" | "$geoWithin" | "$near" | "$countMatching" | "$anyKey" | "$allKeys"
+            | "$setEquals" | "$setIsSubset" | "$setIntersects" | "$bytes",
+        ) => 20,
+        Some("$descend") => 50,
+        Some("$lookup") => 100,
+        Some("$where") => 1000,
+        _ => 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn paths(plan: &Plan) -> Vec<&str> {
+        plan.children
+            .iter()
+            .map(|child| child.path.as_str())
+            .collect()
+    }
+
+    #[test]
+    pub fn test_planner() {
+        let matcher =
+            from_str(r#"{"country": "NZ", "status": "active", "verified": true}"#).unwrap();
+        let plan = matcher.compile().unwrap().explain_plan();
+        assert_eq!(paths(&plan), ["country", "status", "verified"]);
+        assert_eq!(plan.selectivity, None);
+
+        let statistics: Statistics = serde_json::from_value(json!({
+            "country": {"cardinality": 2},
+            "status": {"match_rate": 0.99},
+            "verified": {"match_rate": 0.01}
+        }))
+        .unwrap();
+        let options = ParseOptions::new().statistics(Arc::new(statistics));
+        let compiled = matcher.compile_with_options(&options).unwrap();
+        let plan = compiled.explain_plan_with_options(&options);
+        assert_eq!(paths(&plan), ["verified", "country", "status"]);
+        assert_eq!(plan.children[1].selectivity, Some(0.5));
+        let doc = json!({"country": "NZ", "status": "active", "verified": true});
+        assert!(compiled.matches(&doc));
+        assert!(!compiled.matches(&json!({"country": "NZ", "verified": true})));
+
+        // A disjunction first runs the clauses most likely to hold.
+        let any = from_str(r#"{"$or": [{"a": 1}, {"b": 1}]}"#).unwrap();
+        let statistics = Statistics::new().path(
+            "b",
+            PathStatistics {
+                match_rate: Some(0.9),
+                ..Default::default()
+            },
+        );
+        let options = ParseOptions::new().statistics(Arc::new(statistics));
+        let plan = any
+            .compile_with_options(&options)
+            .unwrap()
+            .explain_plan_with_options(&options);
+        assert_eq!(paths(&plan.children[0]), ["b"]);
+
+        let range = from_str(r#"{"n": {"$gte": 5, "$lt": 100, "$ne": 7}}"#).unwrap();
+        assert_eq!(
+            serde_json::to_value(&range.compile().unwrap().explain_plan().children[0]).unwrap(),
+            json!({"step": "$range", "path": "n", "operand": {"$gte": 5, "$lt": 100, "$ne": 7}, "cost": 3, "optimization": "coalesced"})
+        );
+    }
+
+    #[test]
+    pub fn test_explain_plan() {
+        let ids: Vec<u32> = (0..20).collect();
+        let query = json!({
+            "id": {"$in": ids},
+            "n": {"$gt": 1, "$lte": 10, "$ne": 5},
+            "m": {"$gt": 3, "$lt": 2},
+            "tags": {"$len": 2}
+        });
+        let plan = crate::from_json(query)
+            .unwrap()
+            .compile()
+            .unwrap()
+            .explain_plan();
+        let optimizations: Vec<_> = plan.children.iter().map(|c| c.optimization).collect();
+        assert_eq!(
+            optimizations,
+            [
+                Some(Optimization::Unsatisfiable),
+                Some(Optimization::Coalesced),
+                Some(Optimization::Hashed { literals: 20 }),
+                None,
+            ]
+        );
+        assert_eq!(
+            plan.to_string(),
+            "$and, cost 27\n  \
+             $range m {\"$gt\":3,\"$lt\":2}, cost 1, coalesced, never holds\n  \
+             $range n {\"$gt\":1,\"$lte\":10,\"$ne\":5}, cost 3, coalesced\n  \
+             $in id (hashed, 20 literals), cost 11\n  \
+             $len tags {\"$len\":2}, cost 11"
+        );
+        assert_eq!(
+            serde_json::to_value(&plan.children[2]).unwrap()["optimization"],
+            json!({"hashed": {"literals": 20}})
+        );
+    }
+}
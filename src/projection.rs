@@ -0,0 +1,277 @@
+//! MongoDB-style projections, trimming matched documents to the requested fields.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use crate::{from_json, path, ObjMatcher};
+
+#[derive(Debug, Clone)]
+enum Field {
+    Include,
+    Exclude,
+    /// `$slice` with an element count, negative counting from the end, and an optional limit.
+    Slice(i64, Option<u64>),
+    /// `$elemMatch`, keeping the first array element matching the matcher.
+    ElemMatch(ObjMatcher),
+}
+
+/// Selects fields of a document, e.g. `{"name": 1, "address.city": 1}`.
+///
+/// A projection either includes the listed fields and drops everything else, or excludes the
+/// listed fields and keeps everything else; mixing both is an error. Array fields can
+/// additionally be trimmed with `{"$slice": n}`, `{"$slice": [skip, limit]}` or
+/// `{"$elemMatch": query}`, which keep the remaining fields in an exclusion projection. Including
+/// an array index, e.g. `{"items.0": 1}`, keeps the array with just the listed elements.
+#[derive(Debug, Clone)]
+pub struct Projection {
+    inclusive: bool,
+    fields: Vec<(String, Field)>,
+}
+
+fn invalid(path: &str, value: &Value) -> serde_json::Error {
+    serde::de::Error::custom(format!("invalid projection `{value}` for `{path}`"))
+}
+
+fn parse_field(path: &str, value: &Value) -> Result<Field, serde_json::Error> {
+    let operator = match value {
+        Value::Bool(true) => return Ok(Field::Include),
+        Value::Bool(false) => return Ok(Field::Exclude),
+        Value::Number(n) => {
+            return Ok(if n.as_f64() == Some(0.0) {
+                Field::Exclude
+            } else {
+                Field::Include
+            })
+        }
+        Value::Object(o) if o.len() == 1 => o.iter().next().unwrap(),
+        _ => return Err(invalid(path, value)),
+    };
+    match operator {
+        (key, Value::Number(n)) if key == "$slice" => match n.as_i64() {
+            Some(n) => Ok(Field::Slice(n, None)),
+            None => Err(invalid(path, value)),
+        },
+        (key, Value::Array(a)) if key == "$slice" => match a.as_slice() {
+            [skip, limit] => match (skip.as_i64(), limit.as_u64()) {
+                (Some(skip), Some(limit)) if limit > 0 => Ok(Field::Slice(skip, Some(limit))),
+                _ => Err(invalid(path, value)),
+            },
+            _ => Err(invalid(path, value)),
+        },
+        (key, query) if key == "$elemMatch" => Ok(Field::ElemMatch(from_json(query.clone())?)),
+        _ => Err(invalid(path, value)),
+    }
+}
+
+fn slice(array: &[Value], skip: i64, limit: Option<u64>) -> Vec<Value> {
+    let len = array.len();
+    let magnitude = usize::try_from(skip.unsigned_abs()).unwrap_or(usize::MAX);
+    let limit = limit.map(|limit| usize::try_from(limit).unwrap_or(usize::MAX));
+    let from_end = len.saturating_sub(magnitude);
+    let (start, count) = match limit {
+        Some(limit) if skip < 0 => (from_end, limit),
+        Some(limit) => (magnitude, limit),
+        None if skip < 0 => (from_end, len),
+        None => (0, magnitude),
+    };
+    let start = start.min(len);
+    let end = start.saturating_add(count).min(len);
+    array[start..end].to_vec()
+}
+
+impl Field {
+    /// The projected form of a field, or `None` if the field is dropped.
+    fn apply(&self, value: &Value) -> Option<Value> {
+        match (self, value) {
+            (Field::Slice(skip, limit), Value::Array(a)) => {
+                Some(Value::Array(slice(a, *skip, *limit)))
+            }
+            (Field::ElemMatch(matcher), Value::Array(a)) => a
+                .iter()
+                .find(|element| matcher.matches(element))
+                .map(|element| Value::Array(vec![element.clone()])),
+            (Field::Slice(..) | Field::Include, _) => Some(value.clone()),
+            (Field::ElemMatch(_) | Field::Exclude, _) => None,
+        }
+    }
+
+    fn is_include(&self) -> bool {
+        matches!(self, Field::Include)
+    }
+
+    fn is_exclude(&self) -> bool {
+        matches!(self, Field::Exclude)
+    }
+}
+
+/// Turns the objects [`path::insert`] created for array indexes, e.g. `{"items": {"0": ..}}`,
+/// back into arrays of the included elements in their original order.
+fn restore_arrays(projected: &mut Value, source: &Value) {
+    match (projected, source) {
+        (projected @ Value::Object(_), Value::Array(array)) => {
+            let mut elements = BTreeMap::new();
+            if let Value::Object(o) = std::mem::replace(projected, Value::Null) {
+                for (key, mut element) in o {
+                    if let Some(index) = path::index(&key, array.len()) {
+                        restore_arrays(&mut element, &array[index]);
+                        elements.insert(index, element);
+                    }
+                }
+            }
+            *projected = Value::Array(elements.into_values().collect());
+        }
+        (Value::Object(o), Value::Object(source)) => {
+            for (key, value) in o {
+                if let Some(source) = source.get(key) {
+                    restore_arrays(value, source);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Projection {
+    pub fn from_json(v: Value) -> Result<Self, serde_json::Error> {
+        let fields = match v {
+            Value::Object(o) => o
+                .into_iter()
+                .map(|(path, value)| {
+                    let field = parse_field(&path, &value)?;
+                    Ok((path, field))
+                })
+                .collect::<Result<Vec<_>, serde_json::Error>>()?,
+            _ => {
+                return Err(serde::de::Error::custom(
+                    "projection must be an object of field paths",
+                ))
+            }
+        };
+        let includes = fields.iter().any(|(_, field)| field.is_include());
+        let excludes = fields.iter().any(|(_, field)| field.is_exclude());
+        if includes && excludes {
+            return Err(serde::de::Error::custom(
+                "projection cannot mix inclusion and exclusion",
+            ));
+        }
+        Ok(Projection {
+            inclusive: includes,
+            fields,
+        })
+    }
+
+    /// Returns the projected copy of `value`. Values other than objects are returned unchanged.
+    #[must_use]
+    pub fn apply(&self, value: &Value) -> Value {
+        if !value.is_object() {
+            return value.clone();
+        }
+        if self.inclusive {
+            let mut projected = Value::Object(Map::new());
+            for (path, field) in &self.fields {
+                if let Some(value) = path::get(value, path).and_then(|v| field.apply(v)) {
+                    path::insert(&mut projected, path, value);
+                }
+            }
+            restore_arrays(&mut projected, value);
+            return projected;
+        }
+        let mut projected = value.clone();
+        for (path, field) in &self.fields {
+            if let Some(current) = path::get_mut(&mut projected, path) {
+                match field.apply(current) {
+                    Some(value) => *current = value,
+                    None => {
+                        path::remove(&mut projected, path);
+                    }
+                }
+            }
+        }
+        projected
+    }
+}
+
+impl FromStr for Projection {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Projection::from_json(serde_json::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn project(projection: &str, value: &Value) -> Value {
+        projection.parse::<Projection>().unwrap().apply(value)
+    }
+
+    #[test]
+    pub fn test_projection() {
+        let doc = json!({
+            "name": "Ada",
+            "address": {"city": "London", "zip": "N1"},
+            "tags": [1, 2, 3, 4, 5],
+            "scores": [{"k": "a", "v": 1}, {"k": "b", "v": 2}, {"k": "b", "v": 3}],
+        });
+        assert_eq!(
+            project(r#"{"name": 1, "address.city": true, "missing": 1}"#, &doc),
+            json!({"name": "Ada", "address": {"city": "London"}})
+        );
+        assert_eq!(
+            project(r#"{"address.zip": 0, "tags": 0, "scores": 0}"#, &doc),
+            json!({"name": "Ada", "address": {"city": "London"}})
+        );
+        assert_eq!(
+            project(r#"{"name": 1, "tags": {"$slice": -2}}"#, &doc),
+            json!({"name": "Ada", "tags": [4, 5]})
+        );
+        assert_eq!(
+            project(r#"{"tags": {"$slice": [1, 2]}, "scores": 0}"#, &doc),
+            json!({"name": "Ada", "address": {"city": "London", "zip": "N1"}, "tags": [2, 3]})
+        );
+        assert_eq!(
+            project(r#"{"scores": {"$elemMatch": {"k": "b"}}}"#, &doc)["scores"],
+            json!([{"k": "b", "v": 2}])
+        );
+        assert_eq!(
+            project(r#"{"scores": {"$elemMatch": {"k": "c"}}, "tags": 0}"#, &doc),
+            json!({"name": "Ada", "address": {"city": "London", "zip": "N1"}})
+        );
+        assert_eq!(project(r#"{"a": 1}"#, &json!([1])), json!([1]));
+    }
+
+    #[test]
+    pub fn test_projection_array_index() {
+        let doc = json!({
+            "items": [{"sku": "a", "qty": 1}, {"sku": "b", "qty": 2}, {"sku": "c", "qty": 3}],
+            "meta": {"0": "zero"},
+        });
+        assert_eq!(
+            project(r#"{"items.0": 1}"#, &doc),
+            json!({"items": [{"sku": "a", "qty": 1}]})
+        );
+        assert_eq!(
+            project(
+                r#"{"items.-1.sku": 1, "items.0.qty": 1, "meta.0": 1}"#,
+                &doc
+            ),
+            json!({"items": [{"qty": 1}, {"sku": "c"}], "meta": {"0": "zero"}})
+        );
+        assert_eq!(project(r#"{"items.5": 1}"#, &doc), json!({}));
+    }
+
+    #[test]
+    pub fn test_projection_invalid() {
+        assert!("[]".parse::<Projection>().is_err());
+        assert!(r#"{"a": 1, "b": 0}"#.parse::<Projection>().is_err());
+        assert!(r#"{"a": "yes"}"#.parse::<Projection>().is_err());
+        assert!(r#"{"a": {"$slice": [1, 0]}}"#.parse::<Projection>().is_err());
+        assert!(r#"{"a": {"$slice": 1, "$elemMatch": {}}}"#.parse::<Projection>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! `$ref` references to named matchers.
+//!
+//! A reference is an object with a single `$ref` key naming a definition in a
+//! [`MatcherRegistry`], e.g. `{"$and": [{"$ref": "common/active_user"}, {"plan": "pro"}]}`.
+//! References are replaced by their definitions, in place, when a query is parsed with
+//! [`ParseOptions::matchers`](crate::ParseOptions::matchers). Definitions may themselves contain
+//! references, as long as they do not form a cycle.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// Named query definitions that `$ref` references resolve against.
+#[derive(Debug, Clone, Default)]
+pub struct MatcherRegistry {
+    definitions: HashMap<String, Value>,
+}
+
+impl MatcherRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a definition, replacing any previous definition with the same name.
+    pub fn define<S: Into<String>>(&mut self, name: S, query: Value) {
+        self.definitions.insert(name.into(), query);
+    }
+
+    /// Parses `query` as JSON and adds it as a definition.
+    pub fn define_str<S: Into<String>>(
+        &mut self,
+        name: S,
+        query: &str,
+    ) -> Result<(), serde_json::Error> {
+        self.define(name, serde_json::from_str(query)?);
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.definitions.get(name)
+    }
+
+    /// Returns `query` with every `$ref` replaced by the definition it names.
+    pub fn resolve(&self, query: &Value) -> Result<Value, serde_json::Error> {
+        self.expand(query, &mut Vec::new())
+    }
+
+    fn expand<'a>(
+        &'a self,
+        value: &'a Value,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<Value, serde_json::Error> {
+        if let Some(name) = reference(value) {
+            if stack.contains(&name) {
+                stack.push(name);
+                return Err(serde::de::Error::custom(format!(
+                    "cyclic matcher reference: {}",
+                    stack.join(" -> ")
+                )));
+            }
+            let definition = self.get(name).ok_or_else(|| {
+                serde::de::Error::custom(format!("unknown matcher reference `{name}`"))
+            })?;
+            stack.push(name);
+            let expanded = self.expand(definition, stack)?;
+            stack.pop();
+            return Ok(expanded);
+        }
+        Ok(match value {
+            Value::Object(o) => {
+                let mut expanded = serde_json::Map::new();
+                for (key, value) in o {
+                    expanded.insert(key.clone(), self.expand(value, stack)?);
+                }
+                Value::Object(expanded)
+            }
+            Value::Array(a) => {
+                let mut expanded = Vec::with_capacity(a.len());
+                for value in a {
+                    expanded.push(self.expand(value, stack)?);
+                }
+                Value::Array(expanded)
+            }
+            _ => value.clone(),
+        })
+    }
+}
+
+fn reference(value: &Value) -> Option<&str> {
+    match value {
+        Value::Object(o) if o.len() == 1 => o.get("$ref").and_then(Value::as_str),
+        _ => None,
+    }
+}
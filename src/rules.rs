@@ -0,0 +1,642 @@
+//! Named rules and loading them from rule files.
+//!
+//! A rule file holds either a single rule definition or an array of them:
+//!
+//! ```json
+//! [
+//!     {
+//!         "id": "large-orders",
+//!         "description": "Orders over the review limit",
+//!         "matcher": {"type": "order", "flagged": true}
+//!     },
+//!     {"id": "eu", "matcher": {"region": {"$in": ["eu-west-1", "eu-central-1"]}}}
+//! ]
+//! ```
+//!
+//! JSON files are always supported; YAML files (`.yaml`, `.yml`) require the `yaml` feature.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use crate::compiled::{compile_or_interpret, Interner, NodeId, Nodes};
+use crate::{from_json_with_options, MatchOptions, ObjMatcher, ParseOptions};
+
+/// A matcher with an identifier and an optional human-readable description.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub id: String,
+    pub description: Option<String>,
+    pub matcher: ObjMatcher,
+}
+
+#[derive(Deserialize)]
+struct RuleDefinition {
+    id: String,
+    #[serde(default)]
+    description: Option<String>,
+    matcher: Value,
+}
+
+/// Collection of [`Rule`]s with unique identifiers.
+///
+/// Every matcher is also compiled into a single flat node buffer shared by the whole set, which is
+/// what [`MatcherSet::matching`] evaluates. Clauses common to several rules are evaluated once per
+/// document.
+#[derive(Debug, Clone, Default)]
+pub struct MatcherSet {
+    options: ParseOptions,
+    rules: Vec<Rule>,
+    index: HashMap<String, usize>,
+    roots: Vec<NodeId>,
+    nodes: Nodes,
+    interner: Interner,
+}
+
+impl MatcherSet {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles matchers with the given options instead of the defaults, recognizing the
+    /// operators of their registry. Sets loaded with
+    /// [`load_with_options`](Self::load_with_options) use the options they were loaded with.
+    #[must_use]
+    pub fn options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self.rebuild();
+        self
+    }
+
+    /// Loads every rule file under `path`, which may be a single file or a directory that is
+    /// searched recursively. All failures are reported together.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MatcherSet, LoadError> {
+        Self::load_with_options(path, &ParseOptions::default())
+    }
+
+    pub fn load_with_options<P: AsRef<Path>>(
+        path: P,
+        options: &ParseOptions,
+    ) -> Result<MatcherSet, LoadError> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "load",
+            path = %path.as_ref().display(),
+            rules = tracing::field::Empty,
+            errors = tracing::field::Empty
+        )
+        .entered();
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        collect_files(path.as_ref(), &mut files, &mut errors);
+        files.sort();
+
+        let mut set = MatcherSet::new().options(options.clone());
+        for file in files {
+            match fs::read_to_string(&file) {
+                Ok(source) => set.load_source(&file, &source, options, &mut errors),
+                Err(err) => errors.push(RuleError::new(&file, None, None, err.to_string())),
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            span.record("rules", set.len());
+            span.record("errors", errors.len());
+        }
+        if errors.is_empty() {
+            Ok(set)
+        } else {
+            Err(LoadError { errors })
+        }
+    }
+
+    /// Adds a rule, returning `false` without adding it if a rule with the same identifier
+    /// already exists.
+    pub fn insert(&mut self, rule: Rule) -> bool {
+        if self.index.contains_key(&rule.id) {
+            return false;
+        }
+        let root = self.compile(&rule.matcher);
+        self.index.insert(rule.id.clone(), self.rules.len());
+        self.rules.push(rule);
+        self.roots.push(root);
+        true
+    }
+
+    fn compile(&mut self, matcher: &ObjMatcher) -> NodeId {
+        compile_or_interpret(&mut self.nodes, &mut self.interner, &self.options, matcher)
+    }
+
+    /// Compiles every rule again into fresh buffers.
+    fn rebuild(&mut self) {
+        self.roots.clear();
+        self.nodes.clear();
+        self.interner.clear();
+        for i in 0..self.rules.len() {
+            let matcher = self.rules[i].matcher.clone();
+            let root = self.compile(&matcher);
+            self.roots.push(root);
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<&Rule> {
+        self.index.get(id).map(|&index| &self.rules[index])
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Rule> {
+        let index = self.index.remove(id)?;
+        let rule = self.rules.remove(index);
+        for (index, rule) in self.rules.iter().enumerate().skip(index) {
+            self.index.insert(rule.id.clone(), index);
+        }
+        self.rebuild();
+        Some(rule)
+    }
+
+    /// Keeps only the rules for which `keep` returns `true`, compiling the set again once.
+    pub fn retain<F: FnMut(&Rule) -> bool>(&mut self, mut keep: F) {
+        let len = self.rules.len();
+        self.rules.retain(|rule| keep(rule));
+        if self.rules.len() == len {
+            return;
+        }
+        self.index.clear();
+        for (i, rule) in self.rules.iter().enumerate() {
+            self.index.insert(rule.id.clone(), i);
+        }
+        self.rebuild();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rules whose matcher matches `value`, in insertion order.
+    pub fn matching<'a>(&'a self, value: &'a Value) -> impl Iterator<Item = &'a Rule> + 'a {
+        self.matching_with_options(value, &MatchOptions::default())
+    }
+
+    /// Like [`matching`](Self::matching), evaluating the rules with the given options, e.g. to
+    /// resolve `$context` literals or call custom operators.
+    pub fn matching_with_options<'a>(
+        &'a self,
+        value: &'a Value,
+        options: &MatchOptions,
+    ) -> impl Iterator<Item = &'a Rule> + 'a {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("match_set", rules = self.rules.len()).entered();
+        let matched = self.nodes.eval_all(&self.roots, value, options);
+        #[cfg(feature = "tracing")]
+        for (rule, outcome) in self.rules.iter().zip(&matched) {
+            tracing::trace!(rule = %rule.id, outcome, "evaluated rule");
+        }
+        self.rules
+            .iter()
+            .zip(matched)
+            .filter(|(_, matched)| *matched)
+            .map(|(rule, _)| rule)
+    }
+
+    fn load_source(
+        &mut self,
+        file: &Path,
+        source: &str,
+        options: &ParseOptions,
+        errors: &mut Vec<RuleError>,
+    ) {
+        let definitions = match parse_definitions(file, source) {
+            Ok(definitions) => definitions,
+            Err(err) => return errors.push(err),
+        };
+        for (line, definition) in definitions {
+            let definition: RuleDefinition = match serde_json::from_value(definition) {
+                Ok(definition) => definition,
+                Err(err) => {
+                    errors.push(RuleError::new(file, line, None, err.to_string()));
+                    continue;
+                }
+            };
+            let id = definition.id;
+            let rule = match from_json_with_options(definition.matcher, options) {
+                Ok(matcher) => Rule {
+                    id: id.clone(),
+                    description: definition.description,
+                    matcher,
+                },
+                Err(err) => {
+                    errors.push(RuleError::new(file, line, Some(id), err.to_string()));
+                    continue;
+                }
+            };
+            if !self.insert(rule) {
+                let message = "duplicate rule id".to_string();
+                errors.push(RuleError::new(file, line, Some(id), message));
+            }
+        }
+    }
+}
+
+pub(crate) fn collect_files(path: &Path, files: &mut Vec<PathBuf>, errors: &mut Vec<RuleError>) {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return;
+    }
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) => return errors.push(RuleError::new(path, None, None, err.to_string())),
+    };
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => {
+                errors.push(RuleError::new(path, None, None, err.to_string()));
+                continue;
+            }
+        };
+        if path.is_dir() {
+            collect_files(&path, files, errors);
+        } else if is_rule_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_rule_file(path: &Path) -> bool {
+    let json = path.extension().and_then(|extension| extension.to_str()) == Some("json");
+    json || (cfg!(feature = "yaml") && is_yaml(path))
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yaml" | "yml")
+    )
+}
+
+/// Splits a rule file into its rule definitions, each with the line on which it starts when
+/// that is known.
+pub(crate) fn parse_definitions(
+    file: &Path,
+    source: &str,
+) -> Result<Vec<(Option<usize>, Value)>, RuleError> {
+    if is_yaml(file) {
+        return parse_yaml_definitions(file, source);
+    }
+    let syntax_error =
+        |err: serde_json::Error| RuleError::new(file, Some(err.line()), None, err.to_string());
+    let line_of = |raw: &RawValue| {
+        let offset = raw.get().as_ptr() as usize - source.as_ptr() as usize;
+        Some(source[..offset].matches('\n').count() + 1)
+    };
+    let raw: &RawValue = serde_json::from_str(source).map_err(syntax_error)?;
+    let raws: Vec<&RawValue> = if raw.get().starts_with('[') {
+        serde_json::from_str(raw.get()).map_err(syntax_error)?
+    } else {
+        vec![raw]
+    };
+    raws.into_iter()
+        .map(|raw| {
+            let value = serde_json::from_str(raw.get()).map_err(syntax_error)?;
+            Ok((line_of(raw), value))
+        })
+        .collect()
+}
+
+#[cfg(feature = "yaml")]
+fn parse_yaml_definitions(
+    file: &Path,
+    source: &str,
+) -> Result<Vec<(Option<usize>, Value)>, RuleError> {
+    let value: Value = serde_yaml::from_str(source).map_err(|err| {
+        let line = err.location().map(|location| location.line());
+        RuleError::new(file, line, None, err.to_string())
+    })?;
+    let definitions = match value {
+        Value::Array(definitions) => definitions,
+        definition => vec![definition],
+    };
+    let lines = yaml_definition_lines(source, definitions.len());
+    Ok(definitions
+        .into_iter()
+        .enumerate()
+        .map(|(i, definition)| (lines.as_ref().map(|lines| lines[i]), definition))
+        .collect())
+}
+
+/// Lines on which each of `count` definitions starts: the top-level `- ` items of a sequence, or
+/// the first line of a single definition. `None` if the file is laid out differently, e.g. as a
+/// flow sequence.
+#[cfg(feature = "yaml")]
+fn yaml_definition_lines(source: &str, count: usize) -> Option<Vec<usize>> {
+    let content = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#') && !line.starts_with("---")
+        })
+        .collect::<Vec<_>>();
+    let items = content
+        .iter()
+        .filter(|(_, line)| *line == "-" || line.starts_with("- "))
+        .map(|(line, _)| *line)
+        .collect::<Vec<_>>();
+    let lines = if items.is_empty() {
+        content.first().map(|(line, _)| vec![*line])?
+    } else {
+        items
+    };
+    if lines.len() == count {
+        Some(lines)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "yaml"))]
+fn parse_yaml_definitions(
+    file: &Path,
+    _source: &str,
+) -> Result<Vec<(Option<usize>, Value)>, RuleError> {
+    let message = "YAML rule files require the `yaml` feature".to_string();
+    Err(RuleError::new(file, None, None, message))
+}
+
+/// A rule file, or a rule within it, that could not be loaded.
+#[derive(Debug, Clone)]
+pub struct RuleError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub id: Option<String>,
+    pub message: String,
+}
+
+impl RuleError {
+    pub(crate) fn new(
+        path: &Path,
+        line: Option<usize>,
+        id: Option<String>,
+        message: String,
+    ) -> Self {
+        RuleError {
+            path: path.to_path_buf(),
+            line,
+            id,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(id) = &self.id {
+            write!(f, ": rule `{id}`")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Every failure encountered while loading rule files.
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub(crate) errors: Vec<RuleError>,
+}
+
+impl LoadError {
+    #[must_use]
+    pub fn errors(&self) -> &[RuleError] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "serde_json_matcher_rules_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let dir = rules_dir("ok");
+        fs::write(
+            dir.join("orders.json"),
+            r#"[
+                {"id": "large", "description": "Large orders", "matcher": {"size": "large"}},
+                {"id": "eu", "matcher": {"region": {"$in": ["eu-west-1", "eu-central-1"]}}}
+            ]"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("nested/admin.json"),
+            r#"{"id": "admin", "matcher": {"role": "admin"}}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "not a rule").unwrap();
+
+        let set = MatcherSet::load(&dir).unwrap();
+        assert_eq!(set.len(), 3);
+        assert_eq!(
+            set.get("large").unwrap().description.as_deref(),
+            Some("Large orders")
+        );
+        let doc = json!({"size": "large", "region": "eu-west-1"});
+        let ids: Vec<&str> = set.matching(&doc).map(|rule| rule.id.as_str()).collect();
+        assert_eq!(ids, vec!["large", "eu"]);
+
+        let set = MatcherSet::load(dir.join("nested/admin.json")).unwrap();
+        assert_eq!(set.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let rule = |id: &str, query: &str| Rule {
+            id: id.to_string(),
+            description: None,
+            matcher: crate::from_str(query).unwrap(),
+        };
+        let mut set = MatcherSet::new();
+        assert!(set.insert(rule("a", r#"{"x": {"$ne": 1}}"#)));
+        assert!(set.insert(rule("b", r#"{"x": {"$in": [1, 2]}}"#)));
+        assert!(!set.insert(rule("b", r#"{"x": 3}"#)));
+        // Matchers that fail to compile can still be inserted and removed.
+        assert!(set.insert(Rule {
+            id: "c".to_string(),
+            description: None,
+            matcher: ObjMatcher::Value(json!({"y": {"$type": "nope"}})),
+        }));
+        assert!(set.remove("c").is_some());
+        let doc = json!({"x": 2});
+        let ids: Vec<&str> = set.matching(&doc).map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        assert!(set.remove("a").is_some());
+        let doc = json!({"x": 1});
+        let ids: Vec<&str> = set.matching(&doc).map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["b"]);
+        assert!(set.get("a").is_none());
+        assert_eq!(set.get("b").unwrap().id, "b");
+        assert!(set.insert(rule("a", r#"{"x": 1}"#)));
+        let ids: Vec<&str> = set.matching(&doc).map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+
+        set.retain(|rule| rule.id == "a");
+        assert!(set.get("b").is_none());
+        let ids: Vec<&str> = set.matching(&doc).map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["a"]);
+    }
+
+    struct Even;
+
+    impl crate::CustomOperator for Even {
+        fn name(&self) -> &'static str {
+            "$even"
+        }
+
+        fn parse(&self, operand: &Value) -> Result<(), String> {
+            operand
+                .as_bool()
+                .map(|_| ())
+                .ok_or_else(|| "expected a boolean".to_string())
+        }
+
+        fn evaluate(&self, operand: &Value, value: &Value) -> bool {
+            value.as_u64().map(|n| n % 2 == 0) == operand.as_bool()
+        }
+    }
+
+    #[test]
+    fn test_options() {
+        let dir = rules_dir("options");
+        fs::write(
+            dir.join("rules.json"),
+            r#"[
+                {"id": "even", "matcher": {"n": {"$even": true}}},
+                {"id": "adult", "matcher": {"age": {"$gte": 18}}}
+            ]"#,
+        )
+        .unwrap();
+        let mut registry = crate::OperatorRegistry::new();
+        registry.register(Even);
+        let registry = std::sync::Arc::new(registry);
+        let set =
+            MatcherSet::load_with_options(&dir, &ParseOptions::new().registry(registry.clone()))
+                .unwrap();
+        let options = MatchOptions::new()
+            .registry(registry)
+            .coercion(crate::Coercion::all());
+        let doc = json!({"n": 4, "age": "42"});
+        let ids: Vec<&str> = set
+            .matching_with_options(&doc, &options)
+            .map(|rule| rule.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["even", "adult"]);
+        assert_eq!(set.matching(&doc).count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let dir = rules_dir("errors");
+        fs::write(
+            dir.join("a.json"),
+            "[\n  {\"id\": \"ok\", \"matcher\": {\"a\": 1}},\n  {\"id\": \"denied\", \"matcher\": {\"a\": {\"$nin\": [1]}}},\n  {\"matcher\": {}}\n]",
+        )
+        .unwrap();
+        fs::write(dir.join("b.json"), "{\"id\": \"ok\", \"matcher\": {}}").unwrap();
+        fs::write(
+            dir.join("nested/c.json"),
+            "{\n  \"id\": \"broken\",\n  oops\n}",
+        )
+        .unwrap();
+
+        let options = ParseOptions::new().deny_operator("$nin");
+        let err = MatcherSet::load_with_options(&dir, &options).unwrap_err();
+        let messages: Vec<String> = err.errors().iter().map(ToString::to_string).collect();
+        assert_eq!(messages.len(), 4);
+        assert!(messages[0].contains("a.json:3: rule `denied`: operator `$nin` is not allowed"));
+        assert!(messages[1].contains("a.json:4: missing field `id`"));
+        assert!(messages[2].contains("b.json:1: rule `ok`: duplicate rule id"));
+        assert!(messages[3].contains("c.json:3: "));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_load_yaml() {
+        let dir = rules_dir("yaml");
+        fs::write(
+            dir.join("rules.yaml"),
+            "- id: admin\n  matcher:\n    role: admin\n- id: numbers\n  matcher:\n    n:\n      $in: [1, 2]\n",
+        )
+        .unwrap();
+        let set = MatcherSet::load(&dir).unwrap();
+        assert_eq!(set.len(), 2);
+        assert!(set
+            .get("numbers")
+            .unwrap()
+            .matcher
+            .matches(&json!({"n": 2})));
+
+        fs::write(
+            dir.join("rules.yaml"),
+            "# rules\n- id: admin\n  matcher:\n    role: admin\n\n- matcher:\n    n: 1\n",
+        )
+        .unwrap();
+        let err = MatcherSet::load(&dir).unwrap_err();
+        assert!(err.errors()[0]
+            .to_string()
+            .contains("rules.yaml:6: missing field `id`"));
+
+        fs::write(dir.join("rules.yaml"), "- id: [unclosed\n").unwrap();
+        let err = MatcherSet::load(&dir).unwrap_err();
+        assert!(err.errors()[0].line.is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
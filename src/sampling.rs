@@ -0,0 +1,166 @@
+//! `$sampleRate` and `$bucket`, for splitting traffic between experiments.
+//!
+//! `{"$sampleRate": 0.01}` matches one value in a hundred, at random. `{"user_id": {"$bucket":
+//! {"of": 100, "in": [0, 1, 2]}}}` hashes `user_id` into one of 100 buckets and matches the
+//! values falling in the first three, so that a given user always lands in the same bucket. An
+//! optional `salt` string assigns the buckets independently of other experiments hashing the same
+//! field.
+
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{BucketOperator, MatchOptions, MatchesValue, SampleRateOperator};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Operand of `$sampleRate`: the probability of matching, from 0 to 1.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct SampleRate(f64);
+
+impl TryFrom<f64> for SampleRate {
+    type Error = String;
+
+    fn try_from(rate: f64) -> Result<Self, String> {
+        if (0.0..=1.0).contains(&rate) {
+            Ok(SampleRate(rate))
+        } else {
+            Err(format!("sample rate {rate} is not between 0 and 1"))
+        }
+    }
+}
+
+impl From<SampleRate> for f64 {
+    fn from(rate: SampleRate) -> f64 {
+        rate.0
+    }
+}
+
+/// Operand of `$bucket`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "BucketDefinition", into = "BucketDefinition")]
+pub struct Bucket(BucketDefinition);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BucketDefinition {
+    of: u64,
+    #[serde(rename = "in")]
+    buckets: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+}
+
+impl TryFrom<BucketDefinition> for Bucket {
+    type Error = String;
+
+    fn try_from(definition: BucketDefinition) -> Result<Self, String> {
+        if definition.of == 0 {
+            return Err("`of` must be at least 1".to_string());
+        }
+        match definition.buckets.iter().find(|b| **b >= definition.of) {
+            Some(bucket) => Err(format!(
+                "bucket {} is out of range for {} buckets",
+                bucket, definition.of
+            )),
+            None => Ok(Bucket(definition)),
+        }
+    }
+}
+
+impl From<Bucket> for BucketDefinition {
+    fn from(bucket: Bucket) -> BucketDefinition {
+        bucket.0
+    }
+}
+
+impl Bucket {
+    /// The bucket of `value`, hashed with FNV-1a over its JSON text so that it is the same across
+    /// processes and platforms.
+    fn of(&self, value: &Value) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+        if let Some(salt) = &self.0.salt {
+            write(salt.as_bytes());
+            write(&[0xff]);
+        }
+        write(value.to_string().as_bytes());
+        hash % self.0.of
+    }
+}
+
+impl MatchesValue for SampleRateOperator {
+    // The 53 random bits are exact in an `f64`.
+    #[allow(clippy::cast_precision_loss)]
+    fn matches(&self, _other: &Value, _options: &MatchOptions) -> bool {
+        // Each `RandomState` is keyed differently, which is random enough for sampling.
+        let random = RandomState::new().build_hasher().finish();
+        ((random >> 11) as f64 / (1u64 << 53) as f64) < self.val.0
+    }
+}
+
+/// `null`, and so missing fields, are in no bucket.
+impl MatchesValue for BucketOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        !other.is_null() && self.val.0.buckets.contains(&self.val.of(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_sample_rate() {
+        let never = from_str(r#"{"$sampleRate": 0}"#).unwrap();
+        let always = from_str(r#"{"$sampleRate": 1}"#).unwrap();
+        let half = from_str(r#"{"$and": [{"a": 1}, {"$sampleRate": 0.5}]}"#).unwrap();
+        let doc = json!({"a": 1});
+        let matched = (0..1000).filter(|_| half.matches(&doc)).count();
+        assert!(matched > 400 && matched < 600, "{}", matched);
+        assert!(!(0..100).any(|_| never.matches(&doc)));
+        assert!((0..100).all(|_| always.matches(&doc)));
+        assert!(from_str(r#"{"$sampleRate": 1.5}"#).is_err());
+        assert!(from_str(r#"{"$sampleRate": "x"}"#).is_err());
+    }
+
+    #[test]
+    pub fn test_bucket() {
+        let control = from_str(r#"{"user_id": {"$bucket": {"of": 4, "in": [0, 1]}}}"#).unwrap();
+        let treatment = from_str(r#"{"user_id": {"$bucket": {"of": 4, "in": [2, 3]}}}"#).unwrap();
+        let salted =
+            from_str(r#"{"user_id": {"$bucket": {"of": 4, "in": [0, 1], "salt": "exp2"}}}"#)
+                .unwrap();
+        let mut in_control = 0;
+        let mut differs = 0;
+        for id in 0..400 {
+            let doc = json!({ "user_id": id });
+            assert_ne!(control.matches(&doc), treatment.matches(&doc));
+            assert_eq!(control.matches(&doc), control.matches(&doc));
+            in_control += usize::from(control.matches(&doc));
+            differs += usize::from(control.matches(&doc) != salted.matches(&doc));
+        }
+        assert!(in_control > 150 && in_control < 250, "{}", in_control);
+        assert!(differs > 100, "{}", differs);
+        assert!(!control.matches(&json!({})) && !treatment.matches(&json!({})));
+        // The assignment is stable across releases.
+        assert!(control.matches(&json!({"user_id": "u-1"})));
+        assert!(treatment.matches(&json!({"user_id": "u-2"})));
+        assert!(treatment.matches(&json!({"user_id": 42})));
+
+        assert!(from_str(r#"{"a": {"$bucket": {"of": 0, "in": []}}}"#).is_err());
+        assert!(from_str(r#"{"a": {"$bucket": {"of": 2, "in": [2]}}}"#).is_err());
+        assert!(from_str(r#"{"a": {"$bucket": {"of": 2, "in": [0], "x": 1}}}"#).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! `$where`, evaluating a [rhai](https://rhai.rs) script against the candidate value.
+//!
+//! The candidate is available to the script as `doc`. The value of the last expression must be a
+//! boolean; scripts that fail, or that evaluate to anything else, do not match. Scripts that do
+//! not compile are rejected when the query is parsed.
+
+use std::convert::TryFrom;
+use std::panic::AssertUnwindSafe;
+
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{MatchOptions, MatchesValue, WhereOperator};
+
+/// Upper bound on the operations a single script may perform, so that a runaway loop fails
+/// instead of hanging the caller.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Upper bounds on the strings, arrays and maps a script may build, and on how deeply it may
+/// call functions, so that a script cannot exhaust memory or the stack either.
+const MAX_STRING_SIZE: usize = 1 << 20;
+const MAX_ARRAY_SIZE: usize = 100_000;
+const MAX_MAP_SIZE: usize = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+
+thread_local! {
+    static ENGINE: Engine = {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE)
+            .set_max_call_levels(MAX_CALL_LEVELS);
+        engine
+    };
+}
+
+/// Operand of `$where`, compiled when the query is parsed so that syntax errors are reported
+/// then and the script is not compiled again for every value.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Script {
+    source: String,
+    // Never mutated once compiled, so matchers stay unwind safe as they are without `rhai`.
+    ast: AssertUnwindSafe<AST>,
+}
+
+impl Clone for Script {
+    fn clone(&self) -> Self {
+        Script {
+            source: self.source.clone(),
+            ast: AssertUnwindSafe(self.ast.0.clone()),
+        }
+    }
+}
+
+impl TryFrom<String> for Script {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        let ast = ENGINE
+            .with(|engine| engine.compile(&source))
+            .map_err(|err| format!("invalid `$where` script: {err}"))?;
+        Ok(Script {
+            source,
+            ast: AssertUnwindSafe(ast),
+        })
+    }
+}
+
+impl From<Script> for String {
+    fn from(script: Script) -> String {
+        script.source
+    }
+}
+
+impl MatchesValue for WhereOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        let doc = dynamic(other);
+        ENGINE.with(|engine| {
+            let mut scope = Scope::new();
+            scope.push_dynamic("doc", doc);
+            engine
+                .eval_ast_with_scope::<bool>(&mut scope, &self.val.ast)
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// `value` as a script value, integers that fit becoming `i64` and other numbers `f64`.
+///
+/// Unlike `rhai::serde::to_dynamic`, this reads numbers the same way whether or not
+/// `serde_json` keeps their text, as it does with the `arbitrary-precision` feature.
+fn dynamic(value: &Value) -> Dynamic {
+    match value {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(b) => Dynamic::from(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Dynamic::from(i),
+            None => Dynamic::from(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => Dynamic::from(s.clone()),
+        Value::Array(a) => Dynamic::from_array(a.iter().map(dynamic).collect()),
+        Value::Object(o) => Dynamic::from_map(
+            o.iter()
+                .map(|(key, value)| (key.as_str().into(), dynamic(value)))
+                .collect(),
+        ),
+    }
+}
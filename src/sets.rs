@@ -0,0 +1,87 @@
+//! `$setEquals`, `$setIsSubset` and `$setIntersects`, comparing arrays as sets.
+//!
+//! `{"tags": {"$setEquals": ["a", "b"]}}` matches `["b", "a"]` and `["a", "b", "a"]`,
+//! `$setIsSubset` arrays whose elements all appear in the operand, including the empty array, and
+//! `$setIntersects` arrays sharing at least one element with it. Elements are compared like
+//! literals, so with the string options of the [`MatchOptions`]. Values other than arrays never
+//! match.
+
+use serde_json::Value;
+
+use crate::{
+    values_equal, MatchOptions, MatchesValue, SetEqualsOperator, SetIntersectsOperator,
+    SetIsSubsetOperator,
+};
+
+fn contains(set: &[Value], value: &Value, options: &MatchOptions) -> bool {
+    set.iter()
+        .any(|element| values_equal(element, value, options))
+}
+
+fn is_subset(a: &[Value], b: &[Value], options: &MatchOptions) -> bool {
+    a.iter().all(|value| contains(b, value, options))
+}
+
+impl MatchesValue for SetEqualsOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Array(a) => is_subset(a, &self.val, options) && is_subset(&self.val, a, options),
+            _ => false,
+        }
+    }
+}
+
+impl MatchesValue for SetIsSubsetOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Array(a) => is_subset(a, &self.val, options),
+            _ => false,
+        }
+    }
+}
+
+impl MatchesValue for SetIntersectsOperator {
+    fn matches(&self, other: &Value, options: &MatchOptions) -> bool {
+        match other {
+            Value::Array(a) => a.iter().any(|value| contains(&self.val, value, options)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_str, MatchOptions};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_set_operators() {
+        let equals = from_str(r#"{"tags": {"$setEquals": ["a", "b"]}}"#).unwrap();
+        assert!(equals.matches(&json!({"tags": ["b", "a"]})));
+        assert!(equals.matches(&json!({"tags": ["a", "b", "a"]})));
+        assert!(!equals.matches(&json!({"tags": ["a"]})));
+        assert!(!equals.matches(&json!({"tags": ["a", "b", "c"]})));
+        assert!(!equals.matches(&json!({"tags": "a"})));
+
+        let subset = from_str(r#"{"tags": {"$setIsSubset": ["a", "b", {"x": 1}]}}"#).unwrap();
+        assert!(subset.matches(&json!({"tags": [{"x": 1}, "a"]})));
+        assert!(subset.matches(&json!({"tags": []})));
+        assert!(!subset.matches(&json!({"tags": ["a", "z"]})));
+        assert!(!subset.matches(&json!({})));
+
+        let intersects = from_str(r#"{"tags": {"$setIntersects": ["urgent", "p0"]}}"#).unwrap();
+        assert!(intersects.matches(&json!({"tags": ["x", "p0"]})));
+        assert!(!intersects.matches(&json!({"tags": ["x"]})));
+        assert!(!intersects.matches(&json!({"tags": []})));
+        assert!(intersects.matches_with_options(
+            &json!({"tags": ["URGENT"]}),
+            &MatchOptions::new().lowercase(true)
+        ));
+        assert!(intersects
+            .compile()
+            .unwrap()
+            .matches(&json!({"tags": ["urgent"]})));
+
+        assert!(from_str(r#"{"tags": {"$setEquals": "a"}}"#).is_err());
+    }
+}
@@ -0,0 +1,462 @@
+//! The JSON shape of a Rust type, probed through its `Deserialize` implementation.
+//!
+//! The probe is a deserializer that offers every field a struct asks for and a default value for
+//! every primitive, recording what the type requested along the way. Whatever cannot be probed,
+//! such as untagged enums, flattened structs or `serde_json::Value`, is recorded as
+//! [`Shape::Any`], so a shape may be less precise than the type but never stricter.
+
+use std::cell::RefCell;
+
+use serde::de::value::Error;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+
+/// How deep the probe descends before giving up, bounding recursion through enums.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Shape {
+    Any,
+    Null,
+    Bool,
+    Number,
+    String,
+    Optional(Box<Shape>),
+    Array(Box<Shape>),
+    Tuple(Vec<Shape>),
+    /// An object with arbitrary keys, such as a `HashMap`.
+    Map(Box<Shape>),
+    Struct(&'static str, Vec<(&'static str, Shape)>),
+    /// An externally tagged enum with the given variant names.
+    Enum(&'static [&'static str]),
+    /// The struct of that name enclosing this one.
+    Recursive(&'static str),
+}
+
+impl Shape {
+    pub(crate) fn of<T: DeserializeOwned>() -> Shape {
+        let slot = RefCell::new(Shape::Any);
+        let _ = T::deserialize(Probe {
+            slot: &slot,
+            ancestors: &[],
+            cutoff: false,
+            depth: 0,
+        });
+        slot.into_inner()
+    }
+
+    /// The struct named `name` within this shape, the target of a [`Shape::Recursive`].
+    pub(crate) fn find(&self, name: &str) -> Option<&Shape> {
+        match self {
+            Shape::Struct(n, _) if *n == name => Some(self),
+            Shape::Struct(_, fields) => fields.iter().find_map(|(_, shape)| shape.find(name)),
+            Shape::Optional(shape) | Shape::Array(shape) | Shape::Map(shape) => shape.find(name),
+            Shape::Tuple(shapes) => shapes.iter().find_map(|shape| shape.find(name)),
+            _ => None,
+        }
+    }
+}
+
+struct Probe<'a> {
+    slot: &'a RefCell<Shape>,
+    ancestors: &'a [&'static str],
+    /// Set below a recursive struct, whose shape is already known: the probe then only produces
+    /// the smallest value it can, e.g. `None` for options and empty sequences.
+    cutoff: bool,
+    depth: usize,
+}
+
+impl Probe<'_> {
+    fn record(&self, shape: Shape) {
+        if !self.cutoff {
+            *self.slot.borrow_mut() = shape;
+        }
+    }
+
+    fn check_depth(&self) -> Result<(), Error> {
+        if self.depth > MAX_DEPTH {
+            Err(de::Error::custom("type too deep to probe"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn child<'b>(&'b self, slot: &'b RefCell<Shape>) -> Probe<'b> {
+        Probe {
+            slot,
+            ancestors: self.ancestors,
+            cutoff: self.cutoff,
+            depth: self.depth + 1,
+        }
+    }
+}
+
+macro_rules! probe_primitive {
+    ($($method:ident => $shape:expr, $visit:ident($($value:expr)?);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.record($shape);
+                visitor.$visit($($value)?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Probe<'_> {
+    type Error = Error;
+
+    probe_primitive! {
+        deserialize_any => Shape::Any, visit_unit();
+        deserialize_ignored_any => Shape::Any, visit_unit();
+        deserialize_bool => Shape::Bool, visit_bool(false);
+        deserialize_i8 => Shape::Number, visit_i64(0);
+        deserialize_i16 => Shape::Number, visit_i64(0);
+        deserialize_i32 => Shape::Number, visit_i64(0);
+        deserialize_i64 => Shape::Number, visit_i64(0);
+        deserialize_u8 => Shape::Number, visit_u64(0);
+        deserialize_u16 => Shape::Number, visit_u64(0);
+        deserialize_u32 => Shape::Number, visit_u64(0);
+        deserialize_u64 => Shape::Number, visit_u64(0);
+        deserialize_f32 => Shape::Number, visit_f64(0.0);
+        deserialize_f64 => Shape::Number, visit_f64(0.0);
+        deserialize_char => Shape::String, visit_char('a');
+        deserialize_str => Shape::String, visit_str("");
+        deserialize_string => Shape::String, visit_str("");
+        deserialize_identifier => Shape::String, visit_str("");
+        deserialize_bytes => Shape::Array(Box::new(Shape::Number)), visit_bytes(&[]);
+        deserialize_byte_buf => Shape::Array(Box::new(Shape::Number)), visit_bytes(&[]);
+        deserialize_unit => Shape::Null, visit_unit();
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        if self.cutoff {
+            return visitor.visit_none();
+        }
+        let inner = RefCell::new(Shape::Any);
+        let result = visitor.visit_some(self.child(&inner));
+        self.record(Shape::Optional(Box::new(inner.into_inner())));
+        result
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        let elements = if self.cutoff {
+            Vec::new()
+        } else {
+            vec![RefCell::new(Shape::Any)]
+        };
+        let result = visitor.visit_seq(Elements {
+            probe: &self,
+            slots: &elements,
+            index: 0,
+        });
+        let element = elements.into_iter().next().map(RefCell::into_inner);
+        self.record(Shape::Array(Box::new(element.unwrap_or(Shape::Any))));
+        result
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        let elements: Vec<_> = (0..len).map(|_| RefCell::new(Shape::Any)).collect();
+        let result = visitor.visit_seq(Elements {
+            probe: &self,
+            slots: &elements,
+            index: 0,
+        });
+        self.record(Shape::Tuple(
+            elements.into_iter().map(RefCell::into_inner).collect(),
+        ));
+        result
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        let value = RefCell::new(Shape::Any);
+        let result = visitor.visit_map(Entry {
+            probe: &self,
+            value: if self.cutoff { None } else { Some(&value) },
+        });
+        self.record(Shape::Map(Box::new(value.into_inner())));
+        result
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        let recursive = self.ancestors.contains(&name);
+        if recursive {
+            self.record(Shape::Recursive(name));
+        } else {
+            self.record(Shape::Struct(
+                name,
+                fields.iter().map(|field| (*field, Shape::Any)).collect(),
+            ));
+        }
+        let mut ancestors = self.ancestors.to_vec();
+        ancestors.push(name);
+        visitor.visit_map(StructFields {
+            slot: if self.cutoff || recursive {
+                None
+            } else {
+                Some(self.slot)
+            },
+            ancestors: &ancestors,
+            cutoff: self.cutoff || recursive,
+            depth: self.depth,
+            fields,
+            index: 0,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.check_depth()?;
+        self.record(Shape::Enum(variants));
+        let variant = match variants.first() {
+            Some(variant) => *variant,
+            None => return Err(de::Error::custom("enum without variants")),
+        };
+        let discarded = RefCell::new(Shape::Any);
+        visitor.visit_enum(Variant {
+            probe: Probe {
+                slot: &discarded,
+                ancestors: self.ancestors,
+                cutoff: true,
+                depth: self.depth + 1,
+            },
+            variant,
+        })
+    }
+}
+
+/// The elements of a sequence or tuple, one per slot.
+struct Elements<'p, 'a> {
+    probe: &'p Probe<'a>,
+    slots: &'p [RefCell<Shape>],
+    index: usize,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_, '_> {
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Error> {
+        let slot = match self.slots.get(self.index) {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        self.index += 1;
+        seed.deserialize(self.probe.child(slot)).map(Some)
+    }
+}
+
+/// A single map entry, or none below a recursive struct.
+struct Entry<'p, 'a> {
+    probe: &'p Probe<'a>,
+    value: Option<&'p RefCell<Shape>>,
+}
+
+impl<'de> MapAccess<'de> for Entry<'_, '_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if self.value.is_none() {
+            return Ok(None);
+        }
+        let discarded = RefCell::new(Shape::Any);
+        let mut key = self.probe.child(&discarded);
+        key.cutoff = true;
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Error> {
+        match self.value.take() {
+            Some(slot) => seed.deserialize(self.probe.child(slot)),
+            None => Err(de::Error::custom("value without a key")),
+        }
+    }
+}
+
+/// Every field of a struct, recording the shape of each into the struct's slot.
+struct StructFields<'a> {
+    slot: Option<&'a RefCell<Shape>>,
+    ancestors: &'a [&'static str],
+    cutoff: bool,
+    depth: usize,
+    fields: &'static [&'static str],
+    index: usize,
+}
+
+impl<'de> MapAccess<'de> for StructFields<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.fields.get(self.index) {
+            Some(field) => seed
+                .deserialize(IntoDeserializer::<Error>::into_deserializer(*field))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Error> {
+        let value = RefCell::new(Shape::Any);
+        let result = seed.deserialize(Probe {
+            slot: &value,
+            ancestors: self.ancestors,
+            cutoff: self.cutoff,
+            depth: self.depth + 1,
+        });
+        if let Some(slot) = self.slot {
+            if let Shape::Struct(_, fields) = &mut *slot.borrow_mut() {
+                fields[self.index].1 = value.into_inner();
+            }
+        }
+        self.index += 1;
+        result
+    }
+}
+
+/// The first variant of an enum, whichever kind it is.
+struct Variant<'a> {
+    probe: Probe<'a>,
+    variant: &'static str,
+}
+
+impl<'de, 'a> EnumAccess<'de> for Variant<'a> {
+    type Error = Error;
+    type Variant = Probe<'a>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Probe<'a>), Error> {
+        let variant =
+            seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.variant))?;
+        Ok((variant, self.probe))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Probe<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_struct("", fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Role {
+        Admin,
+        Guest(String),
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Node {
+        name: String,
+        #[serde(rename = "kids")]
+        children: Vec<Node>,
+        parent: Option<Box<Node>>,
+        role: Role,
+        labels: HashMap<String, (u8, bool)>,
+        #[serde(skip)]
+        cache: u8,
+    }
+
+    #[test]
+    pub fn test_probe() {
+        let node = Shape::Struct(
+            "Node",
+            vec![
+                ("name", Shape::String),
+                ("kids", Shape::Array(Box::new(Shape::Recursive("Node")))),
+                (
+                    "parent",
+                    Shape::Optional(Box::new(Shape::Recursive("Node"))),
+                ),
+                ("role", Shape::Enum(&["admin", "guest"])),
+                (
+                    "labels",
+                    Shape::Map(Box::new(Shape::Tuple(vec![Shape::Number, Shape::Bool]))),
+                ),
+            ],
+        );
+        let shape = Shape::of::<Node>();
+        assert_eq!(shape, node);
+        assert_eq!(shape.find("Node"), Some(&node));
+        assert_eq!(Shape::of::<serde_json::Value>(), Shape::Any);
+        assert_eq!(
+            Shape::of::<Vec<Option<f64>>>(),
+            Shape::Array(Box::new(Shape::Optional(Box::new(Shape::Number))))
+        );
+    }
+}
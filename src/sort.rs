@@ -0,0 +1,268 @@
+//! MongoDB-style sort specifications over field paths.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+
+use crate::{order, path, MatchOptions, ObjMatcher};
+
+/// Orders documents by one or more field paths, e.g. `{"created": -1, "name": 1}`.
+///
+/// Keys are compared in the order they are listed, `1` sorting ascending and `-1` descending.
+/// Values of different types are ordered `null` < numbers < strings < objects < arrays <
+/// booleans, and missing fields sort as `null`.
+///
+/// Keys can also be listed as an array of single-key objects, e.g.
+/// `[{"created": -1}, {"name": 1}]`. Object key order is only preserved when deserializing from
+/// text, as `from_str` does; `serde_json::Value` objects keep their keys sorted by name, so
+/// [`Sort::from_json`] rejects objects with several keys and needs the array form instead.
+#[derive(Debug, Clone, Default)]
+pub struct Sort {
+    keys: Vec<(String, bool)>,
+}
+
+impl Sort {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sorts by `path` in ascending order, after the keys already added.
+    #[must_use]
+    pub fn ascending<S: Into<String>>(mut self, path: S) -> Self {
+        self.keys.push((path.into(), false));
+        self
+    }
+
+    /// Sorts by `path` in descending order, after the keys already added.
+    #[must_use]
+    pub fn descending<S: Into<String>>(mut self, path: S) -> Self {
+        self.keys.push((path.into(), true));
+        self
+    }
+
+    pub fn from_json(v: Value) -> Result<Self, serde_json::Error> {
+        check_key_order(&v)?;
+        Sort::deserialize(v)
+    }
+
+    #[must_use]
+    pub fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        self.compare_with_options(a, b, &MatchOptions::default())
+    }
+
+    /// Compares two documents, comparing strings as `options` does when matching.
+    #[must_use]
+    pub fn compare_with_options(&self, a: &Value, b: &Value, options: &MatchOptions) -> Ordering {
+        for (path, descending) in &self.keys {
+            let ordering =
+                order::compare_values(path::lookup(a, path), path::lookup(b, path), options);
+            let ordering = if *descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl<'de> Deserialize<'de> for Sort {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SortVisitor;
+
+        impl<'de> Visitor<'de> for SortVisitor {
+            type Value = Sort;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an object mapping field paths to 1 or -1, or an array of them")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Sort, A::Error> {
+                let mut keys = Vec::new();
+                while let Some((path, direction)) = map.next_entry::<String, i64>()? {
+                    let descending = match direction {
+                        1 => false,
+                        -1 => true,
+                        _ => {
+                            return Err(de::Error::custom(format!(
+                                "invalid sort direction `{direction}` for `{path}`"
+                            )))
+                        }
+                    };
+                    keys.push((path, descending));
+                }
+                Ok(Sort { keys })
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Sort, A::Error> {
+                let mut keys = Vec::new();
+                while let Some(sort) = seq.next_element::<Sort>()? {
+                    if sort.keys.len() != 1 {
+                        return Err(de::Error::custom(
+                            "each element of a sort array must have a single key",
+                        ));
+                    }
+                    keys.extend(sort.keys);
+                }
+                if keys.is_empty() {
+                    return Err(de::Error::custom("a sort array must not be empty"));
+                }
+                Ok(Sort { keys })
+            }
+        }
+
+        deserializer.deserialize_any(SortVisitor)
+    }
+}
+
+impl FromStr for Sort {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+/// Rejects a sort object with several keys held in a `Value`, whose key order is lost.
+pub(crate) fn check_key_order(v: &Value) -> Result<(), serde_json::Error> {
+    match v {
+        Value::Object(o) if o.len() > 1 => Err(de::Error::custom(
+            "a sort on several keys built from a `Value` must list them as an array, such as \
+             `[{\"a\": 1}, {\"b\": -1}]`, as `Value` objects do not keep key order",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Sorts `values` in place, keeping the relative order of documents that compare equal.
+pub fn sort_values(values: &mut [Value], sort: &Sort) {
+    sort_values_with_options(values, sort, &MatchOptions::default());
+}
+
+pub fn sort_values_with_options(values: &mut [Value], sort: &Sort, options: &MatchOptions) {
+    values.sort_by(|a, b| sort.compare_with_options(a, b, options));
+}
+
+/// A candidate of [`top_k`], ordered so that the heap's greatest element is the one to evict
+/// next: the last in sort order, and among equal documents the last seen.
+struct Ranked<'a> {
+    value: &'a Value,
+    index: usize,
+    sort: &'a Sort,
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort
+            .compare(self.value, other.value)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked<'_> {}
+
+/// The first `k` of the `values` matching `matcher` in `sort` order, as [`sort_values`] would
+/// return them, using memory proportional to `k` rather than to the number of matches.
+pub fn top_k<'a, I>(values: I, matcher: &ObjMatcher, sort: &'a Sort, k: usize) -> Vec<&'a Value>
+where
+    I: IntoIterator<Item = &'a Value>,
+{
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+    for (index, value) in values.into_iter().enumerate() {
+        if !matcher.matches(value) {
+            continue;
+        }
+        let ranked = Ranked { value, index, sort };
+        if heap.len() < k {
+            heap.push(ranked);
+        } else if heap.peek().map_or(false, |worst| ranked < *worst) {
+            heap.pop();
+            heap.push(ranked);
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|r| r.value)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_sort_values() {
+        let mut values = vec![
+            json!({"id": 1, "created": 2, "name": "b"}),
+            json!({"id": 2, "created": 3, "name": "a"}),
+            json!({"id": 3, "created": 2, "name": "a"}),
+            json!({"id": 4, "name": "c"}),
+            json!({"id": 5, "created": "later"}),
+            json!({"id": 6, "created": 2.5}),
+        ];
+        let sort: Sort = r#"{"created": -1, "name": 1}"#.parse().unwrap();
+        sort_values(&mut values, &sort);
+        let ids: Vec<_> = values.iter().map(|v| v["id"].clone()).collect();
+        assert_eq!(ids, vec![5, 2, 6, 3, 1, 4]);
+
+        sort_values(&mut values, &Sort::new().ascending("name").descending("id"));
+        let ids: Vec<_> = values.iter().map(|v| v["id"].clone()).collect();
+        assert_eq!(ids, vec![6, 5, 3, 2, 1, 4]);
+
+        assert!(r#"{"created": 0}"#.parse::<Sort>().is_err());
+        assert!("[]".parse::<Sort>().is_err());
+        assert!(r#"[{"created": -1, "name": 1}]"#.parse::<Sort>().is_err());
+
+        let ordered = Sort::new().descending("name").ascending("created");
+        let listed = Sort::from_json(json!([{"name": -1}, {"created": 1}])).unwrap();
+        assert_eq!(listed.keys, ordered.keys);
+        let parsed: Sort = r#"{"name": -1, "created": 1}"#.parse().unwrap();
+        assert_eq!(parsed.keys, ordered.keys);
+        assert!(Sort::from_json(json!({"name": -1, "created": 1})).is_err());
+        assert!(Sort::from_json(json!({"name": -1})).is_ok());
+    }
+
+    #[test]
+    pub fn test_top_k() {
+        let values: Vec<Value> = (0..100)
+            .map(|i| json!({"id": i, "score": (i * 37) % 10, "kind": i % 2}))
+            .collect();
+        let even = crate::from_str(r#"{"kind": 0}"#).unwrap();
+        let sort: Sort = r#"{"score": -1}"#.parse().unwrap();
+
+        let mut expected: Vec<Value> = values.iter().filter(|v| even.matches(v)).cloned().collect();
+        sort_values(&mut expected, &sort);
+        expected.truncate(7);
+        let top: Vec<Value> = top_k(&values, &even, &sort, 7)
+            .into_iter()
+            .cloned()
+            .collect();
+        assert_eq!(top, expected);
+        assert!(top_k(&values, &even, &sort, 0).is_empty());
+        assert_eq!(top_k(&values, &even, &sort, 1000).len(), 50);
+    }
+}
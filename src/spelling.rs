@@ -0,0 +1,123 @@
+//! Reading operators spelled without the `$` prefix, as JavaScript ORMs such as Prisma and
+//! visual rule builders write them, e.g.
+//! `{"age": {"gte": 21}, "OR": [{"role": {"in": ["admin"]}}]}`.
+//!
+//! Enabled by [`ParseOptions::bare_operators`](crate::ParseOptions::bare_operators), which
+//! rewrites every object key spelled as in `SPELLINGS` into its operator before the query is
+//! parsed. A field named like one of them is still addressed with a backslash, e.g. `"\\in"`.
+
+use serde_json::{Map, Value};
+
+const SPELLINGS: &[(&str, &str)] = &[
+    ("eq", "$eq"),
+    ("equals", "$eq"),
+    ("ne", "$ne"),
+    ("gt", "$gt"),
+    ("gte", "$gte"),
+    ("lt", "$lt"),
+    ("lte", "$lte"),
+    ("in", "$in"),
+    ("nin", "$nin"),
+    ("notIn", "$nin"),
+    ("and", "$and"),
+    ("AND", "$and"),
+    ("or", "$or"),
+    ("OR", "$or"),
+    ("not", "$not"),
+    ("NOT", "$not"),
+];
+
+/// The query `value` with its bare operators given their `$` prefix.
+pub(crate) fn add_prefixes(value: Value) -> Value {
+    match value {
+        Value::Object(o) => {
+            let mut rewritten = Map::new();
+            let mut negation = None;
+            for (key, value) in o {
+                let value = add_prefixes(value);
+                match SPELLINGS.iter().find(|(bare, _)| *bare == key) {
+                    // Prisma negates each of a list of conditions, i.e. none of them holds.
+                    Some((_, "$not")) if value.is_array() => {
+                        negation = Some(condition("$or", value));
+                    }
+                    Some((_, "$not")) => negation = Some(value),
+                    Some((_, operator)) => {
+                        rewritten.insert((*operator).to_string(), value);
+                    }
+                    None => {
+                        rewritten.insert(key, value);
+                    }
+                }
+            }
+            if let Some(negation) = negation {
+                // Only `$and` and `$or` may stand next to field conditions, as Prisma's `NOT` does.
+                let fields = rewritten.keys().any(|key| !key.starts_with('$'));
+                match rewritten.get_mut("$and") {
+                    Some(Value::Array(clauses)) if fields => {
+                        clauses.push(condition("$not", negation));
+                    }
+                    None if fields => {
+                        let clauses = vec![condition("$not", negation)];
+                        rewritten.insert("$and".to_string(), Value::Array(clauses));
+                    }
+                    _ => {
+                        rewritten.insert("$not".to_string(), negation);
+                    }
+                }
+            }
+            Value::Object(rewritten)
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(add_prefixes).collect()),
+        value => value,
+    }
+}
+
+fn condition(operator: &str, operand: Value) -> Value {
+    let mut condition = Map::new();
+    condition.insert(operator.to_string(), operand);
+    Value::Object(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_json, from_json_with_options, ParseOptions};
+    use serde_json::{json, to_value};
+
+    #[test]
+    pub fn test_bare_operators() {
+        let options = ParseOptions::new().bare_operators(true);
+        let parse = |query| from_json_with_options(query, &options).unwrap();
+        let matcher = parse(json!({
+            "age": {"gte": 21, "lt": 65},
+            "OR": [{"role": {"in": ["admin", "editor"]}}, {"karma": {"gt": 100}}],
+            "status": {"not": "banned", "notIn": ["deleted"]},
+            "NOT": [{"email": {"equals": null}}, {"\\in": true}],
+        }));
+        let canonical = from_json(json!({
+            "age": {"$gte": 21, "$lt": 65},
+            "$or": [{"role": {"$in": ["admin", "editor"]}}, {"karma": {"$gt": 100}}],
+            "status": {"$not": "banned", "$nin": ["deleted"]},
+            "$and": [{"$not": {"$or": [{"email": {"$eq": null}}, {"\\in": true}]}}],
+        }))
+        .unwrap();
+        assert_eq!(to_value(&matcher).unwrap(), to_value(&canonical).unwrap());
+
+        let doc = json!({"age": 30, "role": "admin", "status": "active", "email": "a@b.c"});
+        assert!(matcher.matches(&doc));
+        assert!(!matcher
+            .matches(&json!({"age": 30, "role": "admin", "status": "banned", "email": "a@b.c"})));
+        assert!(!matcher.matches(
+            &json!({"age": 30, "role": "admin", "status": "active", "email": "a@b.c", "in": true})
+        ));
+
+        // Both spellings may be mixed, and other keys are left alone.
+        assert_eq!(
+            to_value(parse(json!({"type": "user", "n": {"$gt": 1, "lt": 5}}))).unwrap(),
+            json!({"type": "user", "n": {"$gt": 1, "$lt": 5}})
+        );
+        // Without the option, bare names are fields.
+        assert!(from_json(json!({"age": {"gte": 21}}))
+            .unwrap()
+            .matches(&json!({"age": {"gte": 21}})));
+    }
+}
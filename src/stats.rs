@@ -0,0 +1,129 @@
+//! Size and shape measurements of matchers, for enforcing complexity limits.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::walk::{self, Condition};
+use crate::{compiled, ObjMatcher};
+
+/// Measurements of a matcher, returned by [`ObjMatcher::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MatcherStats {
+    /// Nesting depth of operators and field comparisons; a single literal has depth 1.
+    pub depth: usize,
+    /// Number of operators, field comparisons and literals.
+    pub nodes: usize,
+    /// Number of uses of each operator.
+    pub operators: BTreeMap<String, usize>,
+    /// Number of distinct dotted field paths compared.
+    pub paths: usize,
+    /// Largest number of values of a `$in` or `$nin`.
+    pub largest_in: usize,
+}
+
+#[derive(Default)]
+struct Walker {
+    stats: MatcherStats,
+    paths: BTreeSet<String>,
+}
+
+impl Walker {
+    fn node(&mut self, depth: usize) {
+        self.stats.nodes += 1;
+        self.stats.depth = self.stats.depth.max(depth);
+    }
+
+    fn operator(&mut self, name: &str, depth: usize) {
+        self.node(depth);
+        *self.stats.operators.entry(name.to_string()).or_insert(0) += 1;
+    }
+
+    fn matcher(&mut self, matcher: &ObjMatcher, path: &str, depth: usize) {
+        let (name, children): (&str, &[ObjMatcher]) = match matcher {
+            ObjMatcher::Eq(op) => ("$eq", std::slice::from_ref(&*op.val)),
+            ObjMatcher::Ne(op) => ("$ne", std::slice::from_ref(&*op.val)),
+            ObjMatcher::Not(op) => ("$not", std::slice::from_ref(&*op.val)),
+            ObjMatcher::Len(op) => ("$len", std::slice::from_ref(&*op.val)),
+            ObjMatcher::In(op) => ("$in", &op.val),
+            ObjMatcher::Nin(op) => ("$nin", &op.val),
+            ObjMatcher::And(op) => ("$and", &op.val),
+            ObjMatcher::Or(op) => ("$or", &op.val),
+            ObjMatcher::Value(value) => return self.value(value, path, depth),
+            _ => {
+                let name = compiled::operator_name(matcher).unwrap_or("$unknown");
+                return self.operator(name, depth);
+            }
+        };
+        self.operator(name, depth);
+        if let ObjMatcher::In(_) | ObjMatcher::Nin(_) = matcher {
+            self.stats.largest_in = self.stats.largest_in.max(children.len());
+        }
+        for child in children {
+            self.matcher(child, path, depth + 1);
+        }
+    }
+
+    fn value(&mut self, value: &Value, path: &str, depth: usize) {
+        let fields = match walk::value(value, None) {
+            walk::Clause::Operator(matcher) => return self.matcher(&matcher, path, depth),
+            walk::Clause::Fields(fields) => fields,
+            walk::Clause::Literal(_) => return self.node(depth),
+        };
+        for (key, condition) in walk::conditions(fields, None) {
+            let path = if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{path}.{key}")
+            };
+            self.node(depth);
+            match condition {
+                Condition::Operator(matcher) => self.matcher(&matcher, &path, depth + 1),
+                Condition::Literal(_) => self.node(depth + 1),
+            }
+            self.paths.insert(path);
+        }
+    }
+}
+
+impl ObjMatcher {
+    /// Measures the matcher, without resolving `$ref`s or evaluating anything.
+    #[must_use]
+    pub fn stats(&self) -> MatcherStats {
+        let mut walker = Walker::default();
+        walker.matcher(self, "", 1);
+        walker.stats.paths = walker.paths.len();
+        walker.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+
+    #[test]
+    pub fn test_stats() {
+        let matcher = from_str(
+            r#"{"$or": [
+                {"a": {"$in": [1, 2, 3]}, "b.c": {"$type": ["string"]}},
+                {"a": {"$ne": 4}, "$comment": "x"}
+            ]}"#,
+        )
+        .unwrap();
+        let stats = matcher.stats();
+        assert_eq!(stats.depth, 4);
+        assert_eq!(stats.nodes, 11);
+        assert_eq!(stats.paths, 2);
+        assert_eq!(stats.largest_in, 3);
+        let operators: Vec<_> = stats
+            .operators
+            .iter()
+            .map(|(k, v)| (k.as_str(), *v))
+            .collect();
+        assert_eq!(
+            operators,
+            vec![("$in", 1), ("$ne", 1), ("$or", 1), ("$type", 1)]
+        );
+    }
+}
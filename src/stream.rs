@@ -0,0 +1,318 @@
+//! Filtering concatenated or newline-delimited JSON, or the elements of a top-level array, while
+//! it is deserialized.
+
+use std::io::{self, BufRead, BufReader};
+
+use serde_json::de::{IoRead, Read, StreamDeserializer};
+use serde_json::{Deserializer, Value};
+
+use crate::ObjMatcher;
+
+/// Iterator over the values of a [`StreamDeserializer`] matching a matcher, created by
+/// [`filter_stream`].
+///
+/// Values that fail to deserialize are yielded as errors rather than skipped. After a syntax
+/// error the underlying stream cannot resume, so that error is the last item.
+pub struct MatchingStream<'de, 'm, R> {
+    stream: StreamDeserializer<'de, R, Value>,
+    matcher: &'m ObjMatcher,
+}
+
+/// Filters `stream` down to the values matching `matcher`, e.g.
+/// `filter_stream(serde_json::Deserializer::from_reader(socket).into_iter(), &matcher)`.
+pub fn filter_stream<'de, 'm, R: Read<'de>>(
+    stream: StreamDeserializer<'de, R, Value>,
+    matcher: &'m ObjMatcher,
+) -> MatchingStream<'de, 'm, R> {
+    MatchingStream { stream, matcher }
+}
+
+impl<'de, R: Read<'de>> MatchingStream<'de, '_, R> {
+    /// Number of bytes consumed so far, see [`StreamDeserializer::byte_offset`].
+    #[must_use]
+    pub fn byte_offset(&self) -> usize {
+        self.stream.byte_offset()
+    }
+}
+
+impl<'de, R: Read<'de>> Iterator for MatchingStream<'de, '_, R> {
+    type Item = Result<Value, serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stream.next()? {
+                Ok(value) if !self.matcher.matches(&value) => {}
+                item => return Some(item),
+            }
+        }
+    }
+}
+
+/// Iterator over the elements of a JSON array matching a matcher, created by [`filter_array`].
+///
+/// Elements are deserialized one at a time, so only the current one is held in memory. Like
+/// [`MatchingStream`], errors are yielded rather than skipped and end the iteration, including
+/// an array that is malformed, unterminated or followed by anything but whitespace.
+pub struct MatchingArray<'m, R: io::Read> {
+    elements: MatchingStream<'static, 'm, IoRead<Elements<R>>>,
+}
+
+/// Filters the elements of the array that `reader` holds down to those matching `matcher`, e.g.
+/// `filter_array(File::open("export.json")?, &matcher)`. The reader is buffered internally.
+///
+/// Compressed input is reported as an error; wrap the reader in a decoder such as
+/// `flate2::read::GzDecoder` first.
+pub fn filter_array<R: io::Read>(reader: R, matcher: &ObjMatcher) -> MatchingArray<'_, R> {
+    let elements = Elements {
+        inner: BufReader::new(reader),
+        state: State::Start,
+    };
+    MatchingArray {
+        elements: filter_stream(Deserializer::from_reader(elements).into_iter(), matcher),
+    }
+}
+
+impl<R: io::Read> MatchingArray<'_, R> {
+    /// Number of bytes of the array consumed so far.
+    #[must_use]
+    pub fn byte_offset(&self) -> usize {
+        self.elements.byte_offset()
+    }
+}
+
+impl<R: io::Read> Iterator for MatchingArray<'_, R> {
+    type Item = Result<Value, serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.elements.next()
+    }
+}
+
+/// Presents the elements of an array as whitespace-separated values, replacing the brackets and
+/// commas of the array itself with spaces, so that offsets in both are the same.
+struct Elements<R> {
+    inner: BufReader<R>,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Start,
+    /// After the opening bracket.
+    Open,
+    /// After a comma.
+    Comma,
+    Scalar,
+    /// In an element that is a string, `depth` being 0, or that contains brackets.
+    Nested {
+        depth: usize,
+        string: bool,
+        escaped: bool,
+    },
+    /// After an element.
+    Element,
+    End,
+}
+
+impl State {
+    /// The byte standing for `byte` in the stream of elements.
+    fn next(&mut self, byte: u8) -> io::Result<u8> {
+        let whitespace = byte == b' ' || byte == b'\n' || byte == b'\r' || byte == b'\t';
+        let (state, byte) = match (*self, byte) {
+            (
+                State::Nested {
+                    depth,
+                    string: true,
+                    escaped,
+                },
+                _,
+            ) => {
+                let state = match byte {
+                    _ if escaped => State::Nested {
+                        depth,
+                        string: true,
+                        escaped: false,
+                    },
+                    b'\\' => State::Nested {
+                        depth,
+                        string: true,
+                        escaped: true,
+                    },
+                    b'"' if depth == 0 => State::Element,
+                    b'"' => State::Nested {
+                        depth,
+                        string: false,
+                        escaped: false,
+                    },
+                    _ => *self,
+                };
+                (state, byte)
+            }
+            (State::Nested { depth, .. }, _) => {
+                let state = match byte {
+                    b'"' => State::Nested {
+                        depth,
+                        string: true,
+                        escaped: false,
+                    },
+                    b'[' | b'{' => State::Nested {
+                        depth: depth + 1,
+                        string: false,
+                        escaped: false,
+                    },
+                    b']' | b'}' if depth == 1 => State::Element,
+                    b']' | b'}' => State::Nested {
+                        depth: depth - 1,
+                        string: false,
+                        escaped: false,
+                    },
+                    _ => *self,
+                };
+                (state, byte)
+            }
+            (_, _) if whitespace => match *self {
+                State::Scalar => (State::Element, byte),
+                state => (state, byte),
+            },
+            (State::Start, b'[') => (State::Open, b' '),
+            // The first bytes of the gzip and zstd magic numbers, which callers must decompress.
+            (State::Start, 0x1f) => return Err(invalid("expected an array, found gzip data")),
+            (State::Start, 0x28) => return Err(invalid("expected an array, found zstd data")),
+            (State::Start, _) => return Err(invalid("expected an array")),
+            (State::Open | State::Scalar | State::Element, b']') => (State::End, b' '),
+            (State::Scalar | State::Element, b',') => (State::Comma, b' '),
+            (State::Open | State::Comma, _) => {
+                let state = match byte {
+                    b',' | b']' | b'}' => return Err(invalid("expected an array element")),
+                    b'"' => State::Nested {
+                        depth: 0,
+                        string: true,
+                        escaped: false,
+                    },
+                    b'[' | b'{' => State::Nested {
+                        depth: 1,
+                        string: false,
+                        escaped: false,
+                    },
+                    _ => State::Scalar,
+                };
+                (state, byte)
+            }
+            (State::Scalar, b'"' | b'[' | b'{') | (State::Element, _) => {
+                return Err(invalid("expected `,` or `]`"))
+            }
+            (State::Scalar, _) => (State::Scalar, byte),
+            (State::End, _) => return Err(invalid("trailing characters after the array")),
+        };
+        *self = state;
+        Ok(byte)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: io::Read> io::Read for Elements<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.inner.fill_buf()?;
+        if available.is_empty() {
+            return match self.state {
+                State::End => Ok(0),
+                State::Start => Err(invalid("expected an array")),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "unterminated array",
+                )),
+            };
+        }
+        let len = available.len().min(buf.len());
+        for (out, &byte) in buf.iter_mut().zip(&available[..len]) {
+            *out = self.state.next(byte)?;
+        }
+        self.inner.consume(len);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_filter_stream() {
+        let matcher = crate::from_str(r#"{"level": {"$in": ["warn", "error"]}}"#).unwrap();
+        let input = br#"{"level": "info"} {"level": "warn", "n": 1}
+            {"level": "error", "n": 2}{"level": "debug"}"#;
+        let stream = Deserializer::from_slice(input).into_iter();
+        let values: Vec<Value> = filter_stream(stream, &matcher)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                json!({"level": "warn", "n": 1}),
+                json!({"level": "error", "n": 2})
+            ]
+        );
+
+        let input = r#"{"level": "error"} {"level": oops} {"level": "error"}"#;
+        let stream = Deserializer::from_reader(input.as_bytes()).into_iter();
+        let mut matching = filter_stream(stream, &matcher);
+        assert!(matching.next().unwrap().is_ok());
+        assert!(matching.next().unwrap().is_err());
+        assert!(matching.next().is_none());
+    }
+
+    #[test]
+    pub fn test_filter_array() {
+        let matcher = crate::from_str(r#"{"level": {"$in": ["warn", "error"]}}"#).unwrap();
+        let input = r#" [
+            {"level": "info", "tags": ["a]", "b,"]},
+            {"level": "warn", "msg": "say \"hi\" ]"},
+            1, "error", null, [{"level": "error"}],
+            {"level": "error", "n": [1, {"m": 2}]}
+        ] "#;
+        let values: Vec<Value> = filter_array(input.as_bytes(), &matcher)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                json!({"level": "warn", "msg": "say \"hi\" ]"}),
+                json!({"level": "error", "n": [1, {"m": 2}]})
+            ]
+        );
+
+        let everything = crate::from_str("{}").unwrap();
+        assert_eq!(filter_array(&b"[]"[..], &everything).count(), 0);
+        let mut elements = filter_array(&b"[1,-2.5e3,true]"[..], &everything);
+        assert_eq!(elements.next().unwrap().unwrap(), json!(1));
+        assert_eq!(elements.next().unwrap().unwrap().as_f64(), Some(-2500.0));
+        assert_eq!(elements.next().unwrap().unwrap(), json!(true));
+        assert!(elements.next().is_none());
+        assert_eq!(elements.byte_offset(), 15);
+
+        for malformed in &[
+            "",
+            "{}",
+            "[1 2]",
+            "[1,,2]",
+            "[1,]",
+            "[1\"a\"]",
+            r#"[{"a": 1} {"a": 2}]"#,
+            "[1, 2",
+            "[1] 2",
+        ] {
+            let items = filter_array(malformed.as_bytes(), &everything).collect::<Vec<_>>();
+            assert!(
+                items.last().map_or(false, Result::is_err),
+                "accepted {:?}",
+                malformed
+            );
+        }
+        let gzip = filter_array(&[0x1f, 0x8b, 0x08][..], &everything).next();
+        assert!(gzip.unwrap().unwrap_err().to_string().contains("gzip"));
+    }
+}
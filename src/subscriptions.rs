@@ -0,0 +1,219 @@
+//! Notifying subscribers of the documents matching their matchers, as in webhook filtering.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::Sender;
+
+use serde_json::Value;
+
+use crate::{MatchOptions, MatcherSet, ObjMatcher, ParseOptions, Rule};
+
+/// Identifier of a subscription, returned when subscribing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+impl fmt::Display for SubscriptionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+enum Subscriber {
+    Callback(Box<dyn Fn(&Value) + Send + Sync>),
+    Channel(Sender<Value>),
+}
+
+/// Matchers registered with a callback or a channel, notified of every published document they
+/// match.
+///
+/// The matchers are compiled into a single [`MatcherSet`], so clauses shared by several
+/// subscriptions are evaluated once per document. Unsubscribing only forgets the subscriber; the
+/// set is compacted once removed subscriptions outnumber the live ones, so churn costs amortized
+/// constant time.
+#[derive(Default)]
+pub struct Subscriptions {
+    set: MatcherSet,
+    subscribers: HashMap<String, (SubscriptionId, Subscriber)>,
+    removed: usize,
+    next: u64,
+}
+
+impl Subscriptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles matchers with the given options instead of the defaults, recognizing the
+    /// operators of their registry.
+    #[must_use]
+    pub fn options(mut self, options: ParseOptions) -> Self {
+        self.set = self.set.options(options);
+        self
+    }
+
+    /// Calls `callback` with every published document matching `matcher`.
+    pub fn subscribe<F>(&mut self, matcher: ObjMatcher, callback: F) -> SubscriptionId
+    where
+        F: Fn(&Value) + Send + Sync + 'static,
+    {
+        self.insert(matcher, Subscriber::Callback(Box::new(callback)))
+    }
+
+    /// Sends every published document matching `matcher` to `sender`.
+    pub fn subscribe_channel(
+        &mut self,
+        matcher: ObjMatcher,
+        sender: Sender<Value>,
+    ) -> SubscriptionId {
+        self.insert(matcher, Subscriber::Channel(sender))
+    }
+
+    fn insert(&mut self, matcher: ObjMatcher, subscriber: Subscriber) -> SubscriptionId {
+        let id = SubscriptionId(self.next);
+        self.next += 1;
+        let key = id.to_string();
+        self.set.insert(Rule {
+            id: key.clone(),
+            description: None,
+            matcher,
+        });
+        self.subscribers.insert(key, (id, subscriber));
+        id
+    }
+
+    /// Removes a subscription, returning whether it existed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        if self.subscribers.remove(&id.to_string()).is_none() {
+            return false;
+        }
+        self.removed += 1;
+        if self.removed > self.subscribers.len() {
+            let subscribers = &self.subscribers;
+            self.set.retain(|rule| subscribers.contains_key(&rule.id));
+            self.removed = 0;
+        }
+        true
+    }
+
+    /// Notifies the subscribers whose matcher matches `value`, in subscription order, and returns
+    /// their identifiers. Subscribers whose channel is disconnected are left out; they stay
+    /// registered until unsubscribed.
+    #[must_use]
+    pub fn publish(&self, value: &Value) -> Vec<SubscriptionId> {
+        self.publish_with_options(value, &MatchOptions::default())
+    }
+
+    /// Like [`publish`](Self::publish), evaluating the matchers with the given options.
+    #[must_use]
+    pub fn publish_with_options(
+        &self,
+        value: &Value,
+        options: &MatchOptions,
+    ) -> Vec<SubscriptionId> {
+        let mut notified = Vec::new();
+        for rule in self.set.matching_with_options(value, options) {
+            let (id, subscriber) = match self.subscribers.get(&rule.id) {
+                Some(subscriber) => subscriber,
+                None => continue,
+            };
+            let delivered = match subscriber {
+                Subscriber::Callback(callback) => {
+                    callback(value);
+                    true
+                }
+                Subscriber::Channel(sender) => sender.send(value.clone()).is_ok(),
+            };
+            if delivered {
+                notified.push(*id);
+            }
+        }
+        notified
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriptions")
+            .field("set", &self.set)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    pub fn test_subscriptions() {
+        let mut subscriptions = Subscriptions::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let orders =
+            subscriptions.subscribe(from_str(r#"{"type": "order"}"#).unwrap(), move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        let (sender, receiver) = mpsc::channel();
+        let large = subscriptions.subscribe_channel(
+            from_str(r#"{"type": "order", "total": {"$gte": 100}}"#).unwrap(),
+            sender,
+        );
+        assert_eq!(subscriptions.len(), 2);
+
+        let doc = json!({"type": "order", "total": 250});
+        assert_eq!(subscriptions.publish(&doc), vec![orders, large]);
+        assert_eq!(receiver.try_recv().unwrap(), doc);
+        assert_eq!(
+            subscriptions.publish(&json!({"type": "order", "total": 5})),
+            vec![orders]
+        );
+        assert!(subscriptions.publish(&json!({"type": "refund"})).is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        assert!(subscriptions.unsubscribe(orders));
+        assert!(!subscriptions.unsubscribe(orders));
+        assert_eq!(subscriptions.publish(&doc), vec![large]);
+        drop(receiver);
+        assert!(subscriptions.publish(&doc).is_empty());
+    }
+
+    #[test]
+    pub fn test_churn() {
+        let mut subscriptions = Subscriptions::new();
+        let matcher = from_str(r#"{"type": "order"}"#).unwrap();
+        let kept = subscriptions.subscribe(matcher.clone(), |_| {});
+        let doc = json!({"type": "order"});
+        for _ in 0..10 {
+            let id = subscriptions.subscribe(matcher.clone(), |_| {});
+            assert_eq!(subscriptions.publish(&doc), vec![kept, id]);
+            assert!(subscriptions.unsubscribe(id));
+            assert_eq!(subscriptions.publish(&doc), vec![kept]);
+        }
+        assert_eq!(subscriptions.len(), 1);
+        assert!(subscriptions.set.len() <= 3);
+
+        let mut subscriptions = Subscriptions::new();
+        let id =
+            subscriptions.subscribe(from_str(r#"{"n": {"$gt": 1, "$lt": 5}}"#).unwrap(), |_| {});
+        let options = MatchOptions::new().coercion(crate::Coercion::all());
+        assert_eq!(
+            subscriptions.publish_with_options(&json!({"n": "3"}), &options),
+            vec![id]
+        );
+        assert!(subscriptions.publish(&json!({"n": "3"})).is_empty());
+    }
+}
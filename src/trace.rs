@@ -0,0 +1,74 @@
+//! Spans emitted with the `tracing` feature.
+//!
+//! Parsing, compiling and loading rules are recorded at the `DEBUG` level; evaluations at the
+//! `TRACE` level. Durations are those of the spans, as reported by the subscriber.
+
+use serde_json::Value;
+use tracing::span::EnteredSpan;
+
+use crate::walk::{self, Condition};
+use crate::ObjMatcher;
+
+pub(crate) fn evaluate_span(matcher: &ObjMatcher) -> EnteredSpan {
+    let span = tracing::trace_span!(
+        "evaluate",
+        clauses = tracing::field::Empty,
+        outcome = tracing::field::Empty
+    );
+    if !span.is_disabled() {
+        span.record("clauses", clauses(matcher));
+    }
+    span.entered()
+}
+
+pub(crate) fn record_parse(span: &EnteredSpan, result: &Result<ObjMatcher, serde_json::Error>) {
+    if let Ok(matcher) = result {
+        if !span.is_disabled() {
+            span.record("clauses", clauses(matcher));
+        }
+    }
+    span.record("ok", result.is_ok());
+}
+
+/// Number of operators and field comparisons in a matcher.
+pub(crate) fn clauses(matcher: &ObjMatcher) -> usize {
+    match matcher {
+        ObjMatcher::Eq(op) => 1 + clauses(&op.val),
+        ObjMatcher::Ne(op) => 1 + clauses(&op.val),
+        ObjMatcher::Not(op) => 1 + clauses(&op.val),
+        ObjMatcher::Len(op) => 1 + clauses(&op.val),
+        ObjMatcher::In(op) => 1 + op.val.iter().map(clauses).sum::<usize>(),
+        ObjMatcher::Nin(op) => 1 + op.val.iter().map(clauses).sum::<usize>(),
+        ObjMatcher::And(op) => 1 + op.val.iter().map(clauses).sum::<usize>(),
+        ObjMatcher::Or(op) => 1 + op.val.iter().map(clauses).sum::<usize>(),
+        ObjMatcher::Value(value) => value_clauses(value),
+        _ => 1,
+    }
+}
+
+fn value_clauses(value: &Value) -> usize {
+    match walk::value(value, None) {
+        walk::Clause::Operator(matcher) => clauses(&matcher),
+        walk::Clause::Fields(fields) => walk::conditions(fields, None)
+            .map(|(_, condition)| match condition {
+                Condition::Operator(matcher) => clauses(&matcher),
+                Condition::Literal(_) => 1,
+            })
+            .sum(),
+        walk::Clause::Literal(_) => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    #[test]
+    pub fn test_clauses() {
+        let matcher = from_str(r#"{"a": 1, "b": {"$in": [1, 2]}, "$comment": "x"}"#).unwrap();
+        assert_eq!(clauses(&matcher), 4);
+        let matcher = from_str(r#"{"$or": [{"a": {"$ne": 1}}, {"b": 2}]}"#).unwrap();
+        assert_eq!(clauses(&matcher), 4);
+    }
+}
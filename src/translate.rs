@@ -0,0 +1,38 @@
+//! Clauses that translations of matchers into other query languages cannot express.
+
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::ObjMatcher;
+
+/// A clause of a matcher with no equivalent in the language it is translated into.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnsupportedClause {
+    /// Dotted path of the value the clause applies to, empty for the document itself.
+    pub path: String,
+    /// The clause, as it would be serialized.
+    pub clause: Value,
+}
+
+impl UnsupportedClause {
+    pub(crate) fn new(matcher: &ObjMatcher, path: &str) -> Self {
+        UnsupportedClause {
+            path: path.to_string(),
+            clause: serde_json::to_value(matcher).unwrap_or(Value::Null),
+        }
+    }
+}
+
+impl fmt::Display for UnsupportedClause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "unsupported clause `{}`", self.clause)
+        } else {
+            write!(f, "unsupported clause `{}` on `{}`", self.clause, self.path)
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedClause {}
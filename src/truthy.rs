@@ -0,0 +1,80 @@
+//! `$truthy` and `$falsy`, matching values that are, or are not, set to something meaningful.
+//!
+//! `{"assignee": {"$truthy": true}}` matches when `assignee` is truthy, that is a non-empty
+//! string, a number other than zero, `true`, or a non-empty array or object. Everything else,
+//! `null` and missing fields included, is falsy. `{"$falsy": true}` is the same as
+//! `{"$truthy": false}`.
+//!
+//! Extended JSON literals count as the values they stand for, so `{"$numberLong": "0"}` is falsy.
+//! Unlike in `$expr`, empty strings, arrays and objects are falsy.
+
+use serde_json::Value;
+
+use crate::{ejson, FalsyOperator, MatchOptions, MatchesValue, TruthyOperator};
+
+pub(crate) fn is_truthy(value: &Value) -> bool {
+    match &*ejson::decoded(value) {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+impl MatchesValue for TruthyOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        is_truthy(other) == self.val
+    }
+}
+
+impl MatchesValue for FalsyOperator {
+    fn matches(&self, other: &Value, _options: &MatchOptions) -> bool {
+        is_truthy(other) != self.val
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_truthy() {
+        let set = from_str(r#"{"assignee": {"$truthy": true}}"#).unwrap();
+        let unset = from_str(r#"{"assignee": {"$falsy": true}}"#).unwrap();
+        for value in &[
+            json!("ann"),
+            json!(-1),
+            json!(0.5),
+            json!(true),
+            json!([0]),
+            json!({"a": null}),
+        ] {
+            let doc = json!({ "assignee": value });
+            assert!(set.matches(&doc), "{}", doc);
+            assert!(!unset.matches(&doc), "{}", doc);
+        }
+        for value in &[
+            json!(null),
+            json!(""),
+            json!(0),
+            json!(-0.0),
+            json!(false),
+            json!([]),
+            json!({}),
+            json!({"$numberLong": "0"}),
+        ] {
+            let doc = json!({ "assignee": value });
+            assert!(!set.matches(&doc), "{}", doc);
+            assert!(unset.matches(&doc), "{}", doc);
+        }
+        assert!(unset.matches(&json!({})));
+        assert!(!set.compile().unwrap().matches(&json!({"assignee": ""})));
+        assert!(from_str(r#"{"a": {"$truthy": false}}"#)
+            .unwrap()
+            .matches(&json!({"a": []})));
+        assert!(from_str(r#"{"a": {"$truthy": 1}}"#).is_err());
+    }
+}
@@ -0,0 +1,331 @@
+//! Matchers checked against the documents they are meant for, so that a misspelled field or a
+//! literal of the wrong type is reported when parsing instead of silently matching nothing.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::shape::Shape;
+use crate::{
+    context, from_json_with_options, from_str_with_options, params, parse_operator, ObjMatcher,
+    ParseOptions,
+};
+
+static ANY: Shape = Shape::Any;
+
+/// An [`ObjMatcher`] whose field paths and literals are known to fit documents of type `T`.
+///
+/// The shape of `T` is read from its `Deserialize` implementation, so field names follow its
+/// serde attributes. Parts of `T` whose shape cannot be determined, such as untagged enums,
+/// flattened structs or `serde_json::Value` fields, accept any path and literal. `null` is
+/// accepted for every field, as missing fields compare equal to it.
+pub struct TypedMatcher<T> {
+    matcher: ObjMatcher,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> TypedMatcher<T> {
+    /// Checks an existing matcher against `T`.
+    pub fn new(matcher: ObjMatcher) -> Result<Self, serde_json::Error> {
+        let shape = Shape::of::<T>();
+        Checker { root: &shape }.matcher(&matcher, &shape, "")?;
+        Ok(TypedMatcher {
+            matcher,
+            marker: PhantomData,
+        })
+    }
+
+    pub fn parse(s: &str) -> Result<Self, serde_json::Error> {
+        Self::parse_with_options(s, &ParseOptions::default())
+    }
+
+    pub fn parse_with_options(s: &str, options: &ParseOptions) -> Result<Self, serde_json::Error> {
+        Self::new(from_str_with_options(s, options)?)
+    }
+
+    pub fn from_json(v: Value) -> Result<Self, serde_json::Error> {
+        Self::from_json_with_options(v, &ParseOptions::default())
+    }
+
+    pub fn from_json_with_options(
+        v: Value,
+        options: &ParseOptions,
+    ) -> Result<Self, serde_json::Error> {
+        Self::new(from_json_with_options(v, options)?)
+    }
+}
+
+impl<T> TypedMatcher<T> {
+    #[must_use]
+    pub fn matches(&self, other: &Value) -> bool {
+        self.matcher.matches(other)
+    }
+
+    #[must_use]
+    pub fn matcher(&self) -> &ObjMatcher {
+        &self.matcher
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> ObjMatcher {
+        self.matcher
+    }
+}
+
+impl<T> Clone for TypedMatcher<T> {
+    fn clone(&self) -> Self {
+        TypedMatcher {
+            matcher: self.matcher.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> fmt::Debug for TypedMatcher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedMatcher")
+            .field("matcher", &self.matcher)
+            .finish()
+    }
+}
+
+struct Checker<'s> {
+    root: &'s Shape,
+}
+
+impl<'s> Checker<'s> {
+    /// Follows recursive references back to the struct they name.
+    fn resolve(&self, shape: &'s Shape) -> &'s Shape {
+        match shape {
+            Shape::Recursive(name) => self.root.find(name).unwrap_or(&ANY),
+            Shape::Optional(shape) => self.resolve(shape),
+            _ => shape,
+        }
+    }
+
+    fn matcher(
+        &self,
+        matcher: &ObjMatcher,
+        shape: &'s Shape,
+        path: &str,
+    ) -> Result<(), serde_json::Error> {
+        match matcher {
+            ObjMatcher::Eq(op) => self.matcher(&op.val, shape, path),
+            ObjMatcher::Ne(op) => self.matcher(&op.val, shape, path),
+            ObjMatcher::Not(op) => self.matcher(&op.val, shape, path),
+            ObjMatcher::Len(op) => self.matcher(&op.val, &Shape::Number, path),
+            ObjMatcher::In(op) => self.matchers(&op.val, shape, path),
+            ObjMatcher::Nin(op) => self.matchers(&op.val, shape, path),
+            ObjMatcher::And(op) => self.matchers(&op.val, shape, path),
+            ObjMatcher::Or(op) => self.matchers(&op.val, shape, path),
+            ObjMatcher::Gt(op) => self.literal(&op.val, shape, path),
+            ObjMatcher::Gte(op) => self.literal(&op.val, shape, path),
+            ObjMatcher::Lt(op) => self.literal(&op.val, shape, path),
+            ObjMatcher::Lte(op) => self.literal(&op.val, shape, path),
+            ObjMatcher::Value(value) => self.value(value, shape, path),
+            _ => Ok(()),
+        }
+    }
+
+    fn matchers(
+        &self,
+        matchers: &[ObjMatcher],
+        shape: &'s Shape,
+        path: &str,
+    ) -> Result<(), serde_json::Error> {
+        matchers
+            .iter()
+            .try_for_each(|matcher| self.matcher(matcher, shape, path))
+    }
+
+    fn value(&self, value: &Value, shape: &'s Shape, path: &str) -> Result<(), serde_json::Error> {
+        if let Some(matcher) = parse_operator(value.clone(), None)? {
+            return self.matcher(&matcher, shape, path);
+        }
+        let o = match value {
+            Value::Object(o) if !context::is_reference(value) && !params::is_placeholder(value) => {
+                o
+            }
+            _ => return self.literal(value, shape, path),
+        };
+        for (key, value) in o {
+            if key == "$comment" {
+                continue;
+            }
+            let path = join(path, key);
+            let mut field = shape;
+            for segment in crate::path::segments(key) {
+                field = self.field(field, &segment, &path)?;
+            }
+            match parse_operator(value.clone(), None)? {
+                Some(matcher) => self.matcher(&matcher, field, &path)?,
+                None => self.literal(value, field, &path)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn field(
+        &self,
+        shape: &'s Shape,
+        key: &str,
+        path: &str,
+    ) -> Result<&'s Shape, serde_json::Error> {
+        let field = match self.resolve(shape) {
+            Shape::Any => Some(&ANY),
+            Shape::Struct(_, fields) => fields
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, shape)| shape),
+            Shape::Map(shape) => Some(&**shape),
+            Shape::Array(shape) => key.parse::<i64>().ok().map(|_| &**shape),
+            Shape::Tuple(shapes) => key
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| shapes.get(index)),
+            Shape::Enum(variants) => variants.iter().find(|v| **v == key).map(|_| &ANY),
+            _ => None,
+        };
+        field.ok_or_else(|| serde::de::Error::custom(format!("unknown field `{key}` in `{path}`")))
+    }
+
+    fn literal(
+        &self,
+        value: &Value,
+        shape: &'s Shape,
+        path: &str,
+    ) -> Result<(), serde_json::Error> {
+        if value.is_null() || context::is_reference(value) || params::is_placeholder(value) {
+            return Ok(());
+        }
+        let shape = self.resolve(shape);
+        match (shape, value) {
+            (Shape::Any, _)
+            | (Shape::Bool, Value::Bool(_))
+            | (Shape::Number, Value::Number(_))
+            | (Shape::String, Value::String(_)) => Ok(()),
+            (Shape::Array(shape), Value::Array(a)) => a
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, value)| self.literal(value, shape, &join(path, &i.to_string()))),
+            (Shape::Tuple(shapes), Value::Array(a)) => a
+                .iter()
+                .zip(shapes)
+                .enumerate()
+                .try_for_each(|(i, (value, shape))| {
+                    self.literal(value, shape, &join(path, &i.to_string()))
+                }),
+            (Shape::Struct(..) | Shape::Map(_), Value::Object(o)) => {
+                o.iter().try_for_each(|(key, value)| {
+                    let path = join(path, &crate::escape_field(key));
+                    let field = self.field(shape, key, &path)?;
+                    self.literal(value, field, &path)
+                })
+            }
+            (Shape::Enum(variants), Value::String(s)) if variants.contains(&s.as_str()) => Ok(()),
+            (Shape::Enum(variants), Value::Object(o))
+                if o.len() == 1 && o.keys().all(|key| variants.contains(&key.as_str())) =>
+            {
+                Ok(())
+            }
+            _ => Err(serde::de::Error::custom(format!(
+                "expected {} for `{}`, found {}",
+                describe(shape),
+                path,
+                value
+            ))),
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn describe(shape: &Shape) -> String {
+    match shape {
+        Shape::Null => "null".to_string(),
+        Shape::Bool => "a boolean".to_string(),
+        Shape::Number => "a number".to_string(),
+        Shape::String => "a string".to_string(),
+        Shape::Array(_) | Shape::Tuple(_) => "an array".to_string(),
+        Shape::Enum(variants) => format!("one of `{}`", variants.join("`, `")),
+        _ => "an object".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Address {
+        city: String,
+        zip: Option<String>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct User {
+        display_name: String,
+        age: u32,
+        address: Address,
+        tags: Vec<String>,
+        status: Status,
+        extra: Value,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Status {
+        Active,
+        Banned,
+    }
+
+    #[test]
+    pub fn test_typed_matcher() {
+        let matcher = TypedMatcher::<User>::parse(
+            r#"{"displayName": "ann", "age": {"$gte": 21}, "address.city": {"$in": ["London"]},
+                "address.zip": null, "tags.0": "a", "status": {"$ne": "banned"},
+                "extra.anything": [1]}"#,
+        )
+        .unwrap();
+        assert!(matcher.matches(&json!({
+            "displayName": "ann", "age": 30, "address": {"city": "London"}, "tags": ["a"],
+            "status": "active", "extra": {"anything": [1]},
+        })));
+        assert!(TypedMatcher::<User>::parse(
+            r#"{"$or": [{"address": {"city": "Paris", "zip": "75001"}}, {"tags": ["a", "b"]}]}"#
+        )
+        .is_ok());
+
+        let err = TypedMatcher::<User>::parse(r#"{"display_name": "ann"}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `display_name`"));
+        let err = TypedMatcher::<User>::parse(r#"{"$or": [{"address.cty": "x"}]}"#).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unknown field `cty` in `address.cty`"));
+        let err = TypedMatcher::<User>::parse(r#"{"age": {"$gt": "21"}}"#).unwrap_err();
+        assert!(err.to_string().contains("expected a number for `age`"));
+        let err = TypedMatcher::<User>::parse(r#"{"status": "deleted"}"#).unwrap_err();
+        assert!(err.to_string().contains("one of `active`, `banned`"));
+        let err = TypedMatcher::<User>::parse(r#"{"address": {"town": "x"}}"#).unwrap_err();
+        assert!(err.to_string().contains("`address.town`"));
+
+        let matcher = crate::Field::new("age").gt(21);
+        assert!(TypedMatcher::<User>::new(matcher).is_ok());
+        let matcher = crate::Field::new("tags.x").eq("a");
+        assert!(TypedMatcher::<User>::new(matcher).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! MongoDB-style update operators applied to documents in place.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{Number, Value};
+
+use crate::{from_json, path, ObjMatcher};
+
+#[derive(Debug, Clone)]
+enum Op {
+    Set(Value),
+    Unset,
+    Inc(Number),
+    Push(Vec<Value>),
+    Pull(ObjMatcher),
+}
+
+/// Modifies documents with `$set`, `$unset`, `$inc`, `$push` and `$pull`, e.g.
+/// `{"$set": {"status": "done"}, "$inc": {"stats.runs": 1}}`.
+///
+/// Fields are addressed with the same dotted paths as queries. `$push` appends a single value, or
+/// every value of `{"$each": [...]}`, and `$pull` removes the array elements matching a query.
+#[derive(Debug, Clone)]
+pub struct Update {
+    ops: Vec<(String, Op)>,
+}
+
+/// Error returned when an [`Update`] cannot be applied to a document.
+#[derive(Debug)]
+pub struct UpdateError {
+    pub path: String,
+    pub message: String,
+}
+
+impl UpdateError {
+    fn new(path: &str, message: &str) -> Self {
+        UpdateError {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+fn parse_op(operator: &str, path: &str, value: Value) -> Result<Op, serde_json::Error> {
+    match (operator, value) {
+        ("$set", value) => Ok(Op::Set(value)),
+        ("$unset", _) => Ok(Op::Unset),
+        ("$inc", Value::Number(n)) => Ok(Op::Inc(n)),
+        ("$push", Value::Object(mut o)) if o.contains_key("$each") => {
+            match (o.remove("$each"), o.is_empty()) {
+                (Some(Value::Array(values)), true) => Ok(Op::Push(values)),
+                _ => Err(serde::de::Error::custom(format!(
+                    "invalid `$push` for `{path}`: `$each` must be the only key and an array"
+                ))),
+            }
+        }
+        ("$push", value) => Ok(Op::Push(vec![value])),
+        ("$pull", value) => Ok(Op::Pull(from_json(value)?)),
+        ("$inc", value) => Err(serde::de::Error::custom(format!(
+            "invalid `$inc` for `{path}`: `{value}` is not a number"
+        ))),
+        (operator, _) => Err(serde::de::Error::custom(format!(
+            "unknown update operator `{operator}`"
+        ))),
+    }
+}
+
+pub(crate) fn add(a: &Number, b: &Number) -> Option<Number> {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        if let Some(sum) = a.checked_add(b) {
+            return Some(sum.into());
+        }
+    }
+    Number::from_f64(a.as_f64()? + b.as_f64()?)
+}
+
+impl Op {
+    fn apply(&self, document: &mut Value, path: &str) -> Result<(), UpdateError> {
+        let current = if let Some(current) = path::get_mut(document, path) {
+            current
+        } else {
+            let value = match self {
+                Op::Set(value) => value.clone(),
+                Op::Inc(n) => Value::Number(n.clone()),
+                Op::Push(values) => Value::Array(values.clone()),
+                Op::Unset | Op::Pull(_) => return Ok(()),
+            };
+            if !path::insert(document, path, value) {
+                return Err(UpdateError::new(
+                    path,
+                    "cannot create a field inside a non-object value",
+                ));
+            }
+            return Ok(());
+        };
+        match (self, current) {
+            (Op::Set(value), current) => *current = value.clone(),
+            (Op::Unset, _) => {
+                path::remove(document, path);
+            }
+            (Op::Inc(n), Value::Number(current)) => {
+                *current = add(current, n).ok_or_else(|| {
+                    UpdateError::new(path, "`$inc` result is not a finite number")
+                })?;
+            }
+            (Op::Inc(_), _) => {
+                return Err(UpdateError::new(path, "cannot `$inc` a non-numeric value"))
+            }
+            (Op::Push(values), Value::Array(current)) => current.extend(values.iter().cloned()),
+            (Op::Push(_), _) => {
+                return Err(UpdateError::new(
+                    path,
+                    "cannot `$push` to a non-array value",
+                ))
+            }
+            (Op::Pull(matcher), Value::Array(current)) => {
+                current.retain(|element| !matcher.matches(element));
+            }
+            (Op::Pull(_), _) => {
+                return Err(UpdateError::new(
+                    path,
+                    "cannot `$pull` from a non-array value",
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Update {
+    pub fn from_json(v: Value) -> Result<Self, serde_json::Error> {
+        let operators = match v {
+            Value::Object(o) => o,
+            _ => {
+                return Err(serde::de::Error::custom(
+                    "update must be an object of update operators",
+                ))
+            }
+        };
+        let mut ops = Vec::new();
+        for (operator, fields) in operators {
+            let fields = match fields {
+                Value::Object(fields) => fields,
+                _ => {
+                    return Err(serde::de::Error::custom(format!(
+                        "`{operator}` must be an object of field paths"
+                    )))
+                }
+            };
+            for (path, value) in fields {
+                let op = parse_op(&operator, &path, value)?;
+                ops.push((path, op));
+            }
+        }
+        Ok(Update { ops })
+    }
+
+    /// Applies the update to `document`, returning whether it changed. On error `document` is
+    /// left unchanged.
+    pub fn apply(&self, document: &mut Value) -> Result<bool, UpdateError> {
+        let mut updated = document.clone();
+        for (path, op) in &self.ops {
+            op.apply(&mut updated, path)?;
+        }
+        let changed = updated != *document;
+        *document = updated;
+        Ok(changed)
+    }
+}
+
+/// Applies `update` to every document matching `matcher`, returning how many changed.
+///
+/// Stops at the first document the update cannot be applied to, leaving the documents before it
+/// updated.
+pub fn update_matching(
+    documents: &mut [Value],
+    matcher: &ObjMatcher,
+    update: &Update,
+) -> Result<usize, UpdateError> {
+    let mut changed = 0;
+    for document in documents.iter_mut().filter(|d| matcher.matches(d)) {
+        if update.apply(document)? {
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Removes every document matching `matcher`, returning how many were removed.
+pub fn delete_matching(documents: &mut Vec<Value>, matcher: &ObjMatcher) -> usize {
+    let len = documents.len();
+    documents.retain(|document| !matcher.matches(document));
+    len - documents.len()
+}
+
+/// Applies `update` to the first document matching `matcher`, returning the document as it was
+/// before the update.
+pub fn find_and_modify(
+    documents: &mut [Value],
+    matcher: &ObjMatcher,
+    update: &Update,
+) -> Result<Option<Value>, UpdateError> {
+    match documents.iter_mut().find(|d| matcher.matches(d)) {
+        Some(document) => {
+            let original = document.clone();
+            update.apply(document)?;
+            Ok(Some(original))
+        }
+        None => Ok(None),
+    }
+}
+
+impl FromStr for Update {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Update::from_json(serde_json::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(update: &str, mut value: Value) -> Result<Value, UpdateError> {
+        update.parse::<Update>().unwrap().apply(&mut value)?;
+        Ok(value)
+    }
+
+    #[test]
+    pub fn test_update() {
+        let doc = json!({"name": "Ada", "stats": {"runs": 1}, "tags": ["a", "b", "a"]});
+        assert_eq!(
+            update(
+                r#"{"$set": {"name": "Grace", "address.city": "NYC"}, "$unset": {"missing": 1}}"#,
+                doc.clone()
+            )
+            .unwrap(),
+            json!({
+                "name": "Grace",
+                "address": {"city": "NYC"},
+                "stats": {"runs": 1},
+                "tags": ["a", "b", "a"],
+            })
+        );
+        assert_eq!(
+            update(
+                r#"{"$inc": {"stats.runs": 2, "stats.time": 0.5}, "$unset": {"name": ""}}"#,
+                doc.clone()
+            )
+            .unwrap(),
+            json!({"stats": {"runs": 3, "time": 0.5}, "tags": ["a", "b", "a"]})
+        );
+        assert_eq!(
+            update(r#"{"$pull": {"tags": "a"}}"#, doc.clone()).unwrap()["tags"],
+            json!(["b"])
+        );
+        assert_eq!(
+            update(r#"{"$push": {"tags": {"$each": ["c", "d"]}}}"#, doc.clone()).unwrap()["tags"],
+            json!(["a", "b", "a", "c", "d"])
+        );
+        assert_eq!(
+            update(r#"{"$push": {"log": {"n": 1}}}"#, doc.clone()).unwrap()["log"],
+            json!([{"n": 1}])
+        );
+        assert_eq!(
+            update(
+                r#"{"$pull": {"items": {"qty": {"$in": [0, 1]}}}}"#,
+                json!({"items": [{"qty": 0}, {"qty": 2}, {"qty": 1}]})
+            )
+            .unwrap(),
+            json!({"items": [{"qty": 2}]})
+        );
+
+        let mut unchanged = doc.clone();
+        let set = r#"{"$set": {"name": "Ada"}}"#.parse::<Update>().unwrap();
+        assert!(!set.apply(&mut unchanged).unwrap());
+    }
+
+    #[test]
+    pub fn test_update_matching() {
+        let mut docs = vec![
+            json!({"id": 1, "status": "new"}),
+            json!({"id": 2, "status": "done"}),
+            json!({"id": 3, "status": "new"}),
+        ];
+        let new = crate::from_str(r#"{"status": "new"}"#).unwrap();
+        let done = r#"{"$set": {"status": "done"}}"#.parse::<Update>().unwrap();
+        let original = find_and_modify(&mut docs, &new, &done).unwrap();
+        assert_eq!(original, Some(json!({"id": 1, "status": "new"})));
+        assert_eq!(docs[0], json!({"id": 1, "status": "done"}));
+        assert_eq!(update_matching(&mut docs, &new, &done).unwrap(), 1);
+        assert_eq!(find_and_modify(&mut docs, &new, &done).unwrap(), None);
+
+        let all = crate::from_str("{}").unwrap();
+        assert_eq!(update_matching(&mut docs, &all, &done).unwrap(), 0);
+        let odd = crate::from_str(r#"{"id": {"$in": [1, 3]}}"#).unwrap();
+        assert_eq!(delete_matching(&mut docs, &odd), 2);
+        assert_eq!(docs, vec![json!({"id": 2, "status": "done"})]);
+    }
+
+    #[test]
+    pub fn test_update_errors() {
+        assert!(r#"{"$rename": {"a": "b"}}"#.parse::<Update>().is_err());
+        assert!(r#"{"$inc": {"a": "1"}}"#.parse::<Update>().is_err());
+        assert!(r#"{"$set": 1}"#.parse::<Update>().is_err());
+
+        let mut doc = json!({"name": "Ada", "n": 1});
+        let update = r#"{"$set": {"n": 2}, "$push": {"name": 1}}"#.parse::<Update>().unwrap();
+        let err = update.apply(&mut doc).unwrap_err();
+        assert_eq!(err.path, "name");
+        assert_eq!(doc, json!({"name": "Ada", "n": 1}));
+        assert!(
+            r#"{"$set": {"name.first": "Ada"}}"#.parse::<Update>().unwrap().apply(&mut doc).is_err()
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! How the [`ObjMatcher::Value`] nodes of a matcher break down into clauses.
+//!
+//! Evaluating, explaining, observing, measuring and repairing a matcher all walk it clause by
+//! clause; they share this breakdown so that they agree on what is an operator, what is a group
+//! of field conditions and what is a literal.
+
+use serde_json::{Map, Value};
+
+use crate::{context, ejson, try_into_operator, ObjMatcher, OperatorRegistry};
+
+/// What a value in a matcher stands for.
+pub(crate) enum Clause<'a> {
+    /// An operator object, such as `{"$gt": 1}`. Invalid operands give a matcher that matches
+    /// nothing.
+    Operator(ObjMatcher),
+    /// Conditions on fields, such as `{"a": 1, "b": {"$gt": 1}}`, listed by [`conditions`].
+    Fields(&'a Map<String, Value>),
+    /// A literal the value must equal.
+    Literal(&'a Value),
+}
+
+/// Breaks down a value applied to a whole document or field, as held by [`ObjMatcher::Value`].
+pub(crate) fn value<'a>(value: &'a Value, registry: Option<&OperatorRegistry>) -> Clause<'a> {
+    if let Some(matcher) = try_into_operator(value, registry) {
+        return Clause::Operator(matcher);
+    }
+    match value {
+        Value::Object(o) if !context::is_reference(value) && !ejson::is_literal(value) => {
+            Clause::Fields(o)
+        }
+        _ => Clause::Literal(value),
+    }
+}
+
+/// The condition on a single field of a [`Clause::Fields`].
+pub(crate) enum Condition<'a> {
+    Operator(ObjMatcher),
+    /// A literal the field must equal, which includes objects without operators.
+    Literal(&'a Value),
+}
+
+pub(crate) fn condition<'a>(
+    value: &'a Value,
+    registry: Option<&OperatorRegistry>,
+) -> Condition<'a> {
+    match try_into_operator(value, registry) {
+        Some(matcher) => Condition::Operator(matcher),
+        None => Condition::Literal(value),
+    }
+}
+
+/// The conditions of a [`Clause::Fields`] with their field paths, leaving out `$comment`.
+pub(crate) fn conditions<'a>(
+    fields: &'a Map<String, Value>,
+    registry: Option<&'a OperatorRegistry>,
+) -> impl Iterator<Item = (&'a str, Condition<'a>)> + 'a {
+    fields
+        .iter()
+        .filter(|(key, _)| *key != "$comment")
+        .map(move |(key, value)| (key.as_str(), condition(value, registry)))
+}
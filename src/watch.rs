@@ -0,0 +1,188 @@
+//! Reloading a [`MatcherSet`] when its rule files change.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{LoadError, MatcherSet, ParseOptions};
+
+/// Error returned when a [`WatchedMatcherSet`] cannot be started.
+#[derive(Debug)]
+pub enum WatchError {
+    /// The initial load of the rule files failed.
+    Load(LoadError),
+    /// The rule files could not be watched.
+    Notify(notify::Error),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::Load(err) => write!(f, "{err}"),
+            WatchError::Notify(err) => write!(f, "cannot watch rule files: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+struct Shared {
+    path: PathBuf,
+    options: ParseOptions,
+    current: RwLock<Arc<MatcherSet>>,
+    last_error: Mutex<Option<LoadError>>,
+}
+
+impl Shared {
+    fn reload(&self) -> Result<(), LoadError> {
+        let result = MatcherSet::load_with_options(&self.path, &self.options);
+        let mut last_error = self.last_error.lock().unwrap();
+        match result {
+            Ok(set) => {
+                *self.current.write().unwrap() = Arc::new(set);
+                *last_error = None;
+                Ok(())
+            }
+            Err(err) => {
+                *last_error = Some(err.clone());
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A [`MatcherSet`] that reloads itself whenever a file under its path is created, modified or
+/// removed.
+///
+/// Reloads are atomic: the new rules replace the old ones only once every file has loaded
+/// successfully, and until then [`current`](Self::current) keeps returning the previous set.
+pub struct WatchedMatcherSet {
+    shared: Arc<Shared>,
+    _watcher: RecommendedWatcher,
+}
+
+impl WatchedMatcherSet {
+    /// Loads the rules under `path` and starts watching it.
+    pub fn watch<P: AsRef<Path>>(path: P, options: ParseOptions) -> Result<Self, WatchError> {
+        let path = path.as_ref().to_path_buf();
+        let set = MatcherSet::load_with_options(&path, &options).map_err(WatchError::Load)?;
+        let shared = Arc::new(Shared {
+            path,
+            options,
+            current: RwLock::new(Arc::new(set)),
+            last_error: Mutex::new(None),
+        });
+
+        let handler_shared = Arc::clone(&shared);
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let changed = match event {
+                Ok(event) => {
+                    event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()
+                }
+                Err(_) => false,
+            };
+            if changed {
+                let _ = handler_shared.reload();
+            }
+        })
+        .map_err(WatchError::Notify)?;
+        watcher
+            .watch(&shared.path, RecursiveMode::Recursive)
+            .map_err(WatchError::Notify)?;
+
+        Ok(WatchedMatcherSet {
+            shared,
+            _watcher: watcher,
+        })
+    }
+
+    /// The most recently loaded rules.
+    #[must_use]
+    pub fn current(&self) -> Arc<MatcherSet> {
+        Arc::clone(&self.shared.current.read().unwrap())
+    }
+
+    /// The failure of the latest reload, if it failed.
+    #[must_use]
+    pub fn last_error(&self) -> Option<LoadError> {
+        self.shared.last_error.lock().unwrap().clone()
+    }
+
+    /// Reloads the rules immediately, without waiting for a change notification.
+    pub fn reload(&self) -> Result<(), LoadError> {
+        self.shared.reload()
+    }
+}
+
+impl fmt::Debug for WatchedMatcherSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchedMatcherSet")
+            .field("path", &self.shared.path)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn wait_for<F: Fn() -> bool>(condition: F) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let dir =
+            std::env::temp_dir().join(format!("serde_json_matcher_watch_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("rules.json");
+        fs::write(&file, r#"{"id": "r", "matcher": {"a": 1}}"#).unwrap();
+
+        let watched = WatchedMatcherSet::watch(&dir, ParseOptions::default()).unwrap();
+        assert!(watched
+            .current()
+            .get("r")
+            .unwrap()
+            .matcher
+            .matches(&json!({"a": 1})));
+
+        fs::write(&file, r#"{"id": "r", "matcher": {"a": 2}}"#).unwrap();
+        assert!(wait_for(|| watched
+            .current()
+            .get("r")
+            .unwrap()
+            .matcher
+            .matches(&json!({"a": 2}))));
+
+        fs::write(&file, r#"{"id": "r", "matcher": "#).unwrap();
+        assert!(wait_for(|| watched.last_error().is_some()));
+        assert!(watched
+            .current()
+            .get("r")
+            .unwrap()
+            .matcher
+            .matches(&json!({"a": 2})));
+
+        fs::write(dir.join("more.json"), r#"{"id": "s", "matcher": {}}"#).unwrap();
+        fs::write(&file, r#"{"id": "r", "matcher": {"a": 3}}"#).unwrap();
+        assert!(wait_for(|| watched.current().len() == 2));
+        assert!(watched.last_error().is_none());
+
+        drop(watched);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
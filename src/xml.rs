@@ -0,0 +1,337 @@
+//! Matching XML documents, converted into values.
+//!
+//! A document becomes an object with a single field, named after the root element, holding the
+//! root element's value:
+//!
+//! - An element with neither attributes nor child elements is its text, e.g. `<id>7</id>` is
+//!   `"7"` and `<note/>` is `""`.
+//! - Any other element is an object. Each attribute is a field named after it with a leading `@`,
+//!   e.g. `"@currency"`, each child element a field named after it, and the element's text, if
+//!   not only whitespace, the `"#text"` field. Children sharing a name are gathered, in order, in
+//!   an array.
+//!
+//! Values are strings, so `{"order.total": {"$gt": "100"}}` compares text; names keep their
+//! namespace prefixes as written, e.g. `"soap:Envelope"`. Comments, processing instructions and
+//! the document type declaration are skipped, CDATA sections are text, and only the predefined
+//! and numeric character references are expanded.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::ObjMatcher;
+
+/// Error parsing an XML document.
+#[derive(Debug, Clone)]
+pub struct XmlError {
+    /// Line on which the error was found, from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+/// Converts an XML document into a value as described in the [module documentation](self).
+pub fn from_xml(text: &str) -> Result<Value, XmlError> {
+    let mut parser = Parser { text, pos: 0 };
+    parser.misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err(parser.error("expected the root element"));
+    }
+    let (name, value) = parser.element()?;
+    parser.misc()?;
+    if parser.pos < text.len() {
+        return Err(parser.error("unexpected content after the root element"));
+    }
+    let mut document = Map::new();
+    document.insert(name, value);
+    Ok(Value::Object(document))
+}
+
+impl ObjMatcher {
+    /// Matches the value of the XML document `text`, see [`from_xml`].
+    pub fn matches_xml(&self, text: &str) -> Result<bool, XmlError> {
+        Ok(self.matches(&from_xml(text)?))
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> XmlError {
+        XmlError {
+            line: self.text[..self.pos].matches('\n').count() + 1,
+            message: message.to_string(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Moves past `end`, returning the text before it.
+    fn until(&mut self, end: &str, what: &str) -> Result<&'a str, XmlError> {
+        match self.rest().find(end) {
+            Some(i) => {
+                let skipped = &self.rest()[..i];
+                self.pos += i + end.len();
+                Ok(skipped)
+            }
+            None => Err(self.error(&format!("unterminated {what}"))),
+        }
+    }
+
+    /// Skips whitespace, comments, processing instructions and the document type declaration.
+    fn misc(&mut self) -> Result<(), XmlError> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<!--") {
+                self.until("-->", "comment")?;
+            } else if rest.starts_with("<?") {
+                self.until("?>", "processing instruction")?;
+            } else if rest.starts_with("<!DOCTYPE") {
+                self.doctype()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn doctype(&mut self) -> Result<(), XmlError> {
+        let mut depth = 0;
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '>' if depth == 0 => {
+                    self.pos += i + 1;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        Err(self.error("unterminated document type declaration"))
+    }
+
+    fn name(&mut self) -> Result<&'a str, XmlError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), XmlError> {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{token}`")))
+        }
+    }
+
+    /// Parses the element starting at `<`, returning its name and value.
+    fn element(&mut self) -> Result<(String, Value), XmlError> {
+        self.expect("<")?;
+        let name = self.name()?;
+        let mut fields = Map::new();
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok((name.to_string(), value(fields, String::new())));
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = if self.rest().starts_with('"') {
+                "\""
+            } else {
+                "'"
+            };
+            self.expect(quote)?;
+            let start = self.pos;
+            let raw = self.until(quote, "attribute value")?;
+            if raw.contains('<') {
+                self.pos = start;
+                return Err(self.error("`<` in attribute value"));
+            }
+            let unescaped = self.unescape(raw, start)?;
+            if fields
+                .insert(format!("@{attribute}"), Value::String(unescaped))
+                .is_some()
+            {
+                return Err(self.error(&format!("duplicate attribute `{attribute}`")));
+            }
+        }
+        let mut text = String::new();
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                if self.name()? != name {
+                    return Err(self.error(&format!("expected `</{name}>`")));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok((name.to_string(), value(fields, text)));
+            } else if rest.starts_with("<!--") {
+                self.until("-->", "comment")?;
+            } else if rest.starts_with("<?") {
+                self.until("?>", "processing instruction")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.pos += "<![CDATA[".len();
+                text.push_str(self.until("]]>", "CDATA section")?);
+            } else if rest.starts_with('<') {
+                let (child, value) = self.element()?;
+                add_child(&mut fields, child, value);
+            } else if rest.is_empty() {
+                return Err(self.error(&format!("unterminated element `{name}`")));
+            } else {
+                let start = self.pos;
+                let len = rest.find('<').unwrap_or(rest.len());
+                self.pos += len;
+                text.push_str(&self.unescape(&rest[..len], start)?);
+            }
+        }
+    }
+
+    /// Expands the character references in `raw`, which starts at `start`.
+    fn unescape(&mut self, raw: &str, start: usize) -> Result<String, XmlError> {
+        let mut unescaped = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(i) = rest.find('&') {
+            unescaped.push_str(&rest[..i]);
+            let end = if let Some(end) = rest[i..].find(';') {
+                i + end
+            } else {
+                self.pos = start + (raw.len() - rest.len()) + i;
+                return Err(self.error("unterminated character reference"));
+            };
+            let reference = &rest[i + 1..end];
+            let c = match reference {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if reference.starts_with("#x") => u32::from_str_radix(&reference[2..], 16)
+                    .ok()
+                    .and_then(std::char::from_u32),
+                _ if reference.starts_with('#') => {
+                    reference[1..].parse().ok().and_then(std::char::from_u32)
+                }
+                _ => None,
+            };
+            if let Some(c) = c {
+                unescaped.push(c)
+            } else {
+                self.pos = start + (raw.len() - rest.len()) + i;
+                return Err(self.error(&format!("unknown reference `&{reference};`")));
+            }
+            rest = &rest[end + 1..];
+        }
+        unescaped.push_str(rest);
+        Ok(unescaped)
+    }
+}
+
+fn add_child(fields: &mut Map<String, Value>, name: String, value: Value) {
+    match fields.get_mut(&name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None => {
+            fields.insert(name, value);
+        }
+    }
+}
+
+fn value(mut fields: Map<String, Value>, text: String) -> Value {
+    if fields.is_empty() {
+        return Value::String(text);
+    }
+    if !text.trim().is_empty() {
+        fields.insert("#text".to_string(), Value::String(text));
+    }
+    Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use serde_json::json;
+
+    #[test]
+    pub fn test_from_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE order [<!ELEMENT order ANY>]>
+            <!-- webhook -->
+            <order id="42" status='paid'>
+                <customer>Ann &amp; Bob</customer>
+                <item sku="A1">2</item>
+                <item sku="B2"><![CDATA[<gift>]]></item>
+                <note/>
+                <total currency="EUR">&#x31;00.50</total>
+            </order>"#;
+        assert_eq!(
+            from_xml(xml).unwrap(),
+            json!({"order": {
+                "@id": "42",
+                "@status": "paid",
+                "customer": "Ann & Bob",
+                "item": [{"@sku": "A1", "#text": "2"}, {"@sku": "B2", "#text": "<gift>"}],
+                "note": "",
+                "total": {"@currency": "EUR", "#text": "100.50"},
+            }})
+        );
+
+        let matcher =
+            from_str(r#"{"order.@status": "paid", "order.item.1.@sku": "B2", "order.total.#text": {"$gt": "100"}}"#)
+                .unwrap();
+        assert!(matcher.matches_xml(xml).unwrap());
+        assert!(!matcher
+            .matches_xml(r#"<order status="open"><item/></order>"#)
+            .unwrap());
+
+        for (malformed, line) in &[
+            ("", 1),
+            ("<a><b></a>", 1),
+            ("<a>\n<b x=1/></a>", 2),
+            ("<a x='1' x='2'/>", 1),
+            ("<a>&nbsp;</a>", 1),
+            ("<a/><b/>", 1),
+            ("<a>\n\ntext", 3),
+        ] {
+            let err = from_xml(malformed).unwrap_err();
+            assert_eq!(err.line, *line, "{malformed:?}: {err}");
+        }
+    }
+}